@@ -0,0 +1,353 @@
+//! `#[derive(FromMultitext)]` and `include_multitext!`, re-exported by the
+//! `multitext` crate when its `derive` feature is enabled
+//!
+//! # `#[derive(FromMultitext)]`
+//!
+//! Implements `multitext::FromMultitext`. Each named field is bound to the
+//! section whose key is the field's name, or the key given by
+//! `#[multitext(rename = "...")]`. The field's type is built from the section
+//! body with `From<String>`. A field of type `Option<T>` is `None` when its
+//! section is missing; any other field is required unless it has
+//! `#[multitext(default)]`, which falls back to `Default::default()`, or
+//! `#[multitext(default = "...")]`, which falls back to the given text.
+//!
+//! Fields can also demand more of their sections:
+//!
+//! - `required` makes a missing section an error even for an `Option` field
+//! - `max_len = N` rejects a body longer than `N` bytes
+//! - `matches = "..."` rejects a body that doesn't match a pattern, in the
+//!   syntax of `multitext::redact::Selection::Pattern`
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+use std::path::PathBuf;
+
+#[proc_macro_derive(FromMultitext, attributes(multitext))]
+pub fn derive_from_multitext(input: TokenStream) -> TokenStream {
+    let code = match expand(input) {
+        Ok(code) => code,
+        Err(message) => format!("compile_error!({:?});", message),
+    };
+    code.parse().expect("the generated code is valid Rust")
+}
+
+/// Parses a multitext file at compile time, expanding to its sections as a
+/// `&'static [(&'static str, &'static str)]` of keys and bodies in file order
+///
+/// The path is relative to the directory of the crate's `Cargo.toml`. Any
+/// further arguments are keys the file must have. A file without a header, a
+/// repeated key or a missing required key is a compile error, and editing the
+/// file triggers a rebuild. Continuation parts are left as separate sections.
+///
+/// ```ignore
+/// const SHADERS: &[(&str, &str)] =
+///     multitext::include_multitext!("shaders/basic.mt", "vertex shader", "fragment shader");
+///
+/// let vertex = SHADERS.iter().find(|(key, _)| *key == "vertex shader").unwrap().1;
+/// ```
+#[proc_macro]
+pub fn include_multitext(input: TokenStream) -> TokenStream {
+    let code = match expand_include(input) {
+        Ok(code) => code,
+        Err(message) => format!("compile_error!({:?})", message),
+    };
+    code.parse().expect("the generated code is valid Rust")
+}
+
+fn expand_include(input: TokenStream) -> Result<String, String> {
+    let mut arguments = Vec::new();
+    for argument in split_commas(input) {
+        match &argument[..] {
+            [TokenTree::Literal(literal)] => arguments.push(unquote(&literal.to_string())?),
+            _ => return Err("include_multitext! takes a path and then required keys, all string literals".to_string()),
+        }
+    }
+    let (path, required) = arguments.split_first().ok_or("include_multitext! needs the path of a file")?;
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from).unwrap_or_default();
+    let path = dir.join(path);
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let sections = split_sections(&text).map_err(|message| format!("{}: {}", path.display(), message))?;
+    for key in required {
+        if !sections.iter().any(|(k, _)| k == key) {
+            return Err(format!("{}: missing section {:?}", path.display(), key));
+        }
+    }
+
+    let mut items = String::new();
+    for (key, body) in &sections {
+        items.push_str(&format!("({:?}, {:?}),", key, body));
+    }
+    Ok(format!(
+        "{{ const _: &[u8] = include_bytes!({:?}); &[{}] as &'static [(&'static str, &'static str)] }}",
+        path.to_string_lossy(), items,
+    ))
+}
+
+/// The value of a plain string literal
+fn unquote(literal: &str) -> Result<String, String> {
+    let inner = literal.strip_prefix('"').and_then(|l| l.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string literal, found {}", literal))?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            _ => return Err(format!("unsupported escape in {}", literal)),
+        });
+    }
+    Ok(out)
+}
+
+/// Splits `text` into `(key, body)` pairs the way the multitext parser does
+/// by default, escaped marker lines included, except that a repeated key is
+/// an error
+fn split_sections(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut lines = text.lines();
+    let marker = lines.by_ref().find_map(|line| line.find("multitext header").map(|i| line[..i].trim_end()))
+        .ok_or("missing multitext header")?;
+    let mut sections = vec![("multitext header".to_string(), String::new())];
+    for line in lines {
+        match line.strip_prefix(marker) {
+            Some(rest) => {
+                let key = rest.trim().to_string();
+                if sections.iter().any(|(k, _)| *k == key) {
+                    return Err(format!("duplicate key {:?}", key));
+                }
+                sections.push((key, String::new()));
+            }
+            None => {
+                let body = &mut sections.last_mut().expect("the header section is always there").1;
+                body.push_str(unescape(line, marker));
+                body.push('\n');
+            }
+        }
+    }
+    Ok(sections)
+}
+
+/// Drops one backslash from a body line that is backslashes followed by the
+/// marker, which is how the writer escapes such lines
+///
+/// Markers that themselves start with a backslash have no escape.
+fn unescape<'a>(line: &'a str, marker: &str) -> &'a str {
+    let escapable = !marker.is_empty() && !marker.starts_with('\\');
+    match line.strip_prefix('\\') {
+        Some(rest) if escapable && rest.trim_start_matches('\\').starts_with(marker) => rest,
+        _ => line,
+    }
+}
+
+/// What to use when a non-optional field's section is missing
+enum Fallback {
+    None,
+    Default,
+    Text(String),
+}
+
+struct Field {
+    name: String,
+    optional: bool,
+    options: FieldOptions,
+}
+
+/// What a field's `#[multitext(...)]` attributes ask for
+struct FieldOptions {
+    /// The key as a string literal, quotes and escapes included
+    key: Option<String>,
+    fallback: Fallback,
+    required: bool,
+    /// The limit as an integer literal
+    max_len: Option<String>,
+    /// The pattern as a string literal
+    matches: Option<String>,
+}
+
+fn expand(input: TokenStream) -> Result<String, String> {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let position = tokens.iter().position(|t| is_ident(t, "struct"))
+        .ok_or("FromMultitext can only be derived for structs")?;
+    let name = match tokens.get(position + 1) {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected a struct name".to_string()),
+    };
+    let fields = match tokens.get(position + 2) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => parse_fields(group.stream())?,
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '<' => {
+            return Err("FromMultitext can't be derived for generic structs".to_string());
+        }
+        _ => return Err("FromMultitext needs a struct with named fields".to_string()),
+    };
+
+    let mut initializers = String::new();
+    for field in &fields {
+        let options = &field.options;
+        let key = options.key.as_deref().expect("every field has a key");
+        let required = options.required || (!field.optional && matches!(options.fallback, Fallback::None));
+        let mut value = if required {
+            format!(
+                "let body = ::multitext::bind::required(map, {key}, {ty:?}, {field:?})?;",
+                key = key, ty = name, field = field.name,
+            )
+        } else {
+            format!("let body = map.get({}).map(::std::string::String::as_str);", key)
+        };
+        if options.max_len.is_some() || options.matches.is_some() {
+            let check = format!(
+                "::multitext::bind::validate(body, {key}, {max_len}, {matches}, {ty:?}, {field:?})?;",
+                key = key,
+                max_len = options.max_len.as_ref().map_or("::std::option::Option::None".to_string(), |n| {
+                    format!("::std::option::Option::Some({})", n)
+                }),
+                matches = options.matches.as_ref().map_or("::std::option::Option::None".to_string(), |p| {
+                    format!("::std::option::Option::Some({})", p)
+                }),
+                ty = name, field = field.name,
+            );
+            value.push_str(&if required { check } else { format!("if let Some(body) = body {{ {} }}", check) });
+        }
+        let convert = "::std::convert::From::from(::std::string::String::from(body))";
+        let fallback = match &options.fallback {
+            Fallback::Text(text) => format!("::std::convert::From::from(::std::string::String::from({}))", text),
+            _ => "::std::default::Default::default()".to_string(),
+        };
+        value.push_str(&match (required, field.optional, &options.fallback) {
+            (true, true, _) => format!("::std::option::Option::Some({})", convert),
+            (true, false, _) => convert.to_string(),
+            (false, true, Fallback::Text(_)) => format!(
+                "::std::option::Option::Some(match body {{ \
+                     ::std::option::Option::Some(body) => {}, ::std::option::Option::None => {} }})",
+                convert, fallback,
+            ),
+            (false, true, _) => format!("body.map(|body| {})", convert),
+            (false, false, _) => format!(
+                "match body {{ ::std::option::Option::Some(body) => {}, ::std::option::Option::None => {} }}",
+                convert, fallback,
+            ),
+        });
+        initializers.push_str(&format!("{}: {{ {} }},\n", field.name, value));
+    }
+
+    Ok(format!(
+        "impl ::multitext::FromMultitext for {name} {{
+             fn from_map(map: &::multitext::Map) -> ::std::result::Result<Self, ::multitext::Error> {{
+                 ::std::result::Result::Ok({name} {{ {initializers} }})
+             }}
+         }}
+         impl<'a> ::std::convert::TryFrom<&'a ::multitext::Map> for {name} {{
+             type Error = ::multitext::Error;
+             fn try_from(map: &'a ::multitext::Map) -> ::std::result::Result<Self, ::multitext::Error> {{
+                 <Self as ::multitext::FromMultitext>::from_map(map)
+             }}
+         }}",
+        name = name, initializers = initializers,
+    ))
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+fn is_punct(token: &TokenTree, c: char) -> bool {
+    matches!(token, TokenTree::Punct(punct) if punct.as_char() == c)
+}
+
+/// Splits `tokens` at commas outside of angle brackets
+fn split_commas(tokens: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    let mut depth = 0usize;
+    let mut previous_dash = false;
+    for token in tokens {
+        match &token {
+            t if is_punct(t, '<') => depth += 1,
+            // `->` in a function pointer type isn't a closing bracket
+            t if is_punct(t, '>') && !previous_dash => depth = depth.saturating_sub(1),
+            t if is_punct(t, ',') && depth == 0 => {
+                parts.push(Vec::new());
+                previous_dash = false;
+                continue;
+            }
+            _ => {}
+        }
+        previous_dash = is_punct(&token, '-');
+        parts.last_mut().expect("there is always a part").push(token);
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn parse_fields(tokens: TokenStream) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    for tokens in split_commas(tokens) {
+        let mut rest = &tokens[..];
+        let mut options = FieldOptions { key: None, fallback: Fallback::None, required: false, max_len: None, matches: None };
+        while let [pound, TokenTree::Group(attribute), after @ ..] = rest {
+            if !is_punct(pound, '#') {
+                break;
+            }
+            let attribute: Vec<TokenTree> = attribute.stream().into_iter().collect();
+            if let [name, TokenTree::Group(group)] = &attribute[..] {
+                if is_ident(name, "multitext") {
+                    parse_options(group.stream(), &mut options)?;
+                }
+            }
+            rest = after;
+        }
+        if let [first, after @ ..] = rest {
+            if is_ident(first, "pub") {
+                rest = match after {
+                    [TokenTree::Group(group), field @ ..] if group.delimiter() == Delimiter::Parenthesis => field,
+                    _ => after,
+                };
+            }
+        }
+        let (name, ty) = match rest {
+            [TokenTree::Ident(name), colon, ty @ ..] if is_punct(colon, ':') => (name.to_string(), ty),
+            _ => return Err("FromMultitext needs a struct with named fields".to_string()),
+        };
+        let optional = matches!(ty.iter().take_while(|t| !is_punct(t, '<')).last(), Some(t) if is_ident(t, "Option"));
+        options.key.get_or_insert_with(|| format!("{:?}", name.trim_start_matches("r#")));
+        fields.push(Field { name, optional, options });
+    }
+    Ok(fields)
+}
+
+/// Reads the options of one `#[multitext(...)]` attribute
+fn parse_options(tokens: TokenStream, options: &mut FieldOptions) -> Result<(), String> {
+    for option in split_commas(tokens) {
+        let string = |value: &TokenTree, name: &str| match value {
+            TokenTree::Literal(literal) if literal.to_string().starts_with('"') => Ok(literal.to_string()),
+            _ => Err(format!("`{}` needs a string, as in `{} = \"...\"`", name, name)),
+        };
+        match &option[..] {
+            [name, equals, value] if is_ident(name, "rename") && is_punct(equals, '=') => {
+                options.key = Some(string(value, "rename")?);
+            }
+            [name, equals, value] if is_ident(name, "default") && is_punct(equals, '=') => {
+                options.fallback = Fallback::Text(string(value, "default")?);
+            }
+            [name] if is_ident(name, "default") => options.fallback = Fallback::Default,
+            [name] if is_ident(name, "required") => options.required = true,
+            [name, equals, TokenTree::Literal(value)] if is_ident(name, "max_len") && is_punct(equals, '=') => {
+                let value = value.to_string();
+                value.parse::<usize>().map_err(|_| format!("`max_len` needs a number of bytes, not {}", value))?;
+                options.max_len = Some(value);
+            }
+            [name, equals, value] if is_ident(name, "matches") && is_punct(equals, '=') => {
+                options.matches = Some(string(value, "matches")?);
+            }
+            _ => {
+                let option: TokenStream = option.into_iter().collect();
+                return Err(format!("unknown multitext attribute `{}`", option));
+            }
+        }
+    }
+    Ok(())
+}
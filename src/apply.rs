@@ -0,0 +1,148 @@
+//! Edits described in JSON, for tools written in other languages
+//!
+//! An edit list is a JSON array of operations, applied in order:
+//!
+//! ```text
+//! [
+//!   {"op": "set", "key": "vertex", "body": "void main() {}\n"},
+//!   {"op": "append", "key": "notes", "text": "one more line\n"},
+//!   {"op": "rename", "key": "frag", "to": "fragment"},
+//!   {"op": "remove", "key": "old"}
+//! ]
+//! ```
+//!
+//! `set` replaces the body of a section, adding it at the end if it doesn't
+//! exist. `append` adds text to the end of a section's body, likewise adding
+//! the section if needed. `rename` changes a section's key in place, and
+//! `remove` deletes it; both fail if the section doesn't exist. Every
+//! operation fails on a locked section. Applying is all or nothing: if one
+//! operation fails, the document is left as it was.
+//!
+//! This is what `multitext apply` runs. Since the document is parsed with
+//! attributes and its preamble, everything outside the edited sections is
+//! written back unchanged.
+
+use crate::json::{self, Value};
+use crate::{Document, Error, ErrorKind};
+
+/// One operation of an edit list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Set { key: String, body: String },
+    Append { key: String, text: String },
+    Rename { key: String, to: String },
+    Remove { key: String },
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operation::Set { key, .. } => write!(f, "set \"{}\"", key),
+            Operation::Append { key, .. } => write!(f, "append to \"{}\"", key),
+            Operation::Rename { key, to } => write!(f, "rename \"{}\" to \"{}\"", key, to),
+            Operation::Remove { key } => write!(f, "remove \"{}\"", key),
+        }
+    }
+}
+
+/// Reads an edit list in the format described in the [module
+/// documentation](self)
+///
+/// # Examples
+/// ```
+/// use multitext::apply::{parse_operations, Operation};
+///
+/// let ops = parse_operations(r#"[{"op": "remove", "key": "old"}]"#).unwrap();
+/// assert_eq!(ops, [Operation::Remove { key: "old".to_string() }]);
+/// assert!(parse_operations(r#"[{"op": "remove"}]"#).is_err());
+/// ```
+pub fn parse_operations(text: &str) -> Result<Vec<Operation>, Error> {
+    let value = json::parse(text).map_err(|(offset, message)| {
+        Error::new(ErrorKind::Malformed, format!("invalid JSON at byte {}: {}", offset, message))
+    })?;
+    let items = match value {
+        Value::Array(items) => items,
+        _ => return Err(Error::new(ErrorKind::Malformed, "an edit list must be a JSON array")),
+    };
+    items.iter().enumerate().map(|(index, item)| {
+        let field = |name: &str| item.get(name).and_then(Value::as_str).map(str::to_string).ok_or_else(|| {
+            Error::new(ErrorKind::Malformed, format!("edit {}: missing string \"{}\"", index + 1, name))
+        });
+        match item.get("op").and_then(Value::as_str) {
+            Some("set") => Ok(Operation::Set { key: field("key")?, body: field("body")? }),
+            Some("append") => Ok(Operation::Append { key: field("key")?, text: field("text")? }),
+            Some("rename") => Ok(Operation::Rename { key: field("key")?, to: field("to")? }),
+            Some("remove") => Ok(Operation::Remove { key: field("key")? }),
+            Some(op) => Err(Error::new(ErrorKind::Malformed, format!("edit {}: unknown op \"{}\"", index + 1, op))),
+            None => Err(Error::new(ErrorKind::Malformed, format!("edit {}: missing string \"op\"", index + 1))),
+        }
+    }).collect()
+}
+
+/// Applies `operations` to `doc` in order, or none of them if one fails
+///
+/// # Examples
+/// ```
+/// use multitext::apply::{apply_operations, parse_operations};
+///
+/// let mut doc: multitext::Document = vec![("a", "1\n"), ("b", "2\n")].into_iter().collect();
+/// let ops = parse_operations(r#"[
+///     {"op": "append", "key": "a", "text": "more\n"},
+///     {"op": "rename", "key": "b", "to": "c"}
+/// ]"#).unwrap();
+/// apply_operations(&mut doc, &ops).unwrap();
+/// assert_eq!(&doc["a"], "1\nmore\n");
+/// assert_eq!(&doc["c"], "2\n");
+/// ```
+pub fn apply_operations(doc: &mut Document, operations: &[Operation]) -> Result<(), Error> {
+    let mut edited = doc.clone();
+    for (index, operation) in operations.iter().enumerate() {
+        apply_one(&mut edited, operation).map_err(|mut e| {
+            e.error_message = format!("edit {} ({}): {}", index + 1, operation, e.error_message);
+            e
+        })?;
+    }
+    *doc = edited;
+    Ok(())
+}
+
+fn apply_one(doc: &mut Document, operation: &Operation) -> Result<(), Error> {
+    match operation {
+        Operation::Set { key, body } => doc.insert(key.as_str(), body.as_str()).map(drop),
+        Operation::Append { key, text } => {
+            let body = format!("{}{}", doc.get(key).unwrap_or(""), text);
+            doc.insert(key.as_str(), body).map(drop)
+        }
+        Operation::Rename { key, to } => doc.rename(key, to),
+        Operation::Remove { key } => {
+            doc.require(key)?;
+            doc.remove(key).map(drop)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_is_all_or_nothing() {
+        let mut doc: Document = vec![("a", "1\n"), ("b", "2\n")].into_iter().collect();
+        let ops = parse_operations(r#"[
+            {"op": "set", "key": "a", "body": "xé\n", "note": "ignored"},
+            {"op": "remove", "key": "missing"}
+        ]"#).unwrap();
+        let e = apply_operations(&mut doc, &ops).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingSection);
+        assert!(e.message().starts_with("edit 2 (remove \"missing\"): "));
+        assert_eq!(&doc["a"], "1\n");
+
+        apply_operations(&mut doc, &ops[..1]).unwrap();
+        assert_eq!(&doc["a"], "x\u{e9}\n");
+
+        let e = parse_operations("[{\"op\": \"set\", \"key\": \"a\", \"body\": 1}]").unwrap_err();
+        assert_eq!(e.message(), "edit 1: missing string \"body\"");
+        assert!(parse_operations("[{\"op\": \"set\",}]").unwrap_err().message().starts_with("invalid JSON at byte 14"));
+    }
+}
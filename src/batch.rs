@@ -0,0 +1,85 @@
+//! Running one operation over many files at once
+//!
+//! [`find_files`] collects the files under a directory that match a glob, and
+//! [`run_parallel`] processes a list of files on every available core, which
+//! is what the `multitext` command line tool does with `--recursive`.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returns every file under `dir` whose path relative to `dir` matches
+/// `glob`, sorted
+///
+/// The glob uses `/` as the separator and supports `*`, `?`, `[...]` and
+/// `**`, which also matches no directories at all, so `**/*.mt` finds `.mt`
+/// files at any depth including `dir` itself.
+pub fn find_files<P: AsRef<Path>>(dir: P, glob: &str) -> io::Result<Vec<PathBuf>> {
+    let glob: Vec<char> = glob.chars().collect();
+    let mut found = Vec::new();
+    let mut pending = vec![(dir.as_ref().to_path_buf(), String::new())];
+    while let Some((dir, relative)) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let relative = if relative.is_empty() { name } else { format!("{}/{}", relative, name) };
+            if entry.file_type()?.is_dir() {
+                pending.push((entry.path(), relative));
+            } else if crate::ignore::glob_match(&glob, &relative.chars().collect::<Vec<_>>()) {
+                found.push(entry.path());
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Applies `f` to every path on `jobs` threads, or one per core if `jobs` is
+/// `None`, returning the results in the order of `paths`
+///
+/// # Examples
+/// ```
+/// let paths = vec!["a.mt".into(), "b.mt".into(), "c.mt".into()];
+/// let names = multitext::batch::run_parallel(&paths, Some(2), |p| p.display().to_string());
+/// assert_eq!(names, ["a.mt", "b.mt", "c.mt"]);
+/// ```
+pub fn run_parallel<T, F>(paths: &[PathBuf], jobs: Option<usize>, f: F) -> Vec<T>
+where T: Send, F: Fn(&Path) -> T + Sync
+{
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .max(1);
+    if jobs == 1 || paths.len() < 2 {
+        return paths.iter().map(|p| f(p)).collect();
+    }
+    let chunk_size = paths.len().div_ceil(jobs);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|p| f(p)).collect::<Vec<T>>()))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_files() {
+        let dir = crate::testing::TempDir::new("batch").unwrap();
+        std::fs::create_dir_all(dir.join("shaders/lit")).unwrap();
+        for file in ["top.mt", "notes.txt", "shaders/a.mt", "shaders/lit/b.mt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let relative = |glob| -> Vec<String> {
+            find_files(&dir, glob).unwrap().iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect()
+        };
+        assert_eq!(relative("**/*.mt"), ["shaders/a.mt", "shaders/lit/b.mt", "top.mt"]);
+        assert_eq!(relative("shaders/*.mt"), ["shaders/a.mt"]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Binding sections to the fields of a struct
+//!
+//! With the `derive` feature, `#[derive(FromMultitext)]` implements
+//! [`FromMultitext`] and `TryFrom<&Map>` for a struct with named fields. Each
+//! field is bound to the section named after it, or to the key given by
+//! `#[multitext(rename = "...")]`, and built from the body with
+//! `From<String>`. `Option` fields are `None` when their section is missing;
+//! other fields are required unless they have `#[multitext(default)]` or
+//! `#[multitext(default = "...")]`. `#[multitext(required)]` makes even an
+//! `Option` field's section mandatory, and `max_len = N` and
+//! `matches = "..."` check a present body's size in bytes and its contents,
+//! failing [`from_map`](FromMultitext::from_map) with a message naming the
+//! section and field.
+//!
+//! ```ignore
+//! #[derive(multitext::FromMultitext)]
+//! struct Shader {
+//!     #[multitext(rename = "vertex shader", max_len = 4096, matches = "^#version")]
+//!     vertex: String,
+//!     #[multitext(rename = "fragment shader")]
+//!     fragment: String,
+//!     notes: Option<String>,
+//!     #[multitext(default = "330")]
+//!     version: String,
+//! }
+//!
+//! let shader = Shader::from_map(&multitext::open_and_parse_file("basic.mt")?)?;
+//! ```
+
+use crate::pattern::Pattern;
+use crate::{Error, ErrorKind, Map};
+
+/// Types that can be built from the sections of a parsed file
+pub trait FromMultitext: Sized {
+    /// Builds a value from `map`, failing if a section it needs is missing
+    fn from_map(map: &Map) -> Result<Self, Error>;
+}
+
+/// Returns the body of the section `key`, or an error naming the field of
+/// `type_name` that needs it
+///
+/// # Examples
+/// ```
+/// let map = multitext::Map::new();
+/// let e = multitext::bind::required(&map, "vertex shader", "Shader", "vertex").unwrap_err();
+/// assert!(e.to_string().contains("missing section \"vertex shader\" for field `vertex` of `Shader`"));
+/// ```
+pub fn required<'a>(map: &'a Map, key: &str, type_name: &str, field: &str) -> Result<&'a str, Error> {
+    map.get(key).map(String::as_str).ok_or_else(|| {
+        let message = format!("missing section {:?} for field `{}` of `{}`", key, field, type_name);
+        Error::new(ErrorKind::MissingSection, message)
+    })
+}
+
+/// Checks the body of section `key`, which the field `field` of `type_name`
+/// is built from, against a size limit in bytes and a pattern
+///
+/// The pattern has the syntax of
+/// [`Selection::Pattern`](crate::redact::Selection::Pattern).
+///
+/// # Examples
+/// ```
+/// use multitext::bind::validate;
+///
+/// assert!(validate("#version 330\n", "vertex", Some(4096), Some("^#version"), "Shader", "vertex").is_ok());
+/// let e = validate("void main() {}\n", "vertex", None, Some("^#version"), "Shader", "vertex").unwrap_err();
+/// assert!(e.to_string().contains("section \"vertex\" for field `vertex` of `Shader` doesn't match `^#version`"));
+/// ```
+pub fn validate(body: &str, key: &str, max_len: Option<usize>, pattern: Option<&str>, type_name: &str, field: &str)
+    -> Result<(), Error>
+{
+    let error = |problem: String| {
+        Error::new(ErrorKind::Other, format!("section {:?} for field `{}` of `{}` {}", key, field, type_name, problem))
+    };
+    if let Some(max_len) = max_len.filter(|&max_len| body.len() > max_len) {
+        return Err(error(format!("is {} bytes, over the limit of {}", body.len(), max_len)));
+    }
+    if let Some(source) = pattern {
+        let pattern = Pattern::new(source).map_err(|message| error(format!("has an invalid pattern: {}", message)))?;
+        if !pattern.is_match(body) {
+            return Err(error(format!("doesn't match `{}`", source)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[derive(Debug, crate::FromMultitext)]
+    struct Shader {
+        #[multitext(rename = "vertex shader")]
+        vertex: String,
+        /// Documented fields work too
+        pub notes: Option<String>,
+        #[multitext(default = "330\n")]
+        version: String,
+        #[multitext(default)]
+        r#type: String,
+    }
+
+    #[test]
+    fn test_derive() {
+        let lines = ["@@ multitext header", "@@ vertex shader", "void main() {}", "@@ type", "lit"];
+        let shader = Shader::from_map(&crate::parse_lines(lines.iter()).unwrap()).unwrap();
+        assert_eq!(shader.vertex, "void main() {}\n");
+        assert_eq!(shader.notes, None);
+        assert_eq!(shader.version, "330\n");
+        assert_eq!(shader.r#type, "lit\n");
+
+        let e = Shader::try_from(&Map::new()).unwrap_err();
+        assert!(e.to_string().contains("missing section \"vertex shader\" for field `vertex` of `Shader`"));
+    }
+
+    #[derive(Debug, crate::FromMultitext)]
+    struct Checked {
+        #[multitext(required, max_len = 8)]
+        short: Option<String>,
+        #[multitext(matches = "^#version", default = "#version 330\n")]
+        version: String,
+    }
+
+    #[test]
+    fn test_derive_validation() {
+        let parse = |lines: &[&str]| Checked::from_map(&crate::parse_lines(lines.iter()).unwrap());
+        let checked = parse(&["@@ multitext header", "@@ short", "ok"]).unwrap();
+        assert_eq!((checked.short.as_deref(), checked.version.as_str()), (Some("ok\n"), "#version 330\n"));
+
+        assert!(parse(&["@@ multitext header"]).unwrap_err().to_string().contains("missing section \"short\""));
+        let e = parse(&["@@ multitext header", "@@ short", "much too long"]).unwrap_err();
+        assert!(e.to_string().contains("is 14 bytes, over the limit of 8"));
+        let e = parse(&["@@ multitext header", "@@ short", "@@ version", "void main() {}"]).unwrap_err();
+        assert!(e.to_string().contains("doesn't match `^#version`"));
+    }
+
+    #[test]
+    fn test_include_multitext() {
+        const SECTIONS: &[(&str, &str)] = crate::include_multitext!("testdata/basic.mt", "vertex shader");
+        let keys: Vec<_> = SECTIONS.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, ["multitext header", "vertex shader", "fragment shader"]);
+        assert_eq!(SECTIONS[1].1, "void main() {\n    gl_Position = vec4(0.0);\n}\n");
+    }
+
+    #[test]
+    fn test_include_multitext_unescapes_markers() {
+        const SECTIONS: &[(&str, &str)] = crate::include_multitext!("testdata/escaped.mt");
+        assert_eq!(SECTIONS[1], ("notes", "@@ not a marker\n\\\\@@ still not\n\\ text\n"));
+        let parsed = crate::parse_lines(include_str!("../testdata/escaped.mt").lines()).unwrap();
+        assert_eq!(parsed["notes"], SECTIONS[1].1);
+    }
+}
@@ -0,0 +1,72 @@
+//! Parsing without copying section bodies
+
+use crate::{Error, ErrorKind};
+use std::collections::HashMap;
+
+/// Keys and bodies borrowed from the parsed text
+pub type BorrowedMap<'a> = HashMap<&'a str, &'a str>;
+
+/// Parses `text` into slices of itself, without allocating any key or body
+///
+/// Each body is exactly the text between its marker line and the next, so
+/// unlike with [`parse_lines`](crate::parse_lines) a `\r\n` stays as it is
+/// and the last body only ends in a newline if `text` does. Continuation
+/// sections are not joined, since that would mean copying them, and a repeated
+/// key replaces the earlier section. For the same reason, escaped lines keep
+/// the backslash in front of their marker.
+///
+/// # Examples
+/// ```
+/// let text = "@@ multitext header\n@@ vertex shader\nvoid main() {}\n@@ fragment shader\nout vec4 c;";
+/// let map = multitext::parse_str(text).unwrap();
+/// assert_eq!(map["vertex shader"], "void main() {}\n");
+/// assert_eq!(map["fragment shader"], "out vec4 c;");
+/// assert_eq!(map["multitext header"], "");
+/// ```
+pub fn parse_str(text: &str) -> Result<BorrowedMap<'_>, Error> {
+    let mut lines = text.split_inclusive('\n');
+    let mut offset = 0;
+    let mut line_number = 0;
+    let marker = loop {
+        line_number += 1;
+        let line = lines.next().ok_or_else(|| {
+            Error::new(ErrorKind::MissingHeader, "missing multitext header").with_line(line_number)
+        })?;
+        offset += line.len();
+        if let Some(index) = line.find("multitext header") {
+            break line[..index].trim_end();
+        }
+    };
+
+    let mut map = BorrowedMap::new();
+    let mut key = "multitext header";
+    let mut body_start = offset;
+    for line in lines {
+        if let Some(rest) = line.strip_prefix(marker) {
+            map.insert(key, &text[body_start..offset]);
+            key = rest.trim();
+            body_start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    map.insert(key, &text[body_start..offset]);
+    Ok(map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_str_borrows() {
+        let text = "preamble\r\n## multitext header\r\nnotes\r\n##   a  \r\n1\r\n\r\n## b";
+        let map = parse_str(text).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["multitext header"], "notes\r\n");
+        assert_eq!(map["a"], "1\r\n\r\n");
+        assert_eq!(map["b"], "");
+        let range = text.as_bytes().as_ptr_range();
+        assert!(range.contains(&map["a"].as_ptr()));
+        assert!(parse_str("no header").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! A size-limited cache of parsed multitext files
+//!
+//! Applications that open the same bundles repeatedly can go through a
+//! [`BundleCache`] instead of [`open_and_parse_document`](crate::open_and_parse_document).
+//! A cached document is reused as long as the file's modification time hasn't
+//! changed, and the least recently used documents are dropped once the cache
+//! grows past its capacity.
+
+use crate::{Document, Error};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// How large a [`BundleCache`] may grow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capacity {
+    /// At most this many documents
+    Entries(usize),
+    /// At most this many bytes, as measured by
+    /// [`Document::memory_footprint`]
+    Bytes(usize),
+}
+
+#[derive(Debug)]
+struct Entry {
+    doc: Arc<Document>,
+    modified: Option<SystemTime>,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Parsed documents keyed by path, with LRU eviction and mtime validation
+///
+/// # Examples
+/// ```no_run
+/// use multitext::cache::{BundleCache, Capacity};
+///
+/// let mut cache = BundleCache::new(Capacity::Bytes(64 * 1024 * 1024));
+/// let shaders = cache.open("shaders/basic.mt")?;
+/// let again = cache.open("shaders/basic.mt")?; // not reparsed unless the file changed
+/// assert!(std::sync::Arc::ptr_eq(&shaders, &again));
+/// # Ok::<(), multitext::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct BundleCache {
+    capacity: Capacity,
+    entries: HashMap<PathBuf, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl BundleCache {
+    pub fn new(capacity: Capacity) -> BundleCache {
+        BundleCache {
+            capacity,
+            entries: HashMap::new(),
+            bytes: 0,
+            clock: 0,
+        }
+    }
+
+    /// Returns the document at `path`, parsing it only if it isn't cached or
+    /// the file has been modified since it was
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<Arc<Document>, Error> {
+        let path = path.as_ref();
+        let modified = std::fs::metadata(path)?.modified().ok();
+        self.clock += 1;
+
+        if let Some(entry) = self.entries.get_mut(path) {
+            if modified.is_some() && entry.modified == modified {
+                entry.last_used = self.clock;
+                return Ok(Arc::clone(&entry.doc));
+            }
+        }
+        self.invalidate(path);
+
+        let doc = Arc::new(crate::open_and_parse_document(path)?);
+        let bytes = doc.memory_footprint().total();
+        self.bytes += bytes;
+        self.entries.insert(path.to_path_buf(), Entry {
+            doc: Arc::clone(&doc),
+            modified,
+            bytes,
+            last_used: self.clock,
+        });
+        self.evict();
+        Ok(doc)
+    }
+
+    /// Drops the cached document for `path`, if any
+    pub fn invalidate<P: AsRef<Path>>(&mut self, path: P) {
+        if let Some(entry) = self.entries.remove(path.as_ref()) {
+            self.bytes -= entry.bytes;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The combined memory footprint of the cached documents
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn over_capacity(&self) -> bool {
+        match self.capacity {
+            Capacity::Entries(max) => self.entries.len() > max,
+            Capacity::Bytes(max) => self.bytes > max,
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.over_capacity() {
+            let oldest = self.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => self.invalidate(path),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let dir = crate::testing::TempDir::new("cache").unwrap();
+        let paths: Vec<_> = (0..3).map(|i| dir.join(format!("{}.mt", i))).collect();
+        for path in &paths {
+            std::fs::write(path, "@ multitext header\n@ a\nbody\n").unwrap();
+        }
+
+        let mut cache = BundleCache::new(Capacity::Entries(2));
+        let first = cache.open(&paths[0]).unwrap();
+        cache.open(&paths[1]).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.open(&paths[0]).unwrap()));
+        cache.open(&paths[2]).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.entries.contains_key(&paths[0]));
+        assert!(!cache.entries.contains_key(&paths[1]));
+    }
+}
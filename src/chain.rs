@@ -0,0 +1,122 @@
+//! Layered lookup across several documents
+//!
+//! Configuration is often split into layers, such as user settings over
+//! project settings over built-in defaults. A [`DocumentChain`] looks a key up
+//! in each layer in turn and reports which one supplied it.
+
+use crate::{Document, Section};
+
+/// A section found in a [`DocumentChain`], along with the index of the
+/// document it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Found<'a> {
+    /// The position of the supplying document in the chain, 0 being searched
+    /// first
+    pub layer: usize,
+    pub section: &'a Section,
+}
+
+impl<'a> Found<'a> {
+    pub fn body(&self) -> &'a str {
+        self.section.body()
+    }
+}
+
+/// Documents searched in order, the first being the most specific
+///
+/// # Examples
+/// ```
+/// use multitext::chain::DocumentChain;
+///
+/// let user: multitext::Document = vec![("theme", "dark\n")].into_iter().collect();
+/// let defaults: multitext::Document = vec![("theme", "light\n"), ("font", "mono\n")].into_iter().collect();
+/// let chain = DocumentChain::new(vec![user, defaults]);
+///
+/// assert_eq!(chain.get("theme"), Some("dark\n"));
+/// assert_eq!(chain.find("font").unwrap().layer, 1);
+/// assert_eq!(chain.keys().collect::<Vec<_>>(), ["theme", "font"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocumentChain {
+    layers: Vec<Document>,
+}
+
+impl DocumentChain {
+    pub fn new<I: IntoIterator<Item = Document>>(layers: I) -> DocumentChain {
+        DocumentChain { layers: layers.into_iter().collect() }
+    }
+
+    /// Adds a document to be searched after all the others
+    pub fn push(&mut self, layer: Document) {
+        self.layers.push(layer);
+    }
+
+    pub fn layers(&self) -> &[Document] {
+        &self.layers
+    }
+
+    pub fn into_layers(self) -> Vec<Document> {
+        self.layers
+    }
+
+    /// Returns the body from the first document containing `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.find(key).map(|found| found.body())
+    }
+
+    /// Returns the section from the first document containing `key`, and
+    /// which document that was
+    pub fn find(&self, key: &str) -> Option<Found<'_>> {
+        self.layers.iter().enumerate().find_map(|(layer, doc)| {
+            doc.section(key).map(|section| Found { layer, section })
+        })
+    }
+
+    /// Returns every section called `key`, most specific first
+    pub fn find_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = Found<'a>> + 'a {
+        self.layers.iter().enumerate().filter_map(move |(layer, doc)| {
+            doc.section(key).map(|section| Found { layer, section })
+        })
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.layers.iter().any(|doc| doc.contains_key(key))
+    }
+
+    /// Every key in any document, each once, in the order first seen
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        let mut seen = std::collections::HashSet::new();
+        self.layers.iter()
+            .flat_map(|doc| doc.iter().map(Section::key))
+            .filter(move |key| seen.insert(*key))
+    }
+
+    /// Collapses the chain into one document holding the section each key
+    /// resolves to
+    pub fn flatten(&self) -> Document {
+        let mut doc = Document::new();
+        for key in self.keys() {
+            if let Some(found) = self.find(key) {
+                doc.insert_section(found.section.clone());
+            }
+        }
+        doc
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chain_resolution() {
+        let user: Document = vec![("b", "user")].into_iter().collect();
+        let project: Document = vec![("a", "project"), ("b", "project")].into_iter().collect();
+        let chain = DocumentChain::new(vec![user, project, Document::new()]);
+
+        assert_eq!(chain.find_all("b").map(|f| f.layer).collect::<Vec<_>>(), [0, 1]);
+        assert!(chain.find("c").is_none());
+        let flat = chain.flatten();
+        assert_eq!(flat.iter().map(|s| (s.key(), s.body())).collect::<Vec<_>>(), [("b", "user"), ("a", "project")]);
+    }
+}
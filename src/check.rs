@@ -0,0 +1,413 @@
+//! One-call verification of a multitext file for CI
+//!
+//! [`check_file`] parses a file and runs every check configured in a
+//! [`CheckProfile`], collecting all problems into a [`CheckReport`] rather than
+//! stopping at the first. The report renders as text through `Display` or as
+//! JSON through [`CheckReport::to_json`].
+
+use crate::diagnostics::Warning;
+use crate::keys::KeyPolicy;
+use crate::manifest::{Change, Manifest};
+use crate::options::{LineEndings, ParseOptions};
+use crate::{json, Document};
+use std::path::{Path, PathBuf};
+
+/// Which checks [`check_file`] runs
+///
+/// The default only requires the file to parse.
+///
+/// # Examples
+/// ```no_run
+/// use multitext::check::{check_file, CheckProfile};
+///
+/// let profile = CheckProfile::new()
+///     .strict(true)
+///     .required_keys(&["vertex shader", "fragment shader"])
+///     .max_section_bytes(64 * 1024);
+/// let report = check_file("shaders/basic.mt", &profile);
+/// println!("{}", report.to_json());
+/// std::process::exit(if report.passed() { 0 } else { 1 });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CheckProfile {
+    strict: bool,
+    key_policy: Option<KeyPolicy>,
+    required_keys: Vec<String>,
+    manifest: Option<Manifest>,
+    max_file_bytes: Option<usize>,
+    max_section_bytes: Option<usize>,
+    references: Vec<ReferenceSyntax>,
+}
+
+impl CheckProfile {
+    pub fn new() -> CheckProfile {
+        CheckProfile::default()
+    }
+
+    /// Whether parse warnings (duplicate keys, empty keys, discarded text,
+    /// mixed line endings) count as problems
+    pub fn strict(mut self, strict: bool) -> CheckProfile {
+        self.strict = strict;
+        self
+    }
+
+    pub fn key_policy(mut self, policy: KeyPolicy) -> CheckProfile {
+        self.key_policy = Some(policy);
+        self
+    }
+
+    /// Sections that must be present
+    pub fn required_keys<S: AsRef<str>>(mut self, keys: &[S]) -> CheckProfile {
+        self.required_keys = keys.iter().map(|k| k.as_ref().to_string()).collect();
+        self
+    }
+
+    /// A manifest whose hashes every section must match
+    pub fn manifest(mut self, manifest: Manifest) -> CheckProfile {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    pub fn max_file_bytes(mut self, max: usize) -> CheckProfile {
+        self.max_file_bytes = Some(max);
+        self
+    }
+
+    pub fn max_section_bytes(mut self, max: usize) -> CheckProfile {
+        self.max_section_bytes = Some(max);
+        self
+    }
+
+    /// Ways sections refer to each other; every reference must name an
+    /// existing section
+    pub fn references(mut self, syntaxes: &[ReferenceSyntax]) -> CheckProfile {
+        self.references = syntaxes.to_vec();
+        self
+    }
+}
+
+/// A way for a line of one section's body to name another section: the
+/// trimmed line starts with `prefix`, and the key runs up to `suffix`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReferenceSyntax {
+    prefix: String,
+    suffix: String,
+}
+
+impl ReferenceSyntax {
+    pub fn new<P: Into<String>, S: Into<String>>(prefix: P, suffix: S) -> ReferenceSyntax {
+        ReferenceSyntax { prefix: prefix.into(), suffix: suffix.into() }
+    }
+
+    /// `#include "key"`, as resolved by the `glsl` module
+    pub fn include() -> ReferenceSyntax {
+        ReferenceSyntax::new("#include \"", "\"")
+    }
+
+    /// `{% extends "key" %}`, as used by template engines
+    pub fn extends() -> ReferenceSyntax {
+        ReferenceSyntax::new("{% extends \"", "\"")
+    }
+
+    /// `{% include "key" %}`, as used by template engines
+    pub fn template_include() -> ReferenceSyntax {
+        ReferenceSyntax::new("{% include \"", "\"")
+    }
+
+    /// All of the above
+    pub fn all() -> Vec<ReferenceSyntax> {
+        vec![ReferenceSyntax::include(), ReferenceSyntax::extends(), ReferenceSyntax::template_include()]
+    }
+
+    /// Returns the key named by `line`, if it is a reference
+    fn target<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let rest = line.trim().strip_prefix(self.prefix.as_str())?;
+        let end = rest.find(self.suffix.as_str())?;
+        Some(&rest[..end])
+    }
+}
+
+/// Finds every reference to a section that doesn't exist, reported at the
+/// line making it
+///
+/// # Examples
+/// ```
+/// use multitext::check::{orphaned_references, ReferenceSyntax};
+///
+/// let lines = ["@@ multitext header", "@@ page", "{% extends \"base\" %}", "#include \"footer\""];
+/// let doc = multitext::parse_document(lines.iter()).unwrap();
+/// let problems = orphaned_references(&doc, &ReferenceSyntax::all());
+/// assert_eq!(problems.len(), 2);
+/// assert_eq!(problems[1].line_number, Some(4));
+/// assert_eq!(problems[1].message, "section \"page\" refers to missing section \"footer\"");
+/// ```
+pub fn orphaned_references(doc: &Document, syntaxes: &[ReferenceSyntax]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for section in doc.iter() {
+        for (index, line) in section.body().lines().enumerate() {
+            let target = syntaxes.iter().find_map(|syntax| syntax.target(line));
+            match target {
+                Some(target) if !doc.contains_key(target) => problems.push(Problem {
+                    code: "orphaned-reference",
+                    line_number: section.line_number().map(|n| n + index),
+                    key: Some(section.key().to_string()),
+                    message: format!("section \"{}\" refers to missing section \"{}\"", section.key(), target),
+                }),
+                _ => {}
+            }
+        }
+    }
+    problems
+}
+
+/// One failed check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// A stable identifier such as `missing-key`, for scripts to match on;
+    /// see [`explain`]
+    pub code: &'static str,
+    pub line_number: Option<usize>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+/// Everything [`check_file`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Renders the report as a single JSON object
+    pub fn to_json(&self) -> String {
+        let problems: Vec<String> = self.problems.iter().map(|p| {
+            format!(
+                "{{\"code\":{},\"line\":{},\"key\":{},\"message\":{}}}",
+                json::string(p.code),
+                json::optional_number(p.line_number),
+                json::optional_string(p.key.as_deref()),
+                json::string(&p.message),
+            )
+        }).collect();
+        format!(
+            "{{\"path\":{},\"passed\":{},\"problems\":[{}]}}",
+            json::string(&self.path.to_string_lossy()),
+            self.passed(),
+            problems.join(","),
+        )
+    }
+
+    fn push(&mut self, code: &'static str, line_number: Option<usize>, key: Option<&str>, message: String) {
+        self.problems.push(Problem { code, line_number, key: key.map(str::to_string), message });
+    }
+}
+
+impl std::fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for problem in &self.problems {
+            write!(f, "{}", self.path.display())?;
+            if let Some(line_number) = problem.line_number {
+                write!(f, "({})", line_number)?;
+            }
+            writeln!(f, " : {} : {}", problem.code, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem code with an extended explanation and how to fix it
+const EXPLANATIONS: &[(&str, &str)] = &[
+    ("io-error", "\
+The file couldn't be read or written, because it doesn't exist, isn't readable
+or isn't UTF-8.
+
+Check the path and the file's permissions, and re-save it as UTF-8."),
+    ("parse-error", "\
+The file isn't a multitext bundle. Usually no line contains \"multitext header\",
+so there is no marker to split the file by.
+
+Add a header line near the top, such as `@@ multitext header`; whatever precedes
+\"multitext header\" on that line becomes the marker that starts each section."),
+    ("parse-warning", "\
+The file parses, but something in it was probably a mistake: a key used twice
+(the later section replaces the earlier one), a marker line with no key, text
+above the header (it is discarded) or a mix of \\n and \\r\\n line endings.
+
+The message names the line. Rename or merge duplicate sections, give empty
+markers a key, move the text above the header into the header section, and
+re-save the file with one kind of line ending."),
+    ("key-policy", "\
+A key breaks the key policy: it contains control characters or non-ASCII
+characters, is too long, or is empty.
+
+Rename the section to follow the policy."),
+    ("missing-key", "\
+A section that every bundle must have, listed in the required keys or declared
+by the schema, is missing.
+
+Add the section, or check its key for typos and stray whitespace."),
+    ("checksum-mismatch", "\
+The section differs from the manifest: it was added, removed or edited without
+the manifest being regenerated.
+
+If the change was intended, regenerate the manifest; otherwise restore the
+section."),
+    ("file-too-large", "\
+The file is over the configured size budget.
+
+Move some sections into another bundle or raise `max_file_bytes`."),
+    ("section-too-large", "\
+A section is over the configured size budget.
+
+Split the section, or raise `max_section_bytes`."),
+    ("orphaned-reference", "\
+A line such as `#include \"key\"` or `{% extends \"key\" %}` names a section that
+doesn't exist, typically because it was renamed or removed.
+
+Point the reference at the section's current key, or restore the section."),
+    ("not-formatted", "\
+`multitext fmt --check` found a file that formatting would change.
+
+Run `multitext fmt` on the file and commit the result."),
+    ("conflict", "\
+The file changed while `multitext fmt` was rewriting it, so it was left alone
+rather than overwriting the other change.
+
+Run the command again once nothing else is editing the file."),
+    ("normalize-error", "\
+A command under `[format.normalize]` in `.multitext.toml` failed on a section
+whose `type` attribute names it: the command couldn't be run, exited with an
+error, or wrote output that isn't UTF-8.
+
+The message names the section and includes the command's error output. Fix the
+section's contents, or the command in the config file."),
+    ("config-error", "\
+The nearest `.multitext.toml` couldn't be read: it has a syntax error, an
+unknown setting, a value of the wrong type, or names a schema that can't be
+parsed.
+
+The message names the file and line; fix the setting there."),
+];
+
+/// The extended explanation of a problem code, with suggestions for fixing it
+///
+/// # Examples
+/// ```
+/// let text = multitext::check::explain("orphaned-reference").unwrap();
+/// assert!(text.contains("renamed or removed"));
+/// assert!(multitext::check::explain("no-such-code").is_none());
+/// ```
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS.iter().find(|(c, _)| *c == code).map(|(_, text)| *text)
+}
+
+/// Every problem code, in the order they're documented
+pub fn codes() -> impl Iterator<Item = &'static str> {
+    EXPLANATIONS.iter().map(|(code, _)| *code)
+}
+
+/// Parses the file at `path` and runs every check in `profile`
+pub fn check_file<P: AsRef<Path>>(path: P, profile: &CheckProfile) -> CheckReport {
+    let path = path.as_ref();
+    let mut report = CheckReport { path: path.to_path_buf(), problems: Vec::new() };
+
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            report.push("io-error", None, None, e.to_string());
+            return report;
+        }
+    };
+    if let Some(max) = profile.max_file_bytes {
+        if text.len() > max {
+            report.push("file-too-large", None, None,
+                format!("file is {} bytes, over the budget of {}", text.len(), max));
+        }
+    }
+
+    let mut warnings: Vec<Warning> = Vec::new();
+    let options = ParseOptions::new().line_endings(LineEndings::Normalize);
+    let lines = crate::read_lines(text.as_bytes());
+    let doc = match crate::parse_document_with_options_and_sink(lines, &options, &mut warnings) {
+        Ok(doc) => doc,
+        Err(e) => {
+            report.push("parse-error", e.line_number, None, e.error_message);
+            return report;
+        }
+    };
+    if profile.strict {
+        for warning in warnings {
+            report.push("parse-warning", Some(warning.line_number), None, warning.message);
+        }
+    }
+
+    check_document(&doc, profile, &mut report);
+    report
+}
+
+fn check_document(doc: &Document, profile: &CheckProfile, report: &mut CheckReport) {
+    let marker_line = |key: &str| doc.section(key).and_then(|s| s.line_number()).map(|n| n - 1);
+
+    if let Some(policy) = &profile.key_policy {
+        for section in doc.iter() {
+            if let Err(message) = policy.check(section.key()) {
+                report.push("key-policy", marker_line(section.key()), Some(section.key()), message);
+            }
+        }
+    }
+
+    for key in &profile.required_keys {
+        if !doc.contains_key(key) {
+            report.push("missing-key", None, Some(key), format!("required section \"{}\" is missing", key));
+        }
+    }
+
+    if let Some(manifest) = &profile.manifest {
+        for change in manifest.changes(doc) {
+            let (key, message) = match &change {
+                Change::Added(key) => (key, "section is not in the manifest"),
+                Change::Removed(key) => (key, "section in the manifest is missing"),
+                Change::Modified(key) => (key, "section does not match its manifest hash"),
+            };
+            report.push("checksum-mismatch", marker_line(key), Some(key), format!("\"{}\": {}", key, message));
+        }
+    }
+
+    report.problems.extend(orphaned_references(doc, &profile.references));
+
+    if let Some(max) = profile.max_section_bytes {
+        for section in doc.iter() {
+            if section.body().len() > max {
+                report.push("section-too-large", marker_line(section.key()), Some(section.key()),
+                    format!("section \"{}\" is {} bytes, over the budget of {}",
+                        section.key(), section.body().len(), max));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_file_collects_all_problems() {
+        let dir = crate::testing::TempDir::new("check").unwrap();
+        let path = dir.join("check.mt");
+        std::fs::write(&path, "@ multitext header\n@ a\n1\n@ a\n2\n@ big\n0123456789\n").unwrap();
+
+        let profile = CheckProfile::new()
+            .strict(true)
+            .required_keys(&["b"])
+            .max_section_bytes(8);
+        let report = check_file(&path, &profile);
+        let codes: Vec<_> = report.problems.iter().map(|p| p.code).collect();
+        assert_eq!(codes, ["parse-warning", "missing-key", "section-too-large"]);
+        assert!(report.to_json().starts_with("{\"path\":"));
+    }
+}
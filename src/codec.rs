@@ -0,0 +1,354 @@
+//! Mapping section keys to file paths and back
+//!
+//! [`pack`](crate::pack) and unpacking use a [`KeyCodec`] to turn keys into
+//! `/`-separated relative paths and file paths back into keys. The default,
+//! [`PercentEncoding`], escapes only the characters that some filesystem can't
+//! store, so ordinary keys map to the obvious file names and every key
+//! survives a round trip through a directory.
+//!
+//! A [`KeyTemplate`] makes the mapping configurable, for layouts where keys
+//! and file names follow different conventions.
+
+use crate::{Error, ErrorKind};
+use std::fmt::Debug;
+
+/// Converts between section keys and `/`-separated relative file paths
+///
+/// `decode(&encode(key))` must give back `key`. A codec doesn't need to make
+/// paths safe: unpacking checks the encoded path for absolute paths, `..`
+/// components and symbolic links afterwards.
+pub trait KeyCodec: Debug + Send + Sync {
+    fn encode(&self, key: &str) -> String;
+    fn decode(&self, path: &str) -> String;
+}
+
+/// Uses keys as paths unchanged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Verbatim;
+
+impl KeyCodec for Verbatim {
+    fn encode(&self, key: &str) -> String {
+        key.to_string()
+    }
+
+    fn decode(&self, path: &str) -> String {
+        path.to_string()
+    }
+}
+
+/// Percent-encodes characters that aren't portable in file names
+///
+/// Within each `/`-separated component this escapes `%`, control characters,
+/// `\ : * ? " < > |`, and a trailing space or dot. Decoding leaves a `%` that
+/// isn't followed by an escape it could have produced as it is, so files that
+/// weren't written by unpacking keep their names as keys.
+///
+/// # Examples
+/// ```
+/// use multitext::codec::{KeyCodec, PercentEncoding};
+///
+/// assert_eq!(PercentEncoding.encode("shaders/lit: fragment?"), "shaders/lit%3A fragment%3F");
+/// assert_eq!(PercentEncoding.decode("shaders/lit%3A fragment%3F"), "shaders/lit: fragment?");
+/// assert_eq!(PercentEncoding.decode("100%.txt"), "100%.txt");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PercentEncoding;
+
+impl KeyCodec for PercentEncoding {
+    fn encode(&self, key: &str) -> String {
+        let components: Vec<String> = key.split('/').map(encode_component).collect();
+        components.join("/")
+    }
+
+    fn decode(&self, path: &str) -> String {
+        let mut out = String::with_capacity(path.len());
+        let mut rest = path;
+        while let Some(index) = rest.find('%') {
+            out.push_str(&rest[..index]);
+            let escaped = rest.get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .filter(|byte| byte.is_ascii());
+            match escaped {
+                Some(byte) => {
+                    out.push(byte as char);
+                    rest = &rest[index + 3..];
+                }
+                None => {
+                    out.push('%');
+                    rest = &rest[index + 1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+fn encode_component(component: &str) -> String {
+    if component == "." || component == ".." {
+        return component.to_string();
+    }
+    let mut out = String::with_capacity(component.len());
+    let last = component.char_indices().last().map(|(i, _)| i);
+    for (i, c) in component.char_indices() {
+        let escape = matches!(c, '%' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+            || c.is_ascii_control()
+            || (Some(i) == last && (c == ' ' || c == '.'));
+        if escape {
+            out.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Maps keys and paths through templates such as `shaders/{stem}.{ext}`
+///
+/// Packing fills the key template from each file's path; unpacking matches
+/// each key against the key template and fills the path template from what it
+/// captured. The placeholders are:
+///
+/// | placeholder | meaning                                                 |
+/// |-------------|---------------------------------------------------------|
+/// | `{path}`    | the whole `/`-separated relative path                   |
+/// | `{dir}`     | the directory part with its trailing `/`, or nothing    |
+/// | `{name}`    | the file name                                           |
+/// | `{stem}`    | the file name up to its last `.`                        |
+/// | `{ext}`     | the file name after its last `.`, or nothing            |
+///
+/// Placeholders the key didn't capture are worked out from the ones it did
+/// where possible, and are otherwise empty. A key that doesn't match the key
+/// template is percent-encoded as by [`PercentEncoding`]. Unlike other codecs,
+/// a template can lose information, for instance a key template without
+/// `{dir}` drops directories, so it is only a round trip when the two
+/// templates carry the same placeholders.
+///
+/// # Examples
+/// ```
+/// use multitext::codec::{KeyCodec, KeyTemplate};
+///
+/// let template = KeyTemplate::new("shaders/{stem}.{ext}", "{dir}{stem}.{ext}").unwrap();
+/// assert_eq!(template.decode("basic.vert"), "shaders/basic.vert");
+/// assert_eq!(template.encode("shaders/basic.vert"), "basic.vert");
+///
+/// let template = KeyTemplate::new("{stem} shader", "glsl/{stem}.glsl").unwrap();
+/// assert_eq!(template.decode("src/lit.glsl"), "lit shader");
+/// assert_eq!(template.encode("lit shader"), "glsl/lit.glsl");
+///
+/// assert!(KeyTemplate::new("{basename}", "{path}").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyTemplate {
+    key: Vec<Piece>,
+    path: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Path,
+    Dir,
+    Name,
+    Stem,
+    Ext,
+}
+
+impl Field {
+    const ALL: [Field; 5] = [Field::Path, Field::Dir, Field::Name, Field::Stem, Field::Ext];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Path => "path",
+            Field::Dir => "dir",
+            Field::Name => "name",
+            Field::Stem => "stem",
+            Field::Ext => "ext",
+        }
+    }
+
+    /// Whether `value` is something this placeholder could stand for
+    fn allows(self, value: &str) -> bool {
+        match self {
+            Field::Path => !value.is_empty(),
+            Field::Dir => value.is_empty() || value.ends_with('/'),
+            Field::Name | Field::Stem => !value.is_empty() && !value.contains('/'),
+            Field::Ext => !value.contains(['/', '.']),
+        }
+    }
+}
+
+/// The values of the placeholders, indexed by `Field as usize`
+#[derive(Debug, Default)]
+struct Fields([Option<String>; 5]);
+
+impl Fields {
+    fn of_path(path: &str) -> Fields {
+        let mut fields = Fields::default();
+        fields.set(Field::Path, path.to_string());
+        fields.complete();
+        fields
+    }
+
+    fn get(&self, field: Field) -> Option<&str> {
+        self.0[field as usize].as_deref()
+    }
+
+    fn set(&mut self, field: Field, value: String) {
+        self.0[field as usize] = Some(value);
+    }
+
+    /// Works out the missing fields from the ones that are known
+    fn complete(&mut self) {
+        if let Some(path) = self.get(Field::Path).map(str::to_string) {
+            let (dir, name) = match path.rfind('/') {
+                Some(index) => path.split_at(index + 1),
+                None => ("", path.as_str()),
+            };
+            self.0[Field::Dir as usize].get_or_insert_with(|| dir.to_string());
+            self.0[Field::Name as usize].get_or_insert_with(|| name.to_string());
+        }
+        if let (None, Some(stem)) = (self.get(Field::Name), self.get(Field::Stem)) {
+            let name = match self.get(Field::Ext) {
+                Some(ext) if !ext.is_empty() => format!("{}.{}", stem, ext),
+                _ => stem.to_string(),
+            };
+            self.0[Field::Ext as usize].get_or_insert_with(String::new);
+            self.set(Field::Name, name);
+        }
+        if let Some(name) = self.get(Field::Name).map(str::to_string) {
+            let (stem, ext) = match name.rfind('.') {
+                Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+                _ => (name.as_str(), ""),
+            };
+            self.0[Field::Stem as usize].get_or_insert_with(|| stem.to_string());
+            self.0[Field::Ext as usize].get_or_insert_with(|| ext.to_string());
+            if self.get(Field::Path).is_none() {
+                let path = format!("{}{}", self.get(Field::Dir).unwrap_or(""), name);
+                self.set(Field::Path, path);
+            }
+        }
+    }
+}
+
+impl KeyTemplate {
+    /// Creates a codec that packs paths into keys with `key` and unpacks keys
+    /// into paths with `path`; `"{path}"` for both uses keys as paths
+    pub fn new(key: &str, path: &str) -> Result<KeyTemplate, Error> {
+        Ok(KeyTemplate {
+            key: parse_template(key)?,
+            path: parse_template(path)?,
+        })
+    }
+}
+
+impl KeyCodec for KeyTemplate {
+    fn encode(&self, key: &str) -> String {
+        let mut fields = Fields::default();
+        if !match_template(&self.key, key, &mut fields) {
+            return PercentEncoding.encode(key);
+        }
+        fields.complete();
+        render(&self.path, &fields)
+    }
+
+    fn decode(&self, path: &str) -> String {
+        render(&self.key, &Fields::of_path(path))
+    }
+}
+
+fn parse_template(template: &str) -> Result<Vec<Piece>, Error> {
+    let error = |message: String| Error::new(ErrorKind::Malformed, message);
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            pieces.push(Piece::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..].find('}')
+            .ok_or_else(|| error(format!("unclosed placeholder in template \"{}\"", template)))?;
+        let name = &rest[start + 1..start + end];
+        let field = Field::ALL.iter().copied().find(|f| f.name() == name)
+            .ok_or_else(|| error(format!("unknown placeholder {{{}}} in template \"{}\"", name, template)))?;
+        pieces.push(Piece::Field(field));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest.to_string()));
+    }
+    Ok(pieces)
+}
+
+fn render(pieces: &[Piece], fields: &Fields) -> String {
+    pieces.iter().map(|piece| match piece {
+        Piece::Text(text) => text.as_str(),
+        Piece::Field(field) => fields.get(*field).unwrap_or(""),
+    }).collect()
+}
+
+/// Matches `text` against `pieces`, preferring the longest value for each
+/// placeholder, and records the values in `fields`
+fn match_template(pieces: &[Piece], text: &str, fields: &mut Fields) -> bool {
+    match pieces.split_first() {
+        None => text.is_empty(),
+        Some((Piece::Text(literal), rest)) => match text.strip_prefix(literal.as_str()) {
+            Some(after) => match_template(rest, after, fields),
+            None => false,
+        },
+        Some((Piece::Field(field), rest)) => {
+            if let Some(value) = fields.get(*field).map(str::to_string) {
+                return match text.strip_prefix(value.as_str()) {
+                    Some(after) => match_template(rest, after, fields),
+                    None => false,
+                };
+            }
+            for end in (0..=text.len()).rev().filter(|&end| text.is_char_boundary(end)) {
+                if field.allows(&text[..end]) {
+                    fields.set(*field, text[..end].to_string());
+                    if match_template(rest, &text[end..], fields) {
+                        return true;
+                    }
+                    fields.0[*field as usize] = None;
+                }
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_percent_encoding_round_trip() {
+        for key in &["plain.txt", "a/b/c", "50% off", "tab\there", "ends with.", "ends with ", "ü/ñ", "../up"] {
+            let path = PercentEncoding.encode(key);
+            assert_eq!(&PercentEncoding.decode(&path), key);
+        }
+        assert_eq!(PercentEncoding.encode("../up"), "../up");
+        assert_eq!(PercentEncoding.encode("ends with."), "ends with%2E");
+    }
+
+    #[test]
+    fn test_key_template() {
+        let template = KeyTemplate::new("{dir}{stem}", "{dir}{stem}.txt").unwrap();
+        assert_eq!(template.decode("a/b/c.tar.gz"), "a/b/c.tar");
+        assert_eq!(template.encode("a/b/c.tar"), "a/b/c.tar.txt");
+        assert_eq!(template.decode("README"), "README");
+
+        let template = KeyTemplate::new("{stem}", "{name}").unwrap();
+        assert_eq!(template.encode("c.tar"), "c.tar");
+
+        let template = KeyTemplate::new("{stem}-{stem}", "{name}").unwrap();
+        assert_eq!(template.encode("x-x"), "x");
+        assert_eq!(template.encode("x-y"), "x-y");
+
+        assert!(KeyTemplate::new("{stem", "{path}").is_err());
+    }
+}
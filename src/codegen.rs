@@ -0,0 +1,198 @@
+//! Generating typed Rust structs for well-known bundle shapes
+//!
+//! A schema is itself a multitext file with one section per section the
+//! bundle must have. In each schema section, an `optional: true` line makes
+//! the section optional and any other text becomes the field's doc comment:
+//!
+//! ```text
+//! @@ multitext header
+//! The shaders for one material.
+//! @@ vertex shader
+//! GLSL for the vertex stage.
+//! @@ notes
+//! optional: true
+//! ```
+//!
+//! [`generate_file`] is meant to be called from a build script, with the
+//! result pulled in by `include!`.
+
+use crate::header::Header;
+use crate::{Document, Error, ErrorKind};
+use std::path::Path;
+
+/// One section a bundle is declared to have
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SectionSpec {
+    pub key: String,
+    pub optional: bool,
+    pub doc: String,
+}
+
+/// The sections a bundle is declared to have, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Schema {
+    /// Describes the bundle as a whole, from the schema's header
+    pub doc: String,
+    pub sections: Vec<SectionSpec>,
+}
+
+impl Schema {
+    /// Reads a schema from a parsed schema file
+    pub fn from_document(doc: &Document) -> Schema {
+        let mut schema = Schema::default();
+        for section in doc.iter() {
+            let header = Header::parse(section.body());
+            let doc = header.paragraphs().join("\n\n");
+            if section.key() == "multitext header" {
+                schema.doc = doc;
+            } else {
+                schema.sections.push(SectionSpec {
+                    key: section.key().to_string(),
+                    optional: header.field("optional") == Some("true"),
+                    doc,
+                });
+            }
+        }
+        schema
+    }
+}
+
+/// Generates a struct called `type_name` with one `String` field per declared
+/// section (`Option<String>` if optional), plus `load` and `from_document`
+/// constructors
+///
+/// Field names are the keys in snake case. Fails if two keys give the same
+/// field name.
+///
+/// # Examples
+/// ```
+/// use multitext::codegen::{generate, Schema};
+///
+/// let lines = ["@@ multitext header", "@@ vertex shader", "@@ notes", "optional: true"];
+/// let schema = Schema::from_document(&multitext::parse_document(lines.iter()).unwrap());
+/// let code = generate(&schema, "Material").unwrap();
+/// assert!(code.contains("pub vertex_shader: String,"));
+/// assert!(code.contains("pub notes: Option<String>,"));
+/// ```
+pub fn generate(schema: &Schema, type_name: &str) -> Result<String, Error> {
+    let mut fields = Vec::new();
+    for spec in &schema.sections {
+        let name = field_name(&spec.key);
+        if let Some((other, _)) = fields.iter().find(|(_, n): &&(&SectionSpec, String)| *n == name) {
+            return Err(Error::new(ErrorKind::InvalidKey, format!(
+                    "keys \"{}\" and \"{}\" both become the field `{}`", other.key, spec.key, name
+                )));
+        }
+        fields.push((spec, name));
+    }
+
+    let mut out = String::new();
+    push_doc(&mut out, "", &schema.doc);
+    out.push_str("#[derive(Debug, Clone, PartialEq, Eq)]\n");
+    out.push_str(&format!("pub struct {} {{\n", type_name));
+    for (spec, name) in &fields {
+        push_doc(&mut out, "    ", &spec.doc);
+        let ty = if spec.optional { "Option<String>" } else { "String" };
+        out.push_str(&format!("    pub {}: {},\n", name, ty));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!("impl {} {{\n", type_name));
+    out.push_str("    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, multitext::Error> {\n");
+    out.push_str("        Self::from_document(&multitext::open_and_parse_document(path)?)\n");
+    out.push_str("    }\n\n");
+    out.push_str("    pub fn from_document(doc: &multitext::Document) -> Result<Self, multitext::Error> {\n");
+    out.push_str("        Ok(Self {\n");
+    for (spec, name) in &fields {
+        let value = if spec.optional {
+            format!("doc.get({:?}).map(str::to_string)", spec.key)
+        } else {
+            format!("doc.require({:?})?.to_string()", spec.key)
+        };
+        out.push_str(&format!("            {}: {},\n", name, value));
+    }
+    out.push_str("        })\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// Reads the schema at `schema_path` and writes the generated code to
+/// `out_path`, for use from a build script
+///
+/// # Examples
+/// ```no_run
+/// // build.rs
+/// let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("material.rs");
+/// multitext::codegen::generate_file("material.schema.mt", "Material", &out).unwrap();
+/// println!("cargo:rerun-if-changed=material.schema.mt");
+/// ```
+pub fn generate_file<P: AsRef<Path>, Q: AsRef<Path>>(schema_path: P, type_name: &str, out_path: Q)
+    -> Result<(), Error>
+{
+    let schema = Schema::from_document(&crate::open_and_parse_document(schema_path)?);
+    std::fs::write(out_path, generate(&schema, type_name)?)?;
+    Ok(())
+}
+
+fn push_doc(out: &mut String, indent: &str, doc: &str) {
+    for line in doc.lines() {
+        out.push_str(indent);
+        out.push_str("///");
+        if !line.is_empty() {
+            out.push(' ');
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+}
+
+fn field_name(key: &str) -> String {
+    let mut name = String::new();
+    for c in key.chars() {
+        if c.is_alphanumeric() {
+            name.extend(c.to_lowercase());
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_matches('_');
+    let name = if name.is_empty() || name.starts_with(|c: char| c.is_numeric()) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    };
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+        "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+        "where", "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv",
+        "typeof", "unsized", "virtual", "yield", "try",
+    ];
+    if KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_field_names() {
+        assert_eq!(field_name("Vertex Shader"), "vertex_shader");
+        assert_eq!(field_name("2d/sprite.frag"), "_2d_sprite_frag");
+        assert_eq!(field_name("type"), "r#type");
+
+        let schema = Schema {
+            doc: String::new(),
+            sections: vec![
+                SectionSpec { key: "a-b".to_string(), optional: false, doc: String::new() },
+                SectionSpec { key: "a b".to_string(), optional: false, doc: String::new() },
+            ],
+        };
+        assert!(generate(&schema, "Clash").is_err());
+    }
+}
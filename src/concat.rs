@@ -0,0 +1,120 @@
+//! Importing files glued together with banner comments
+//!
+//! Shader packs and similar bundles are often single files where each part
+//! starts with a comment line naming it:
+//!
+//! ```text
+//! // --- lit.vert ---
+//! void main() {}
+//! // --- lit.frag ---
+//! void main() {}
+//! ```
+//!
+//! [`import`] turns such a file into a [`Document`], given the [`Banner`]
+//! styles it uses.
+
+use crate::{Document, Error, ErrorKind, Section};
+
+/// One style of banner line: a prefix, the name, then a suffix
+///
+/// Whitespace around the name and at either end of the line is ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Banner {
+    prefix: String,
+    suffix: String,
+}
+
+impl Banner {
+    pub fn new<P: Into<String>, S: Into<String>>(prefix: P, suffix: S) -> Banner {
+        Banner { prefix: prefix.into(), suffix: suffix.into() }
+    }
+
+    /// `// --- name ---`
+    pub fn slashes() -> Banner {
+        Banner::new("// ---", "---")
+    }
+
+    /// `# --- name ---`
+    pub fn hashes() -> Banner {
+        Banner::new("# ---", "---")
+    }
+
+    /// `/* --- name --- */`
+    pub fn block_comment() -> Banner {
+        Banner::new("/* ---", "--- */")
+    }
+
+    /// Returns the name if `line` is a banner of this style
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::concat::Banner;
+    ///
+    /// assert_eq!(Banner::slashes().name("  // --- lit.vert ---"), Some("lit.vert"));
+    /// assert_eq!(Banner::slashes().name("// --- ---"), None);
+    /// assert_eq!(Banner::slashes().name("// lit.vert"), None);
+    /// ```
+    pub fn name<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let name = line.trim()
+            .strip_prefix(self.prefix.as_str())?
+            .strip_suffix(self.suffix.as_str())?
+            .trim();
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+}
+
+/// Splits `text` into a section per banner line, trying each banner style in
+/// turn
+///
+/// Non-blank text before the first banner becomes the body of the
+/// "multitext header" section. Line numbers point at lines of `text`, and a
+/// name used twice is an error.
+///
+/// # Examples
+/// ```
+/// use multitext::concat::{import, Banner};
+///
+/// let text = "// shared by both stages\n\
+///             /* --- lit.vert --- */\n\
+///             void main() {}\n\
+///             // --- lit.frag ---\n\
+///             out vec4 color;\n";
+/// let doc = import(text, &[Banner::slashes(), Banner::block_comment()]).unwrap();
+/// assert_eq!(&doc["multitext header"], "// shared by both stages\n");
+/// assert_eq!(&doc["lit.vert"], "void main() {}\n");
+/// assert_eq!(&doc["lit.frag"], "out vec4 color;\n");
+/// assert_eq!(doc.section("lit.frag").unwrap().line_number(), Some(5));
+/// ```
+pub fn import(text: &str, banners: &[Banner]) -> Result<Document, Error> {
+    let mut doc = Document::new();
+    let mut current = Section::at_line("multitext header", 1);
+    for (index, line) in text.lines().enumerate() {
+        let name = banners.iter().find_map(|banner| banner.name(line));
+        match name {
+            Some(name) => {
+                if doc.contains_key(name) || current.key() == name {
+                    let message = format!("\"{}\" appears twice", name);
+                    return Err(Error::new(ErrorKind::DuplicateKey, message).with_line(index + 1));
+                }
+                finish(&mut doc, current);
+                current = Section::at_line(name, index + 2);
+            }
+            None => {
+                current.body.push_str(line);
+                current.body.push('\n');
+            }
+        }
+    }
+    finish(&mut doc, current);
+    Ok(doc)
+}
+
+fn finish(doc: &mut Document, section: Section) {
+    if section.key() != "multitext header" || !section.body.trim().is_empty() {
+        doc.insert_section(section);
+    }
+}
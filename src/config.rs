@@ -0,0 +1,342 @@
+//! Shared settings read from a `.multitext.toml` file
+//!
+//! A team can check a `.multitext.toml` into its repository so that every
+//! tool built on the crate lints and formats bundles the same way.
+//! [`Config::discover`] finds the nearest one by walking up from a bundle's
+//! directory. The file is a small subset of TOML: tables, strings, integers,
+//! booleans and single-line arrays of strings.
+//!
+//! ```toml
+//! # the marker new bundles are written with
+//! marker = "@@"
+//! # a schema file, relative to this file, whose required sections every
+//! # bundle must have
+//! schema = "shaders/schema.mt"
+//!
+//! [lint]
+//! strict = true
+//! required_keys = ["vertex shader", "fragment shader"]
+//! max_file_bytes = 1048576
+//! max_section_bytes = 65536
+//! references = true
+//!
+//! [format]
+//! order = "natural"
+//! max_body_bytes = 4096
+//! max_blank_lines = 1
+//!
+//! # commands that put section bodies into canonical form, by the
+//! # section's `type` attribute
+//! [format.normalize]
+//! rust = ["rustfmt", "--emit=stdout", "--quiet"]
+//! ```
+//!
+//! Unknown settings are errors, so a misspelled name doesn't go unnoticed.
+
+use crate::check::{CheckProfile, ReferenceSyntax};
+use crate::codegen::Schema;
+use crate::normalize::Normalizers;
+use crate::options::{SectionOrder, WriteOptions};
+use crate::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// The name of the file [`Config::discover`] looks for
+pub const CONFIG_FILE_NAME: &str = ".multitext.toml";
+
+/// Settings from a `.multitext.toml` file
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// The file the settings were read from, if any
+    pub path: Option<PathBuf>,
+    pub marker: Option<String>,
+    /// The schema file, resolved against the directory of the config file
+    pub schema: Option<PathBuf>,
+    pub strict: bool,
+    pub required_keys: Vec<String>,
+    pub max_file_bytes: Option<usize>,
+    pub max_section_bytes: Option<usize>,
+    /// Whether references to other sections must resolve; see
+    /// [`orphaned_references`](crate::check::orphaned_references)
+    pub references: bool,
+    pub order: Option<SectionOrder>,
+    pub max_body_bytes: Option<usize>,
+    pub max_blank_lines: Option<usize>,
+    /// Commands that normalize bodies, by content type; see
+    /// [`Document::normalize`](crate::Document::normalize)
+    pub normalizers: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(usize),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+impl Config {
+    /// Reads settings from the text of a config file
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::config::Config;
+    ///
+    /// let config = Config::parse("marker = \"##\"\n[lint]\nstrict = true # warnings fail\n").unwrap();
+    /// assert_eq!(config.marker.as_deref(), Some("##"));
+    /// assert!(config.strict);
+    ///
+    /// let e = Config::parse("[lint]\nstirct = true\n").unwrap_err();
+    /// assert!(e.to_string().contains("unknown setting \"lint.stirct\""));
+    /// ```
+    pub fn parse(text: &str) -> Result<Config, Error> {
+        let mut config = Config::default();
+        let mut table = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| Error::new(ErrorKind::Malformed, message).with_line(line_number);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                table = name.strip_suffix(']').ok_or_else(|| error("unclosed table header".to_string()))?
+                    .trim().to_string();
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or_else(|| error("expected `name = value`".to_string()))?;
+            let name = match table.as_str() {
+                "" => name.trim().to_string(),
+                table => format!("{}.{}", table, name.trim()),
+            };
+            let value = parse_value(value.trim()).map_err(&error)?;
+            config.set(&name, value).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let mismatch = |expected: &str| Err(format!("\"{}\" should be {}", name, expected));
+        if let Some(content_type) = name.strip_prefix("format.normalize.") {
+            match value {
+                Value::Array(command) if !command.is_empty() => {
+                    self.normalizers.retain(|(t, _)| t != content_type);
+                    self.normalizers.push((content_type.to_string(), command));
+                    return Ok(());
+                }
+                _ => return mismatch("a command and its arguments, as an array of strings"),
+            }
+        }
+        match (name, value) {
+            ("marker", Value::String(marker)) => self.marker = Some(marker),
+            ("schema", Value::String(path)) => self.schema = Some(PathBuf::from(path)),
+            ("lint.strict", Value::Bool(strict)) => self.strict = strict,
+            ("lint.required_keys", Value::Array(keys)) => self.required_keys = keys,
+            ("lint.max_file_bytes", Value::Integer(max)) => self.max_file_bytes = Some(max),
+            ("lint.max_section_bytes", Value::Integer(max)) => self.max_section_bytes = Some(max),
+            ("lint.references", Value::Bool(references)) => self.references = references,
+            ("format.order", Value::String(order)) => {
+                self.order = Some(match order.as_str() {
+                    "document" => SectionOrder::Document,
+                    "alphabetical" => SectionOrder::Alphabetical,
+                    "natural" => SectionOrder::Natural,
+                    _ => return mismatch("\"document\", \"alphabetical\" or \"natural\""),
+                });
+            }
+            ("format.max_body_bytes", Value::Integer(max)) => self.max_body_bytes = Some(max),
+            ("format.max_blank_lines", Value::Integer(max)) => self.max_blank_lines = Some(max),
+            ("marker", _) | ("schema", _) | ("format.order", _) => return mismatch("a string"),
+            ("lint.strict", _) | ("lint.references", _) => return mismatch("true or false"),
+            ("lint.required_keys", _) => return mismatch("an array of strings"),
+            ("lint.max_file_bytes", _) | ("lint.max_section_bytes", _) | ("format.max_body_bytes", _)
+            | ("format.max_blank_lines", _) => {
+                return mismatch("a number");
+            }
+            _ => return Err(format!("unknown setting \"{}\"", name)),
+        }
+        Ok(())
+    }
+
+    /// Reads the config file at `path`, resolving the schema path against its
+    /// directory
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| Error::from(e).with_path(path))?;
+        let mut config = Config::parse(&text).map_err(|e| e.with_path(path))?;
+        if let (Some(schema), Some(dir)) = (&config.schema, path.parent()) {
+            config.schema = Some(dir.join(schema));
+        }
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Finds and reads the nearest `.multitext.toml` in `start` or one of its
+    /// ancestors, where `start` is a bundle or the directory holding it
+    pub fn discover<P: AsRef<Path>>(start: P) -> Result<Option<Config>, Error> {
+        let start = start.as_ref();
+        let mut dir = if start.is_dir() { Some(start) } else { start.parent() };
+        while let Some(current) = dir {
+            let candidate = current.join(CONFIG_FILE_NAME);
+            if candidate.is_file() {
+                return Config::open(candidate).map(Some);
+            }
+            dir = current.parent();
+        }
+        Ok(None)
+    }
+
+    /// The lint settings as a profile for [`check_file`](crate::check::check_file)
+    ///
+    /// The required keys include the required sections of the schema, which
+    /// is read here.
+    pub fn check_profile(&self) -> Result<CheckProfile, Error> {
+        let mut required_keys = self.required_keys.clone();
+        if let Some(schema) = &self.schema {
+            let schema = Schema::from_document(&crate::open_and_parse_document(schema)?);
+            required_keys.extend(schema.sections.into_iter().filter(|s| !s.optional).map(|s| s.key));
+        }
+        let mut profile = CheckProfile::new().strict(self.strict).required_keys(&required_keys);
+        if let Some(max) = self.max_file_bytes {
+            profile = profile.max_file_bytes(max);
+        }
+        if let Some(max) = self.max_section_bytes {
+            profile = profile.max_section_bytes(max);
+        }
+        if self.references {
+            profile = profile.references(&ReferenceSyntax::all());
+        }
+        Ok(profile)
+    }
+
+    /// The format settings as options for
+    /// [`Document::write_with`](crate::Document::write_with)
+    pub fn write_options(&self) -> WriteOptions {
+        let mut options = WriteOptions::new();
+        if let Some(marker) = &self.marker {
+            options = options.marker(marker.as_str());
+        }
+        if let Some(order) = self.order {
+            options = options.order(order);
+        }
+        if let Some(max) = self.max_body_bytes {
+            options = options.max_body_bytes(max);
+        }
+        if let Some(max) = self.max_blank_lines {
+            options = options.max_blank_lines(max);
+        }
+        options
+    }
+
+    /// The normalizing commands as [`Normalizers`]
+    pub fn normalizers(&self) -> Normalizers {
+        self.normalizers.iter().fold(Normalizers::new(), |normalizers, (content_type, command)| {
+            normalizers.register_command(content_type.as_str(), command)
+        })
+    }
+}
+
+/// Cuts a `#` comment off a line, leaving any `#` inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if text.starts_with('"') {
+        let (string, rest) = parse_string(text)?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected text after string: {}", rest.trim()));
+        }
+        return Ok(Value::String(string));
+    }
+    if let Some(items) = text.strip_prefix('[') {
+        let mut rest = items.trim_start();
+        let mut strings = Vec::new();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                if !after.trim().is_empty() {
+                    return Err(format!("unexpected text after array: {}", after.trim()));
+                }
+                return Ok(Value::Array(strings));
+            }
+            let (string, after) = parse_string(rest)?;
+            strings.push(string);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        }
+    }
+    text.replace('_', "").parse().map(Value::Integer).map_err(|_| format!("invalid value: {}", text))
+}
+
+/// Parses a `"..."` string at the start of `text`, returning it and the text
+/// after the closing quote
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let body = text.strip_prefix('"').ok_or_else(|| format!("expected a string: {}", text))?;
+    let mut string = String::new();
+    let mut chars = body.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &body[index + 1..])),
+            '\\' => string.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                other => return Err(format!("unknown escape \\{}", other.map_or(String::new(), String::from))),
+            }),
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let text = "marker = \"#@\" # not a comment inside \"#\"\n\
+                    schema = \"schema.mt\"\n\
+                    [lint]\n\
+                    required_keys = [\"a\", \"b \\\"quoted\\\"\"]\n\
+                    max_section_bytes = 64_000\n\
+                    [format]\n\
+                    order = \"natural\"\n\
+                    [format.normalize]\n\
+                    rust = [\"rustfmt\", \"--emit=stdout\"]\n";
+        let config = Config::parse(text).unwrap();
+        assert_eq!(config.marker.as_deref(), Some("#@"));
+        assert_eq!(config.required_keys, ["a", "b \"quoted\""]);
+        assert_eq!(config.max_section_bytes, Some(64000));
+        assert!(matches!(config.order, Some(SectionOrder::Natural)));
+        assert_eq!(config.normalizers, [("rust".to_string(), vec!["rustfmt".to_string(), "--emit=stdout".to_string()])]);
+
+        let e = Config::parse("[format]\norder = 3\n").unwrap_err();
+        assert_eq!(e.line_number, Some(2));
+
+        let dir = crate::testing::TempDir::new("config").unwrap();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join(CONFIG_FILE_NAME), text).unwrap();
+        std::fs::write(dir.join("schema.mt"), "@@ multitext header\n@@ vertex\n@@ notes\noptional: true\n").unwrap();
+        let found = Config::discover(dir.join("nested/bundle.mt")).unwrap().unwrap();
+        assert_eq!(found.schema, Some(dir.join("schema.mt")));
+        let profile = found.check_profile().unwrap();
+        assert!(format!("{:?}", profile).contains("\"vertex\""));
+        assert!(!format!("{:?}", profile).contains("\"notes\""));
+    }
+}
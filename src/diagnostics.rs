@@ -0,0 +1,295 @@
+//! Reporting of suspicious but non-fatal input found while parsing
+//!
+//! The parser recovers from these on its own, so they never become an
+//! [`Error`](crate::Error). Applications that want to surface them hand a
+//! [`DiagnosticsSink`] to [`parse_document_with_sink`](crate::parse_document_with_sink)
+//! and route each [`Warning`] into their own logging or telemetry.
+//!
+//! Tools that check the contents of sections, such as shader compilers,
+//! report positions within the text they were given. [`map_diagnostics`]
+//! turns those [`SectionDiagnostic`]s into [`FileDiagnostic`]s positioned in
+//! the multitext file, so editors and the command line can show them
+//! alongside the file's own problems.
+//!
+//! Fatal errors found on a particular line carry a [`Snippet`] of it, and
+//! [`Error::report`] renders them with the line shown and the offending part
+//! underlined, for command line tools and build scripts.
+
+use crate::{json, Document, Error};
+use std::ops::Range;
+
+/// The kinds of anomaly the parser reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    /// Non-blank text above the header line, which is discarded
+    DiscardedPreamble,
+    /// A marker line with nothing after the marker
+    EmptyKey,
+    /// A key that was already used earlier in the file; the later body wins
+    DuplicateKey,
+    /// Some lines end in `\r\n` and others in `\n`; reported once, at the
+    /// first line with the less common ending
+    ///
+    /// Only detectable when the lines handed to the parser keep their `\r`,
+    /// as those from [`read_lines`](crate::read_lines) do.
+    MixedLineEndings,
+    /// No header line was found, so there are no sections; only reported by
+    /// [`parse_lines_lenient`](crate::parse_lines_lenient)
+    MissingHeader,
+    /// A line longer than
+    /// [`ParseOptions::max_line_len`](crate::options::ParseOptions::max_line_len)
+    /// allows; only reported by
+    /// [`parse_lines_lenient`](crate::parse_lines_lenient)
+    LongLine,
+    /// Any other error, which stopped parsing there; only reported by
+    /// [`parse_lines_lenient`](crate::parse_lines_lenient)
+    Unparsed,
+}
+
+/// One anomaly found while parsing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// The 1-based line the anomaly was found on
+    pub line_number: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "multitext warning : {} : ({})", self.message, self.line_number)
+    }
+}
+
+/// Receives every warning produced during a parse
+///
+/// Closures taking `&Warning` implement this trait, so a sink can be as
+/// simple as `|w: &Warning| log::warn!("{}", w)`.
+pub trait DiagnosticsSink {
+    fn report(&mut self, warning: &Warning);
+}
+
+impl<F: FnMut(&Warning)> DiagnosticsSink for F {
+    fn report(&mut self, warning: &Warning) {
+        self(warning)
+    }
+}
+
+/// Discards every warning; what the plain parse functions use
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl DiagnosticsSink for NoopSink {
+    fn report(&mut self, _: &Warning) {}
+}
+
+/// Writes each warning as one line to an `io::Write`, such as stderr or a
+/// log file
+#[derive(Debug)]
+pub struct WriteSink<W: std::io::Write>(pub W);
+
+impl<W: std::io::Write> DiagnosticsSink for WriteSink<W> {
+    fn report(&mut self, warning: &Warning) {
+        // A sink has nowhere to send its own failures, so a failed write only
+        // loses the warning.
+        let _ = writeln!(self.0, "{}", warning);
+    }
+}
+
+/// Collects warnings into a `Vec`
+impl DiagnosticsSink for Vec<Warning> {
+    fn report(&mut self, warning: &Warning) {
+        self.push(warning.clone());
+    }
+}
+
+/// How serious an external diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+/// A diagnostic from an external tool, positioned within the body of one
+/// section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionDiagnostic {
+    pub key: String,
+    /// The 1-based line within the body
+    pub line: Option<usize>,
+    /// The 1-based column within that line, passed through unchanged
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A [`SectionDiagnostic`] positioned within the multitext file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiagnostic {
+    /// The file the section was read from, if known
+    pub file: Option<String>,
+    pub key: String,
+    /// The 1-based line of the file, when it can be determined
+    pub line_number: Option<usize>,
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl FileDiagnostic {
+    /// Renders the diagnostic as a single JSON object
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"file\":{},\"key\":{},\"line\":{},\"column\":{},\"severity\":{},\"message\":{}}}",
+            json::optional_string(self.file.as_deref()),
+            json::string(&self.key),
+            json::optional_number(self.line_number),
+            json::optional_number(self.column),
+            json::string(&self.severity.to_string()),
+            json::string(&self.message),
+        )
+    }
+}
+
+impl std::fmt::Display for FileDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut location = self.file.clone().unwrap_or_default();
+        match (self.line_number, self.column) {
+            (Some(line), Some(column)) => location.push_str(&format!("({},{}) : ", line, column)),
+            (Some(line), None) => location.push_str(&format!("({}) : ", line)),
+            _ if !location.is_empty() => location.push_str(" : "),
+            _ => {}
+        }
+        write!(f, "{}{} : {} : {}", location, self.severity, self.key, self.message)
+    }
+}
+
+/// Positions each of `diagnostics` within the file `doc` was parsed from
+///
+/// A diagnostic keeps a line of `None` when its section is missing, was
+/// built or changed in memory, or doesn't have the line; see
+/// [`Section::origin_at`](crate::Section::origin_at).
+///
+/// # Examples
+/// ```
+/// use multitext::diagnostics::{map_diagnostics, SectionDiagnostic, Severity};
+///
+/// let lines = ["@@ multitext header", "@@ vertex", "#version 430", "void main() {", "}"];
+/// let doc = multitext::parse_document(lines.iter()).unwrap();
+/// let mapped = map_diagnostics(&doc, vec![SectionDiagnostic {
+///     key: "vertex".to_string(),
+///     line: Some(2),
+///     column: Some(6),
+///     severity: Severity::Error,
+///     message: "expected ';'".to_string(),
+/// }]);
+/// assert_eq!(mapped[0].line_number, Some(4));
+/// assert_eq!(mapped[0].to_string(), "(4,6) : error : vertex : expected ';'");
+/// ```
+pub fn map_diagnostics<I>(doc: &Document, diagnostics: I) -> Vec<FileDiagnostic>
+    where I: IntoIterator<Item = SectionDiagnostic>
+{
+    diagnostics.into_iter().map(|d| {
+        let section = doc.section(&d.key);
+        let origin = section.zip(d.line).and_then(|(section, line)| section.origin_at(line));
+        FileDiagnostic {
+            file: origin.as_ref().and_then(|o| o.file.clone())
+                .or_else(|| section.and_then(|s| s.origin()).and_then(|o| o.file)),
+            key: d.key,
+            line_number: origin.map(|o| o.line_number),
+            column: d.column,
+            severity: d.severity,
+            message: d.message,
+        }
+    }).collect()
+}
+
+/// The line of input an [`Error`] points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// The text of the line, without its line ending
+    pub line: String,
+    /// The bytes of `line` the error is about
+    pub span: Range<usize>,
+}
+
+impl Snippet {
+    /// Points at the part of `line` that `part`, a slice of it, covers, or
+    /// at the whole line if `part` isn't one
+    pub(crate) fn of_part(line: &str, part: &str) -> Snippet {
+        let start = (part.as_ptr() as usize).wrapping_sub(line.as_ptr() as usize);
+        let span = if start <= line.len() && start + part.len() <= line.len() {
+            start..start + part.len()
+        } else {
+            0..line.len()
+        };
+        Snippet { line: line.to_string(), span }
+    }
+
+    /// The 1-based column, in characters, where the span starts
+    pub fn column(&self) -> usize {
+        self.line[..self.span.start].chars().count() + 1
+    }
+}
+
+/// An [`Error`] rendered over several lines with the offending line shown,
+/// as returned by [`Error::report`]
+///
+/// # Examples
+/// ```
+/// use multitext::options::{DuplicateKeys, ParseOptions};
+///
+/// let lines = ["@@ multitext header", "@@ vertex shader", "@@ vertex shader"];
+/// let options = ParseOptions::new().duplicate_keys(DuplicateKeys::Error);
+/// let e = multitext::parse_document_with(lines.iter(), &options).unwrap_err();
+/// assert_eq!(e.report().to_string(), "\
+/// error: duplicate key \"vertex shader\"
+///   --> 3:4
+///   |
+/// 3 | @@ vertex shader
+///   |    ^^^^^^^^^^^^^
+/// ");
+/// ```
+pub struct Report<'a> {
+    pub(crate) error: &'a Error,
+}
+
+impl std::fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let error = self.error;
+        writeln!(f, "error: {}", error.message())?;
+        let file = error.path().map(|path| path.display().to_string());
+        let (line_number, snippet) = match (error.line_number(), error.snippet()) {
+            (Some(line_number), Some(snippet)) => (line_number, snippet),
+            (line_number, _) => {
+                let location = match (file, line_number) {
+                    (Some(file), Some(line_number)) => format!("{}:{}", file, line_number),
+                    (Some(file), None) => file,
+                    (None, Some(line_number)) => line_number.to_string(),
+                    (None, None) => return Ok(()),
+                };
+                return writeln!(f, " --> {}", location);
+            }
+        };
+        let gutter = " ".repeat(line_number.to_string().len());
+        match file {
+            Some(file) => writeln!(f, "{} --> {}:{}:{}", gutter, file, line_number, snippet.column())?,
+            None => writeln!(f, "{} --> {}:{}", gutter, line_number, snippet.column())?,
+        }
+        let width = snippet.line[snippet.span.clone()].chars().count().max(1);
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line_number, snippet.line)?;
+        writeln!(f, "{} | {}{}", gutter, " ".repeat(snippet.column() - 1), "^".repeat(width))
+    }
+}
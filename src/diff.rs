@@ -0,0 +1,208 @@
+//! Comparing two documents section by section
+//!
+//! A line-based diff of two whole files is little help once sections have been
+//! reordered. [`diff_documents`] instead pairs sections up by key, and
+//! recognizes a section that disappeared under one key and reappeared with the
+//! same body under another as a rename. [`unified_diff`] shows what changed
+//! inside one body.
+
+use crate::Document;
+use std::fmt;
+
+/// One difference between two documents
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SectionChange<'a> {
+    Added { key: &'a str, body: &'a str },
+    Removed { key: &'a str, body: &'a str },
+    /// The body is the same under a new key
+    Renamed { from: &'a str, to: &'a str },
+    Modified { key: &'a str, old_body: &'a str, new_body: &'a str },
+}
+
+impl fmt::Display for SectionChange<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectionChange::Added { key, .. } => write!(f, "added section \"{}\"", key),
+            SectionChange::Removed { key, .. } => write!(f, "removed section \"{}\"", key),
+            SectionChange::Renamed { from, to } => write!(f, "renamed section \"{}\" to \"{}\"", from, to),
+            SectionChange::Modified { key, .. } => write!(f, "modified section \"{}\"", key),
+        }
+    }
+}
+
+/// Lists the differences between `old` and `new`: changes to sections of
+/// `old` in its order, then the sections only `new` has in its order
+///
+/// # Examples
+/// ```
+/// use multitext::diff::{diff_documents, SectionChange};
+///
+/// let old = multitext::parse_document(["@@ multitext header", "@@ a", "1", "@@ b", "2", "@@ c", "3"].iter()).unwrap();
+/// let new = multitext::parse_document(["@@ multitext header", "@@ c", "3!", "@@ a", "1", "@@ beta", "2"].iter()).unwrap();
+/// let changes: Vec<String> = diff_documents(&old, &new).iter().map(|c| c.to_string()).collect();
+/// assert_eq!(changes, ["renamed section \"b\" to \"beta\"", "modified section \"c\""]);
+/// ```
+pub fn diff_documents<'a>(old: &'a Document, new: &'a Document) -> Vec<SectionChange<'a>> {
+    let mut changes = Vec::new();
+    let mut added: Vec<(&str, &str)> = new.iter()
+        .filter(|s| old.get(s.key()).is_none())
+        .map(|s| (s.key(), s.body()))
+        .collect();
+    for section in old.iter() {
+        let (key, body) = (section.key(), section.body());
+        match new.get(key) {
+            Some(new_body) if new_body == body => {}
+            Some(new_body) => changes.push(SectionChange::Modified { key, old_body: body, new_body }),
+            None => match added.iter().position(|(_, b)| *b == body) {
+                Some(index) => changes.push(SectionChange::Renamed { from: key, to: added.remove(index).0 }),
+                None => changes.push(SectionChange::Removed { key, body }),
+            },
+        }
+    }
+    changes.extend(added.into_iter().map(|(key, body)| SectionChange::Added { key, body }));
+    changes
+}
+
+/// Formats the line changes from `old` to `new` as unified diff hunks, with
+/// `context` unchanged lines around each change, or returns an empty string
+/// if they are equal
+///
+/// Lines common to the start and end are skipped cheaply; the rest is
+/// compared in time proportional to the product of the two lengths.
+///
+/// # Examples
+/// ```
+/// let diff = multitext::diff::unified_diff("a\nb\nc\n", "a\nB\nc\n", 1);
+/// assert_eq!(diff, "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+/// ```
+pub fn unified_diff(old: &str, new: &str, context: usize) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = line_ops(&a, &b);
+
+    let mut out = String::new();
+    let mut start = 0;
+    while let Some(first_change) = ops[start..].iter().position(|op| *op != Op::Same).map(|i| start + i) {
+        let hunk_start = first_change.saturating_sub(context).max(start);
+        // Extend the hunk while changes are close enough for their contexts
+        // to touch
+        let mut hunk_end = first_change;
+        let mut i = first_change;
+        while i < ops.len() {
+            if ops[i] != Op::Same {
+                hunk_end = i + 1;
+                i += 1;
+            } else if i - hunk_end < 2 * context {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        let hunk_end = (hunk_end + context).min(ops.len());
+
+        let (old_start, new_start) = positions(&ops[..hunk_start]);
+        let (old_len, new_len) = positions(&ops[hunk_start..hunk_end]);
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len), range(new_start, new_len),
+        ));
+        let (mut x, mut y) = (old_start, new_start);
+        for op in &ops[hunk_start..hunk_end] {
+            match op {
+                Op::Same => {
+                    out.push_str(&format!(" {}\n", a[x]));
+                    x += 1;
+                    y += 1;
+                }
+                Op::Delete => {
+                    out.push_str(&format!("-{}\n", a[x]));
+                    x += 1;
+                }
+                Op::Insert => {
+                    out.push_str(&format!("+{}\n", b[y]));
+                    y += 1;
+                }
+            }
+        }
+        start = hunk_end;
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Same,
+    Delete,
+    Insert,
+}
+
+/// How many lines of the old and new text `ops` cover
+fn positions(ops: &[Op]) -> (usize, usize) {
+    let old = ops.iter().filter(|op| **op != Op::Insert).count();
+    let new = ops.iter().filter(|op| **op != Op::Delete).count();
+    (old, new)
+}
+
+/// A hunk range in unified diff notation, 1-based, or the line before an
+/// empty range
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// The edit script turning `a` into `b`, by longest common subsequence
+fn line_ops(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // lcs[i][j] is the length of the longest common subsequence of a_mid[i..]
+    // and b_mid[j..]
+    let (n, m) = (a_mid.len(), b_mid.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if a_mid[i] == b_mid[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut ops = vec![Op::Same; prefix];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a_mid[i] == b_mid[j] {
+            ops.push(Op::Same);
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops.resize(ops.len() + suffix, Op::Same);
+    ops
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("same\n", "same\n", 3), "");
+        assert_eq!(unified_diff("", "new\n", 3), "@@ -0,0 +1,1 @@\n+new\n");
+
+        let old: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let new: String = (1..=20).filter(|&n| n != 19)
+            .map(|n| if n == 2 { "two\n".to_string() } else { format!("{}\n", n) })
+            .collect();
+        assert_eq!(
+            unified_diff(&old, &new, 1),
+            "@@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n@@ -18,3 +18,2 @@\n 18\n-19\n 20\n",
+        );
+    }
+}
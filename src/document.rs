@@ -0,0 +1,1285 @@
+use crate::{json, Error, ErrorKind, Map};
+use crate::options::DuplicateKeys;
+use crate::history::{Edit, History};
+use crate::events::{ChangeEvent, Observers};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Where a section was originally read from
+///
+/// Sections keep their origin when they are moved between documents by
+/// merging or concatenation, so errors about a combined document can point
+/// at the file the text actually came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Origin {
+    /// The file the section was read from, if it was read from a file
+    pub file: Option<String>,
+    /// The 1-based line on which the body starts
+    pub line_number: usize,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}", file)?;
+        }
+        write!(f, "({})", self.line_number)
+    }
+}
+
+/// A single named section of a multitext file
+///
+/// Two sections are equal when their keys and bodies are; where they came from
+/// in a file and when they were modified are not compared.
+#[derive(Debug, Clone)]
+pub struct Section {
+    key: String,
+    pub(crate) body: String,
+    pub(crate) attrs: Vec<(String, String)>,
+    line_number: Option<usize>,
+    pub(crate) end_line: Option<usize>,
+    file: Option<Arc<str>>,
+    modified: Option<SystemTime>,
+}
+
+impl Section {
+    pub fn new<K: Into<String>, B: Into<String>>(key: K, body: B) -> Section {
+        Section {
+            key: key.into(),
+            body: body.into(),
+            attrs: Vec::new(),
+            line_number: None,
+            end_line: None,
+            file: None,
+            modified: None,
+        }
+    }
+
+    /// An empty section whose body starts on line `line_number` of its file
+    pub(crate) fn at_line(key: &str, line_number: usize) -> Section {
+        Section {
+            key: key.to_string(),
+            body: String::new(),
+            attrs: Vec::new(),
+            line_number: Some(line_number),
+            end_line: None,
+            file: None,
+            modified: None,
+        }
+    }
+
+    /// Records the line after the last line of the body
+    pub(crate) fn set_end_line(&mut self, end_line: usize) {
+        self.end_line = Some(end_line);
+    }
+
+    pub(crate) fn set_file(&mut self, file: Arc<str>) {
+        self.file = Some(file);
+    }
+
+    /// The trimmed text following the marker
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The text between this section's marker line and the next one
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Returns the value of the attribute `name`
+    ///
+    /// Attributes are `name=value` words at the end of a marker line, read
+    /// when parsing with [`ParseOptions::attributes`](crate::options::ParseOptions::attributes).
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Returns true if the section has the attribute `locked=true`
+    ///
+    /// The editing methods of [`Document`] refuse to change locked sections
+    /// unless [`Document::override_locks`] is on.
+    pub fn is_locked(&self) -> bool {
+        self.attr("locked") == Some("true")
+    }
+
+    /// The attributes this crate understands, plus the rest as they were
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ lit owner=render locked=true x-tool=7", "..."];
+    /// let doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+    /// let meta = doc.section("lit").unwrap().meta();
+    /// assert!(meta.locked);
+    /// assert_eq!(meta.unknown_attrs, [("owner", "render"), ("x-tool", "7")]);
+    /// ```
+    pub fn meta(&self) -> SectionMeta<'_> {
+        SectionMeta {
+            locked: self.is_locked(),
+            unknown_attrs: self.attrs()
+                .filter(|(name, _)| !SectionMeta::KNOWN_ATTRS.contains(name))
+                .collect(),
+        }
+    }
+
+    /// Iterates over the attributes as `(name, value)`, in marker line order
+    pub fn attrs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attrs.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The same section with the attribute `name` set to `value`
+    pub fn with_attr<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Section {
+        self.set_attr(name.into(), value.into());
+        self
+    }
+
+    pub(crate) fn set_attr(&mut self, name: String, value: String) -> Option<String> {
+        match self.attrs.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, existing)) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.attrs.push((name, value));
+                None
+            }
+        }
+    }
+
+    /// The same section under a different key
+    pub(crate) fn renamed(self, key: String) -> Section {
+        Section { key, ..self }
+    }
+
+    /// The 1-based line of the file on which the body starts, if the section
+    /// was parsed rather than built in memory
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
+
+    /// The 1-based lines of the file the body was read from, marker line
+    /// excluded
+    ///
+    /// `None` if the section was built in memory, or has changed since the
+    /// document was loaded or last marked clean. A section joined from
+    /// continuation parts spans the marker lines between them.
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ a", "1", "2", "@@ b"];
+    /// let mut doc = multitext::parse_document(lines.iter()).unwrap();
+    /// assert_eq!(doc.section("a").unwrap().lines(), Some(3..5));
+    /// assert_eq!(doc.section("b").unwrap().lines(), Some(6..6));
+    ///
+    /// doc.insert("a", "changed\n").unwrap();
+    /// assert_eq!(doc.section("a").unwrap().lines(), None);
+    /// ```
+    pub fn lines(&self) -> Option<std::ops::Range<usize>> {
+        if self.is_dirty() {
+            return None;
+        }
+        Some(self.line_number?..self.end_line?)
+    }
+
+    /// The file and line the section was read from, if it was parsed rather
+    /// than built in memory
+    ///
+    /// # Examples
+    /// ```no_run
+    /// let doc = multitext::open_and_parse_document("shaders.mt")?;
+    /// if let Some(origin) = doc.section("vertex").and_then(|s| s.origin()) {
+    ///     println!("vertex shader starts at {}", origin);
+    /// }
+    /// # Ok::<(), multitext::Error>(())
+    /// ```
+    pub fn origin(&self) -> Option<Origin> {
+        Some(Origin {
+            file: self.file.as_ref().map(|f| f.to_string()),
+            line_number: self.line_number?,
+        })
+    }
+
+    /// Where line `body_line` of the body, counting from 1, was read from, for
+    /// mapping a compiler's line numbers back to the multitext file
+    ///
+    /// `None` if the body has no such line, or [`Section::lines`] is `None`,
+    /// or the section was joined from continuation parts, whose marker lines
+    /// break the correspondence.
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ vertex", "#version 430", "void main() {", "}"];
+    /// let doc = multitext::parse_document(lines.iter()).unwrap();
+    /// let vertex = doc.section("vertex").unwrap();
+    /// assert_eq!(vertex.origin_at(2).unwrap().line_number, 4);
+    /// assert!(vertex.origin_at(4).is_none());
+    /// ```
+    pub fn origin_at(&self, body_line: usize) -> Option<Origin> {
+        let lines = self.lines()?;
+        if lines.len() != self.body.lines().count() || body_line == 0 || body_line > lines.len() {
+            return None;
+        }
+        Some(Origin {
+            file: self.file.as_ref().map(|f| f.to_string()),
+            line_number: lines.start + body_line - 1,
+        })
+    }
+
+    /// An error located at this section's marker line in its original file
+    pub(crate) fn error(&self, kind: ErrorKind, error_message: String) -> Error {
+        let error = Error::new(kind, error_message).with_line(self.line_number.map(|n| n - 1).filter(|&n| n > 0));
+        match &self.file {
+            Some(file) => error.with_path(&**file),
+            None => error,
+        }
+    }
+
+    /// When the section was last changed through the owning [`Document`], or
+    /// `None` if it hasn't been since it was loaded or last marked clean
+    pub fn modified_at(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.modified.is_some()
+    }
+
+    pub(crate) fn touch(&mut self) {
+        self.modified = Some(SystemTime::now());
+    }
+
+    /// Splits the section into its key and body without copying either
+    pub fn into_parts(self) -> (String, String) {
+        (self.key, self.body)
+    }
+}
+
+impl PartialEq for Section {
+    fn eq(&self, other: &Section) -> bool {
+        self.key == other.key && self.body == other.body && self.attrs == other.attrs
+    }
+}
+
+impl Eq for Section {}
+
+impl std::hash::Hash for Section {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+        self.body.hash(state);
+        self.attrs.hash(state);
+    }
+}
+
+/// The attributes of a [`Section`], split into those this version of the
+/// crate understands and the rest
+///
+/// Every attribute is kept on the section in marker line order and written
+/// back out unchanged, whether or not it's known, so metadata added by a newer
+/// tool survives a round trip through an older one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SectionMeta<'a> {
+    /// `locked=true`; see [`Section::is_locked`]
+    pub locked: bool,
+    /// Every other attribute, in marker line order
+    pub unknown_attrs: Vec<(&'a str, &'a str)>,
+}
+
+impl SectionMeta<'_> {
+    /// Names of the attributes with a field of their own
+    pub const KNOWN_ATTRS: &'static [&'static str] = &["locked"];
+}
+
+/// Heap memory held by a [`Document`], in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MemoryFootprint {
+    /// Capacity of the key strings
+    pub keys: usize,
+    /// Capacity of the body strings
+    pub bodies: usize,
+    /// Everything else: the section table itself
+    pub metadata: usize,
+}
+
+impl MemoryFootprint {
+    pub fn total(&self) -> usize {
+        self.keys + self.bodies + self.metadata
+    }
+}
+
+/// The sections of a multitext file, kept in the order they were parsed
+///
+/// `Document` is `Send` and `Sync`, and [`Document::into_sections`] hands out
+/// owned sections, so bodies can be moved to other threads without cloning.
+///
+/// A parsed document remembers the marker its file used, and tracks which
+/// sections have been changed or removed since it was loaded. Like line
+/// numbers, neither is considered when comparing documents.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub(crate) sections: Vec<Section>,
+    /// The index in `sections` of each key
+    positions: HashMap<String, usize>,
+    marker: Option<String>,
+    preamble: Option<String>,
+    pub(crate) removed: Vec<String>,
+    pub(crate) history: Option<History>,
+    pub(crate) observers: Observers,
+    override_locks: bool,
+    pub(crate) keep_versions: bool,
+}
+
+impl PartialEq for Document {
+    fn eq(&self, other: &Document) -> bool {
+        self.sections == other.sections
+    }
+}
+
+impl Eq for Document {}
+
+impl Document {
+    pub fn new() -> Document {
+        Document::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// The marker read from the header line, or set by
+    /// [`Document::retarget_marker`]
+    pub fn marker(&self) -> Option<&str> {
+        self.marker.as_deref()
+    }
+
+    pub(crate) fn set_marker(&mut self, marker: String) {
+        self.marker = Some(marker);
+    }
+
+    /// The text above the header line, if it was kept with
+    /// [`ParseOptions::keep_preamble`] or set with [`Document::set_preamble`]
+    pub fn preamble(&self) -> Option<&str> {
+        self.preamble.as_deref()
+    }
+
+    /// Sets or clears the text written above the header line
+    ///
+    /// The writer adds a final newline if the text lacks one, and fails if
+    /// any line of it contains "multitext header".
+    pub fn set_preamble(&mut self, preamble: Option<String>) {
+        self.preamble = preamble;
+    }
+
+    /// Switches the document to a new marker, failing if the marker is unusable
+    ///
+    /// Body lines that start with the new marker are escaped when written.
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ script", "# a comment", "echo hi"];
+    /// let mut doc = multitext::parse_document(lines.iter()).unwrap();
+    /// assert_eq!(doc.marker(), Some("@@"));
+    /// assert!(doc.retarget_marker("#  ").is_err());
+    /// doc.retarget_marker("#").unwrap();
+    /// assert_eq!(doc.marker(), Some("#"));
+    ///
+    /// let text = String::from_utf8(doc.write_with(Vec::new(), &Default::default()).unwrap()).unwrap();
+    /// assert_eq!(text, "# multitext header\n# script\n\\# a comment\necho hi\n");
+    /// assert_eq!(multitext::parse_document(text.lines()).unwrap(), doc);
+    /// ```
+    pub fn retarget_marker(&mut self, marker: &str) -> Result<(), Error> {
+        crate::writer::check_marker(marker)?;
+        self.marker = Some(marker.to_string());
+        Ok(())
+    }
+
+    /// Returns the body of the section named `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.section(key).map(|s| s.body.as_str())
+    }
+
+    /// The index of the section named `key`
+    pub(crate) fn position(&self, key: &str) -> Option<usize> {
+        self.positions.get(key).copied()
+    }
+
+    /// Records the positions of the sections from `start` on, after sections
+    /// were inserted or removed before them
+    fn reindex_from(&mut self, start: usize) {
+        for (index, section) in self.sections.iter().enumerate().skip(start) {
+            self.positions.insert(section.key.clone(), index);
+        }
+    }
+
+    /// Returns the body of the section named `key`, or an error naming the
+    /// missing section
+    pub fn require(&self, key: &str) -> Result<&str, Error> {
+        self.get(key).ok_or_else(|| Error::new(ErrorKind::MissingSection, format!("missing section \"{}\"", key)))
+    }
+
+    /// Checks that every one of `keys` names a section, failing with a single
+    /// error that lists all the missing ones, each with the existing key it
+    /// was most likely a misspelling of
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ vertex shader", "...", "@@ Fragment Shader", "..."];
+    /// let doc = multitext::parse_document(lines.iter()).unwrap();
+    /// assert!(doc.verify_keys(&["vertex shader"]).is_ok());
+    ///
+    /// let e = doc.verify_keys(&["vertex shadr", "fragment shader", "geometry shader"]).unwrap_err();
+    /// assert!(e.to_string().contains(
+    ///     "missing sections \"vertex shadr\" (did you mean \"vertex shader\"?), \
+    ///      \"fragment shader\" (did you mean \"Fragment Shader\"?), \"geometry shader\""
+    /// ));
+    /// ```
+    pub fn verify_keys(&self, keys: &[&str]) -> Result<(), Error> {
+        let missing: Vec<String> = keys.iter()
+            .filter(|key| !self.contains_key(key))
+            .map(|key| match crate::keys::suggest(key, self.iter().map(Section::key)) {
+                Some(suggestion) => format!("\"{}\" (did you mean \"{}\"?)", key, suggestion),
+                None => format!("\"{}\"", key),
+            })
+            .collect();
+        match missing.len() {
+            0 => Ok(()),
+            n => {
+                let message = format!("missing section{} {}", if n == 1 { "" } else { "s" }, missing.join(", "));
+                Err(Error::new(ErrorKind::MissingSection, message))
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Lets the editing methods change locked sections
+    ///
+    /// Off by default, in which case [`Document::insert`],
+    /// [`Document::remove`], [`Document::rename`] and [`Document::set_attr`]
+    /// fail with [`ErrorKind::Locked`] on a section with `locked=true`.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ generated locked=true", "fn f() {}"];
+    /// let mut doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+    /// assert!(doc.insert("generated", "edited\n").is_err());
+    /// assert!(doc.remove("generated").is_err());
+    /// assert_eq!(&doc["generated"], "fn f() {}\n");
+    ///
+    /// doc.override_locks(true);
+    /// doc.insert("generated", "regenerated\n").unwrap();
+    /// assert_eq!(&doc["generated"], "regenerated\n");
+    /// ```
+    pub fn override_locks(&mut self, yes: bool) {
+        self.override_locks = yes;
+    }
+
+    /// Returns an error if the section named `key` is locked and locks aren't
+    /// overridden
+    pub fn check_unlocked(&self, key: &str) -> Result<(), Error> {
+        match self.section(key) {
+            Some(section) if section.is_locked() && !self.override_locks => {
+                Err(section.error(ErrorKind::Locked, format!("section \"{}\" is locked", key)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the body of the section named `key`, returning the previous body
+    ///
+    /// A new key is appended to the end of the document; an existing one keeps
+    /// its position. Fails, changing nothing, if the section is locked and
+    /// locks aren't overridden.
+    pub fn insert<K: Into<String>, B: Into<String>>(&mut self, key: K, body: B) -> Result<Option<String>, Error> {
+        let key = key.into();
+        self.check_unlocked(&key)?;
+        Ok(self.insert_unchecked(key, body))
+    }
+
+    /// [`Document::insert`] without the lock check, for edits the crate
+    /// makes on the document's behalf, such as undoing
+    pub(crate) fn insert_unchecked<K: Into<String>, B: Into<String>>(&mut self, key: K, body: B) -> Option<String> {
+        let key = key.into();
+        let body = body.into();
+        match self.position(&key) {
+            Some(index) => {
+                let section = &mut self.sections[index];
+                if section.body == body {
+                    return Some(body);
+                }
+                section.touch();
+                let old_body = std::mem::replace(&mut section.body, body);
+                if self.keep_versions {
+                    self.save_version(index, old_body.clone());
+                }
+                if !self.observers.is_empty() {
+                    let new_body = self.sections[index].body.clone();
+                    self.emit(&ChangeEvent::Modified { key: &key, old_body: &old_body, new_body: &new_body });
+                }
+                if self.history.is_some() {
+                    let new_body = self.sections[index].body.clone();
+                    self.record(Edit::Replace { key, old_body: old_body.clone(), new_body });
+                }
+                Some(old_body)
+            }
+            None => {
+                let index = self.sections.len();
+                if self.history.is_some() {
+                    self.record(Edit::Insert { key: key.clone(), index, body: body.clone() });
+                }
+                self.insert_raw(index, key, body);
+                None
+            }
+        }
+    }
+
+    /// Adds a new section, failing if `key` is already used
+    ///
+    /// The error points at the existing section's marker line when the
+    /// document was parsed.
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ vertex", "..."];
+    /// let mut doc = multitext::parse_document(lines.iter()).unwrap();
+    /// assert!(doc.try_insert("fragment", "...").is_ok());
+    /// let e = doc.try_insert("vertex", "again").unwrap_err();
+    /// assert!(e.to_string().ends_with("(2)"));
+    /// ```
+    pub fn try_insert<K: Into<String>, B: Into<String>>(&mut self, key: K, body: B) -> Result<(), Error> {
+        let key = key.into();
+        if let Some(existing) = self.section(&key) {
+            return Err(existing.error(ErrorKind::DuplicateKey, format!("key \"{}\" is already in use", key)));
+        }
+        self.insert_unchecked(key, body);
+        Ok(())
+    }
+
+    /// Adds many sections, resolving keys that are already in use, or used
+    /// twice among the new sections, by `strategy`
+    ///
+    /// With [`DuplicateKeys::Error`] nothing is inserted if any key clashes,
+    /// and with any strategy nothing is inserted if a locked section would
+    /// change.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::DuplicateKeys;
+    ///
+    /// let mut doc: multitext::Document = vec![("a", "1\n")].into_iter().collect();
+    /// assert!(doc.insert_all(vec![("b", "2\n"), ("a", "x\n")], DuplicateKeys::Error).is_err());
+    /// assert_eq!(doc.len(), 1);
+    ///
+    /// doc.insert_all(vec![("b", "2\n"), ("a", "x\n")], DuplicateKeys::Concatenate).unwrap();
+    /// assert_eq!(&doc["a"], "1\nx\n");
+    /// ```
+    pub fn insert_all<I, K, B>(&mut self, sections: I, strategy: DuplicateKeys) -> Result<(), Error>
+    where I: IntoIterator<Item = (K, B)>, K: Into<String>, B: Into<String>
+    {
+        let sections: Vec<(String, String)> = sections.into_iter().map(|(k, b)| (k.into(), b.into())).collect();
+        if strategy != DuplicateKeys::KeepFirst {
+            for (key, _) in &sections {
+                self.check_unlocked(key)?;
+            }
+        }
+        if strategy == DuplicateKeys::Error {
+            let mut seen = std::collections::HashSet::new();
+            for (key, _) in &sections {
+                if let Some(existing) = self.section(key) {
+                    return Err(existing.error(ErrorKind::DuplicateKey, format!("key \"{}\" is already in use", key)));
+                }
+                if !seen.insert(key.as_str()) {
+                    let message = format!("key \"{}\" appears twice among the inserted sections", key);
+                    return Err(Error::new(ErrorKind::DuplicateKey, message));
+                }
+            }
+        }
+        for (key, body) in sections {
+            match (self.get(&key), strategy) {
+                (None, _) | (Some(_), DuplicateKeys::KeepLast) => {
+                    self.insert_unchecked(key, body);
+                }
+                (Some(_), DuplicateKeys::KeepFirst) => {}
+                (Some(existing), DuplicateKeys::Concatenate) => {
+                    let joined = format!("{}{}", existing, body);
+                    self.insert_unchecked(key, joined);
+                }
+                (Some(_), DuplicateKeys::Error) => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Keys of the sections added or changed since the document was loaded or
+    /// last marked clean, in document order
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ a", "1", "@@ b", "2"];
+    /// let mut doc = multitext::parse_document(lines.iter()).unwrap();
+    /// assert!(!doc.is_dirty());
+    ///
+    /// doc.insert("b", "two\n").unwrap();
+    /// doc.insert("c", "3\n").unwrap();
+    /// doc.remove("a").unwrap();
+    /// assert_eq!(doc.dirty_keys(), ["b", "c"]);
+    /// assert_eq!(doc.removed_keys(), ["a"]);
+    ///
+    /// doc.mark_clean();
+    /// assert!(!doc.is_dirty());
+    /// ```
+    pub fn dirty_keys(&self) -> Vec<&str> {
+        self.sections.iter().filter(|s| s.is_dirty()).map(|s| s.key.as_str()).collect()
+    }
+
+    /// Keys of the sections removed since the document was loaded or last
+    /// marked clean
+    pub fn removed_keys(&self) -> &[String] {
+        &self.removed
+    }
+
+    /// Returns true if any section was added, changed or removed
+    pub fn is_dirty(&self) -> bool {
+        !self.removed.is_empty() || self.sections.iter().any(Section::is_dirty)
+    }
+
+    /// Forgets all recorded changes, typically after the document was saved
+    pub fn mark_clean(&mut self) {
+        self.removed.clear();
+        for section in &mut self.sections {
+            section.modified = None;
+        }
+    }
+
+    /// Adds a whole section, replacing any existing section with the same key
+    /// in place
+    pub(crate) fn insert_section(&mut self, section: Section) {
+        match self.position(&section.key) {
+            Some(index) => self.sections[index] = section,
+            None => self.push_section(section),
+        }
+    }
+
+    /// Appends a section whose key isn't in use
+    fn push_section(&mut self, section: Section) {
+        self.positions.insert(section.key.clone(), self.sections.len());
+        self.sections.push(section);
+    }
+
+    /// Keeps only the sections for which `keep` returns true
+    pub(crate) fn retain_sections<F: FnMut(&Section) -> bool>(&mut self, keep: F) {
+        self.sections.retain(keep);
+        self.positions.clear();
+        self.reindex_from(0);
+    }
+
+    /// Adds a whole section, appending its body to any existing section with
+    /// the same key
+    pub(crate) fn append_section(&mut self, section: Section) {
+        match self.position(&section.key) {
+            Some(index) => {
+                let existing = &mut self.sections[index];
+                existing.body.push_str(&section.body);
+                existing.end_line = None;
+            }
+            None => self.push_section(section),
+        }
+    }
+
+    /// Records `file` as the origin of every section
+    pub(crate) fn set_file(&mut self, file: &str) {
+        let file: Arc<str> = Arc::from(file);
+        for section in &mut self.sections {
+            section.set_file(file.clone());
+        }
+    }
+
+    /// Moves every recorded line number down by `offset`, for documents
+    /// parsed from a slice of a larger file
+    pub(crate) fn shift_line_numbers(&mut self, offset: usize) {
+        for section in &mut self.sections {
+            section.line_number = section.line_number.map(|n| n + offset);
+            section.end_line = section.end_line.map(|n| n + offset);
+        }
+    }
+
+    /// Stably sorts the sections with a comparison function
+    pub fn sort_sections_by<F>(&mut self, compare: F)
+    where F: FnMut(&Section, &Section) -> std::cmp::Ordering
+    {
+        self.sections.sort_by(compare);
+        self.reindex_from(0);
+    }
+
+    /// Returns the section named `key`, with its body, attributes, origin
+    /// and change state
+    pub fn section(&self, key: &str) -> Option<&Section> {
+        self.position(key).map(|index| &self.sections[index])
+    }
+
+    /// Sets an attribute on the section named `key`, returning its previous
+    /// value
+    ///
+    /// Fails if there is no such section, or if it is locked and locks aren't
+    /// overridden, so a section can be locked with this method but not
+    /// unlocked. Attribute changes mark the section dirty but aren't recorded
+    /// in the undo history.
+    pub fn set_attr<N: Into<String>, V: Into<String>>(&mut self, key: &str, name: N, value: V)
+        -> Result<Option<String>, Error>
+    {
+        self.require(key)?;
+        self.check_unlocked(key)?;
+        let index = self.position(key).unwrap();
+        let section = &mut self.sections[index];
+        let name = name.into();
+        let value = value.into();
+        let previous = section.set_attr(name.clone(), value.clone());
+        if previous.as_ref() != Some(&value) {
+            section.touch();
+            self.emit(&ChangeEvent::AttrChanged { key, name: &name, value: &value });
+        }
+        Ok(previous)
+    }
+
+    /// Removes the section named `key`, returning its body, or `None` if
+    /// there is no such section
+    ///
+    /// Fails, changing nothing, if the section is locked and locks aren't
+    /// overridden.
+    pub fn remove(&mut self, key: &str) -> Result<Option<String>, Error> {
+        self.check_unlocked(key)?;
+        Ok(self.remove_unchecked(key))
+    }
+
+    /// [`Document::remove`] without the lock check
+    pub(crate) fn remove_unchecked(&mut self, key: &str) -> Option<String> {
+        let index = self.position(key)?;
+        let body = self.remove_raw(index);
+        if self.history.is_some() {
+            self.record(Edit::Remove { key: key.to_string(), index, body: body.clone() });
+        }
+        Some(body)
+    }
+
+    /// Gives the section named `key` the key `to`, keeping its place, body
+    /// and attributes
+    ///
+    /// Fails if there is no such section, if it is locked, or if `to` is
+    /// already in use. With history enabled, the rename is journaled as a
+    /// removal and an insertion.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut doc: multitext::Document = vec![("a", "1\n"), ("b", "2\n")].into_iter().collect();
+    /// doc.rename("a", "c").unwrap();
+    /// assert_eq!(doc.iter().map(|s| s.key()).collect::<Vec<_>>(), ["c", "b"]);
+    /// assert!(doc.rename("c", "b").is_err());
+    /// ```
+    pub fn rename(&mut self, key: &str, to: &str) -> Result<(), Error> {
+        if let Some(existing) = self.section(to) {
+            return Err(existing.error(ErrorKind::DuplicateKey, format!("key \"{}\" is already in use", to)));
+        }
+        self.require(key)?;
+        self.check_unlocked(key)?;
+        let index = self.position(key).unwrap();
+        let attrs = self.sections[index].attrs.clone();
+        let body = self.remove_raw(index);
+        self.insert_raw(index, to.to_string(), body.clone());
+        self.sections[index].attrs = attrs;
+        if self.history.is_some() {
+            self.record(Edit::Remove { key: key.to_string(), index, body: body.clone() });
+            self.record(Edit::Insert { key: to.to_string(), index, body });
+        }
+        Ok(())
+    }
+
+    /// Inserts a new, dirty section at `index` without recording history
+    pub(crate) fn insert_raw(&mut self, index: usize, key: String, body: String) {
+        self.removed.retain(|k| *k != key);
+        self.emit(&ChangeEvent::Inserted { key: &key, body: &body });
+        let mut section = Section::new(key, body);
+        section.touch();
+        self.sections.insert(index, section);
+        self.reindex_from(index);
+    }
+
+    /// Removes the section at `index` without recording history
+    pub(crate) fn remove_raw(&mut self, index: usize) -> String {
+        let section = self.sections.remove(index);
+        self.positions.remove(&section.key);
+        self.reindex_from(index);
+        self.emit(&ChangeEvent::Removed { key: &section.key, body: &section.body });
+        self.removed.push(section.key);
+        section.body
+    }
+
+    /// Iterates over the sections in document order
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { inner: self.sections.iter() }
+    }
+
+    /// Consumes the document, yielding each section by value in document order
+    pub fn into_sections(self) -> IntoSections {
+        IntoSections { inner: self.sections.into_iter() }
+    }
+
+    /// Applies `f` to every section in parallel and rebuilds the document in
+    /// the original order
+    ///
+    /// Sections are split into contiguous chunks, one per available core, and
+    /// processed on scoped threads. The marker, preamble and settings of the
+    /// document are kept, and so is whatever `f` keeps of each section, such
+    /// as its attributes and origin. Sections `f` changes are marked dirty,
+    /// but the edits aren't recorded in the undo history or sent to
+    /// observers. If `f` renames two sections to the same key, the later
+    /// one's body wins, as with [`Document::insert`].
+    ///
+    /// # Examples
+    /// ```
+    /// let doc: multitext::Document = vec![("a", "  x  "), ("b", " y ")].into_iter().collect();
+    /// let doc = doc.par_map_sections(|s| {
+    ///     let (key, body) = s.into_parts();
+    ///     multitext::Section::new(key, body.trim())
+    /// });
+    /// assert_eq!(&doc["a"], "x");
+    /// assert_eq!(&doc["b"], "y");
+    /// ```
+    pub fn par_map_sections<F>(mut self, f: F) -> Document
+    where F: Fn(Section) -> Section + Sync
+    {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = self.sections.len().div_ceil(threads).max(1);
+
+        let mut chunks = Vec::new();
+        let mut sections = std::mem::take(&mut self.sections).into_iter();
+        self.positions.clear();
+        loop {
+            let chunk: Vec<Section> = sections.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            chunks.push(chunk);
+        }
+
+        // Hashing before and after spots a change without copying the body
+        let fingerprint = |section: &Section| {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            section.hash(&mut hasher);
+            hasher.finish()
+        };
+        let map_one = |section: Section| {
+            let key = section.key.clone();
+            let before = fingerprint(&section);
+            let mut mapped = f(section);
+            if fingerprint(&mapped) != before {
+                mapped.touch();
+            }
+            (key, mapped)
+        };
+        let map_one = &map_one;
+        let mapped: Vec<Vec<(String, Section)>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = chunks.into_iter()
+                .map(|chunk| scope.spawn(move || chunk.into_iter().map(map_one).collect()))
+                .collect();
+            handles.into_iter()
+                .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        });
+
+        for (old_key, section) in mapped.into_iter().flatten() {
+            if section.key != old_key {
+                self.removed.push(old_key);
+            }
+            self.insert_section(section);
+        }
+        let removed = std::mem::take(&mut self.removed);
+        self.removed = removed.into_iter().filter(|key| !self.contains_key(key)).collect();
+        self
+    }
+
+    /// Splits the document into consecutive pieces, starting a new piece at
+    /// each section named in `keys`
+    ///
+    /// Keys that don't exist are ignored, and every section lands in exactly
+    /// one piece, so concatenating the pieces restores the document.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::Document;
+    ///
+    /// let doc: Document = vec![("a", ""), ("b", ""), ("c", ""), ("d", "")].into_iter().collect();
+    /// let pieces = doc.split_at_keys(&["c"]);
+    /// assert_eq!(pieces.len(), 2);
+    /// assert!(pieces[0].contains_key("b"));
+    /// assert!(pieces[1].contains_key("d"));
+    /// ```
+    pub fn split_at_keys(&self, keys: &[&str]) -> Vec<Document> {
+        let mut pieces: Vec<Document> = Vec::new();
+        for section in self.iter() {
+            match pieces.last_mut() {
+                Some(piece) if !keys.contains(&section.key()) => piece.push_section(section.clone()),
+                _ => pieces.push(Document {
+                    sections: vec![section.clone()],
+                    positions: HashMap::from([(section.key.clone(), 0)]),
+                    marker: self.marker.clone(),
+                    preamble: None,
+                    removed: Vec::new(),
+                    history: None,
+                    observers: Observers::default(),
+                    override_locks: self.override_locks,
+                    keep_versions: self.keep_versions,
+                }),
+            }
+        }
+        pieces
+    }
+
+    /// Joins documents in order, resolving keys that appear in more than one
+    /// according to `strategy`
+    ///
+    /// Sections keep the position of their first appearance.
+    pub fn concat_documents<I>(docs: I, strategy: DuplicateKeys) -> Result<Document, Error>
+    where I: IntoIterator<Item = Document>
+    {
+        let mut result = Document::new();
+        for doc in docs {
+            for section in doc.into_sections() {
+                result.merge_section(section, strategy)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Adds `section`, resolving a clash with an existing key by `strategy`
+    pub(crate) fn merge_section(&mut self, section: Section, strategy: DuplicateKeys) -> Result<(), Error> {
+        let index = match self.position(&section.key) {
+            Some(index) => index,
+            None => {
+                self.emit(&ChangeEvent::Inserted { key: &section.key, body: &section.body });
+                self.push_section(section);
+                return Ok(());
+            }
+        };
+        let existing = &mut self.sections[index];
+        let old_body = match strategy {
+            DuplicateKeys::Error => return Err(section.error(ErrorKind::DuplicateKey, format!("duplicate key \"{}\"", section.key))),
+            DuplicateKeys::KeepFirst => return Ok(()),
+            DuplicateKeys::KeepLast => {
+                existing.line_number = section.line_number;
+                existing.end_line = section.end_line;
+                existing.file = section.file;
+                std::mem::replace(&mut existing.body, section.body)
+            }
+            DuplicateKeys::Concatenate => {
+                let old_body = existing.body.clone();
+                existing.body.push_str(&section.body);
+                old_body
+            }
+        };
+        if existing.body != old_body {
+            existing.touch();
+            if !self.observers.is_empty() {
+                let existing = &self.sections[index];
+                let (key, new_body) = (existing.key.clone(), existing.body.clone());
+                self.emit(&ChangeEvent::Modified { key: &key, old_body: &old_body, new_body: &new_body });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports the heap memory owned by the document
+    ///
+    /// Capacities are counted rather than lengths, since that is what the
+    /// allocator actually holds. The size of the `Document` value itself is
+    /// not included.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        MemoryFootprint {
+            keys: self.sections.iter().map(|s| s.key.capacity()).sum(),
+            bodies: self.sections.iter().map(|s| s.body.capacity()).sum(),
+            metadata: self.sections.capacity() * std::mem::size_of::<Section>(),
+        }
+    }
+
+    /// Converts into an unordered [`Map`], dropping the order, attributes and
+    /// line numbers of the sections; `Map::from(doc)` does the same
+    pub fn into_map(self) -> Map {
+        self.into_sections().map(Section::into_parts).collect()
+    }
+
+    /// Converts into a [`Map`] with an extra [`ORDER_KEY`] entry listing the
+    /// keys one per line in document order, so that [`Document::from_map`]
+    /// can restore the order after the map passes through containers that
+    /// lose it, such as JSON objects
+    ///
+    /// Fails if the document already has a section named [`ORDER_KEY`], or a
+    /// key with a line break, which the list couldn't hold.
+    ///
+    /// # Examples
+    /// ```
+    /// let doc = multitext::parse_document(["@@ multitext header", "@@ b", "2", "@@ a", "1"].iter()).unwrap();
+    /// let map = doc.clone().into_map_with_order().unwrap();
+    /// assert_eq!(map[multitext::ORDER_KEY], "multitext header\nb\na\n");
+    /// assert_eq!(multitext::Document::from_map(map, "@@").unwrap(), doc);
+    /// ```
+    pub fn into_map_with_order(self) -> Result<Map, Error> {
+        let mut order = String::new();
+        for section in self.iter() {
+            if section.key() == ORDER_KEY || section.key().contains(['\n', '\r']) {
+                let message = format!("can't record the order of section {:?}", section.key());
+                return Err(Error::new(ErrorKind::InvalidKey, message).with_line(section.line_number));
+            }
+            order.push_str(section.key());
+            order.push('\n');
+        }
+        let mut map = self.into_map();
+        map.insert(ORDER_KEY.to_string(), order);
+        Ok(map)
+    }
+
+    /// Describes the document as a JSON object, the format external tools such
+    /// as `multitext` CLI plugins receive
+    ///
+    /// The object has the `marker` (or `null`) and a `sections` array in
+    /// document order. Each section has its `key`, `body`, the `line` its body
+    /// starts on (or `null`) and its `attrs` as an object.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ vertex stage=1", "void main() {}"];
+    /// let doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+    /// assert_eq!(doc.to_json(), concat!(
+    ///     r#"{"marker":"@@","sections":["#,
+    ///     r#"{"key":"multitext header","body":"","line":2,"attrs":{}},"#,
+    ///     r#"{"key":"vertex","body":"void main() {}\n","line":3,"attrs":{"stage":"1"}}]}"#,
+    /// ));
+    /// ```
+    pub fn to_json(&self) -> String {
+        let sections: Vec<String> = self.sections.iter().map(|section| {
+            let attrs: Vec<String> = section.attrs()
+                .map(|(name, value)| format!("{}:{}", json::string(name), json::string(value)))
+                .collect();
+            format!(
+                "{{\"key\":{},\"body\":{},\"line\":{},\"attrs\":{{{}}}}}",
+                json::string(section.key()),
+                json::string(section.body()),
+                json::optional_number(section.line_number()),
+                attrs.join(","),
+            )
+        }).collect();
+        format!(
+            "{{\"marker\":{},\"sections\":[{}]}}",
+            json::optional_string(self.marker()),
+            sections.join(","),
+        )
+    }
+
+    /// Builds a document from a [`Map`] that will be written with `marker`,
+    /// the "multitext header" section first and the rest sorted by key
+    ///
+    /// If the map has an [`ORDER_KEY`] entry, as from
+    /// [`Document::into_map_with_order`], the keys it lists come first in its
+    /// order instead, and the entry itself is dropped. Listed keys missing
+    /// from the map are ignored.
+    ///
+    /// Fails as [`Document::retarget_marker`] does, if the marker is unusable.
+    ///
+    /// # Examples
+    /// ```
+    /// let map = multitext::parse_lines(["## multitext header", "## b", "2", "## a", "1"].iter()).unwrap();
+    /// let doc = multitext::Document::from_map(map.clone(), "##").unwrap();
+    /// let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+    /// assert_eq!(keys, ["multitext header", "a", "b"]);
+    /// assert_eq!(multitext::Map::from(doc), map);
+    ///
+    /// assert!(multitext::Document::from_map(multitext::Map::new(), "").is_err());
+    /// ```
+    pub fn from_map(mut map: Map, marker: &str) -> Result<Document, Error> {
+        let order = map.remove(ORDER_KEY).unwrap_or_default();
+        let mut positions = HashMap::new();
+        for (index, key) in order.lines().enumerate() {
+            positions.entry(key).or_insert(index);
+        }
+        let position = |key: &str| positions.get(key).copied().unwrap_or(usize::MAX);
+        let mut entries: Vec<(String, String)> = map.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| {
+            (position(a), a != "multitext header", a).cmp(&(position(b), b != "multitext header", b))
+        });
+        let mut doc: Document = entries.into_iter().collect();
+        doc.retarget_marker(marker)?;
+        Ok(doc)
+    }
+}
+
+/// The [`Map`] entry that [`Document::into_map_with_order`] records section
+/// order in
+pub const ORDER_KEY: &str = "__order";
+
+impl From<Document> for Map {
+    fn from(doc: Document) -> Map {
+        doc.into_map()
+    }
+}
+
+impl std::ops::Index<&str> for Document {
+    type Output = str;
+
+    fn index(&self, key: &str) -> &str {
+        self.get(key).unwrap_or_else(|| panic!("no section named {:?}", key))
+    }
+}
+
+impl<K: Into<String>, B: Into<String>> std::iter::FromIterator<(K, B)> for Document {
+    fn from_iter<T: IntoIterator<Item = (K, B)>>(iter: T) -> Document {
+        let mut doc = Document::new();
+        doc.extend(iter);
+        doc
+    }
+}
+
+/// Sets each body as [`Document::insert`] would, leaving locked sections as
+/// they are unless locks are overridden
+impl<K: Into<String>, B: Into<String>> Extend<(K, B)> for Document {
+    fn extend<T: IntoIterator<Item = (K, B)>>(&mut self, iter: T) {
+        for (key, body) in iter {
+            let _ = self.insert(key, body);
+        }
+    }
+}
+
+impl IntoIterator for Document {
+    type Item = Section;
+    type IntoIter = IntoSections;
+
+    fn into_iter(self) -> IntoSections {
+        self.into_sections()
+    }
+}
+
+impl<'a> IntoIterator for &'a Document {
+    type Item = &'a Section;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Borrowing iterator over the sections of a [`Document`]
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    inner: std::slice::Iter<'a, Section>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Section;
+
+    fn next(&mut self) -> Option<&'a Section> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<&'a Section> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+/// Owning iterator over the sections of a [`Document`]
+#[derive(Debug)]
+pub struct IntoSections {
+    inner: std::vec::IntoIter<Section>,
+}
+
+impl Iterator for IntoSections {
+    type Item = Section;
+
+    fn next(&mut self) -> Option<Section> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for IntoSections {
+    fn next_back(&mut self) -> Option<Section> {
+        self.inner.next_back()
+    }
+}
+
+impl ExactSizeIterator for IntoSections {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::options::ParseOptions;
+
+    #[test]
+    fn test_par_map_sections_keeps_document_state() {
+        let lines = ["## multitext header", "## a locked=true", "1", "## b stage=2", "2"];
+        let doc = crate::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+        let doc = doc.par_map_sections(|s| if s.key() == "b" { s.with_attr("stage", "3") } else { s });
+
+        assert_eq!(doc.marker(), Some("##"));
+        assert!(doc.section("a").unwrap().is_locked());
+        assert_eq!(doc.section("a").unwrap().line_number(), Some(3));
+        assert_eq!(doc.section("b").unwrap().attr("stage"), Some("3"));
+        assert_eq!(doc.dirty_keys(), ["b"]);
+
+        let doc = doc.par_map_sections(|s| {
+            let (key, body) = s.into_parts();
+            Section::new(if key == "a" { "c".to_string() } else { key }, body)
+        });
+        assert_eq!(doc.iter().map(Section::key).collect::<Vec<_>>(), ["multitext header", "c", "b"]);
+        assert_eq!(doc.removed_keys(), ["a"]);
+    }
+
+    #[test]
+    fn test_positions_follow_edits() {
+        let mut doc: Document = vec![("a", "1\n"), ("b", "2\n"), ("c", "3\n")].into_iter().collect();
+        doc.remove("a").unwrap();
+        doc.rename("c", "d").unwrap();
+        doc.sort_sections_by(|x, y| y.key().cmp(x.key()));
+        doc.insert("e", "5\n").unwrap();
+        for (index, section) in doc.iter().enumerate() {
+            assert_eq!(doc.position(section.key()), Some(index));
+        }
+        assert_eq!(doc.iter().map(Section::key).collect::<Vec<_>>(), ["d", "b", "e"]);
+        assert!(!doc.contains_key("a") && !doc.contains_key("c"));
+        assert_eq!(doc.get("b"), Some("2\n"));
+    }
+
+    #[test]
+    fn test_locks_refuse_edits() {
+        let lines = ["@@ multitext header", "@@ a locked=true", "1"];
+        let mut doc = crate::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+        assert_eq!(doc.insert("a", "2\n").unwrap_err().kind(), ErrorKind::Locked);
+        assert_eq!(doc.remove("a").unwrap_err().kind(), ErrorKind::Locked);
+        assert_eq!(doc.set_attr("a", "locked", "false").unwrap_err().kind(), ErrorKind::Locked);
+        assert_eq!(doc.set_attr("b", "locked", "true").unwrap_err().kind(), ErrorKind::MissingSection);
+        doc.set_header(&crate::header::Header::default()).unwrap();
+        assert_eq!(&doc["a"], "1\n");
+
+        doc.override_locks(true);
+        assert_eq!(doc.insert("a", "3\n").unwrap().as_deref(), Some("1\n"));
+        assert_eq!(doc.remove("a").unwrap().as_deref(), Some("3\n"));
+        assert_eq!(doc.remove("a").unwrap(), None);
+    }
+}
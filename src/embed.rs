@@ -0,0 +1,81 @@
+//! Bundles carried inside the comments of another language's source file
+//!
+//! A bundle can ride along in a script or source file by prefixing each of its
+//! lines with the host language's line comment:
+//!
+//! ```text
+//! #!/usr/bin/env python3
+//! # @@ multitext header
+//! # @@ config
+//! # verbose = true
+//! print("hello")
+//! ```
+//!
+//! [`extract`] finds the first comment line containing "multitext header" and
+//! parses the unbroken run of comment lines starting there; [`embed`] produces
+//! such a block from a document.
+
+use crate::{Document, Error, ErrorKind};
+use crate::writer::write_document;
+
+/// Parses the bundle embedded in `source` behind the comment `prefix`
+///
+/// The prefix may be indented, and one space after it is removed along with
+/// it. Section line numbers refer to lines of `source`.
+///
+/// # Examples
+/// ```
+/// let source = "fn main() {}\n\
+///               // ## multitext header\n\
+///               // ## greeting\n\
+///               // hello\n\
+///               //\n\
+///               // world\n\
+///               const X: u32 = 1;\n";
+/// let doc = multitext::embed::extract(source, "//").unwrap();
+/// assert_eq!(&doc["greeting"], "hello\n\nworld\n");
+/// assert_eq!(doc.section("greeting").unwrap().line_number(), Some(4));
+/// ```
+pub fn extract(source: &str, prefix: &str) -> Result<Document, Error> {
+    let strip = |line: &str| -> Option<String> {
+        let rest = line.trim_start().strip_prefix(prefix)?;
+        Some(rest.strip_prefix(' ').unwrap_or(rest).to_string())
+    };
+
+    let start = source.lines()
+        .position(|line| strip(line).is_some_and(|l| l.contains("multitext header")))
+        .ok_or_else(|| {
+            Error::new(ErrorKind::MissingHeader, format!("no embedded multitext header behind \"{}\"", prefix))
+        })?;
+
+    let lines = source.lines().skip(start).map_while(strip);
+    let mut doc = crate::parse_document(lines)?;
+    doc.shift_line_numbers(start);
+    Ok(doc)
+}
+
+/// Writes `doc` with `marker`, putting `prefix` and a space in front of every
+/// line (just `prefix` on blank lines)
+///
+/// # Examples
+/// ```
+/// let doc: multitext::Document = vec![("config", "verbose = true\n")].into_iter().collect();
+/// let block = multitext::embed::embed(&doc, "@@", "#").unwrap();
+/// assert_eq!(block, "# @@ multitext header\n# @@ config\n# verbose = true\n");
+/// assert_eq!(&multitext::embed::extract(&block, "#").unwrap()["config"], "verbose = true\n");
+/// ```
+pub fn embed(doc: &Document, marker: &str, prefix: &str) -> std::io::Result<String> {
+    let text = String::from_utf8(write_document(Vec::new(), doc, marker)?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        out.push_str(prefix);
+        if !line.is_empty() {
+            out.push(' ');
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
@@ -0,0 +1,130 @@
+//! Callbacks run when a document is edited
+//!
+//! Hot-reload systems and editors can register an observer with
+//! [`Document::on_change`] instead of diffing documents to find out what
+//! changed. Observers see every insertion, body change, removal and attribute
+//! change, including those made by undo and redo. Reordering sections is not
+//! reported.
+
+use crate::Document;
+
+/// One change to a document, as seen by an observer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeEvent<'a> {
+    Inserted { key: &'a str, body: &'a str },
+    Modified { key: &'a str, old_body: &'a str, new_body: &'a str },
+    Removed { key: &'a str, body: &'a str },
+    AttrChanged { key: &'a str, name: &'a str, value: &'a str },
+}
+
+impl<'a> ChangeEvent<'a> {
+    /// The key of the section that changed
+    pub fn key(&self) -> &'a str {
+        match *self {
+            ChangeEvent::Inserted { key, .. }
+            | ChangeEvent::Modified { key, .. }
+            | ChangeEvent::Removed { key, .. }
+            | ChangeEvent::AttrChanged { key, .. } => key,
+        }
+    }
+}
+
+/// Identifies an observer for [`Document::remove_observer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+type Observer = Box<dyn FnMut(&ChangeEvent) + Send + Sync>;
+
+/// The observers registered on one document
+///
+/// Cloning a document doesn't clone its observers: the clone starts with none.
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: u64,
+    list: Vec<(ObserverId, Observer)>,
+}
+
+impl Observers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Observers {
+        Observers::default()
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Observers({})", self.list.len())
+    }
+}
+
+impl Document {
+    /// Registers `observer` to be called after every change to the document
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::events::ChangeEvent;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let changed = Arc::new(Mutex::new(Vec::new()));
+    /// let mut doc = multitext::Document::new();
+    /// let log = changed.clone();
+    /// doc.on_change(move |event: &ChangeEvent| log.lock().unwrap().push(event.key().to_string()));
+    ///
+    /// doc.insert("shader", "v1").unwrap();
+    /// doc.insert("shader", "v2").unwrap();
+    /// doc.remove("shader").unwrap();
+    /// assert_eq!(*changed.lock().unwrap(), ["shader", "shader", "shader"]);
+    /// ```
+    pub fn on_change<F>(&mut self, observer: F) -> ObserverId
+    where F: FnMut(&ChangeEvent) + Send + Sync + 'static
+    {
+        let id = ObserverId(self.observers.next_id);
+        self.observers.next_id += 1;
+        self.observers.list.push((id, Box::new(observer)));
+        id
+    }
+
+    /// Unregisters an observer, returning false if it wasn't registered
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        let before = self.observers.list.len();
+        self.observers.list.retain(|(i, _)| *i != id);
+        self.observers.list.len() != before
+    }
+
+    pub(crate) fn emit(&mut self, event: &ChangeEvent) {
+        for (_, observer) in &mut self.observers.list {
+            observer(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_undo_and_removed_observers() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut doc = Document::new();
+        doc.enable_history();
+        let log = seen.clone();
+        let id = doc.on_change(move |event| log.lock().unwrap().push(format!("{:?}", event)));
+
+        doc.insert("a", "1").unwrap();
+        doc.undo();
+        assert!(doc.remove_observer(id));
+        doc.insert("b", "2").unwrap();
+        assert!(!doc.clone().remove_observer(id));
+
+        assert_eq!(*seen.lock().unwrap(), [
+            "Inserted { key: \"a\", body: \"1\" }",
+            "Removed { key: \"a\", body: \"1\" }",
+        ]);
+    }
+}
@@ -0,0 +1,138 @@
+//! A C interface that hands sections to a callback one at a time
+//!
+//! Sections are passed as pointer and length pairs that are only valid for
+//! the duration of the callback, so a large bundle never has to exist as a
+//! whole on either side of the boundary. Build a shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`, and declare
+//! the functions in C as:
+//!
+//! ```c
+//! typedef int (*multitext_section_cb)(void *user_data,
+//!                                     const char *key, size_t key_len,
+//!                                     const char *body, size_t body_len);
+//!
+//! int multitext_parse_streaming(const char *text, size_t len,
+//!                               multitext_section_cb callback, void *user_data);
+//! int multitext_parse_file_streaming(const char *path,
+//!                                    multitext_section_cb callback, void *user_data);
+//! ```
+//!
+//! Keys and bodies are UTF-8 and not NUL-terminated. Both functions return
+//! [`MULTITEXT_OK`] once every section was delivered, the callback's value if
+//! it returned nonzero to stop early, or one of the negative error codes.
+
+use crate::options::{LineEndings, ParseOptions};
+use crate::diagnostics::NoopSink;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+
+/// Called once per section, in file order; returning nonzero stops parsing
+pub type SectionCallback = extern "C" fn(
+    user_data: *mut c_void,
+    key: *const c_char,
+    key_len: usize,
+    body: *const c_char,
+    body_len: usize,
+) -> c_int;
+
+pub const MULTITEXT_OK: c_int = 0;
+/// A null pointer, or text or a path that isn't UTF-8
+pub const MULTITEXT_INVALID_ARGUMENT: c_int = -1;
+/// The file couldn't be opened
+pub const MULTITEXT_IO_ERROR: c_int = -2;
+/// The input isn't a multitext bundle
+pub const MULTITEXT_PARSE_ERROR: c_int = -3;
+
+fn stream<I>(lines: I, callback: SectionCallback, user_data: *mut c_void) -> c_int
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    let options = ParseOptions::new().line_endings(LineEndings::Normalize);
+    let mut status = MULTITEXT_OK;
+    let result = crate::parse::parse_sections(lines, &options, &mut NoopSink, |section| {
+        if status == MULTITEXT_OK {
+            let (key, body) = (section.key(), section.body());
+            status = callback(user_data, key.as_ptr().cast(), key.len(), body.as_ptr().cast(), body.len());
+        }
+    });
+    match result {
+        Ok(_) => status,
+        Err(_) => MULTITEXT_PARSE_ERROR,
+    }
+}
+
+/// Parses the `len` bytes at `text`, calling `callback` for each section
+///
+/// # Safety
+/// `text` must point to `len` readable bytes, and `callback` must be safe to
+/// call with `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn multitext_parse_streaming(
+    text: *const c_char,
+    len: usize,
+    callback: SectionCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    if text.is_null() {
+        return MULTITEXT_INVALID_ARGUMENT;
+    }
+    let bytes = std::slice::from_raw_parts(text.cast::<u8>(), len);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => stream(text.lines(), callback, user_data),
+        Err(_) => MULTITEXT_INVALID_ARGUMENT,
+    }
+}
+
+/// Reads the file at `path` line by line, calling `callback` for each
+/// section as soon as it is complete
+///
+/// # Safety
+/// `path` must be a NUL-terminated string, and `callback` must be safe to
+/// call with `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn multitext_parse_file_streaming(
+    path: *const c_char,
+    callback: SectionCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    if path.is_null() {
+        return MULTITEXT_INVALID_ARGUMENT;
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return MULTITEXT_INVALID_ARGUMENT,
+    };
+    match std::fs::File::open(path) {
+        Ok(file) => stream(crate::read_lines(std::io::BufReader::new(file)), callback, user_data),
+        Err(_) => MULTITEXT_IO_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern "C" fn collect(user_data: *mut c_void, key: *const c_char, key_len: usize, body: *const c_char, body_len: usize)
+        -> c_int
+    {
+        let sections = unsafe { &mut *user_data.cast::<Vec<(String, String)>>() };
+        let text = |p: *const c_char, len| unsafe {
+            String::from_utf8(std::slice::from_raw_parts(p.cast::<u8>(), len).to_vec()).unwrap()
+        };
+        sections.push((text(key, key_len), text(body, body_len)));
+        if sections.len() == 2 { 7 } else { 0 }
+    }
+
+    #[test]
+    fn test_streaming() {
+        let mut sections: Vec<(String, String)> = Vec::new();
+        let text = "@@ multitext header\r\n@@ a\r\none\r\n@@ b\r\ntwo\r\n@@ c\r\n";
+        let user_data = (&mut sections as *mut Vec<(String, String)>).cast();
+        let status = unsafe { multitext_parse_streaming(text.as_ptr().cast(), text.len(), collect, user_data) };
+        assert_eq!(status, 7);
+        assert_eq!(sections, [("multitext header".to_string(), String::new()), ("a".to_string(), "one\n".to_string())]);
+
+        let text = "no header";
+        let status = unsafe { multitext_parse_streaming(text.as_ptr().cast(), text.len(), collect, user_data) };
+        assert_eq!(status, MULTITEXT_PARSE_ERROR);
+    }
+}
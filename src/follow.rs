@@ -0,0 +1,183 @@
+//! Reading a multitext file while another process appends to it
+//!
+//! With one process appending sections, a multitext file works as a simple
+//! structured journal. [`follow_file`] reads such a file as it grows. A section
+//! is only handed out once the marker line of the next section has been
+//! written after it, so a reader never sees a body that is still being
+//! written; a partly written line is held back until its newline arrives.
+
+use crate::{Error, ErrorKind, Section};
+use std::fs::File;
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Follows a growing multitext file; see [`follow_file`]
+///
+/// As an iterator, `Follow` blocks until the next section is complete and
+/// never ends on its own. Use [`Follow::poll`] to check for new sections
+/// without blocking.
+#[derive(Debug)]
+pub struct Follow {
+    path: PathBuf,
+    file: File,
+    position: u64,
+    partial: Vec<u8>,
+    line_number: usize,
+    marker: Option<String>,
+    current: Option<Section>,
+    ready: VecDeque<Section>,
+    poll_interval: Duration,
+}
+
+/// Opens `path` for following from its start
+///
+/// # Examples
+/// ```no_run
+/// for section in multitext::follow::follow_file("events.mt")? {
+///     let section = section?;
+///     println!("{}: {}", section.key(), section.body().trim_end());
+/// }
+/// # Ok::<(), multitext::Error>(())
+/// ```
+pub fn follow_file<P: AsRef<Path>>(path: P) -> Result<Follow, Error> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| Error::from(e).with_path(path))?;
+    Ok(Follow {
+        path: path.to_path_buf(),
+        file,
+        position: 0,
+        partial: Vec::new(),
+        line_number: 0,
+        marker: None,
+        current: None,
+        ready: VecDeque::new(),
+        poll_interval: Duration::from_millis(250),
+    })
+}
+
+impl Follow {
+    /// How long the iterator sleeps between checks for new data
+    pub fn poll_interval(mut self, interval: Duration) -> Follow {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Reads whatever has been appended and returns the sections it completed
+    ///
+    /// Fails if the file shrinks, since the sections already handed out no
+    /// longer describe it.
+    pub fn poll(&mut self) -> Result<Vec<Section>, Error> {
+        self.read_appended()?;
+        Ok(self.ready.drain(..).collect())
+    }
+
+    fn read_appended(&mut self) -> Result<(), Error> {
+        let len = self.file.metadata().map_err(|e| Error::from(e).with_path(&self.path))?.len();
+        if len < self.position {
+            return Err(Error::new(ErrorKind::Io, "file was truncated while being followed").with_path(&self.path));
+        }
+
+        let mut appended = Vec::new();
+        self.file.seek(SeekFrom::Start(self.position))
+            .and_then(|_| (&mut self.file).take(len - self.position).read_to_end(&mut appended))
+            .map_err(|e| Error::from(e).with_path(&self.path))?;
+        self.position += appended.len() as u64;
+        self.partial.extend_from_slice(&appended);
+
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let raw: Vec<u8> = self.partial.drain(..=end).collect();
+            self.line_number += 1;
+            let line = String::from_utf8(raw).map_err(|_| {
+                Error::new(ErrorKind::InvalidUtf8, "line is not valid UTF-8")
+                    .with_line(self.line_number)
+                    .with_path(&self.path)
+            })?;
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+            if let Some(section) = self.push_line(line) {
+                self.ready.push_back(section);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the last section, which can't be known to be complete because
+    /// nothing has been written after it
+    pub fn take_current(&mut self) -> Option<Section> {
+        self.current.take()
+    }
+
+    fn push_line(&mut self, line: &str) -> Option<Section> {
+        let marker = match &self.marker {
+            Some(marker) => marker,
+            None => {
+                if let Some(index) = line.find("multitext header") {
+                    self.marker = Some(line[..index].trim_end().to_string());
+                    self.current = Some(Section::at_line("multitext header", self.line_number + 1));
+                }
+                return None;
+            }
+        };
+        match line.strip_prefix(marker.as_str()) {
+            Some(key) => {
+                let next = Section::at_line(key.trim(), self.line_number + 1);
+                self.current.replace(next)
+            }
+            None => {
+                if let Some(section) = &mut self.current {
+                    section.body.push_str(crate::parse::unescape(line, marker));
+                    section.body.push('\n');
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Iterator for Follow {
+    type Item = Result<Section, Error>;
+
+    fn next(&mut self) -> Option<Result<Section, Error>> {
+        loop {
+            if let Some(section) = self.ready.pop_front() {
+                return Some(Ok(section));
+            }
+            if let Err(e) = self.read_appended() {
+                return Some(Err(e));
+            }
+            if self.ready.is_empty() {
+                std::thread::sleep(self.poll_interval);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_follow_only_yields_complete_sections() {
+        let dir = crate::testing::TempDir::new("follow").unwrap();
+        let path = dir.join("follow.mt");
+        let mut writer = File::create(&path).unwrap();
+        writer.write_all(b"@ multitext header\n@ first\none\n@ sec").unwrap();
+
+        let mut follow = follow_file(&path).unwrap();
+        let keys = |sections: Vec<Section>| -> Vec<String> { sections.iter().map(|s| s.key().to_string()).collect() };
+        assert_eq!(keys(follow.poll().unwrap()), ["multitext header"]);
+
+        writer.write_all(b"ond\ntwo\n").unwrap();
+        let first = follow.next().unwrap().unwrap();
+        assert_eq!((first.key(), first.body(), first.line_number()), ("first", "one\n", Some(3)));
+
+        writer.write_all(b"@ third\n").unwrap();
+        assert_eq!(keys(follow.poll().unwrap()), ["second"]);
+        assert_eq!(follow.take_current().unwrap().key(), "third");
+
+        std::fs::write(&path, "").unwrap();
+        assert!(follow.poll().is_err());
+    }
+}
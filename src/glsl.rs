@@ -0,0 +1,300 @@
+//! Helpers for documents holding GLSL shader stages
+//!
+//! GLSL has no include mechanism of its own, so sections may pull in other
+//! sections of the same document with `#include "key"`. A section that
+//! includes others gets a `#line` directive at the start, after any
+//! `#version` line, and included text is surrounded by more of them. Their
+//! source-string number is the index of the section the text came from, so
+//! compiler diagnostics still point at the right section and line.
+//!
+//! [`compile_check`] runs every stage section through a user-supplied
+//! [`Compiler`] (typically a thin wrapper around shaderc or glslang) and maps
+//! the reported positions back onto lines of the multitext file. When sections
+//! are handed to a driver directly instead, [`inject_line_directives`] makes
+//! its messages point at lines of the multitext file.
+
+use crate::{Document, Error, ErrorKind, Section};
+use crate::shader::{Compiler, CompileDiagnostic, Stage};
+
+/// Compiles every section whose key names a [`Stage`] and returns all
+/// reported messages, remapped to lines of the original file
+///
+/// Includes are resolved before compiling, so messages inside included text
+/// are attributed to the section that text came from.
+pub fn compile_check<C: Compiler>(doc: &Document, compiler: &mut C)
+    -> Result<Vec<CompileDiagnostic>, Error>
+{
+    let mut diagnostics = Vec::new();
+    for section in doc.iter() {
+        let stage = match Stage::from_key(section.key()) {
+            Some(stage) => stage,
+            None => continue,
+        };
+
+        let mut source = String::new();
+        let mut strings = Vec::new();
+        resolve_into(doc, section.key(), &mut Vec::new(), &mut strings, &mut source)?;
+        for message in compiler.compile(stage, &source) {
+            let origin = message.source_string
+                .filter(|index| strings.contains(index))
+                .and_then(|index| doc.sections.get(index))
+                .unwrap_or(section);
+            diagnostics.push(CompileDiagnostic::remap(origin, message));
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// Returns the body of section `key` with every `#include "other key"` line
+/// replaced by the (recursively resolved) body of that section
+///
+/// # Examples
+/// ```
+/// let lines = [
+///     "@@ multitext header",
+///     "@@ common",
+///     "float square(float x) { return x * x; }",
+///     "@@ fragment shader",
+///     "#version 430 core",
+///     "#include \"common\"",
+///     "void main() {}",
+/// ];
+/// let doc = multitext::parse_document(lines.iter()).unwrap();
+/// let source = multitext::glsl::resolve_includes(&doc, "fragment shader").unwrap();
+/// assert_eq!(source, "#version 430 core\n\
+///                     #line 2 2\n\
+///                     #line 1 1\n\
+///                     float square(float x) { return x * x; }\n\
+///                     #line 3 2\n\
+///                     void main() {}\n");
+/// ```
+pub fn resolve_includes(doc: &Document, key: &str) -> Result<String, Error> {
+    let mut out = String::new();
+    resolve_into(doc, key, &mut Vec::new(), &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+/// Resolves includes in every section except the multitext header
+///
+/// The sections keep their attributes and origins, and the document its
+/// marker; sections whose body changed are marked dirty.
+pub fn resolve_all_includes(doc: &Document) -> Result<Document, Error> {
+    let mut out = doc.clone();
+    for section in &mut out.sections {
+        if section.key() != "multitext header" {
+            let resolved = resolve_includes(doc, section.key())?;
+            replace_body(section, resolved);
+        }
+    }
+    Ok(out)
+}
+
+/// Prefixes each section with a `#line` directive giving the line of the
+/// multitext file its body starts on, so a driver compiling the section on
+/// its own reports lines of the whole file
+///
+/// A `#version` line has to come first, so the directive goes after it when
+/// there is one. With a `filename` the directive names it, as in
+/// `#line 12 "shaders.mt"`, which drivers only accept with the
+/// `GL_GOOGLE_cpp_style_line_directive` extension enabled. The multitext
+/// header, and sections without a position in the file (see
+/// [`Section::origin_at`](crate::Section::origin_at)), are left as they are,
+/// and every section keeps its attributes.
+///
+/// # Examples
+/// ```
+/// let lines = ["@@ multitext header", "@@ vertex", "#version 430 core", "void main() {}"];
+/// let doc = multitext::parse_document(lines.iter()).unwrap();
+/// let doc = multitext::glsl::inject_line_directives(&doc, None);
+/// assert_eq!(&doc["vertex"], "#version 430 core\n#line 4\nvoid main() {}\n");
+/// ```
+pub fn inject_line_directives(doc: &Document, filename: Option<&str>) -> Document {
+    let mut out = doc.clone();
+    for section in &mut out.sections {
+        let body = section.body();
+        let after = body.lines().position(is_version).map_or(0, |index| index + 1);
+        let origin = match section.origin_at(after + 1) {
+            Some(origin) if section.key() != "multitext header" => origin,
+            _ => continue,
+        };
+        let directive = match filename {
+            Some(filename) => format!("#line {} \"{}\"\n", origin.line_number, filename),
+            None => format!("#line {}\n", origin.line_number),
+        };
+        let split = body.split_inclusive('\n').take(after).map(str::len).sum();
+        let body = format!("{}{}{}", &body[..split], directive, &body[split..]);
+        replace_body(section, body);
+    }
+    out
+}
+
+/// Gives `section` a new body, marking it dirty if that changes anything
+fn replace_body(section: &mut Section, body: String) {
+    if section.body != body {
+        section.body = body;
+        section.touch();
+    }
+}
+
+fn is_version(line: &str) -> bool {
+    matches!(line.trim_start().strip_prefix('#'), Some(rest) if rest.trim_start().starts_with("version"))
+}
+
+/// Returns the key named by an `#include "key"` directive
+fn include_target(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("include")?.trim();
+    rest.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Appends the resolved text of section `key` to `out`, and the source-string
+/// number of every `#line` directive it emits to `strings`
+fn resolve_into<'a>(
+    doc: &'a Document, key: &'a str, stack: &mut Vec<&'a str>, strings: &mut Vec<usize>, out: &mut String,
+) -> Result<(), Error> {
+    let index = doc.position(key).ok_or_else(|| {
+        Error::new(ErrorKind::Link, format!("included section \"{}\" does not exist", key))
+    })?;
+    let is_root = stack.is_empty();
+
+    if stack.contains(&key) {
+        stack.push(key);
+        return Err(Error::new(ErrorKind::Link, format!("include cycle: {}", stack.join(" -> "))));
+    }
+    stack.push(key);
+
+    let body = doc.sections[index].body();
+    let has_includes = body.lines().any(|line| include_target(line).is_some());
+    let version_line = body.lines().position(is_version).filter(|_| is_root && has_includes);
+    if is_root && has_includes && version_line.is_none() {
+        strings.push(index);
+        out.push_str(&format!("#line 1 {}\n", index));
+    }
+    for (line_index, line) in body.lines().enumerate() {
+        match include_target(line) {
+            Some(target) => {
+                let target_index = doc.position(target).ok_or_else(|| {
+                    Error::new(ErrorKind::Link, format!(
+                        "section \"{}\" line {} includes missing section \"{}\"",
+                        key, line_index + 1, target
+                    ))
+                })?;
+                strings.extend([target_index, index]);
+                out.push_str(&format!("#line 1 {}\n", target_index));
+                resolve_into(doc, target, stack, strings, out)?;
+                out.push_str(&format!("#line {} {}\n", line_index + 2, index));
+            }
+            None => {
+                out.push_str(line);
+                out.push('\n');
+                if version_line == Some(line_index) {
+                    strings.push(index);
+                    out.push_str(&format!("#line {} {}\n", line_index + 2, index));
+                }
+            }
+        }
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shader::CompilerMessage;
+
+    #[test]
+    fn test_include_cycle() {
+        let lines = [
+            "@@ multitext header",
+            "@@ a", "#include \"b\"",
+            "@@ b", "#include \"a\"",
+        ];
+        let doc = crate::parse_document(lines.iter()).unwrap();
+        let e = resolve_includes(&doc, "a").unwrap_err();
+        assert!(e.to_string().contains("a -> b -> a"));
+    }
+
+    #[test]
+    fn test_compile_check_remaps_lines() {
+        struct RejectSquare;
+        impl Compiler for RejectSquare {
+            fn compile(&mut self, _: Stage, source: &str) -> Vec<CompilerMessage> {
+                let (mut string, mut next_line) = (Some(0), 1);
+                let mut messages = Vec::new();
+                for line in source.lines() {
+                    let line_number = next_line;
+                    next_line += 1;
+                    if let Some(rest) = line.strip_prefix("#line ") {
+                        let mut parts = rest.split(' ').map(|p| p.parse().unwrap());
+                        next_line = parts.next().unwrap();
+                        string = parts.next();
+                    } else if line.contains("square") {
+                        messages.push(CompilerMessage {
+                            source_string: string,
+                            line: Some(line_number),
+                            message: "no square allowed".to_string(),
+                        });
+                    }
+                }
+                messages
+            }
+        }
+
+        let lines = [
+            "@@ multitext header",
+            "@@ common", "// helpers", "float square(float x);",
+            "@@ vertex shader", "#version 430 core", "#include \"common\"", "void main() {}",
+            "@@ fragment shader", "float square2(float x);", "#include \"common\"",
+        ];
+        let doc = crate::parse_document(lines.iter()).unwrap();
+        let diagnostics = compile_check(&doc, &mut RejectSquare).unwrap();
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].key, "common");
+        assert_eq!(diagnostics[0].line_number, Some(4));
+        assert_eq!(diagnostics[1].key, "fragment shader");
+        assert_eq!(diagnostics[1].line_number, Some(10));
+        assert_eq!(diagnostics[2].key, "common");
+
+        let mut doc = doc;
+        doc.insert("common", "float square(float x);\n").unwrap();
+        let diagnostics = compile_check(&doc, &mut RejectSquare).unwrap();
+        assert_eq!((&*diagnostics[0].key, diagnostics[0].line_number), ("common", None));
+        assert_eq!(diagnostics[1].line_number, Some(10));
+    }
+
+    #[test]
+    fn test_inject_line_directives() {
+        let lines = [
+            "@@ multitext header",
+            "@@ common", "float square(float x);",
+            "@@ vertex shader", "// comment", "#version 430 core", "void main() {}",
+        ];
+        let mut doc = crate::parse_document(lines.iter()).unwrap();
+        doc.insert("common", "float cube(float x);\n").unwrap();
+        let injected = inject_line_directives(&doc, Some("shaders.mt"));
+        assert_eq!(&injected["common"], "float cube(float x);\n");
+        assert_eq!(&injected["vertex shader"], "// comment\n#version 430 core\n#line 7 \"shaders.mt\"\nvoid main() {}\n");
+        assert_eq!(&injected["multitext header"], "");
+    }
+
+    #[test]
+    fn test_whole_document_helpers_keep_sections() {
+        let lines = [
+            "## multitext header",
+            "## common owner=render", "float square(float x);",
+            "## vertex shader locked=true", "#include \"common\"", "void main() {}",
+        ];
+        let options = crate::options::ParseOptions::new().attributes(true);
+        let doc = crate::parse_document_with(lines.iter(), &options).unwrap();
+        for out in [resolve_all_includes(&doc).unwrap(), inject_line_directives(&doc, None)] {
+            assert_eq!(out.marker(), Some("##"));
+            assert_eq!(out.section("common").unwrap().attr("owner"), Some("render"));
+            assert!(out.section("vertex shader").unwrap().is_locked());
+            assert!(out.section("vertex shader").unwrap().is_dirty());
+            assert_eq!(out.section("multitext header").unwrap().line_number(), Some(2));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Stable content hashing
+//!
+//! Hashes computed here are written to disk and compared across runs, so they
+//! must not depend on the Rust version or platform the way
+//! `std::collections::hash_map::DefaultHasher` does.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a, fed incrementally
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Fnv1a {
+        Fnv1a(FNV_OFFSET_BASIS)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub(crate) fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+/// Hashes `text` in one go
+pub(crate) fn hash_str(text: &str) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(text.as_bytes());
+    hasher.finish()
+}
+
+impl crate::Document {
+    /// Hashes the canonical form of the document for use as a build cache key
+    ///
+    /// The key depends only on section keys, bodies and their order. The marker
+    /// a file was written with and its line endings (`\n` or `\r\n`) do not
+    /// affect it.
+    ///
+    /// # Examples
+    /// ```
+    /// let a = multitext::parse_document(["## multitext header", "## x", "1"].iter()).unwrap();
+    /// let b = multitext::parse_document(["$$ multitext header\r", "$$ x\r", "1\r"].iter()).unwrap();
+    /// assert_eq!(a.cache_key(), b.cache_key());
+    /// ```
+    pub fn cache_key(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        for section in self.iter() {
+            write_canonical_section(&mut hasher, section);
+        }
+        hasher.finish()
+    }
+
+    /// Like [`cache_key`](crate::Document::cache_key), but for documents whose
+    /// section order carries no meaning, so reordering sections keeps the key
+    pub fn unordered_cache_key(&self) -> u64 {
+        let mut sections: Vec<_> = self.iter().collect();
+        sections.sort_by(|a, b| a.key().cmp(b.key()));
+        let mut hasher = Fnv1a::new();
+        for section in sections {
+            write_canonical_section(&mut hasher, section);
+        }
+        hasher.finish()
+    }
+}
+
+/// Feeds a length-prefixed key and `\n`-terminated body lines to `hasher`
+fn write_canonical_section(hasher: &mut Fnv1a, section: &crate::Section) {
+    let key = section.key();
+    hasher.write(&(key.len() as u64).to_le_bytes());
+    hasher.write(key.as_bytes());
+
+    let body = section.body();
+    hasher.write(&(body.lines().count() as u64).to_le_bytes());
+    for line in body.lines() {
+        hasher.write(line.as_bytes());
+        hasher.write(b"\n");
+    }
+}
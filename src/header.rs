@@ -0,0 +1,150 @@
+//! Structured access to the body of the "multitext header" section
+//!
+//! The header body is free text, but lines of the form `name: value`, where
+//! the name is a single word of letters, digits, `-` and `_`, are treated as
+//! metadata fields. A [`Header`] keeps every line as it was read, so changing
+//! one field rewrites only that line and leaves the surrounding prose alone.
+
+use crate::history::Edit;
+use crate::{Document, Error};
+
+/// The parsed body of a "multitext header" section
+///
+/// # Examples
+/// ```
+/// let lines = ["@@ multitext header", "Shaders for the title screen.", "",
+///              "version: 2", "author:  someone", "@@ a", "1"];
+/// let mut doc = multitext::parse_document(lines.iter()).unwrap();
+///
+/// let mut header = doc.header();
+/// assert_eq!(header.field("author"), Some("someone"));
+/// assert_eq!(header.paragraphs(), ["Shaders for the title screen."]);
+///
+/// header.set_field("version", "3");
+/// doc.set_header(&header).unwrap();
+/// assert_eq!(&doc["multitext header"], "Shaders for the title screen.\n\nversion: 3\nauthor:  someone\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Header {
+    lines: Vec<String>,
+}
+
+impl Header {
+    pub fn new() -> Header {
+        Header::default()
+    }
+
+    pub fn parse(body: &str) -> Header {
+        Header { lines: body.lines().map(str::to_string).collect() }
+    }
+
+    /// Returns the value of the first field called `name`, trimmed
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields().find(|&(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    /// Iterates over every field as `(name, value)`, in order
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| as_field(line))
+    }
+
+    /// Sets a field, rewriting the line of the first field called `name` or
+    /// adding a line after the last field
+    pub fn set_field(&mut self, name: &str, value: &str) {
+        let line = format!("{}: {}", name, value);
+        if let Some(index) = self.lines.iter().position(|l| as_field(l).is_some_and(|(n, _)| n == name)) {
+            self.lines[index] = line;
+            return;
+        }
+        let index = self.lines.iter().rposition(|l| as_field(l).is_some()).map_or(self.lines.len(), |i| i + 1);
+        self.lines.insert(index, line);
+    }
+
+    /// Removes every field called `name`, returning the first value
+    pub fn remove_field(&mut self, name: &str) -> Option<String> {
+        let first = self.field(name).map(str::to_string);
+        self.lines.retain(|l| as_field(l).is_none_or(|(n, _)| n != name));
+        first
+    }
+
+    /// The free text, split into paragraphs at blank lines and field lines
+    pub fn paragraphs(&self) -> Vec<String> {
+        let mut paragraphs = Vec::new();
+        let mut current: Vec<&str> = Vec::new();
+        for line in &self.lines {
+            if line.trim().is_empty() || as_field(line).is_some() {
+                if !current.is_empty() {
+                    paragraphs.push(current.join("\n"));
+                    current.clear();
+                }
+            } else {
+                current.push(line);
+            }
+        }
+        if !current.is_empty() {
+            paragraphs.push(current.join("\n"));
+        }
+        paragraphs
+    }
+}
+
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn as_field(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    let is_word = !name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if is_word { Some((name, value.trim())) } else { None }
+}
+
+impl Document {
+    /// Parses the "multitext header" section, which is empty if missing
+    pub fn header(&self) -> Header {
+        self.get("multitext header").map(Header::parse).unwrap_or_default()
+    }
+
+    /// Replaces the "multitext header" section, adding it as the first
+    /// section if missing
+    ///
+    /// Fails, like [`Document::insert`], if the header section is locked.
+    pub fn set_header(&mut self, header: &Header) -> Result<(), Error> {
+        let body = header.to_string();
+        if self.contains_key("multitext header") {
+            self.insert("multitext header", body)?;
+        } else {
+            if self.history.is_some() {
+                self.record(Edit::Insert { key: "multitext header".to_string(), index: 0, body: body.clone() });
+            }
+            self.insert_raw(0, "multitext header".to_string(), body);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_preserves_unrecognized_lines() {
+        let body = "A note: not a field\n\n  indented prose\nlicense: MIT\n";
+        let mut header = Header::parse(body);
+        assert_eq!(header.to_string(), body);
+        assert_eq!(header.fields().collect::<Vec<_>>(), [("license", "MIT")]);
+
+        header.set_field("version", "1");
+        assert_eq!(header.to_string(), "A note: not a field\n\n  indented prose\nlicense: MIT\nversion: 1\n");
+        assert_eq!(header.remove_field("license").as_deref(), Some("MIT"));
+
+        let mut doc: Document = vec![("a", "1\n")].into_iter().collect();
+        doc.set_header(&header).unwrap();
+        assert_eq!(doc.iter().next().unwrap().key(), "multitext header");
+    }
+}
@@ -0,0 +1,129 @@
+//! Undo and redo for edits made through a [`Document`]
+//!
+//! History is off by default, since it keeps a copy of every replaced or
+//! removed body. Once enabled with [`Document::enable_history`], each
+//! [`Document::insert`] and [`Document::remove`] is journaled as an [`Edit`]
+//! that can be undone and redone.
+
+use crate::Document;
+
+/// One journaled change to a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// A new section was added at `index`
+    Insert { key: String, index: usize, body: String },
+    /// An existing section's body was changed
+    Replace { key: String, old_body: String, new_body: String },
+    /// The section at `index` was removed
+    Remove { key: String, index: usize, body: String },
+}
+
+impl Edit {
+    pub fn key(&self) -> &str {
+        match self {
+            Edit::Insert { key, .. } | Edit::Replace { key, .. } | Edit::Remove { key, .. } => key,
+        }
+    }
+}
+
+impl std::fmt::Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Edit::Insert { key, .. } => write!(f, "add section \"{}\"", key),
+            Edit::Replace { key, .. } => write!(f, "edit section \"{}\"", key),
+            Edit::Remove { key, .. } => write!(f, "remove section \"{}\"", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl Document {
+    /// Starts journaling edits, with an empty history
+    pub fn enable_history(&mut self) {
+        self.history = Some(History::default());
+    }
+
+    /// Stops journaling edits and discards the history
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// The edits that [`Document::undo`] would revert, most recent last
+    pub fn undo_stack(&self) -> &[Edit] {
+        self.history.as_ref().map_or(&[], |h| &h.undo)
+    }
+
+    /// The edits that [`Document::redo`] would reapply, next one last
+    pub fn redo_stack(&self) -> &[Edit] {
+        self.history.as_ref().map_or(&[], |h| &h.redo)
+    }
+
+    /// Reverts the most recent edit, returning it
+    ///
+    /// # Examples
+    /// ```
+    /// let mut doc = multitext::Document::new();
+    /// doc.enable_history();
+    /// doc.insert("a", "1").unwrap();
+    /// doc.insert("a", "2").unwrap();
+    ///
+    /// assert_eq!(doc.undo().unwrap().to_string(), "edit section \"a\"");
+    /// assert_eq!(doc.get("a"), Some("1"));
+    /// doc.undo();
+    /// assert!(doc.is_empty());
+    /// doc.redo();
+    /// assert_eq!(doc.get("a"), Some("1"));
+    /// ```
+    pub fn undo(&mut self) -> Option<Edit> {
+        let edit = self.history.as_mut()?.undo.pop()?;
+        match &edit {
+            Edit::Insert { index, .. } => {
+                self.remove_raw(*index);
+            }
+            Edit::Replace { key, old_body, .. } => self.set_body_raw(key, old_body),
+            Edit::Remove { key, index, body } => self.insert_raw(*index, key.clone(), body.clone()),
+        }
+        if let Some(history) = &mut self.history {
+            history.redo.push(edit.clone());
+        }
+        Some(edit)
+    }
+
+    /// Reapplies the most recently undone edit, returning it
+    pub fn redo(&mut self) -> Option<Edit> {
+        let edit = self.history.as_mut()?.redo.pop()?;
+        match &edit {
+            Edit::Insert { key, index, body } => self.insert_raw(*index, key.clone(), body.clone()),
+            Edit::Replace { key, new_body, .. } => self.set_body_raw(key, new_body),
+            Edit::Remove { index, .. } => {
+                self.remove_raw(*index);
+            }
+        }
+        if let Some(history) = &mut self.history {
+            history.undo.push(edit.clone());
+        }
+        Some(edit)
+    }
+
+    /// Journals a new edit, which makes the redo stack obsolete
+    pub(crate) fn record(&mut self, edit: Edit) {
+        if let Some(history) = &mut self.history {
+            history.undo.push(edit);
+            history.redo.clear();
+        }
+    }
+
+    fn set_body_raw(&mut self, key: &str, body: &str) {
+        if let Some(index) = self.position(key) {
+            let section = &mut self.sections[index];
+            let old_body = std::mem::replace(&mut section.body, body.to_string());
+            section.touch();
+            self.emit(&crate::events::ChangeEvent::Modified { key, old_body: &old_body, new_body: body });
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! A gitignore-style pattern matcher for directory packing
+//!
+//! Supports the commonly used subset of gitignore syntax: `#` comments,
+//! `!` negation, trailing `/` for directories only, a leading or inner `/`
+//! to anchor a pattern to the directory of its ignore file, and the globs
+//! `*`, `?`, `[...]` and `**`.
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// The directory holding the ignore file, relative to the packed root,
+    /// with a trailing `/` unless it is the root itself
+    base: String,
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+/// The patterns in effect while walking a directory tree
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Adds the patterns from one ignore file found in directory `base`
+    pub(crate) fn add_file(&mut self, base: &str, text: &str) {
+        let base = if base.is_empty() { String::new() } else { format!("{}/", base) };
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            self.patterns.push(Pattern {
+                base: base.clone(),
+                glob: line.chars().collect(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Returns true if `path` (relative to the packed root, `/`-separated)
+    /// should be left out
+    pub(crate) fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            let relative = match path.strip_prefix(&pattern.base) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            let subject = if pattern.anchored {
+                relative
+            } else {
+                relative.rsplit('/').next().unwrap_or(relative)
+            };
+            let subject: Vec<char> = subject.chars().collect();
+            if glob_match(&pattern.glob, &subject) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+pub(crate) fn glob_match(glob: &[char], text: &[char]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some('*') if glob.get(1) == Some(&'*') => {
+            // `**/` also matches zero directories
+            let rest = &glob[2..];
+            let rest_after_slash = rest.strip_prefix(&['/']).unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]) || glob_match(rest_after_slash, &text[i..]))
+        }
+        Some('*') => {
+            let rest = &glob[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match text.first() {
+            Some(&c) if c != '/' => glob_match(&glob[1..], &text[1..]),
+            _ => false,
+        },
+        Some('[') => {
+            let close = match glob.iter().skip(2).position(|&c| c == ']') {
+                Some(index) => index + 2,
+                None => return text.first() == Some(&'[') && glob_match(&glob[1..], &text[1..]),
+            };
+            let c = match text.first() {
+                Some(&c) if c != '/' => c,
+                _ => return false,
+            };
+            let class = &glob[1..close];
+            let (negated, class) = match class.first() {
+                Some('!') | Some('^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    found |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    found |= class[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && glob_match(&glob[close + 1..], &text[1..])
+        }
+        Some(&g) => text.first() == Some(&g) && glob_match(&glob[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let mut rules = IgnoreRules::default();
+        rules.add_file("", "target/\n*.sw?\n!keep.swp\n/build\ndocs/**/*.html\n");
+        rules.add_file("sub", "local.txt\n");
+
+        assert!(rules.is_ignored("target", true));
+        assert!(!rules.is_ignored("target", false));
+        assert!(rules.is_ignored("src/.main.rs.swp", false));
+        assert!(!rules.is_ignored("keep.swp", false));
+        assert!(rules.is_ignored("build", true));
+        assert!(!rules.is_ignored("src/build", true));
+        assert!(rules.is_ignored("docs/a/b/index.html", false));
+        assert!(rules.is_ignored("docs/index.html", false));
+        assert!(rules.is_ignored("sub/deeper/local.txt", false));
+        assert!(!rules.is_ignored("local.txt", false));
+    }
+}
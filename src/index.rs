@@ -0,0 +1,328 @@
+//! Sidecar indexes for reading single sections of large files
+//!
+//! [`write_index`] scans a file once and stores the byte range and hash of
+//! every section next to it, in `<file>.idx`. [`IndexedFile::open`] uses that
+//! index while it still describes the file, so a section can be read by
+//! seeking straight to it; if the file has changed size or modification time
+//! since, the file is scanned again instead.
+//!
+//! The text form is:
+//!
+//! ```text
+//! multitext index 1
+//! <file size in bytes> <modification time as seconds.nanoseconds>
+//! <body offset> <body length> <body line number> <hash as 16 hex digits> <key>
+//! ```
+
+use crate::hash::{hash_str, Fnv1a};
+use crate::parse::unescape;
+use crate::{Error, ErrorKind};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Where one section's body is stored
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IndexEntry {
+    pub key: String,
+    /// Byte offset of the first body line
+    pub offset: u64,
+    /// Length of the body in the file, including line endings
+    pub len: u64,
+    /// The 1-based line on which the body starts
+    pub line_number: usize,
+    /// Hash of the body as parsed, the same as the manifest hash
+    pub hash: u64,
+}
+
+/// The byte ranges of every section of one file, in file order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SectionIndex {
+    pub file_size: u64,
+    /// Modification time of the indexed file, since the Unix epoch
+    pub modified: Duration,
+    pub entries: Vec<IndexEntry>,
+}
+
+const INDEX_HEADER: &str = "multitext index 1";
+
+/// The path of the sidecar index for `path`
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_os_string();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+/// Scans `path` and writes its sidecar index
+pub fn write_index<P: AsRef<Path>>(path: P) -> Result<SectionIndex, Error> {
+    let index = SectionIndex::build(path.as_ref())?;
+    let sidecar = sidecar_path(path);
+    std::fs::write(&sidecar, index.to_string()).map_err(|e| Error::from(e).with_path(&sidecar))?;
+    Ok(index)
+}
+
+impl SectionIndex {
+    /// Scans `path`, recording where every section is
+    ///
+    /// A key that appears twice is recorded at its last occurrence, matching
+    /// what parsing gives.
+    pub fn build<P: AsRef<Path>>(path: P) -> Result<SectionIndex, Error> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::from(e).with_path(path))?;
+        let (file_size, modified) = stamp(&file).map_err(|e| Error::from(e).with_path(path))?;
+        let mut reader = BufReader::new(file);
+
+        let mut entries: Vec<IndexEntry> = Vec::new();
+        let mut current: Option<(IndexEntry, Fnv1a)> = None;
+        let mut marker: Option<String> = None;
+        let mut offset = 0u64;
+        let mut line_number = 0;
+        let mut raw = Vec::new();
+        loop {
+            raw.clear();
+            let read = reader.read_until(b'\n', &mut raw).map_err(|e| Error::from(e).with_path(path))?;
+            if read == 0 {
+                break;
+            }
+            line_number += 1;
+            offset += read as u64;
+            let line = std::str::from_utf8(&raw).map_err(|_| {
+                Error::new(ErrorKind::InvalidUtf8, "line is not valid UTF-8").with_line(line_number).with_path(path)
+            })?;
+            let line = line.strip_suffix('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).unwrap_or(line);
+
+            let key = match &marker {
+                None => match line.find("multitext header") {
+                    Some(index) => {
+                        marker = Some(line[..index].trim_end().to_string());
+                        Some("multitext header")
+                    }
+                    None => continue,
+                },
+                Some(marker) => line.strip_prefix(marker.as_str()).map(str::trim),
+            };
+            match key {
+                Some(key) => {
+                    finish_entry(&mut entries, current.take());
+                    let entry = IndexEntry {
+                        key: key.to_string(),
+                        offset,
+                        len: 0,
+                        line_number: line_number + 1,
+                        hash: 0,
+                    };
+                    current = Some((entry, Fnv1a::new()));
+                }
+                None => {
+                    if let Some((entry, hasher)) = &mut current {
+                        entry.len = offset - entry.offset;
+                        let marker = marker.as_deref().unwrap_or_default();
+                        hasher.write(unescape(line, marker).as_bytes());
+                        hasher.write(b"\n");
+                    }
+                }
+            }
+        }
+        if marker.is_none() {
+            let error = Error::new(ErrorKind::MissingHeader, "missing multitext header");
+            return Err(error.with_line(line_number + 1).with_path(path));
+        }
+        finish_entry(&mut entries, current);
+        Ok(SectionIndex { file_size, modified, entries })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&IndexEntry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    /// Returns true if `path` still has the size and modification time that
+    /// were indexed
+    pub fn is_fresh_for<P: AsRef<Path>>(&self, path: P) -> bool {
+        File::open(path).and_then(|file| stamp(&file)).is_ok_and(|s| s == (self.file_size, self.modified))
+    }
+
+    /// Reads the text form written by `Display`
+    pub fn parse(text: &str) -> Result<SectionIndex, Error> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.trim_end() == INDEX_HEADER => {}
+            _ => return Err(Error::new(ErrorKind::Malformed, "missing index header").with_line(1)),
+        }
+        let stamp = lines.next().and_then(|(_, line)| {
+            let (size, modified) = line.split_once(' ')?;
+            let (secs, nanos) = modified.split_once('.')?;
+            Some((size.parse().ok()?, Duration::new(secs.parse().ok()?, nanos.parse().ok()?)))
+        });
+        let (file_size, modified) = stamp.ok_or_else(|| {
+            Error::new(ErrorKind::Malformed, "malformed file size and time").with_line(2)
+        })?;
+
+        let mut entries = Vec::new();
+        for (index, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(5, ' ');
+            let offset = fields.next().and_then(|f| f.parse().ok());
+            let len = fields.next().and_then(|f| f.parse().ok());
+            let line_number = fields.next().and_then(|f| f.parse().ok());
+            let hash = fields.next().and_then(|f| u64::from_str_radix(f, 16).ok());
+            let key = fields.next();
+            match (offset, len, line_number, hash, key) {
+                (Some(offset), Some(len), Some(line_number), Some(hash), Some(key)) => entries.push(IndexEntry {
+                    key: key.to_string(),
+                    offset,
+                    len,
+                    line_number,
+                    hash,
+                }),
+                _ => return Err(Error::new(ErrorKind::Malformed, "malformed index entry").with_line(index + 1)),
+            }
+        }
+        Ok(SectionIndex { file_size, modified, entries })
+    }
+}
+
+impl std::fmt::Display for SectionIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", INDEX_HEADER)?;
+        writeln!(f, "{} {}.{:09}", self.file_size, self.modified.as_secs(), self.modified.subsec_nanos())?;
+        for e in &self.entries {
+            writeln!(f, "{} {} {} {:016x} {}", e.offset, e.len, e.line_number, e.hash, e.key)?;
+        }
+        Ok(())
+    }
+}
+
+/// A file opened for reading one section at a time
+///
+/// # Examples
+/// ```no_run
+/// use multitext::index::{write_index, IndexedFile};
+///
+/// write_index("levels.mt")?;
+/// // Later, possibly in another process:
+/// let mut file = IndexedFile::open("levels.mt")?;
+/// assert!(file.used_sidecar());
+/// let level = file.read("level 37")?;
+/// # Ok::<(), multitext::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct IndexedFile {
+    path: PathBuf,
+    file: File,
+    marker: String,
+    index: SectionIndex,
+    used_sidecar: bool,
+}
+
+impl IndexedFile {
+    /// Opens `path`, using its sidecar index if it is fresh and scanning the
+    /// file otherwise
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<IndexedFile, Error> {
+        let path = path.as_ref();
+        let sidecar = std::fs::read_to_string(sidecar_path(path)).ok()
+            .and_then(|text| SectionIndex::parse(&text).ok())
+            .filter(|index| index.is_fresh_for(path));
+        let used_sidecar = sidecar.is_some();
+        let index = match sidecar {
+            Some(index) => index,
+            None => SectionIndex::build(path)?,
+        };
+        let file = File::open(path).map_err(|e| Error::from(e).with_path(path))?;
+        let marker = read_marker(&file).map_err(|e| e.with_path(path))?;
+        Ok(IndexedFile { path: path.to_path_buf(), file, marker, index, used_sidecar })
+    }
+
+    pub fn index(&self) -> &SectionIndex {
+        &self.index
+    }
+
+    /// Whether the index came from the sidecar rather than a fresh scan
+    pub fn used_sidecar(&self) -> bool {
+        self.used_sidecar
+    }
+
+    /// Reads the body of `key`, or `None` if there is no such section
+    ///
+    /// Fails if the body no longer matches its indexed hash, which means the
+    /// file changed without its size or modification time changing.
+    pub fn read(&mut self, key: &str) -> Result<Option<String>, Error> {
+        let entry = match self.index.get(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut raw = Vec::new();
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(entry.offset))
+            .and_then(|_| file.take(entry.len).read_to_end(&mut raw))
+            .map_err(|e| Error::from(e).with_path(&self.path))?;
+
+        let mut body = String::with_capacity(raw.len());
+        for line in String::from_utf8_lossy(&raw).lines() {
+            body.push_str(unescape(line, &self.marker));
+            body.push('\n');
+        }
+        if hash_str(&body) != entry.hash {
+            let message = format!("section \"{}\" doesn't match the index; the index is stale", key);
+            return Err(Error::new(ErrorKind::Other, message).with_line(entry.line_number - 1).with_path(&self.path));
+        }
+        Ok(Some(body))
+    }
+}
+
+/// Reads the marker from the header line, which body lines need to be
+/// unescaped
+fn read_marker(mut file: &File) -> Result<String, Error> {
+    for line in BufReader::new(&mut file).lines() {
+        let line = line?;
+        if let Some(index) = line.find("multitext header") {
+            return Ok(line[..index].trim_end().to_string());
+        }
+    }
+    Err(Error::new(ErrorKind::MissingHeader, "missing multitext header"))
+}
+
+fn finish_entry(entries: &mut Vec<IndexEntry>, current: Option<(IndexEntry, Fnv1a)>) {
+    if let Some((mut entry, hasher)) = current {
+        entry.hash = hasher.finish();
+        entries.retain(|e| e.key != entry.key);
+        entries.push(entry);
+    }
+}
+
+fn stamp(file: &File) -> std::io::Result<(u64, Duration)> {
+    let metadata = file.metadata()?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_round_trip() {
+        let dir = crate::testing::TempDir::new("index").unwrap();
+        let path = dir.join("index.mt");
+        std::fs::write(&path, "junk\r\n## multitext header\r\n## a\r\none\r\n\r\n## b\r\ntwo").unwrap();
+
+        let index = write_index(&path).unwrap();
+        assert_eq!(SectionIndex::parse(&index.to_string()).unwrap(), index);
+        let doc = crate::open_and_parse_document(&path).unwrap();
+        assert_eq!(index.get("a").unwrap().hash, hash_str(&doc["a"]));
+        assert_eq!(index.get("b").unwrap().line_number, 7);
+
+        let mut file = IndexedFile::open(&path).unwrap();
+        assert!(file.used_sidecar());
+        assert_eq!(file.read("a").unwrap().as_deref(), Some("one\n\n"));
+        assert_eq!(file.read("b").unwrap().as_deref(), Some("two\n"));
+        assert_eq!(file.read("c").unwrap(), None);
+
+        std::fs::write(&path, "## multitext header\n## a\nchanged\n\\## escaped\n").unwrap();
+        let mut file = IndexedFile::open(&path).unwrap();
+        assert!(!file.used_sidecar());
+        assert_eq!(file.read("a").unwrap().as_deref(), Some("changed\n## escaped\n"));
+    }
+}
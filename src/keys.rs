@@ -0,0 +1,308 @@
+//! Rules for how section keys are matched and what they may contain
+//!
+//! Keys are compared as plain strings: byte for byte, independent of locale,
+//! and ordered by [`str`]'s `Ord` (which is by Unicode code point). That is
+//! what [`Document::get`] and the parser's duplicate detection use.
+//!
+//! Domains with their own identifier rules supply a [`KeyNormalizer`] instead,
+//! which maps each key to the form that is compared. The same normalizer is
+//! used for lookup ([`Document::get_with`]), deduplication
+//! ([`Document::dedup_keys_with`]) and sorting ([`Document::sort_keys_with`]),
+//! so the three never disagree. [`KeyMatch`] provides the built-in modes, and
+//! any `Fn(&str) -> String` closure works too.
+//!
+//! The parser already trims whitespace from both ends of a key; applications
+//! that use keys as identifiers elsewhere (database columns, file names) can
+//! demand more with a [`KeyPolicy`].
+
+use crate::{Document, Error, ErrorKind, Section};
+use crate::options::DuplicateKeys;
+use std::borrow::Cow;
+
+/// Maps a key to the form that is actually compared
+pub trait KeyNormalizer {
+    fn normalize<'k>(&self, key: &'k str) -> Cow<'k, str>;
+
+    fn matches(&self, a: &str, b: &str) -> bool {
+        self.normalize(a) == self.normalize(b)
+    }
+}
+
+impl<F: Fn(&str) -> String> KeyNormalizer for F {
+    fn normalize<'k>(&self, key: &'k str) -> Cow<'k, str> {
+        Cow::Owned(self(key))
+    }
+}
+
+/// How a requested key is compared against the keys in a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyMatch {
+    /// Keys must be identical
+    Exact,
+    /// Runs of whitespace inside keys compare equal to a single space, so
+    /// "vertex  shader" finds "vertex shader"
+    CollapseWhitespace,
+}
+
+impl KeyNormalizer for KeyMatch {
+    fn normalize<'k>(&self, key: &'k str) -> Cow<'k, str> {
+        match self {
+            KeyMatch::Exact => Cow::Borrowed(key),
+            KeyMatch::CollapseWhitespace => collapse_whitespace(key),
+        }
+    }
+}
+
+/// Trims `key` and replaces each internal run of whitespace with one space
+pub fn collapse_whitespace(key: &str) -> Cow<'_, str> {
+    let key = key.trim();
+    let mut previous_space = false;
+    let already_collapsed = key.chars().all(|c| {
+        let ok = (c == ' ' && !previous_space) || !c.is_whitespace();
+        previous_space = c.is_whitespace();
+        ok
+    });
+    if already_collapsed {
+        Cow::Borrowed(key)
+    } else {
+        Cow::Owned(key.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Rules a key must satisfy
+///
+/// The default rejects control characters and otherwise allows anything.
+///
+/// # Examples
+/// ```
+/// use multitext::keys::KeyPolicy;
+///
+/// let policy = KeyPolicy::new().ascii_only(true).max_len(16);
+/// assert!(policy.check("vertex shader").is_ok());
+/// assert!(policy.check("sombreado de vértices").is_err());
+/// assert!(policy.check("a very long key indeed").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPolicy {
+    allow_control: bool,
+    ascii_only: bool,
+    max_len: Option<usize>,
+    allow_empty: bool,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> KeyPolicy {
+        KeyPolicy {
+            allow_control: false,
+            ascii_only: false,
+            max_len: None,
+            allow_empty: true,
+        }
+    }
+}
+
+impl KeyPolicy {
+    pub fn new() -> KeyPolicy {
+        KeyPolicy::default()
+    }
+
+    /// Whether control characters such as tabs or escape codes are accepted
+    pub fn allow_control(mut self, allow: bool) -> KeyPolicy {
+        self.allow_control = allow;
+        self
+    }
+
+    /// Whether keys are restricted to ASCII
+    pub fn ascii_only(mut self, ascii_only: bool) -> KeyPolicy {
+        self.ascii_only = ascii_only;
+        self
+    }
+
+    /// The longest accepted key, in bytes
+    pub fn max_len(mut self, max_len: usize) -> KeyPolicy {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Whether a marker line with no key is accepted
+    pub fn allow_empty(mut self, allow: bool) -> KeyPolicy {
+        self.allow_empty = allow;
+        self
+    }
+
+    /// Returns a description of the first rule `key` breaks
+    pub fn check(&self, key: &str) -> Result<(), String> {
+        if key.is_empty() && !self.allow_empty {
+            return Err("key is empty".to_string());
+        }
+        if let Some(max_len) = self.max_len {
+            if key.len() > max_len {
+                return Err(format!("key \"{}\" is longer than {} bytes", key, max_len));
+            }
+        }
+        if !self.allow_control && key.chars().any(char::is_control) {
+            return Err(format!("key {:?} contains a control character", key));
+        }
+        if self.ascii_only && !key.is_ascii() {
+            return Err(format!("key \"{}\" contains non-ASCII characters", key));
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Returns the body of the first section whose key matches `key` under
+    /// `normalizer`
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::keys::KeyMatch;
+    ///
+    /// let lines = ["@@ multitext header", "@@ vertex  shader", "void main() {}"];
+    /// let doc = multitext::parse_document(lines.iter()).unwrap();
+    /// assert_eq!(doc.get("vertex shader"), None);
+    /// assert_eq!(doc.get_with("vertex shader", &KeyMatch::CollapseWhitespace), Some("void main() {}\n"));
+    ///
+    /// let case_insensitive = |key: &str| key.to_lowercase();
+    /// assert!(doc.get_with("VERTEX  SHADER", &case_insensitive).is_some());
+    /// ```
+    pub fn get_with<N: KeyNormalizer + ?Sized>(&self, key: &str, normalizer: &N) -> Option<&str> {
+        self.section_with(key, normalizer).map(Section::body)
+    }
+
+    /// Returns the first section whose key matches `key` under `normalizer`
+    pub fn section_with<N: KeyNormalizer + ?Sized>(&self, key: &str, normalizer: &N) -> Option<&Section> {
+        let key = normalizer.normalize(key);
+        self.iter().find(|s| normalizer.normalize(s.key()) == key)
+    }
+
+    /// Merges sections whose keys are equal under `normalizer`, resolving
+    /// each collision by `strategy`
+    ///
+    /// The surviving section keeps the key and position of the first one.
+    pub fn dedup_keys_with<N: KeyNormalizer + ?Sized>(&mut self, normalizer: &N, strategy: DuplicateKeys)
+        -> Result<(), Error>
+    {
+        let mut result = Document::new();
+        let mut normalized: Vec<String> = Vec::new();
+        for section in std::mem::take(self).into_sections() {
+            let key = normalizer.normalize(section.key()).into_owned();
+            match normalized.iter().position(|k| *k == key) {
+                Some(index) => {
+                    let first = result.iter().nth(index).map(|s| s.key().to_string()).unwrap_or_default();
+                    result.merge_section(section.renamed(first), strategy)?;
+                }
+                None => {
+                    normalized.push(key);
+                    result.insert_section(section);
+                }
+            }
+        }
+        *self = result;
+        Ok(())
+    }
+
+    /// Stably sorts the sections by their keys under `normalizer`
+    ///
+    /// # Examples
+    /// ```
+    /// let mut doc: multitext::Document = vec![("b", ""), ("A", ""), ("a", "")].into_iter().collect();
+    /// doc.sort_keys_with(&|key: &str| key.to_lowercase());
+    /// let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+    /// assert_eq!(keys, ["A", "a", "b"]);
+    /// ```
+    pub fn sort_keys_with<N: KeyNormalizer + ?Sized>(&mut self, normalizer: &N) {
+        self.sort_sections_by(|a, b| normalizer.normalize(a.key()).cmp(&normalizer.normalize(b.key())));
+    }
+
+    /// Checks every key against `policy`, failing on the first violation with
+    /// the line of the offending marker when it is known
+    pub fn validate_keys(&self, policy: &KeyPolicy) -> Result<(), Error> {
+        for section in self.iter() {
+            policy.check(section.key()).map_err(|message| {
+                Error::new(ErrorKind::InvalidKey, message).with_line(section.line_number().map(|n| n - 1))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares keys the way people expect them sorted
+///
+/// Runs of ASCII digits compare by numeric value and other characters compare
+/// case-insensitively. Keys that are still equal, such as `a01` and `A1`, fall
+/// back to plain byte order so the result is a total order.
+///
+/// # Examples
+/// ```
+/// let mut keys = vec!["pass10", "Pass2", "pass1", "pass 3"];
+/// keys.sort_by(|a, b| multitext::keys::natural_cmp(a, b));
+/// assert_eq!(keys, ["pass 3", "pass1", "Pass2", "pass10"]);
+/// ```
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    let mut x = a.chars().peekable();
+    let mut y = b.chars().peekable();
+    loop {
+        let (c, d) = match (x.peek().copied(), y.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(c), Some(d)) => (c, d),
+        };
+        if c.is_ascii_digit() && d.is_ascii_digit() {
+            let (m, n) = (digit_run(&mut x), digit_run(&mut y));
+            let (m, n) = (m.trim_start_matches('0'), n.trim_start_matches('0'));
+            let ordering = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            let ordering = c.to_lowercase().cmp(d.to_lowercase());
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            x.next();
+            y.next();
+        }
+    }
+}
+
+fn digit_run(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        run.push(c);
+    }
+    run
+}
+
+/// The candidate most likely meant by a misspelled `key`: one differing only
+/// in case, or else the closest within a few edits
+pub(crate) fn suggest<'a, I: IntoIterator<Item = &'a str>>(key: &str, candidates: I) -> Option<&'a str> {
+    let lower = key.to_lowercase();
+    let max_distance = (key.chars().count() / 3).max(1);
+    candidates.into_iter()
+        .map(|candidate| {
+            let distance = if candidate.to_lowercase() == lower { 0 } else { edit_distance(key, candidate) };
+            (distance, candidate)
+        })
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between `a` and `b`, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1).min(row[j] + 1).min(diagonal + usize::from(x != *y));
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
@@ -26,6 +26,12 @@ whitespace on the left and right) as a key in a hash map that matches that key
 to the text that follows it until the next marker. The "multiline header" key
 is included in this hash map.
 
+A line of a file that does start with the marker is stored with a backslash in
+front of it, and the parser removes that backslash again. So that lines which
+really start with backslashes and the marker survive too, the rule is that one
+backslash is added to, and removed from, any line made of one or more
+backslashes followed by the marker. The writers in this crate do this for you.
+
 If this file is parsed, will produce a hash map with 3 entries. The first entry
 will have the key "multitext header", and will be matched to this text
 describing the format. The second entry will have the key "vertex shader", and
@@ -71,15 +77,368 @@ mod test {
         assert_eq!(mt["first thing"], "ft line 1\nft line 2\nft line 3\nft line 4\n");
         assert_eq!(mt["second thing"], "st line 1\n     \nst line 3\n");
     }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Map>();
+        assert_send_sync::<Error>();
+        assert_send_sync::<Document>();
+        assert_send_sync::<Section>();
+        assert_send_sync::<Iter<'static>>();
+        assert_send_sync::<IntoSections>();
+    }
+
+    #[test]
+    fn test_into_sections() {
+        let lines = ["@@ multitext header", "@@ one", "1", "@@ two", "2"];
+        let doc = parse_document(lines.iter()).unwrap();
+        let handles: Vec<_> = doc.into_sections()
+            .map(|section| std::thread::spawn(move || section.into_parts()))
+            .collect();
+        let parts: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(parts[1], ("one".to_string(), "1\n".to_string()));
+        assert_eq!(parts[2], ("two".to_string(), "2\n".to_string()));
+    }
+
+    #[test]
+    fn test_section_writer_escapes_marker_lines() {
+        use std::io::Write;
+        let mut writer = DocumentWriter::new(Vec::new(), "@@").unwrap();
+        let mut section = writer.begin_section("a").unwrap();
+        section.write_all(b"fine\n@").unwrap();
+        section.write_all(b"@ escaped\n\\\\@").unwrap();
+        section.write_all(b"@ twice\n@x\n\\@\n@").unwrap();
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(text, "@@ multitext header\n@@ a\nfine\n\\@@ escaped\n\\\\\\@@ twice\n@x\n\\@\n@\n");
+
+        let doc = parse_document(text.lines()).unwrap();
+        assert_eq!(&doc["a"], "fine\n@@ escaped\n\\\\@@ twice\n@x\n\\@\n@\n");
+        let mut reader = SectionReader::new(text.as_bytes());
+        reader.next_section().unwrap();
+        reader.next_section().unwrap();
+        let mut body = String::new();
+        std::io::Read::read_to_string(&mut reader, &mut body).unwrap();
+        assert_eq!(body, doc["a"]);
+
+        assert!(DocumentWriter::new(Vec::new(), "\\\\").is_err());
+    }
+
+    #[test]
+    fn test_empty_keys() {
+        use options::EmptyKeys;
+        let lines = ["@@ multitext header", "@@ a", "1", "@@", "2"];
+
+        let options = ParseOptions::new().empty_keys(EmptyKeys::Continue);
+        let doc = parse_document_with(lines.iter(), &options).unwrap();
+        assert_eq!(doc.len(), 2);
+        assert_eq!(&doc["a"], "1\n2\n");
+
+        let options = ParseOptions::new().empty_keys(EmptyKeys::Error);
+        let e = parse_document_with(lines.iter(), &options).unwrap_err();
+        assert_eq!((e.kind(), e.line_number()), (ErrorKind::EmptyKey, Some(4)));
+    }
+
+    #[test]
+    fn test_error_report() {
+        let lines = ["@@ multitext header", "@@ a", "héllo wörld héllo wörld"];
+        let options = ParseOptions::new().max_line_len(23);
+        let e = parse_document_with(lines.iter(), &options).unwrap_err();
+        assert_eq!(e.snippet().unwrap().span, 22..27);
+        let e = e.with_path("a.mt");
+        assert_eq!(e.report().to_string(), concat!(
+            "error: line is 27 bytes long, over the limit of 23\n",
+            "  --> a.mt:3:20\n",
+            "  |\n",
+            "3 | héllo wörld héllo wörld\n",
+            "  |                    ^^^^\n",
+        ));
+
+        let e = parse_lines(["text"].iter()).unwrap_err();
+        assert!(e.snippet().is_none());
+        assert!(e.report().to_string().ends_with("\n --> 2\n"));
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        use options::DuplicateKeys;
+        let lines = ["@@ multitext header", "@@ a", "1", "@@ b", "2", "@@ a", "3", "@@ a", "4"];
+
+        let options = ParseOptions::new().duplicate_keys(DuplicateKeys::KeepFirst);
+        let doc = parse_document_with(lines.iter(), &options).unwrap();
+        assert_eq!(doc.len(), 3);
+        assert_eq!((&doc["a"], &doc["b"]), ("1\n", "2\n"));
+
+        let mut warnings: Vec<diagnostics::Warning> = Vec::new();
+        let doc = parse_document_with_options_and_sink(lines.iter(), &ParseOptions::new(), &mut warnings).unwrap();
+        assert_eq!(&doc["a"], "4\n");
+        assert_eq!(warnings.len(), 2);
+
+        let options = ParseOptions::new().duplicate_keys(DuplicateKeys::Concatenate);
+        assert_eq!(parse_lines_with(lines.iter(), &options).unwrap()["a"], "1\n3\n4\n");
+        let pairs: Vec<(String, String)> = parse_lines_into_with(lines.iter(), &options).unwrap();
+        assert_eq!(pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>(),
+            [("multitext header", ""), ("a", "1\n3\n4\n"), ("b", "2\n")]);
+    }
+
+    #[test]
+    fn test_skip_keys() {
+        let lines = ["@@ multitext header", "@@ a", "1", "@@ big (1/2)", "x", "@@ big (2/2)", "y", "@@ b", "2"];
+        let options = ParseOptions::new().skip_keys("^big").unwrap();
+        let map = parse_lines_with(lines.iter(), &options).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!((map["a"].as_str(), map["b"].as_str()), ("1\n", "2\n"));
+        assert!(ParseOptions::new().skip_keys("[unclosed").is_err());
+    }
+
+    #[test]
+    fn test_parse_until_keys() {
+        let lines = ["@@ multitext header", "@@ a (1/2)", "1", "@@ b", "@@ a (2/2)", "2", "@@ a", "3", "@@ c"];
+        let mut it = lines.iter();
+        let map = parse_until_keys(&mut it, &["a", "a"]).unwrap();
+        assert_eq!(map["a"], "1\n2\n");
+        assert_eq!(it.next(), Some(&"3"));
+
+        let map = parse_until_keys(lines.iter(), &["c", "missing"]).unwrap();
+        assert_eq!(map.len(), 1);
+        assert!(parse_until_keys(["no header"].iter(), &["a"]).is_err());
+    }
+
+    #[test]
+    fn test_continuations() {
+        let lines = ["@@ multitext header", "@@ a (1/2)", "1", "@@ a (2/2)", "2", "@@ b (1/3)", "x", "@@ b (2/3)", "y"];
+        let doc = parse_document(lines.iter()).unwrap();
+        let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+        assert_eq!(keys, ["multitext header", "a", "b (1/3)", "b (2/3)"]);
+        assert_eq!(&doc["a"], "1\n2\n");
+        assert_eq!(doc.section("a").unwrap().line_number(), Some(3));
+        assert!(doc.section("a").unwrap().origin_at(2).is_none());
+        assert_eq!(doc.section("b (1/3)").unwrap().origin_at(1).unwrap().line_number, 7);
+
+        let lines = ["@@ multitext header", "@@ a", "0", "@@ a (1/2)", "1", "@@ a (2/2)", "2"];
+        let options = ParseOptions::new().duplicate_keys(options::DuplicateKeys::Error);
+        let e = parse_document_with(lines.iter(), &options).unwrap_err();
+        assert_eq!((e.kind(), e.line_number()), (ErrorKind::DuplicateKey, Some(4)));
+        let options = ParseOptions::new().duplicate_keys(options::DuplicateKeys::KeepFirst);
+        assert_eq!(&parse_document_with(lines.iter(), &options).unwrap()["a"], "0\n");
+        let options = options.join_continuations(false);
+        assert_eq!(parse_document_with(lines.iter(), &options).unwrap().len(), 4);
+
+        let long: Document = vec![("big", "0123456789ABCDEF\nx\n")].into_iter().collect();
+        let options = options::WriteOptions::new().max_body_bytes(4);
+        let text = String::from_utf8(long.write_with(Vec::new(), &options).unwrap()).unwrap();
+        assert!(text.contains("@@@ big (1/2)\n0123456789ABCDEF\n@@@ big (2/2)\nx\n"));
+        assert_eq!(parse_document(text.lines()).unwrap()["big"], long["big"]);
+    }
+
+    #[test]
+    fn test_unknown_attrs_round_trip() {
+        let lines = ["@@ multitext header", "@@ a z=1 locked=true checksum=fnv1a:00ff", "1"];
+        let options = ParseOptions::new().attributes(true);
+        let mut doc = parse_document_with(lines.iter(), &options).unwrap();
+        doc.override_locks(true);
+        doc.insert("a", "2\n").unwrap();
+        doc.set_attr("a", "z", "3").unwrap();
+
+        let text = String::from_utf8(doc.write_with(Vec::new(), &Default::default()).unwrap()).unwrap();
+        assert_eq!(text, "@@ multitext header\n@@ a z=3 locked=true checksum=fnv1a:00ff\n2\n");
+        let reparsed = parse_document_with(text.lines(), &options).unwrap();
+        assert_eq!(reparsed.section("a").unwrap().meta().unknown_attrs, [("z", "3"), ("checksum", "fnv1a:00ff")]);
+    }
+
+    #[test]
+    fn test_trailing_decoration() {
+        use options::TrailingDecoration;
+        let lines = ["-- multitext header --", "-- a stage=vertex --", "1", "-- b ==", "2", "-- --", "3"];
+        let options = ParseOptions::new().trailing_decoration(TrailingDecoration::Marker).attributes(true);
+        let doc = parse_document_with(lines.iter(), &options).unwrap();
+        let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+        assert_eq!(keys, ["multitext header", "a", "b ==", ""]);
+        assert_eq!(doc.section("a").unwrap().attr("stage"), Some("vertex"));
+
+        let options = ParseOptions::new().trailing_decoration(TrailingDecoration::Suffix("==".to_string()));
+        assert!(parse_document_with(lines.iter(), &options).unwrap().contains_key("b"));
+    }
 }
 
 use std::iter::Iterator;
 
+#[cfg(all(test, feature = "derive"))]
+extern crate self as multitext;
+
+mod document;
+pub mod history;
+pub mod events;
+pub mod versions;
+pub mod redact;
+pub mod concat;
+pub use document::{Document, Section, SectionMeta, Origin, Iter, IntoSections, MemoryFootprint, ORDER_KEY};
+mod writer;
+pub use writer::{DocumentWriter, SectionWriter, write_string, write_file};
+
+mod hash;
+pub mod manifest;
+pub mod diagnostics;
+pub mod keys;
+pub mod cache;
+pub mod embed;
+mod ignore;
+pub mod pack;
+pub mod batch;
+pub mod config;
+mod borrowed;
+pub use borrowed::{parse_str, BorrowedMap};
+mod stream;
+pub use stream::{PushParser, SectionIter, SectionReader};
+pub mod testing;
+pub mod normalize;
+pub mod diff;
+pub mod watch;
+pub mod link;
+pub mod registry;
+pub mod apply;
+pub mod storage;
+pub mod bind;
+pub use bind::FromMultitext;
+#[cfg(feature = "derive")]
+pub use multitext_derive::{FromMultitext, include_multitext};
+pub mod report;
+pub mod transaction;
+pub mod check;
+pub mod codec;
+pub mod header;
+pub mod codegen;
+pub mod set;
+pub mod query;
+mod pattern;
+pub mod follow;
+pub mod chain;
+pub mod index;
+pub mod rope;
+#[doc(hidden)]
+pub mod json;
+use diagnostics::{DiagnosticsSink, NoopSink};
+pub mod options;
+use options::ParseOptions;
+mod parse;
+
+#[cfg(any(feature = "glsl", feature = "wgsl"))]
+pub mod shader;
+#[cfg(feature = "glsl")]
+pub mod glsl;
+#[cfg(feature = "wgsl")]
+pub mod wgsl;
+#[cfg(feature = "macroquad")]
+pub mod macroquad;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// What went wrong, for callers that need to tell errors apart
+///
+/// # Examples
+/// ```
+/// use multitext::ErrorKind;
+///
+/// let e = multitext::parse_lines(["@@ a", "1"].iter()).unwrap_err();
+/// assert_eq!(e.kind(), ErrorKind::MissingHeader);
+///
+/// let e = multitext::open_and_parse_document("no such file.mt").unwrap_err();
+/// assert_eq!(e.kind(), ErrorKind::Io);
+/// assert_eq!(e.path(), Some(std::path::Path::new("no such file.mt")));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// No `multitext header` line was found
+    MissingHeader,
+    /// Reading or writing failed
+    Io,
+    /// Two sections have the same key
+    DuplicateKey,
+    /// A marker line has no key
+    EmptyKey,
+    /// A key is rejected, for instance by a key policy or as a file name
+    InvalidKey,
+    /// A line is longer than [`ParseOptions::max_line_len`] allows
+    LineTooLong,
+    /// A line or name is not valid UTF-8
+    InvalidUtf8,
+    /// A required section doesn't exist
+    MissingSection,
+    /// A locked section can't be changed
+    Locked,
+    /// A link or include can't be followed
+    Link,
+    /// A config file, index, manifest, pattern or similar input is malformed
+    Malformed,
+    /// Anything else
+    Other,
+}
+
 #[derive(Debug, Clone)]
 pub struct Error {
+    kind: ErrorKind,
     line_number: Option<usize>,
     filename: Option<String>,
     error_message: String,
+    snippet: Option<diagnostics::Snippet>,
+}
+
+impl Error {
+    /// An error of `kind` that isn't tied to a file or line
+    pub(crate) fn new<M: Into<String>>(kind: ErrorKind, message: M) -> Error {
+        Error { kind, line_number: None, filename: None, error_message: message.into(), snippet: None }
+    }
+
+    /// The same error, found in the file at `path`
+    pub(crate) fn with_path<P: AsRef<std::path::Path>>(mut self, path: P) -> Error {
+        self.filename = Some(path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    /// The same error, found at line `line_number`, if known
+    pub(crate) fn with_line<L: Into<Option<usize>>>(mut self, line_number: L) -> Error {
+        self.line_number = line_number.into();
+        self
+    }
+
+    /// The same error, quoting the offending line
+    pub(crate) fn with_snippet(mut self, snippet: diagnostics::Snippet) -> Error {
+        self.snippet = Some(snippet);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The line the error was found at, counting from 1
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
+
+    /// The file the error was found in, if known
+    pub fn path(&self) -> Option<&std::path::Path> {
+        self.filename.as_deref().map(std::path::Path::new)
+    }
+
+    /// The description of the error, without its location
+    pub fn message(&self) -> &str {
+        &self.error_message
+    }
+
+    /// The line the error was found on, when the parser still had it
+    pub fn snippet(&self) -> Option<&diagnostics::Snippet> {
+        self.snippet.as_ref()
+    }
+
+    /// Renders the error over several lines, showing the offending line if
+    /// there is a [`Error::snippet`]
+    pub fn report(&self) -> diagnostics::Report<'_> {
+        diagnostics::Report { error: self }
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -108,15 +467,14 @@ pub type ParseResult = Result<Map, Error>;
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Error {
-        Error {
-            line_number: None,
-            filename: None,
-            error_message: format!("{}", e),
-        }
+        Error::new(ErrorKind::Io, e.to_string())
     }
 }
 
 /// Parses lines from an iterator
+///
+/// The sections come back in a [`Map`], which forgets their order; use
+/// [`parse_document`] or [`open_and_parse_document`] to keep it.
 /// 
 /// # Examples
 /// ```
@@ -134,51 +492,364 @@ impl From<std::io::Error> for Error {
 /// assert_eq!(mt["fox"], "The quick brown fox jumps over the lazy dog.\n");
 /// assert_eq!(mt["lorem ipsum"], "Lorem ipsum dolor sit amet\n")
 /// ```
-pub fn parse_lines<I>(mut it: I) -> ParseResult
+pub fn parse_lines<I>(it: I) -> ParseResult
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    parse_lines_with(it, &ParseOptions::default())
+}
+
+/// Parses lines from an iterator as configured by `options`
+///
+/// # Examples
+/// ```
+/// use multitext::options::{EmptyKeys, ParseOptions};
+///
+/// let lines = ["@@ multitext header", "@@", "first"];
+/// let options = ParseOptions::new().empty_keys(EmptyKeys::Anonymous);
+/// let map = multitext::parse_lines_with(lines.iter(), &options).unwrap();
+/// assert_eq!(map["__anon_1"], "first\n");
+/// ```
+pub fn parse_lines_with<I>(it: I, options: &ParseOptions) -> ParseResult
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    parse_lines_into_with(it, options)
+}
+
+/// Parses lines from an iterator into any map built from `(key, body)` pairs,
+/// such as a `BTreeMap` for sorted keys
+///
+/// The pairs are collected in the order their sections first appear, once
+/// duplicate keys are resolved, so a map that keeps insertion order keeps the
+/// file's order.
+///
+/// # Examples
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// let lines = ["@@ multitext header", "@@ b", "2", "@@ a", "1"];
+/// let map: BTreeMap<String, String> = multitext::parse_lines_into(lines.iter()).unwrap();
+/// assert_eq!(map.keys().collect::<Vec<_>>(), ["a", "b", "multitext header"]);
+///
+/// let pairs: Vec<(String, String)> = multitext::parse_lines_into(lines.iter()).unwrap();
+/// assert_eq!(pairs[1], ("b".to_string(), "2\n".to_string()));
+/// ```
+pub fn parse_lines_into<M, I>(it: I) -> Result<M, Error>
+where M: std::iter::FromIterator<(String, String)>, I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    parse_lines_into_with(it, &ParseOptions::default())
+}
+
+/// Like [`parse_lines_into`], as configured by `options`
+pub fn parse_lines_into_with<M, I>(it: I, options: &ParseOptions) -> Result<M, Error>
+where M: std::iter::FromIterator<(String, String)>, I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    let concatenate = options.duplicate_keys == options::DuplicateKeys::Concatenate;
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut positions: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    parse::parse_sections(it, options, &mut NoopSink, |section| {
+        let (name, text) = section.into_parts();
+        match positions.get(&name) {
+            Some(&index) if concatenate => pairs[index].1.push_str(&text),
+            Some(&index) => pairs[index].1 = text,
+            None => {
+                positions.insert(name.clone(), pairs.len());
+                pairs.push((name, text));
+            }
+        }
+    })?;
+    Ok(pairs.into_iter().collect())
+}
+
+/// Parses lines from an iterator into a [`Document`], keeping the sections in
+/// the order they appear
+///
+/// # Examples
+/// ```
+/// let lines = ["%% multitext header", "%% b", "second", "%% a", "first"];
+///
+/// let doc = multitext::parse_document(lines.iter()).unwrap();
+/// let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+/// assert_eq!(keys, ["multitext header", "b", "a"]);
+/// assert_eq!(doc.get("a"), Some("first\n"));
+/// ```
+pub fn parse_document<I>(it: I) -> Result<Document, Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    parse_document_with_sink(it, &mut NoopSink)
+}
+
+/// Parses only the sections named in `keys`, and stops reading as soon as the
+/// last of them is complete
+///
+/// A section is complete once the marker line after it has been read, so at
+/// most one line past it is consumed. Only the first occurrence of each key
+/// counts, and continuation parts are joined as usual. Keys that never turn up
+/// are simply absent from the result, after the whole input has been read.
+///
+/// # Examples
+/// ```
+/// let lines = ["@@ multitext header", "@@ vertex", "v", "@@ fragment", "f", "@@ huge", "..."];
+/// let mut it = lines.iter();
+/// let map = multitext::parse_until_keys(&mut it, &["fragment", "vertex"]).unwrap();
+/// assert_eq!(map.len(), 2);
+/// assert_eq!(map["fragment"], "f\n");
+/// assert_eq!(it.next(), Some(&"..."));
+/// ```
+pub fn parse_until_keys<I>(it: I, keys: &[&str]) -> ParseResult
 where I: Iterator, <I as Iterator>::Item: AsRef<str>
 {
     let mut map = Map::new();
-    let mut line_number = 0;
-    let prefix = loop {
-        line_number += 1;
-        let line = it.next().ok_or_else(|| {
-            Error {
-                line_number: Some(line_number),
-                filename: None,
-                error_message: "missing multitext header".to_string(),
+    let mut remaining: Vec<&str> = keys.to_vec();
+    remaining.sort_unstable();
+    remaining.dedup();
+    let mut sections = SectionIter::new(it);
+    while !remaining.is_empty() {
+        let (key, body) = match sections.next() {
+            Some(section) => section?,
+            None => break,
+        };
+        let (base, complete) = match parse::parse_continuation_key(&key) {
+            Some((base, index, count)) if !remaining.contains(&key.as_str()) => (base, index == count),
+            _ => (key.as_str(), true),
+        };
+        if let Some(position) = remaining.iter().position(|k| *k == base) {
+            map.entry(base.to_string()).or_default().push_str(&body);
+            if complete {
+                remaining.remove(position);
             }
-        })?;
+        }
+    }
+    Ok(map)
+}
+
+/// Parses lines into a [`Document`], reporting anomalies such as duplicate or
+/// empty keys to `sink` along the way
+///
+/// # Examples
+/// ```
+/// use multitext::diagnostics::{Warning, WarningKind};
+///
+/// let lines = ["// multitext header", "// a", "1", "// a", "2"];
+/// let mut warnings: Vec<Warning> = Vec::new();
+/// let doc = multitext::parse_document_with_sink(lines.iter(), &mut warnings).unwrap();
+/// assert_eq!(&doc["a"], "2\n");
+/// assert_eq!(warnings[0].kind, WarningKind::DuplicateKey);
+/// assert_eq!(warnings[0].line_number, 4);
+/// ```
+pub fn parse_document_with_sink<I, S>(it: I, sink: &mut S) -> Result<Document, Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>, S: DiagnosticsSink + ?Sized
+{
+    let mut doc = Document::new();
+    let (marker, _) = parse::parse_sections(it, &ParseOptions::default(), sink, |section| doc.insert_section(section))?;
+    doc.set_marker(marker);
+    Ok(doc)
+}
 
-        if let Some(index) = line.as_ref().find("multitext header") {
-            break line.as_ref().split_at(index).0.trim_end().to_string();
+/// Parses lines into a [`Document`] as configured by `options`
+pub fn parse_document_with<I>(it: I, options: &ParseOptions) -> Result<Document, Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    parse_document_with_options_and_sink(it, options, &mut NoopSink)
+}
+
+/// Parses lines into as much of a [`Document`] as possible, returning every
+/// problem found rather than stopping at the first, for editors that show
+/// them all at once
+///
+/// Problems that `options` would make errors are reported as warnings
+/// instead: an empty key is kept, a duplicate key replaces the earlier
+/// section, and an overlong line is kept. Input without a header gives an
+/// empty document and a [`WarningKind::MissingHeader`](diagnostics::WarningKind::MissingHeader)
+/// warning. Any other error that stops parsing is reported as a warning of
+/// the matching kind, and the document keeps the sections read before it.
+/// Warnings are in line order.
+///
+/// # Examples
+/// ```
+/// use multitext::diagnostics::WarningKind;
+/// use multitext::options::{DuplicateKeys, ParseOptions};
+///
+/// let lines = ["stray", "@@ multitext header", "@@ a", "1", "@@", "@@ a", "2"];
+/// let options = ParseOptions::new().duplicate_keys(DuplicateKeys::Error);
+/// let (doc, warnings) = multitext::parse_lines_lenient(lines.iter(), &options);
+/// assert_eq!(&doc["a"], "2\n");
+/// let kinds: Vec<_> = warnings.iter().map(|w| (w.kind, w.line_number)).collect();
+/// assert_eq!(kinds, [
+///     (WarningKind::DiscardedPreamble, 1),
+///     (WarningKind::EmptyKey, 5),
+///     (WarningKind::DuplicateKey, 6),
+/// ]);
+///
+/// let (doc, warnings) = multitext::parse_lines_lenient(["no header"].iter(), &ParseOptions::new());
+/// assert!(doc.is_empty());
+/// assert_eq!(warnings[0].kind, WarningKind::MissingHeader);
+/// ```
+pub fn parse_lines_lenient<I>(it: I, options: &ParseOptions) -> (Document, Vec<diagnostics::Warning>)
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    use diagnostics::{Warning, WarningKind};
+
+    let mut warnings = Vec::new();
+    let mut long_lines = Vec::new();
+    let mut options = options.clone();
+    let max_line_len = options.max_line_len.take();
+    if options.empty_keys == options::EmptyKeys::Error {
+        options.empty_keys = options::EmptyKeys::Keep;
+    }
+    if options.duplicate_keys == options::DuplicateKeys::Error {
+        options.duplicate_keys = options::DuplicateKeys::KeepLast;
+    }
+    let lines = it.enumerate().map(|(index, line)| {
+        match max_line_len {
+            Some(max) if line.as_ref().len() > max => long_lines.push(Warning {
+                kind: WarningKind::LongLine,
+                line_number: index + 1,
+                message: format!("line is {} bytes long, over the limit of {}", line.as_ref().len(), max),
+            }),
+            _ => {}
         }
-    };
-
-    let mut name = "multitext header".to_string();
-    let mut text = String::new();
-    for line in it {
-        if line.as_ref().starts_with(&prefix) {
-            map.insert(name.clone(), text.clone());
-            name = line.as_ref().split_at(prefix.len()).1.trim().to_string();
-            text = String::new();
+        line
+    });
+    let mut doc = Document::new();
+    if let Err(e) = parse_into(lines, &options, &mut warnings, &mut doc) {
+        let kind = match e.kind {
+            ErrorKind::MissingHeader => WarningKind::MissingHeader,
+            ErrorKind::DuplicateKey => WarningKind::DuplicateKey,
+            ErrorKind::EmptyKey => WarningKind::EmptyKey,
+            ErrorKind::LineTooLong => WarningKind::LongLine,
+            _ => WarningKind::Unparsed,
+        };
+        warnings.push(Warning { kind, line_number: e.line_number.unwrap_or(1), message: e.error_message });
+    }
+    warnings.append(&mut long_lines);
+    warnings.sort_by_key(|w| w.line_number);
+    (doc, warnings)
+}
+
+/// Parses lines into a [`Document`] as configured by `options`, reporting
+/// anomalies to `sink`
+///
+/// # Examples
+/// ```
+/// use multitext::diagnostics::{Warning, WarningKind};
+/// use multitext::options::{LineEndings, ParseOptions};
+///
+/// let text = "## multitext header\r\n## a\r\none\n## b\r\ntwo\r\n";
+/// let options = ParseOptions::new().line_endings(LineEndings::Normalize);
+/// let mut warnings: Vec<Warning> = Vec::new();
+/// let doc = multitext::parse_document_with_options_and_sink(
+///     multitext::read_lines(text.as_bytes()), &options, &mut warnings).unwrap();
+/// assert_eq!(&doc["a"], "one\n");
+/// assert_eq!(warnings[0].kind, WarningKind::MixedLineEndings);
+/// assert_eq!(warnings[0].line_number, 3);
+/// ```
+pub fn parse_document_with_options_and_sink<I, S>(it: I, options: &ParseOptions, sink: &mut S)
+    -> Result<Document, Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>, S: DiagnosticsSink + ?Sized
+{
+    let mut doc = Document::new();
+    parse_into(it, options, sink, &mut doc)?;
+    Ok(doc)
+}
+
+/// Adds the sections parsed from `it` to `doc` as they are read, so that on
+/// an error `doc` holds the ones before it
+fn parse_into<I, S>(it: I, options: &ParseOptions, sink: &mut S, doc: &mut Document) -> Result<(), Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>, S: DiagnosticsSink + ?Sized
+{
+    let concatenate = options.duplicate_keys == options::DuplicateKeys::Concatenate;
+    let (marker, preamble) = parse::parse_sections(it, options, sink, |section| {
+        if concatenate {
+            doc.append_section(section);
         } else {
-            text.push_str(line.as_ref());
-            text.push('\n');
+            doc.insert_section(section);
+        }
+    })?;
+    doc.set_marker(marker);
+    doc.set_preamble(preamble);
+    Ok(())
+}
+
+/// Reads the lines of `reader`, removing each `\n` but keeping any `\r`
+/// before it so the parser can report mixed line endings
+///
+/// Reading stops at the first I/O error or invalid UTF-8.
+pub fn read_lines<R: std::io::BufRead>(reader: R) -> ReadLines<R> {
+    ReadLines { reader, error: None }
+}
+
+/// The iterator returned by [`read_lines`]
+#[derive(Debug)]
+pub struct ReadLines<R> {
+    reader: R,
+    error: Option<std::io::Error>,
+}
+
+impl<R: std::io::BufRead> Iterator for ReadLines<R> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                }
+                Some(line)
+            }
         }
     }
+}
 
-    map.insert(name, text);
+/// Parses everything `reader` yields, such as stdin, a socket or an
+/// in-memory cursor
+///
+/// Line endings are normalized as for files. Unlike [`read_lines`], an I/O
+/// error or invalid UTF-8 part way through fails the parse instead of ending
+/// the input early.
+///
+/// # Examples
+/// ```
+/// let input = std::io::Cursor::new("@@ multitext header\r\n@@ a\r\n1\r\n");
+/// let map = multitext::parse_reader(input).unwrap();
+/// assert_eq!(map["a"], "1\n");
+///
+/// let invalid = std::io::Cursor::new(b"@@ multitext header\n@@ a\n\xff\n".to_vec());
+/// assert_eq!(multitext::parse_reader(invalid).unwrap_err().kind(), multitext::ErrorKind::Io);
+/// ```
+pub fn parse_reader<R: std::io::BufRead>(reader: R) -> ParseResult {
+    parse_reader_with(reader, &ParseOptions::new().line_endings(options::LineEndings::Normalize))
+}
 
-    Ok(map)
+/// Like [`parse_reader`], as configured by `options`
+pub fn parse_reader_with<R: std::io::BufRead>(reader: R, options: &ParseOptions) -> ParseResult {
+    let mut lines = read_lines(reader);
+    let result = parse_lines_with(lines.by_ref(), options);
+    match lines.error.take() {
+        Some(e) => Err(e.into()),
+        None => result,
+    }
 }
 
 /// Opens and parses a file stored in the multitext format
 pub fn open_and_parse_file<P: AsRef<std::path::Path>>(path: P) -> ParseResult {
-    use std::io::BufRead;
-    let file = std::fs::File::open(path.as_ref())?;
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).map_err(|e| Error::from(e).with_path(path))?;
+    parse_reader(std::io::BufReader::new(file)).map_err(|e| e.with_path(path))
+}
+
+/// Opens and parses a file stored in the multitext format into a [`Document`]
+pub fn open_and_parse_document<P: AsRef<std::path::Path>>(path: P) -> Result<Document, Error> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).map_err(|e| Error::from(e).with_path(path))?;
     let file = std::io::BufReader::new(file);
-    parse_lines(file.lines().filter_map(|s| s.ok())).or_else(|mut e| {
-        e.filename = Some(path.as_ref().to_str().unwrap().to_string());
-        Err(e)
-    })
-}
\ No newline at end of file
+    let options = ParseOptions::new().line_endings(options::LineEndings::Normalize);
+    let mut doc = parse_document_with(read_lines(file), &options).map_err(|e| e.with_path(path))?;
+    doc.set_file(&path.to_string_lossy());
+    Ok(doc)
+}
@@ -0,0 +1,200 @@
+//! Sections whose text lives in another bundle
+//!
+//! A section with a `ref=BUNDLE#KEY` attribute is a link: its own body is
+//! ignored, and its text is that of section `KEY` of the bundle named
+//! `BUNDLE`. Without `#KEY` the section of the same key is meant. Shared
+//! sections can then live in one file and be used from many.
+//!
+//! Links are weak: parsing never follows them, and a link to something that
+//! doesn't exist is only an error once it is resolved. [`resolve_section`] and
+//! [`resolve_links`] fetch the bundles through a [`Resolver`], which decides
+//! what a bundle name means: [`FileResolver`] reads files, [`MemoryResolver`]
+//! looks them up in a registry held in memory, and any closure returning a
+//! document works too, for instance one that downloads it over HTTP.
+//!
+//! The `ref` attribute is only read when parsing with
+//! [`ParseOptions::attributes`](crate::options::ParseOptions::attributes).
+
+use crate::options::{LineEndings, ParseOptions};
+use crate::{Document, Error, ErrorKind, Section};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The attribute that makes a section a link
+pub const REF_ATTR: &str = "ref";
+
+/// How many links in a row are followed before giving up
+const MAX_DEPTH: usize = 16;
+
+/// Finds the documents that links name
+pub trait Resolver {
+    /// Returns the document called `bundle` in a `ref` attribute
+    fn resolve(&mut self, bundle: &str) -> Result<Arc<Document>, Error>;
+}
+
+impl<F: FnMut(&str) -> Result<Arc<Document>, Error>> Resolver for F {
+    fn resolve(&mut self, bundle: &str) -> Result<Arc<Document>, Error> {
+        self(bundle)
+    }
+}
+
+/// Resolves bundle names as paths relative to a base directory, reading each
+/// file once
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    base: PathBuf,
+    loaded: HashMap<PathBuf, Arc<Document>>,
+}
+
+impl FileResolver {
+    pub fn new<P: AsRef<Path>>(base: P) -> FileResolver {
+        FileResolver { base: base.as_ref().to_path_buf(), loaded: HashMap::new() }
+    }
+}
+
+impl Resolver for FileResolver {
+    fn resolve(&mut self, bundle: &str) -> Result<Arc<Document>, Error> {
+        let path = self.base.join(bundle);
+        if let Some(doc) = self.loaded.get(&path) {
+            return Ok(Arc::clone(doc));
+        }
+        let text = std::fs::read_to_string(&path).map_err(|e| Error::from(e).with_path(&path))?;
+        let options = ParseOptions::new().attributes(true).line_endings(LineEndings::Normalize);
+        let mut doc = crate::parse_document_with(text.lines(), &options).map_err(|e| e.with_path(&path))?;
+        doc.set_file(&path.to_string_lossy());
+        let doc = Arc::new(doc);
+        self.loaded.insert(path, Arc::clone(&doc));
+        Ok(doc)
+    }
+}
+
+/// Resolves bundle names from documents registered in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    bundles: HashMap<String, Arc<Document>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> MemoryResolver {
+        MemoryResolver::default()
+    }
+
+    /// Registers `doc` under `name`, replacing any document already there
+    pub fn insert<N: Into<String>>(&mut self, name: N, doc: Document) {
+        self.bundles.insert(name.into(), Arc::new(doc));
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn resolve(&mut self, bundle: &str) -> Result<Arc<Document>, Error> {
+        self.bundles.get(bundle).cloned()
+            .ok_or_else(|| Error::new(ErrorKind::Link, format!("no bundle named \"{}\"", bundle)))
+    }
+}
+
+/// The bundle and key `section` links to, if it is a link
+pub fn link_target(section: &Section) -> Option<(&str, &str)> {
+    let target = section.attr(REF_ATTR)?;
+    Some(target.split_once('#').unwrap_or((target, section.key())))
+}
+
+/// The text of section `key` of `doc`, following links, or `None` if there
+/// is no such section
+///
+/// Fails if a linked bundle can't be resolved or lacks the section, or if
+/// links lead around in a circle.
+///
+/// # Examples
+/// ```
+/// use multitext::link::{resolve_section, MemoryResolver};
+/// use multitext::options::ParseOptions;
+///
+/// let mut registry = MemoryResolver::new();
+/// registry.insert("lib.mt", vec![("noise", "float noise(vec2 p);\n")].into_iter().collect());
+///
+/// let lines = ["@@ multitext header", "@@ common ref=lib.mt#noise", "@@ main", "void main() {}"];
+/// let doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+/// let common = resolve_section(&doc, "common", &mut registry).unwrap();
+/// assert_eq!(common.as_deref(), Some("float noise(vec2 p);\n"));
+/// assert_eq!(resolve_section(&doc, "main", &mut registry).unwrap().as_deref(), Some("void main() {}\n"));
+/// ```
+pub fn resolve_section<R: Resolver + ?Sized>(doc: &Document, key: &str, resolver: &mut R)
+    -> Result<Option<String>, Error>
+{
+    let section = match doc.section(key) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+    let (mut bundle, mut target) = match link_target(section) {
+        Some((bundle, target)) => (bundle.to_string(), target.to_string()),
+        None => return Ok(Some(section.body().to_string())),
+    };
+    let mut path = vec![format!("\"{}\"", key)];
+    loop {
+        path.push(format!("{}#{}", bundle, target));
+        if path.len() > MAX_DEPTH {
+            return Err(section.error(ErrorKind::Link, format!("links go too deep: {}", path.join(" -> "))));
+        }
+        if path[1..path.len() - 1].contains(path.last().unwrap()) {
+            return Err(section.error(ErrorKind::Link, format!("link cycle: {}", path.join(" -> "))));
+        }
+        let linked = resolver.resolve(&bundle)?;
+        let found = linked.section(&target).ok_or_else(|| {
+            section.error(ErrorKind::Link, format!("{} links to missing section \"{}\" of \"{}\"", path[0], target, bundle))
+        })?;
+        match link_target(found) {
+            Some((next_bundle, next_target)) => {
+                bundle = next_bundle.to_string();
+                target = next_target.to_string();
+            }
+            None => return Ok(Some(found.body().to_string())),
+        }
+    }
+}
+
+/// A copy of `doc` with every link replaced by the text it links to, and its
+/// `ref` attribute removed
+pub fn resolve_links<R: Resolver + ?Sized>(doc: &Document, resolver: &mut R) -> Result<Document, Error> {
+    let mut resolved = doc.clone();
+    for section in &mut resolved.sections {
+        if link_target(section).is_some() {
+            section.body = resolve_section(doc, section.key(), resolver)?.unwrap_or_default();
+            section.attrs.retain(|(name, _)| name != REF_ATTR);
+        }
+    }
+    Ok(resolved)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Document {
+        crate::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_links() {
+        let mut registry = MemoryResolver::new();
+        registry.insert("a.mt", parse(&["@@ multitext header", "@@ x ref=b.mt", "@@ loop ref=b.mt"]));
+        registry.insert("b.mt", parse(&["@@ multitext header", "@@ x", "shared", "@@ loop ref=a.mt"]));
+
+        let doc = parse(&["@@ multitext header", "@@ x ref=a.mt stage=1", "@@ gone ref=a.mt#nothing"]);
+        assert_eq!(resolve_section(&doc, "x", &mut registry).unwrap().as_deref(), Some("shared\n"));
+        let e = resolve_section(&doc, "gone", &mut registry).unwrap_err();
+        assert!(e.to_string().contains("links to missing section \"nothing\" of \"a.mt\""));
+
+        let doc = parse(&["@@ multitext header", "@@ loop ref=a.mt", "@@ elsewhere ref=c.mt"]);
+        let e = resolve_section(&doc, "loop", &mut registry).unwrap_err();
+        assert!(e.to_string().contains("link cycle: \"loop\" -> a.mt#loop -> b.mt#loop -> a.mt#loop"));
+        assert!(resolve_section(&doc, "elsewhere", &mut registry).is_err());
+
+        let doc = parse(&["@@ multitext header", "@@ x ref=a.mt stage=1", "@@ own", "text"]);
+        let resolved = resolve_links(&doc, &mut registry).unwrap();
+        assert_eq!(&resolved["x"], "shared\n");
+        assert_eq!(resolved.section("x").unwrap().attrs().collect::<Vec<_>>(), [("stage", "1")]);
+        assert_eq!(&resolved["own"], "text\n");
+    }
+}
@@ -0,0 +1,70 @@
+//! Loading vertex/fragment shader pairs for miniquad and macroquad
+//!
+//! The strings in a [`ShaderBundle`] are in the shape macroquad's
+//! `ShaderSource::Glsl { vertex, fragment }` expects. Includes between sections
+//! are resolved before the stages are handed out.
+
+use crate::{glsl, Document, Error, ErrorKind};
+use crate::shader::Stage;
+
+/// The two stages of a shader program plus every other section of the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderBundle {
+    pub vertex: String,
+    pub fragment: String,
+    /// The remaining sections, including the multitext header, with their
+    /// attributes and the file's marker
+    pub meta: Document,
+}
+
+impl ShaderBundle {
+    /// Picks the vertex and fragment stages out of `doc`
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = [
+    ///     "@@ multitext header",
+    ///     "@@ vertex shader", "void main() {}",
+    ///     "@@ fragment shader", "void main() {}",
+    ///     "@@ uniforms", "time",
+    /// ];
+    /// let doc = multitext::parse_document(lines.iter()).unwrap();
+    /// let (vertex, fragment, meta) = multitext::macroquad::ShaderBundle::from_document(doc)
+    ///     .unwrap()
+    ///     .into_parts();
+    /// assert_eq!(vertex, "void main() {}\n");
+    /// assert_eq!(fragment, "void main() {}\n");
+    /// assert_eq!(&meta["uniforms"], "time\n");
+    /// assert_eq!(meta.marker(), Some("@@"));
+    /// ```
+    pub fn from_document(doc: Document) -> Result<ShaderBundle, Error> {
+        let vertex = stage_source(&doc, Stage::Vertex)?;
+        let fragment = stage_source(&doc, Stage::Fragment)?;
+        let mut meta = doc;
+        meta.retain_sections(|s| !matches!(Stage::from_key(s.key()), Some(Stage::Vertex) | Some(Stage::Fragment)));
+        Ok(ShaderBundle { vertex, fragment, meta })
+    }
+
+    pub fn into_parts(self) -> (String, String, Document) {
+        (self.vertex, self.fragment, self.meta)
+    }
+}
+
+/// Opens a multitext file and picks out its vertex and fragment stages
+pub fn load_shader_bundle<P: AsRef<std::path::Path>>(path: P) -> Result<ShaderBundle, Error> {
+    let doc = crate::open_and_parse_document(path.as_ref())?;
+    ShaderBundle::from_document(doc).map_err(|e| e.with_path(path.as_ref()))
+}
+
+fn stage_source(doc: &Document, stage: Stage) -> Result<String, Error> {
+    let name = if stage == Stage::Vertex { "vertex" } else { "fragment" };
+    let mut keys = doc.iter().map(|s| s.key()).filter(|k| Stage::from_key(k) == Some(stage));
+    let key = keys.next().ok_or_else(|| {
+        Error::new(ErrorKind::MissingSection, format!("shader bundle has no {} shader section", name))
+    })?;
+    if let Some(other) = keys.next() {
+        let message = format!("shader bundle has more than one {} shader (\"{}\" and \"{}\")", name, key, other);
+        return Err(Error::new(ErrorKind::Other, message).with_line(doc.section(other).and_then(|s| s.line_number())));
+    }
+    glsl::resolve_includes(doc, key)
+}
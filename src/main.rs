@@ -0,0 +1,989 @@
+//! The `multitext` command line tool
+//!
+//! ```text
+//! multitext lint [--strict] [FILES...] [--recursive DIR] [--glob PATTERN] [--jobs N] [--json]
+//! multitext fmt [--check] [FILES...] [--recursive DIR] [--glob PATTERN] [--jobs N] [--json]
+//! multitext stats [FILES...] [--recursive DIR] [--glob PATTERN] [--jobs N] [--json]
+//! multitext explain [CODE]
+//! multitext pack DIR [--output FILE] [--marker MARKER] [--respect-ignore] [TEMPLATES]
+//! multitext unpack FILE [--dir DIR] [--dry-run] [TEMPLATES]
+//! multitext list FILE [--where QUERY]
+//! multitext get FILE KEY...
+//! multitext cat FILE --where QUERY
+//! multitext diff OLD NEW [--context N]
+//! multitext watch FILE [--exec COMMAND] [--unpack DIR] [--interval MS]
+//! multitext log FILE [KEY]
+//! multitext show FILE REVISION [KEY] [--restore]
+//! multitext apply FILE EDITS
+//! multitext redact FILE [KEYS...] [--pattern PATTERN] [--output FILE]
+//! ```
+//!
+//! `list` prints one line per section: its line count, its size in bytes and
+//! its key. `get` prints the bodies of the named sections to stdout, and fails
+//! with status 1 if one of them is missing. `cat` prints the bodies of the
+//! sections matching a [query](multitext::query), in file order, and `list`
+//! lists only those with `--where`.
+//!
+//! `diff` compares two files section by section, so reordering sections isn't
+//! a change. It lists added, removed and renamed sections, and shows a unified
+//! diff of each modified body with `--context` lines around each change, 3 by
+//! default. Like `diff`, it exits with status 1 if the files differ.
+//!
+//! `watch` checks a file every `--interval` milliseconds, 250 by default, and
+//! prints the key of each section added or edited since the last check. With
+//! `--exec`, it also runs `COMMAND` through the shell once per changed
+//! section, with `{key}` replaced by the section's key, quoted for the shell,
+//! and `{file}` by the file's path. With `--unpack`, it writes the changed
+//! sections out under `DIR` as `unpack` would. A file that fails to parse,
+//! perhaps because it is still being saved, is reported and checked again; it
+//! runs until interrupted.
+//!
+//! `log` and `show` read the [versions](multitext::versions) a file keeps of
+//! its sections. `log` lists them newest first, one per line: how many
+//! versions back it is, when it was made, in seconds since the Unix epoch,
+//! and the key of its section, limited to `KEY` if given. `show` prints the
+//! file as it was at `REVISION`, either a number of versions back as `log`
+//! counts them or `@TIMESTAMP`, or only the body of `KEY` back then. With
+//! `--restore`, it writes that state back to the file instead.
+//!
+//! `apply` makes the edits listed in the JSON file `EDITS`, or read from
+//! stdin if it is `-`, and writes the file back; see
+//! [`multitext::apply`](multitext::apply) for the format. Nothing is written
+//! if any edit fails, and everything outside the edited sections, including
+//! text above the header, is kept as it was.
+//!
+//! `redact` replaces the bodies of the named sections, and of those whose keys
+//! match `--pattern`, with [placeholders](multitext::redact) recording their
+//! size and hash. It writes the result to stdout unless `--output` is given,
+//! leaving `FILE` as it was, and fails with status 1 if a named section is
+//! missing.
+//!
+//! `pack` bundles every file under a directory into one multitext file, on
+//! stdout unless `--output` is given, and `unpack` writes the sections back
+//! out as files under `--dir`, the current directory by default. Unpacking
+//! refuses keys that would write outside that directory. Keys are the files'
+//! relative paths unless `--key-template` and `--path-template` say otherwise;
+//! see [`KeyTemplate`](multitext::codec::KeyTemplate).
+//!
+//! `fmt` rewrites each file in canonical form: one space between the marker
+//! and the key, no trailing whitespace on marker lines, and the `[format]`
+//! settings of the config file applied, such as `max_blank_lines` to collapse
+//! runs of blank lines between sections. Text above the header is kept as it
+//! is. With `--check` it only reports the files it would change. Continuation
+//! sections keep their own keys, and a file with a duplicate key is reported
+//! as a parse error and left alone rather than losing one of the bodies.
+//!
+//! # Plugins
+//!
+//! Any other command `NAME` runs the program `multitext-NAME` found on the
+//! `PATH`, the way cargo finds its subcommands, with the remaining arguments
+//! passed through and the exit status passed back. The plugin reads one JSON
+//! object from its stdin:
+//!
+//! ```text
+//! {"abi":1,"file":"shaders.mt","document":{"marker":"@@","sections":[...]}}
+//! ```
+//!
+//! `file` is the first argument that isn't an option, if it names an existing
+//! file, and `document` is that file parsed with attributes, in the format of
+//! [`Document::to_json`](multitext::Document::to_json); both are `null`
+//! otherwise. The environment variable `MULTITEXT_PLUGIN_ABI` holds the
+//! version of this format, currently 1, which will only change in
+//! incompatible ways with a new number.
+//!
+//! Files given to `lint`, `fmt` and `stats` are processed in parallel. Each
+//! file's output is printed in order, followed by a one-line summary on
+//! stderr. With `--json`, each file's problems are printed instead as one
+//! JSON object per line, in the format of
+//! [`CheckReport::to_json`](multitext::check::CheckReport::to_json).
+//!
+//! Every problem has a stable code, which `multitext explain` describes along
+//! with how to fix it: the [lint codes](multitext::check::Problem),
+//! `parse-error`, `io-error`, `not-formatted` from `fmt --check`,
+//! `conflict` when a file changes while `fmt` is rewriting it,
+//! `normalize-error` when one of the config's normalizing commands fails on a
+//! section, and `config-error` for an unreadable config file. The exit status
+//! reports the most severe kind of problem seen:
+//!
+//! | status | meaning                                          |
+//! |--------|--------------------------------------------------|
+//! | 0      | every file passed                                |
+//! | 1      | validation failure: lint problems, unformatted   |
+//! | 2      | bad command line                                 |
+//! | 3      | a file or its config could not be parsed         |
+//! | 4      | a file could not be read or written              |
+//! | 5      | conflict: a file changed while being rewritten   |
+//!
+//! Lint and format settings come from the nearest
+//! [`.multitext.toml`](multitext::config) above each file; `--strict` turns on
+//! strict linting regardless. Without a config file, references between
+//! sections are checked and everything else is left at its default.
+//!
+//! I/O errors outrank parse errors, which outrank conflicts, which outrank
+//! validation failures.
+
+use multitext::apply::{apply_operations, parse_operations};
+use multitext::batch;
+use multitext::check::{check_file, CheckReport, Problem};
+use multitext::codec::KeyTemplate;
+use multitext::config::Config;
+use multitext::options::{DuplicateKeys, ParseOptions, WriteOptions};
+use multitext::pack::{pack_dir_with, unpack_dir_with, PackOptions, UnpackOptions};
+use multitext::redact::Selection;
+use multitext::versions::Revision;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const COMMANDS: &[&str] = &["lint", "fmt", "stats"];
+
+const USAGE: &str = "usage: multitext <lint|fmt|stats> [--strict] [--check] [--json] [FILES...] \
+                     [--recursive DIR] [--glob PATTERN] [--jobs N]
+       multitext explain [CODE]
+       multitext pack DIR [--output FILE] [--marker MARKER] [--respect-ignore] [TEMPLATES]
+       multitext unpack FILE [--dir DIR] [--dry-run] [TEMPLATES]
+       multitext list FILE [--where QUERY]
+       multitext get FILE KEY...
+       multitext cat FILE --where QUERY
+       multitext diff OLD NEW [--context N]
+       multitext watch FILE [--exec COMMAND] [--unpack DIR] [--interval MS]
+       multitext log FILE [KEY]
+       multitext show FILE REVISION [KEY] [--restore]
+       multitext apply FILE EDITS
+       multitext redact FILE [KEYS...] [--pattern PATTERN] [--output FILE]
+       multitext NAME [ARGS...]    (runs the plugin multitext-NAME)
+where TEMPLATES are [--key-template TEMPLATE] [--path-template TEMPLATE]";
+
+#[derive(Debug, Default)]
+struct Args {
+    command: String,
+    files: Vec<PathBuf>,
+    recursive: Vec<PathBuf>,
+    glob: Option<String>,
+    jobs: Option<usize>,
+    strict: bool,
+    check: bool,
+    json: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let command = args.next().ok_or("missing command")?;
+    if !COMMANDS.contains(&command.as_str()) {
+        return Err(format!("unknown command \"{}\"", command));
+    }
+    let mut parsed = Args { command, ..Args::default() };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--recursive" | "-r" => parsed.recursive.push(value(&arg)?.into()),
+            "--glob" => parsed.glob = Some(value(&arg)?),
+            "--jobs" | "-j" => {
+                let jobs = value(&arg)?;
+                parsed.jobs = Some(jobs.parse().map_err(|_| format!("invalid job count \"{}\"", jobs))?);
+            }
+            "--strict" => parsed.strict = true,
+            "--check" => parsed.check = true,
+            "--json" => parsed.json = true,
+            flag if flag.starts_with('-') => return Err(format!("unknown option \"{}\"", flag)),
+            file => parsed.files.push(file.into()),
+        }
+    }
+    if parsed.glob.is_some() && parsed.recursive.is_empty() {
+        return Err("--glob needs --recursive".to_string());
+    }
+    Ok(parsed)
+}
+
+/// The arguments of a command that works on named files rather than batches
+#[derive(Debug, Default)]
+struct Flags {
+    positional: Vec<String>,
+    values: Vec<(String, String)>,
+    switches: Vec<String>,
+}
+
+/// Short forms of flags, with the long forms they stand for
+const SHORT_FLAGS: &[(&str, &str)] = &[("-o", "--output"), ("-d", "--dir")];
+
+impl Flags {
+    /// Sorts `args` into positional arguments, the flags in `options`, which
+    /// take a value, and the flags in `switches`, which don't
+    fn parse<I: Iterator<Item = String>>(mut args: I, options: &[&str], switches: &[&str]) -> Result<Flags, String> {
+        let mut flags = Flags::default();
+        while let Some(arg) = args.next() {
+            let name = SHORT_FLAGS.iter().find(|(short, _)| *short == arg).map_or(arg.as_str(), |(_, long)| long);
+            if options.contains(&name) {
+                let value = args.next().ok_or(format!("{} needs a value", arg))?;
+                flags.values.push((name.to_string(), value));
+            } else if switches.contains(&name) {
+                flags.switches.push(name.to_string());
+            } else if name.starts_with('-') && name != "-" {
+                return Err(format!("unknown option \"{}\"", arg));
+            } else {
+                flags.positional.push(arg);
+            }
+        }
+        Ok(flags)
+    }
+
+    /// The last value given for `name`
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.iter().any(|n| n == name)
+    }
+
+    /// The single positional argument, described as `what` if it is missing
+    fn one(&self, what: &str) -> Result<&str, String> {
+        match &self.positional[..] {
+            [arg] => Ok(arg),
+            [] => Err(format!("missing {}", what)),
+            [_, extra, ..] => Err(format!("unexpected argument \"{}\"", extra)),
+        }
+    }
+
+    /// The key template from `--key-template` and `--path-template`, if either
+    /// was given
+    fn key_template(&self) -> Result<Option<KeyTemplate>, String> {
+        let (key, path) = (self.value("--key-template"), self.value("--path-template"));
+        if key.is_none() && path.is_none() {
+            return Ok(None);
+        }
+        KeyTemplate::new(key.unwrap_or("{path}"), path.unwrap_or("{path}")).map(Some).map_err(|e| e.to_string())
+    }
+}
+
+const TEMPLATE_FLAGS: [&str; 2] = ["--key-template", "--path-template"];
+
+/// The kinds of failure, least severe first; the exit status reports the
+/// most severe one seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Failure {
+    Validation,
+    Conflict,
+    Parse,
+    Io,
+}
+
+impl Failure {
+    fn of(code: &str) -> Failure {
+        match code {
+            "io-error" => Failure::Io,
+            "parse-error" | "config-error" => Failure::Parse,
+            "conflict" => Failure::Conflict,
+            _ => Failure::Validation,
+        }
+    }
+
+    fn exit_code(self) -> u8 {
+        match self {
+            Failure::Validation => 1,
+            Failure::Parse => 3,
+            Failure::Io => 4,
+            Failure::Conflict => 5,
+        }
+    }
+}
+
+/// What processing one file produced
+#[derive(Debug)]
+struct Outcome {
+    report: CheckReport,
+    /// Text printed for a file that didn't fail
+    note: String,
+    sections: usize,
+    bytes: usize,
+}
+
+impl Outcome {
+    fn new(path: &Path) -> Outcome {
+        Outcome {
+            report: CheckReport { path: path.to_path_buf(), problems: Vec::new() },
+            note: String::new(),
+            sections: 0,
+            bytes: 0,
+        }
+    }
+
+    fn failure(path: &Path, code: &'static str, line_number: Option<usize>, message: String) -> Outcome {
+        let mut outcome = Outcome::new(path);
+        outcome.report.problems.push(Problem { code, line_number, key: None, message });
+        outcome
+    }
+
+    fn io_error(path: &Path, e: std::io::Error) -> Outcome {
+        Outcome::failure(path, "io-error", None, e.to_string())
+    }
+
+    fn parse_error(path: &Path, e: multitext::Error) -> Outcome {
+        let code = if e.kind() == multitext::ErrorKind::Io { "io-error" } else { "parse-error" };
+        Outcome::failure(path, code, e.line_number(), e.message().to_string())
+    }
+
+    fn failure_kind(&self) -> Option<Failure> {
+        self.report.problems.iter().map(|p| Failure::of(p.code)).max()
+    }
+}
+
+/// The settings for `path` from the nearest config file, or the defaults
+fn config_for(path: &Path) -> Result<Config, Outcome> {
+    match Config::discover(path) {
+        Ok(Some(config)) => Ok(config),
+        Ok(None) => Ok(Config { references: true, ..Config::default() }),
+        Err(e) => Err(Outcome::failure(path, "config-error", None, e.to_string())),
+    }
+}
+
+fn lint(path: &Path, strict: bool) -> Outcome {
+    let config = match config_for(path) {
+        Ok(config) => config,
+        Err(outcome) => return outcome,
+    };
+    let profile = match config.check_profile() {
+        Ok(profile) => profile.strict(strict || config.strict),
+        Err(e) => return Outcome::failure(path, "config-error", None, e.to_string()),
+    };
+    let mut outcome = Outcome::new(path);
+    outcome.report = check_file(path, &profile);
+    outcome
+}
+
+fn fmt(path: &Path, check: bool) -> Outcome {
+    let config = match config_for(path) {
+        Ok(config) => config,
+        Err(outcome) => return outcome,
+    };
+    let original = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return Outcome::io_error(path, e),
+    };
+    let options = parse_options()
+        .duplicate_keys(DuplicateKeys::Error)
+        .join_continuations(false);
+    let mut doc = match multitext::parse_document_with(original.lines(), &options) {
+        Ok(doc) => doc,
+        Err(e) => return Outcome::parse_error(path, e),
+    };
+    if !config.normalizers.is_empty() {
+        if let Err(e) = doc.normalize(&config.normalizers()) {
+            return Outcome::failure(path, "normalize-error", None, e.to_string());
+        }
+    }
+    let formatted = match doc.write_with(Vec::new(), &config.write_options()) {
+        Ok(bytes) => String::from_utf8(bytes).expect("the writer only writes parsed text"),
+        Err(e) => return Outcome::io_error(path, e),
+    };
+    if formatted == original {
+        return Outcome::new(path);
+    }
+    if check {
+        return Outcome::failure(path, "not-formatted", None, "file is not formatted".to_string());
+    }
+    match std::fs::read_to_string(path) {
+        Ok(current) if current == original => {}
+        Ok(_) => return Outcome::failure(path, "conflict", None, "file changed while formatting".to_string()),
+        Err(e) => return Outcome::io_error(path, e),
+    }
+    match std::fs::write(path, formatted) {
+        Ok(()) => {
+            let mut outcome = Outcome::new(path);
+            outcome.note = format!("formatted {}\n", path.display());
+            outcome
+        }
+        Err(e) => Outcome::io_error(path, e),
+    }
+}
+
+fn stats(path: &Path) -> Outcome {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return Outcome::io_error(path, e),
+    };
+    match multitext::parse_document_with(text.lines(), &parse_options()) {
+        Ok(doc) => {
+            let mut outcome = Outcome::new(path);
+            outcome.sections = doc.len();
+            outcome.bytes = doc.iter().map(|s| s.body().len()).sum();
+            outcome.note = format!("{} : {} sections, {} bytes\n", path.display(), outcome.sections, outcome.bytes);
+            outcome
+        }
+        Err(e) => Outcome::parse_error(path, e),
+    }
+}
+
+fn run(args: Args) -> Result<Option<Failure>, String> {
+    let mut files = args.files;
+    let glob = args.glob.as_deref().unwrap_or("**/*.mt");
+    for dir in &args.recursive {
+        files.extend(batch::find_files(dir, glob).map_err(|e| format!("{} : {}", dir.display(), e))?);
+    }
+    if files.is_empty() {
+        return Err("no files given".to_string());
+    }
+
+    let (check, strict) = (args.check, args.strict);
+    let outcomes = match args.command.as_str() {
+        "lint" => batch::run_parallel(&files, args.jobs, |path| lint(path, strict)),
+        "fmt" => batch::run_parallel(&files, args.jobs, |path| fmt(path, check)),
+        "stats" => batch::run_parallel(&files, args.jobs, stats),
+        _ => unreachable!("commands are checked when parsing arguments"),
+    };
+
+    for outcome in &outcomes {
+        if args.json {
+            println!("{}", outcome.report.to_json());
+        } else if outcome.report.passed() {
+            print!("{}", outcome.note);
+        } else {
+            print!("{}", outcome.report);
+        }
+    }
+    let failed = outcomes.iter().filter(|o| !o.report.passed()).count();
+    eprint!("{} files, {} failed", outcomes.len(), failed);
+    if args.command == "stats" {
+        let sections: usize = outcomes.iter().map(|o| o.sections).sum();
+        let bytes: usize = outcomes.iter().map(|o| o.bytes).sum();
+        eprint!(", {} sections, {} bytes", sections, bytes);
+    }
+    eprintln!();
+    Ok(outcomes.iter().filter_map(Outcome::failure_kind).max())
+}
+
+/// Prints the explanation of `code`, or lists every code
+fn explain(code: Option<String>) -> Result<Option<Failure>, String> {
+    match code {
+        Some(code) => {
+            let text = multitext::check::explain(&code).ok_or(format!("no problem has the code \"{}\"", code))?;
+            println!("{}", text);
+        }
+        None => {
+            for code in multitext::check::codes() {
+                println!("{}", code);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reports an error that stops a single-file command, returning its kind
+fn fail(failure: Failure, message: impl std::fmt::Display) -> Result<Option<Failure>, String> {
+    eprintln!("multitext: {}", message);
+    Ok(Some(failure))
+}
+
+fn pack(flags: Flags) -> Result<Option<Failure>, String> {
+    let dir = flags.one("directory to pack")?;
+    let mut options = PackOptions::new().respect_ignore_files(flags.switch("--respect-ignore"));
+    if let Some(template) = flags.key_template()? {
+        options = options.codec(template);
+    }
+    let doc = match pack_dir_with(dir, &options) {
+        Ok(doc) => doc,
+        Err(e) => return fail(Failure::Io, e),
+    };
+    let mut write_options = WriteOptions::new();
+    if let Some(marker) = flags.value("--marker") {
+        write_options = write_options.marker(marker);
+    }
+    let bytes = match doc.write_with(Vec::new(), &write_options) {
+        Ok(bytes) => bytes,
+        Err(e) => return fail(Failure::Io, e),
+    };
+    let written = match flags.value("--output") {
+        Some(path) => std::fs::write(path, &bytes).map_err(|e| format!("{} : {}", path, e)),
+        None => std::io::Write::write_all(&mut std::io::stdout(), &bytes).map_err(|e| e.to_string()),
+    };
+    match written {
+        Ok(()) => {
+            eprintln!("packed {} files, {} bytes", doc.len(), bytes.len());
+            Ok(None)
+        }
+        Err(message) => fail(Failure::Io, message),
+    }
+}
+
+/// How every subcommand parses files, so they all see the same keys
+fn parse_options() -> ParseOptions {
+    ParseOptions::new().attributes(true).keep_preamble(true)
+}
+
+/// Reads and parses the file at `path`, or says why it couldn't
+fn load(path: &str) -> Result<multitext::Document, (Failure, String)> {
+    let text = std::fs::read_to_string(path).map_err(|e| (Failure::Io, format!("{} : {}", path, e)))?;
+    multitext::parse_document_with(text.lines(), &parse_options())
+        .map_err(|e| (Failure::Parse, format!("{} : {}", path, e.report().to_string().trim_end())))
+}
+
+fn unpack(flags: Flags) -> Result<Option<Failure>, String> {
+    let path = flags.one("file to unpack")?;
+    let dir = Path::new(flags.value("--dir").unwrap_or("."));
+    let doc = match load(path) {
+        Ok(doc) => doc,
+        Err((failure, message)) => return fail(failure, message),
+    };
+    let mut options = UnpackOptions::new().dry_run(flags.switch("--dry-run"));
+    if let Some(template) = flags.key_template()? {
+        options = options.codec(template);
+    }
+    let unsafe_keys: Vec<String> = doc.iter()
+        .filter(|s| s.key() != "multitext header")
+        .filter_map(|s| options.resolve(dir, s.key()).err())
+        .collect();
+    if !unsafe_keys.is_empty() {
+        return fail(Failure::Validation, format!("{} : {}", path, unsafe_keys.join("; ")));
+    }
+    match unpack_dir_with(&doc, dir, &options) {
+        Ok(report) => {
+            print!("{}", report);
+            Ok(None)
+        }
+        Err(e) => fail(Failure::Io, e),
+    }
+}
+
+fn list(flags: Flags) -> Result<Option<Failure>, String> {
+    let doc = match load(flags.one("file to list")?) {
+        Ok(doc) => doc,
+        Err((failure, message)) => return fail(failure, message),
+    };
+    for section in selected(&doc, flags.value("--where"))? {
+        println!("{:>6} {:>8}  {}", section.body().lines().count(), section.body().len(), section.key());
+    }
+    Ok(None)
+}
+
+fn cat(flags: Flags) -> Result<Option<Failure>, String> {
+    let query = flags.value("--where").ok_or("cat needs --where")?;
+    let doc = match load(flags.one("file to read")?) {
+        Ok(doc) => doc,
+        Err((failure, message)) => return fail(failure, message),
+    };
+    for section in selected(&doc, Some(query))? {
+        print!("{}", section.body());
+    }
+    Ok(None)
+}
+
+/// The sections of `doc` matching `query`, or all of them without one
+fn selected<'a>(doc: &'a multitext::Document, query: Option<&str>) -> Result<Vec<&'a multitext::Section>, String> {
+    match query {
+        Some(query) => doc.select(query).map_err(|e| format!("invalid query \"{}\": {}", query, e.message())),
+        None => Ok(doc.iter().collect()),
+    }
+}
+
+fn get(flags: Flags) -> Result<Option<Failure>, String> {
+    let (path, keys) = flags.positional.split_first().ok_or("missing file to read")?;
+    if keys.is_empty() {
+        return Err("missing key to get".to_string());
+    }
+    let doc = match load(path) {
+        Ok(doc) => doc,
+        Err((failure, message)) => return fail(failure, message),
+    };
+    let mut missing = None;
+    for key in keys {
+        match doc.get(key) {
+            Some(body) => print!("{}", body),
+            None => missing = fail(Failure::Validation, format!("{} : no section \"{}\"", path, key))?,
+        }
+    }
+    Ok(missing)
+}
+
+fn log(flags: Flags) -> Result<Option<Failure>, String> {
+    let (path, key) = match &flags.positional[..] {
+        [path] => (path, None),
+        [path, key] => (path, Some(key.as_str())),
+        [_, _, extra, ..] => return Err(format!("unexpected argument \"{}\"", extra)),
+        [] => return Err("missing file to read".to_string()),
+    };
+    let doc = match load(path) {
+        Ok(doc) => doc,
+        Err((failure, message)) => return fail(failure, message),
+    };
+    let versions = doc.versions();
+    for (back, version) in versions.iter().rev().enumerate() {
+        if key.is_none_or(|key| key == version.base_key()) {
+            println!("{:>4}  {}  {}", back + 1, version.timestamp, version.base_key());
+        }
+    }
+    Ok(None)
+}
+
+fn show(flags: Flags) -> Result<Option<Failure>, String> {
+    let (path, revision, key) = match &flags.positional[..] {
+        [path, revision] => (path, revision, None),
+        [path, revision, key] => (path, revision, Some(key)),
+        [_, _, _, extra, ..] => return Err(format!("unexpected argument \"{}\"", extra)),
+        _ => return Err("show needs a file and a revision".to_string()),
+    };
+    let revision = match revision.strip_prefix('@') {
+        Some(time) => time.parse().map(Revision::At),
+        None => revision.parse().map(Revision::Back),
+    }.map_err(|_| format!("invalid revision \"{}\"", revision))?;
+    let restore = flags.switch("--restore");
+    if restore && key.is_some() {
+        return Err("--restore restores the whole file, not one section".to_string());
+    }
+    let doc = match load(path) {
+        Ok(doc) => doc.at_revision(revision),
+        Err((failure, message)) => return fail(failure, message),
+    };
+    if let Some(key) = key {
+        return match doc.get(key) {
+            Some(body) => {
+                print!("{}", body);
+                Ok(None)
+            }
+            None => fail(Failure::Validation, format!("{} : no section \"{}\"", path, key)),
+        };
+    }
+    let bytes = doc.write_with(Vec::new(), &WriteOptions::new()).map_err(|e| e.to_string())?;
+    let written = if restore {
+        std::fs::write(path, &bytes)
+    } else {
+        std::io::Write::write_all(&mut std::io::stdout(), &bytes)
+    };
+    match written {
+        Ok(()) => Ok(None),
+        Err(e) => fail(Failure::Io, format!("{} : {}", path, e)),
+    }
+}
+
+fn apply(flags: Flags) -> Result<Option<Failure>, String> {
+    let (path, edits) = match &flags.positional[..] {
+        [path, edits] => (path, edits),
+        [_, _, extra, ..] => return Err(format!("unexpected argument \"{}\"", extra)),
+        _ => return Err("apply needs a file and an edit list".to_string()),
+    };
+    let edits = if edits == "-" {
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).map(|_| text)
+    } else {
+        std::fs::read_to_string(edits)
+    };
+    let operations = match edits.map_err(multitext::Error::from).and_then(|text| parse_operations(&text)) {
+        Ok(operations) => operations,
+        Err(e) if e.kind() == multitext::ErrorKind::Io => return fail(Failure::Io, e),
+        Err(e) => return fail(Failure::Parse, e),
+    };
+    let original = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return fail(Failure::Io, format!("{} : {}", path, e)),
+    };
+    let mut doc = match multitext::parse_document_with(original.lines(), &parse_options()) {
+        Ok(doc) => doc,
+        Err(e) => return fail(Failure::Parse, format!("{} : {}", path, e.report().to_string().trim_end())),
+    };
+    if let Err(e) = apply_operations(&mut doc, &operations) {
+        return fail(Failure::Validation, format!("{} : {}", path, e.message()));
+    }
+    let bytes = doc.write_with(Vec::new(), &WriteOptions::new()).map_err(|e| e.to_string())?;
+    match std::fs::write(path, bytes) {
+        Ok(()) => Ok(None),
+        Err(e) => fail(Failure::Io, format!("{} : {}", path, e)),
+    }
+}
+
+fn diff(flags: Flags) -> Result<Option<Failure>, String> {
+    let (old_path, new_path) = match &flags.positional[..] {
+        [old, new] => (old, new),
+        [_, _, extra, ..] => return Err(format!("unexpected argument \"{}\"", extra)),
+        _ => return Err("diff needs two files".to_string()),
+    };
+    let context = match flags.value("--context") {
+        Some(n) => n.parse().map_err(|_| format!("invalid context \"{}\"", n))?,
+        None => 3,
+    };
+    let (old, new) = match (load(old_path), load(new_path)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err((failure, message)), _) | (_, Err((failure, message))) => return fail(failure, message),
+    };
+    let changes = multitext::diff::diff_documents(&old, &new);
+    for change in &changes {
+        println!("{}", change);
+        if let multitext::diff::SectionChange::Modified { key, old_body, new_body } = change {
+            println!("--- {} : {}", old_path, key);
+            println!("+++ {} : {}", new_path, key);
+            print!("{}", multitext::diff::unified_diff(old_body, new_body, context));
+        }
+    }
+    Ok(if changes.is_empty() { None } else { Some(Failure::Validation) })
+}
+
+/// Quotes `text` as one word for `sh`, or for `cmd` on Windows
+fn shell_quote(text: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+/// Fills in the `{key}` and `{file}` placeholders of a `--exec` command in
+/// one pass, so text substituted for one is never searched for the other
+fn expand_command(template: &str, key: &str, file: &str) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{key}") {
+            out.push_str(&shell_quote(key));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{file}") {
+            out.push_str(&shell_quote(file));
+            rest = after;
+        } else {
+            out.push('{');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Runs `command` through the shell, reporting if it fails
+fn run_shell(command: &str) {
+    let status = if cfg!(windows) {
+        std::process::Command::new("cmd").args(["/C", command]).status()
+    } else {
+        std::process::Command::new("sh").args(["-c", command]).status()
+    };
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("multitext: \"{}\" failed: {}", command, status),
+        Err(e) => eprintln!("multitext: \"{}\" : {}", command, e),
+    }
+}
+
+fn watch(flags: Flags) -> Result<Option<Failure>, String> {
+    let path = flags.one("file to watch")?;
+    let interval = match flags.value("--interval") {
+        Some(ms) => std::time::Duration::from_millis(ms.parse().map_err(|_| format!("invalid interval \"{}\"", ms))?),
+        None => std::time::Duration::from_millis(250),
+    };
+    let mut watch = match multitext::watch::watch_file_with(path, &parse_options()) {
+        Ok(watch) => watch.poll_interval(interval),
+        Err(e) => return fail(Failure::Parse, e),
+    };
+    eprintln!("watching {}", path);
+    loop {
+        let changed = match watch.poll() {
+            Ok(changed) => changed,
+            Err(e) => {
+                eprintln!("multitext: {}", e);
+                Vec::new()
+            }
+        };
+        let changed: Vec<&String> = changed.iter().filter(|key| *key != "multitext header").collect();
+        for key in &changed {
+            println!("{}", key);
+            if let Some(command) = flags.value("--exec") {
+                run_shell(&expand_command(command, key, path));
+            }
+        }
+        if let (Some(dir), false) = (flags.value("--unpack"), changed.is_empty()) {
+            let sections: multitext::Document = changed.iter()
+                .map(|key| (key.as_str(), &watch.document()[key.as_str()]))
+                .collect();
+            match unpack_dir_with(&sections, dir, &UnpackOptions::new()) {
+                Ok(report) => print!("{}", report),
+                Err(e) => eprintln!("multitext: {}", e),
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// The version of the JSON plugins receive on stdin
+const PLUGIN_ABI: u32 = 1;
+
+const BUILT_IN_COMMANDS: &[&str] = &[
+    "explain", "pack", "unpack", "list", "get", "cat", "diff", "watch", "log", "show", "apply", "redact",
+];
+
+/// Finds the program `multitext-<name>` on the `PATH`
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(std::path::is_separator) {
+        return None;
+    }
+    let program = format!("multitext-{}{}", name, std::env::consts::EXE_SUFFIX);
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).map(|dir| dir.join(&program)).find(|candidate| candidate.is_file())
+}
+
+fn redact(flags: Flags) -> Result<Option<Failure>, String> {
+    let (path, keys) = flags.positional.split_first().ok_or("missing file to redact")?;
+    let pattern = flags.value("--pattern");
+    if keys.is_empty() && pattern.is_none() {
+        return Err("redact needs keys or --pattern".to_string());
+    }
+    let mut doc = match load(path) {
+        Ok(doc) => doc,
+        Err((failure, message)) => return fail(failure, message),
+    };
+    if let Some(key) = keys.iter().find(|key| !doc.contains_key(key)) {
+        return fail(Failure::Validation, format!("{} : no section \"{}\"", path, key));
+    }
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    doc.redact(&keys[..]).map_err(|e| e.message().to_string())?;
+    if let Some(pattern) = pattern {
+        doc.redact(Selection::Pattern(pattern)).map_err(|e| e.message().to_string())?;
+    }
+    let bytes = doc.write_with(Vec::new(), &WriteOptions::new()).map_err(|e| e.to_string())?;
+    let written = match flags.value("--output") {
+        Some(output) => std::fs::write(output, &bytes).map_err(|e| format!("{} : {}", output, e)),
+        None => std::io::Write::write_all(&mut std::io::stdout(), &bytes).map_err(|e| e.to_string()),
+    };
+    match written {
+        Ok(()) => Ok(None),
+        Err(message) => fail(Failure::Io, message),
+    }
+}
+
+/// Runs `plugin` with `args`, feeding it the plugin JSON on stdin
+fn run_plugin(plugin: &Path, args: Vec<String>) -> ExitCode {
+    let file = args.iter().find(|arg| !arg.starts_with('-')).filter(|arg| Path::new(arg).is_file());
+    let document = match file {
+        Some(path) => {
+            let text = match std::fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("multitext: {} : {}", path, e);
+                    return ExitCode::from(Failure::Io.exit_code());
+                }
+            };
+            match multitext::parse_document_with(text.lines(), &parse_options()) {
+                Ok(doc) => doc.to_json(),
+                Err(e) => {
+                    eprintln!("multitext: {} : {}", path, e);
+                    return ExitCode::from(Failure::Parse.exit_code());
+                }
+            }
+        }
+        None => "null".to_string(),
+    };
+    let file = multitext::json::optional_string(file.map(String::as_str));
+    let input = format!("{{\"abi\":{},\"file\":{},\"document\":{}}}\n", PLUGIN_ABI, file, document);
+
+    let mut child = match std::process::Command::new(plugin)
+        .args(&args)
+        .env("MULTITEXT_PLUGIN_ABI", PLUGIN_ABI.to_string())
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("multitext: cannot run {} : {}", plugin.display(), e);
+            return ExitCode::from(Failure::Io.exit_code());
+        }
+    };
+    // A plugin that exits without reading its input is not an error
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = std::io::Write::write_all(&mut stdin, input.as_bytes());
+    }
+    match child.wait() {
+        Ok(status) => ExitCode::from(status.code().map_or(1, |code| code as u8)),
+        Err(e) => {
+            eprintln!("multitext: {} failed : {}", plugin.display(), e);
+            ExitCode::from(Failure::Io.exit_code())
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).peekable();
+    if let Some(name) = args.peek().filter(|name| !COMMANDS.contains(&name.as_str()))
+        .filter(|name| !BUILT_IN_COMMANDS.contains(&name.as_str()))
+    {
+        if let Some(plugin) = find_plugin(name) {
+            return run_plugin(&plugin, args.skip(1).collect());
+        }
+    }
+    let result = match args.peek().map(String::as_str) {
+        Some("explain") => explain(args.nth(1)),
+        Some("pack") => {
+            let options = [&["--output", "--marker"][..], &TEMPLATE_FLAGS].concat();
+            Flags::parse(args.skip(1), &options, &["--respect-ignore"]).and_then(pack)
+        }
+        Some("unpack") => {
+            let options = [&["--dir"][..], &TEMPLATE_FLAGS].concat();
+            Flags::parse(args.skip(1), &options, &["--dry-run"]).and_then(unpack)
+        }
+        Some("list") => Flags::parse(args.skip(1), &["--where"], &[]).and_then(list),
+        Some("get") => Flags::parse(args.skip(1), &[], &[]).and_then(get),
+        Some("cat") => Flags::parse(args.skip(1), &["--where"], &[]).and_then(cat),
+        Some("diff") => Flags::parse(args.skip(1), &["--context"], &[]).and_then(diff),
+        Some("watch") => Flags::parse(args.skip(1), &["--exec", "--unpack", "--interval"], &[]).and_then(watch),
+        Some("log") => Flags::parse(args.skip(1), &[], &[]).and_then(log),
+        Some("show") => Flags::parse(args.skip(1), &[], &["--restore"]).and_then(show),
+        Some("apply") => Flags::parse(args.skip(1), &[], &[]).and_then(apply),
+        Some("redact") => Flags::parse(args.skip(1), &["--pattern", "--output"], &[]).and_then(redact),
+        _ => parse_args(args).and_then(run),
+    };
+    match result {
+        Ok(None) => ExitCode::SUCCESS,
+        Ok(Some(failure)) => ExitCode::from(failure.exit_code()),
+        Err(message) => {
+            eprintln!("multitext: {}\n{}", message, USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fmt_keeps_duplicates_and_continuations() {
+        let dir = multitext::testing::TempDir::new("fmt").unwrap();
+        let path = dir.join("dup.mt");
+        let text = "@@ multitext header\n@@ a\nfirst\n@@   a\nsecond\n";
+        std::fs::write(&path, text).unwrap();
+        let outcome = fmt(&path, false);
+        assert_eq!(outcome.failure_kind(), Some(Failure::Parse));
+        assert_eq!(outcome.report.problems[0].line_number, Some(4));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+
+        let path = dir.join("parts.mt");
+        std::fs::write(&path, "@@ multitext header\n@@  log (1/2)\na\n@@ log (2/2)\nb\n").unwrap();
+        assert_eq!(fmt(&path, false).failure_kind(), None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "@@ multitext header\n@@ log (1/2)\na\n@@ log (2/2)\nb\n");
+    }
+
+    #[test]
+    fn test_selected_filters_by_query() {
+        let lines = ["@@ multitext header", "@@ a stage=vertex", "1", "@@ b", "22"];
+        let doc = multitext::parse_document_with(lines.iter(), &parse_options()).unwrap();
+        let keys = |query| selected(&doc, query).unwrap().iter().map(|s| s.key()).collect::<Vec<_>>();
+        assert_eq!(keys(Some("attr.stage == 'vertex'")), ["a"]);
+        assert_eq!(keys(Some("size > 2")), ["b"]);
+        assert_eq!(keys(None).len(), 3);
+        assert!(selected(&doc, Some("size >")).is_err());
+    }
+
+    #[test]
+    fn test_subcommands_read_attributes_alike() {
+        let dir = multitext::testing::TempDir::new("attrs").unwrap();
+        let path = dir.join("a.mt");
+        std::fs::write(&path, "@@ multitext header\n@@ lit shader stage=vertex\nvoid main() {}\n").unwrap();
+        let doc = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(doc.section("lit shader").unwrap().attr("stage"), Some("vertex"));
+
+        let ops = parse_operations(r#"[{"op": "set", "key": "lit shader", "body": "x\n"}]"#).unwrap();
+        let mut edited = doc.clone();
+        apply_operations(&mut edited, &ops).unwrap();
+        assert_eq!(edited.iter().map(|s| s.key()).collect::<Vec<_>>(), ["multitext header", "lit shader"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expand_command_substitutes_once() {
+        assert_eq!(expand_command("glslc {file} -o {key}.spv {x}", "a b", "s.mt"), "glslc 's.mt' -o 'a b'.spv {x}");
+        assert_eq!(expand_command("echo {key}", "{file}", "s.mt"), "echo '{file}'");
+    }
+}
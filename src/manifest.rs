@@ -0,0 +1,183 @@
+//! Machine-readable summaries of a document's sections
+//!
+//! A [`Manifest`] records the key, content hash, size and content type of
+//! every section. Asset pipelines store it next to their cooked output and
+//! later call [`Manifest::changes`] to find which sections need rebuilding.
+//!
+//! The text form has one line per section:
+//!
+//! ```text
+//! multitext manifest 1
+//! <hash as 16 hex digits> <size in bytes> <content type> <key>
+//! ```
+
+use crate::hash::hash_str;
+use crate::{Document, Error, ErrorKind};
+
+/// What is recorded about one section
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ManifestEntry {
+    pub key: String,
+    pub hash: u64,
+    pub size: usize,
+    pub content_type: String,
+}
+
+/// The recorded state of every section in a document, in document order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// A difference between a manifest and the current document
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Modified(String),
+}
+
+const MANIFEST_HEADER: &str = "multitext manifest 1";
+
+impl Manifest {
+    pub fn get(&self, key: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    /// Lists the sections of `doc` that differ from what was recorded, in
+    /// document order followed by removed keys in manifest order
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::manifest::Change;
+    ///
+    /// let old: multitext::Document = vec![("a", "1"), ("b", "2")].into_iter().collect();
+    /// let manifest = old.manifest();
+    ///
+    /// let new: multitext::Document = vec![("a", "1"), ("b", "3"), ("c", "4")].into_iter().collect();
+    /// assert_eq!(manifest.changes(&new), [
+    ///     Change::Modified("b".to_string()),
+    ///     Change::Added("c".to_string()),
+    /// ]);
+    /// assert!(manifest.verify(&old));
+    /// ```
+    pub fn changes(&self, doc: &Document) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for section in doc.iter() {
+            match self.get(section.key()) {
+                None => changes.push(Change::Added(section.key().to_string())),
+                Some(entry) => {
+                    if entry.size != section.body().len() || entry.hash != hash_str(section.body()) {
+                        changes.push(Change::Modified(section.key().to_string()));
+                    }
+                }
+            }
+        }
+        for entry in &self.entries {
+            if !doc.contains_key(&entry.key) {
+                changes.push(Change::Removed(entry.key.clone()));
+            }
+        }
+        changes
+    }
+
+    /// Returns true if `doc` matches the manifest exactly
+    pub fn verify(&self, doc: &Document) -> bool {
+        self.changes(doc).is_empty()
+    }
+
+    /// Reads the text form written by `Display`
+    pub fn parse(text: &str) -> Result<Manifest, Error> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.trim_end() == MANIFEST_HEADER => {}
+            _ => return Err(Error::new(ErrorKind::Malformed, "missing manifest header").with_line(1)),
+        }
+
+        let mut entries = Vec::new();
+        for (index, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(4, ' ');
+            let hash = fields.next().and_then(|f| u64::from_str_radix(f, 16).ok());
+            let size = fields.next().and_then(|f| f.parse().ok());
+            let content_type = fields.next();
+            let key = fields.next();
+            match (hash, size, content_type, key) {
+                (Some(hash), Some(size), Some(content_type), Some(key)) => entries.push(ManifestEntry {
+                    key: key.to_string(),
+                    hash,
+                    size,
+                    content_type: content_type.to_string(),
+                }),
+                _ => return Err(Error::new(ErrorKind::Malformed, "malformed manifest entry").with_line(index + 1)),
+            }
+        }
+        Ok(Manifest { entries })
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", MANIFEST_HEADER)?;
+        for entry in &self.entries {
+            writeln!(f, "{:016x} {} {} {}", entry.hash, entry.size, entry.content_type, entry.key)?;
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Describes every section by key, hash, size and content type
+    pub fn manifest(&self) -> Manifest {
+        Manifest {
+            entries: self.iter().map(|section| ManifestEntry {
+                key: section.key().to_string(),
+                hash: hash_str(section.body()),
+                size: section.body().len(),
+                content_type: content_type(section.key()).to_string(),
+            }).collect(),
+        }
+    }
+}
+
+/// Guesses a MIME type from the extension of a key, falling back to plain text
+pub fn content_type(key: &str) -> &'static str {
+    let extension = match key.rfind('.') {
+        Some(index) => key[index + 1..].to_ascii_lowercase(),
+        None => return "text/plain",
+    };
+    match extension.as_str() {
+        "glsl" | "vert" | "frag" | "geom" | "comp" | "tesc" | "tese" => "text/x-glsl",
+        "wgsl" => "text/wgsl",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "xml" => "application/xml",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "rs" => "text/rust",
+        _ => "text/plain",
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let doc: Document = vec![("shader.frag", "void main() {}\n"), ("notes", "x y z")]
+            .into_iter()
+            .collect();
+        let manifest = doc.manifest();
+        assert_eq!(manifest.entries[0].content_type, "text/x-glsl");
+        let parsed = Manifest::parse(&manifest.to_string()).unwrap();
+        assert_eq!(parsed, manifest);
+        assert!(parsed.verify(&doc));
+    }
+}
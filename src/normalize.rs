@@ -0,0 +1,133 @@
+//! Canonical forms for section bodies, by content type
+//!
+//! A section's content type is its `type` attribute, as in
+//! `@@ main.rs type=rust`. [`Normalizers`] maps content types to functions
+//! that put a body into canonical form, and [`Document::normalize`] applies
+//! them, so formatting can reach inside sections where a team wants it.
+
+use crate::{Document, Error, ErrorKind};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+type Normalizer = Arc<dyn Fn(&str) -> Result<String, String> + Send + Sync>;
+
+/// Normalizing functions keyed by content type
+#[derive(Clone, Default)]
+pub struct Normalizers {
+    by_type: HashMap<String, Normalizer>,
+}
+
+impl fmt::Debug for Normalizers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut types: Vec<&String> = self.by_type.keys().collect();
+        types.sort();
+        f.debug_struct("Normalizers").field("types", &types).finish()
+    }
+}
+
+impl Normalizers {
+    pub fn new() -> Normalizers {
+        Normalizers::default()
+    }
+
+    /// Normalizes bodies of `content_type` with `f`, which returns the
+    /// canonical body or a reason it couldn't produce one
+    pub fn register<T, F>(mut self, content_type: T, f: F) -> Normalizers
+    where T: Into<String>, F: Fn(&str) -> Result<String, String> + Send + Sync + 'static
+    {
+        self.by_type.insert(content_type.into(), Arc::new(f));
+        self
+    }
+
+    /// Normalizes bodies of `content_type` by piping them through an external
+    /// program, such as `["rustfmt", "--emit=stdout"]`, and taking its output
+    pub fn register_command<T: Into<String>>(self, content_type: T, command: &[String]) -> Normalizers {
+        let command = command.to_vec();
+        self.register(content_type, move |body| run_command(&command, body))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_type.is_empty()
+    }
+
+    /// Returns the canonical form of `body` for `content_type`, or `None` if
+    /// there is no normalizer for it
+    pub fn apply(&self, content_type: &str, body: &str) -> Option<Result<String, String>> {
+        self.by_type.get(content_type).map(|f| f(body))
+    }
+}
+
+fn run_command(command: &[String], body: &str) -> Result<String, String> {
+    let (program, args) = command.split_first().ok_or("empty normalizer command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", program, e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = body.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| format!("{} failed: {}", program, e))?;
+    writer.join().expect("the writer thread doesn't panic").map_err(|e| format!("{} failed: {}", program, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("{} failed with {}", program, output.status),
+            stderr => format!("{} failed: {}", program, stderr),
+        });
+    }
+    String::from_utf8(output.stdout).map_err(|_| format!("{} wrote output that isn't UTF-8", program))
+}
+
+impl Document {
+    /// Replaces each body that has a normalizer for its `type` attribute with
+    /// its canonical form, returning the keys of the sections that changed
+    ///
+    /// Locked sections are left alone. Stops at the first normalizer that
+    /// fails, with an error pointing at its section.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::normalize::Normalizers;
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ notes type=text", "trailing   ", "@@ code", "x   "];
+    /// let mut doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+    /// let normalizers = Normalizers::new().register("text", |body| {
+    ///     Ok(body.lines().map(|line| format!("{}\n", line.trim_end())).collect())
+    /// });
+    /// assert_eq!(doc.normalize(&normalizers).unwrap(), ["notes"]);
+    /// assert_eq!(&doc["notes"], "trailing\n");
+    /// assert_eq!(&doc["code"], "x   \n");
+    /// ```
+    pub fn normalize(&mut self, normalizers: &Normalizers) -> Result<Vec<String>, Error> {
+        let mut changed = Vec::new();
+        for index in 0..self.sections.len() {
+            let section = &self.sections[index];
+            if section.is_locked() && self.check_unlocked(section.key()).is_err() {
+                continue;
+            }
+            let content_type = match section.attr("type") {
+                Some(content_type) => content_type,
+                None => continue,
+            };
+            let normalized = match normalizers.apply(content_type, section.body()) {
+                Some(result) => result.map_err(|message| {
+                    section.error(ErrorKind::Other, format!("cannot normalize section \"{}\": {}", section.key(), message))
+                })?,
+                None => continue,
+            };
+            if normalized != section.body() {
+                let key = section.key().to_string();
+                self.insert(key.clone(), normalized)?;
+                changed.push(key);
+            }
+        }
+        Ok(changed)
+    }
+}
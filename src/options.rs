@@ -0,0 +1,428 @@
+//! Knobs controlling how the parser treats unusual input
+
+use crate::pattern::Pattern;
+use crate::{Error, ErrorKind};
+
+/// What to do with a marker line that has nothing after the marker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmptyKeys {
+    /// Use the empty string as the key, as plain parsing always has; a second
+    /// empty key replaces the first
+    Keep,
+    /// Fail with an error pointing at the marker line
+    Error,
+    /// Name each one `__anon_1`, `__anon_2`, ... in file order
+    Anonymous,
+    /// Treat the marker line as a separator inside the previous section: the
+    /// line itself is dropped and the text after it is appended to that
+    /// section's body
+    Continue,
+}
+
+/// What to do with the `\r` of lines ending in `\r\n`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineEndings {
+    /// Leave it at the end of the line, as part of the body
+    Keep,
+    /// Drop it, so every body line ends in `\n`
+    Normalize,
+}
+
+/// Decoration to strip from the end of a marker line, for files with
+/// symmetric banners such as `=== key ===`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TrailingDecoration {
+    /// Everything after the marker is part of the key
+    Keep,
+    /// Strip a trailing copy of the marker itself
+    Marker,
+    /// Strip this trailing text
+    Suffix(String),
+}
+
+impl TrailingDecoration {
+    /// Removes the decoration from the end of `text`, the trimmed text after
+    /// the marker
+    pub(crate) fn strip<'a>(&self, text: &'a str, marker: &str) -> &'a str {
+        let suffix = match self {
+            TrailingDecoration::Keep => return text,
+            TrailingDecoration::Marker => marker,
+            TrailingDecoration::Suffix(suffix) => suffix.as_str(),
+        };
+        match text.strip_suffix(suffix) {
+            Some(rest) if !suffix.is_empty() => rest.trim_end(),
+            _ => text,
+        }
+    }
+}
+
+/// How to resolve two sections with the same key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DuplicateKeys {
+    /// Fail with an error pointing at the second occurrence
+    Error,
+    /// Keep the body that came first and drop the later one
+    KeepFirst,
+    /// Replace the earlier body with the later one
+    KeepLast,
+    /// Append the later body to the earlier one
+    Concatenate,
+}
+
+/// Options for [`parse_lines_with`](crate::parse_lines_with) and
+/// [`parse_document_with`](crate::parse_document_with)
+///
+/// # Examples
+/// ```
+/// use multitext::options::{EmptyKeys, ParseOptions};
+///
+/// let lines = ["@@ multitext header", "@@", "one", "@@", "two"];
+/// let options = ParseOptions::new().empty_keys(EmptyKeys::Anonymous);
+/// let doc = multitext::parse_document_with(lines.iter(), &options).unwrap();
+/// assert_eq!(&doc["__anon_1"], "one\n");
+/// assert_eq!(&doc["__anon_2"], "two\n");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub(crate) empty_keys: EmptyKeys,
+    pub(crate) attributes: bool,
+    pub(crate) line_endings: LineEndings,
+    pub(crate) header_scan_limit: Option<usize>,
+    pub(crate) trailing_decoration: TrailingDecoration,
+    pub(crate) trim_keys: bool,
+    pub(crate) duplicate_keys: DuplicateKeys,
+    pub(crate) skip_keys: Option<Pattern>,
+    pub(crate) max_line_len: Option<usize>,
+    pub(crate) keep_preamble: bool,
+    pub(crate) join_continuations: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            empty_keys: EmptyKeys::Keep,
+            attributes: false,
+            line_endings: LineEndings::Keep,
+            header_scan_limit: None,
+            trailing_decoration: TrailingDecoration::Keep,
+            trim_keys: true,
+            duplicate_keys: DuplicateKeys::KeepLast,
+            skip_keys: None,
+            max_line_len: None,
+            keep_preamble: false,
+            join_continuations: true,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    pub fn empty_keys(mut self, empty_keys: EmptyKeys) -> ParseOptions {
+        self.empty_keys = empty_keys;
+        self
+    }
+
+    /// Whether trailing `name=value` words on a marker line are read as
+    /// section attributes instead of as part of the key
+    ///
+    /// Names are made of letters, digits, `-` and `_`; values can't contain
+    /// whitespace.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ lit shader stage=vertex", "void main() {}"];
+    /// let doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().attributes(true)).unwrap();
+    /// assert_eq!(doc.section("lit shader").unwrap().attr("stage"), Some("vertex"));
+    /// ```
+    pub fn attributes(mut self, attributes: bool) -> ParseOptions {
+        self.attributes = attributes;
+        self
+    }
+
+    /// What to do with `\r\n` line endings; the file-opening functions
+    /// always normalize them
+    pub fn line_endings(mut self, line_endings: LineEndings) -> ParseOptions {
+        self.line_endings = line_endings;
+        self
+    }
+
+    /// Gives up looking for the header line after `lines` lines, instead of
+    /// reading the whole input
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["not", "a", "multitext", "file", "@@ multitext header"];
+    /// let e = multitext::parse_document_with(lines.iter(), &ParseOptions::new().header_scan_limit(2))
+    ///     .unwrap_err();
+    /// assert!(e.to_string().contains("no multitext header in the first 2 lines"));
+    /// assert!(e.to_string().contains("| not"));
+    /// ```
+    pub fn header_scan_limit(mut self, lines: usize) -> ParseOptions {
+        self.header_scan_limit = Some(lines);
+        self
+    }
+
+    /// What to do when a key appears twice; the default keeps the last
+    /// section, in the position of the first, and reports a warning
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::{DuplicateKeys, ParseOptions};
+    ///
+    /// let lines = ["@@ multitext header", "@@ vertex", "a", "@@ fragment", "b", "@@ vertex", "c"];
+    /// let options = ParseOptions::new().duplicate_keys(DuplicateKeys::Error);
+    /// let e = multitext::parse_lines_with(lines.iter(), &options).unwrap_err();
+    /// assert!(e.to_string().contains("duplicate key \"vertex\" : (6)"));
+    ///
+    /// let options = ParseOptions::new().duplicate_keys(DuplicateKeys::Concatenate);
+    /// let doc = multitext::parse_document_with(lines.iter(), &options).unwrap();
+    /// assert_eq!(&doc["vertex"], "a\nc\n");
+    /// ```
+    pub fn duplicate_keys(mut self, policy: DuplicateKeys) -> ParseOptions {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Whether whitespace around keys is trimmed, as it is by default
+    ///
+    /// Without trimming only the single space after the marker is removed.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@  indented  ", "x"];
+    /// let map = multitext::parse_lines_with(lines.iter(), &ParseOptions::new().trim_keys(false)).unwrap();
+    /// assert!(map.contains_key(" indented  "));
+    /// ```
+    pub fn trim_keys(mut self, trim: bool) -> ParseOptions {
+        self.trim_keys = trim;
+        self
+    }
+
+    /// Decoration to strip from the end of marker lines, before any
+    /// attributes are read
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::{ParseOptions, TrailingDecoration};
+    ///
+    /// let lines = ["=== multitext header ===", "=== lit shader ===", "void main() {}"];
+    /// let options = ParseOptions::new().trailing_decoration(TrailingDecoration::Marker);
+    /// let doc = multitext::parse_document_with(lines.iter(), &options).unwrap();
+    /// assert_eq!(&doc["lit shader"], "void main() {}\n");
+    /// ```
+    pub fn trailing_decoration(mut self, decoration: TrailingDecoration) -> ParseOptions {
+        self.trailing_decoration = decoration;
+        self
+    }
+
+    /// Leaves out every section whose key matches `pattern`, discarding its
+    /// body line by line as it is read so it never takes up memory
+    ///
+    /// The pattern is a small regular expression, as in
+    /// [`Selection::Pattern`](crate::redact::Selection::Pattern), and is
+    /// matched against the key as written on the marker line, so it sees each
+    /// continuation part's own key. The header is never skipped. Fails if the
+    /// pattern is invalid.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ vertex shader", "v", "@@ baked lightmap", "...", "@@ notes", "n"];
+    /// let options = ParseOptions::new().skip_keys("^baked ").unwrap();
+    /// let doc = multitext::parse_document_with(lines.iter(), &options).unwrap();
+    /// let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+    /// assert_eq!(keys, ["multitext header", "vertex shader", "notes"]);
+    /// ```
+    pub fn skip_keys(mut self, pattern: &str) -> Result<ParseOptions, Error> {
+        let pattern = Pattern::new(pattern).map_err(|message| {
+            Error::new(ErrorKind::Malformed, format!("invalid pattern \"{}\": {}", pattern, message))
+        })?;
+        self.skip_keys = Some(pattern);
+        Ok(self)
+    }
+
+    /// Fails on any line longer than `bytes`, instead of accepting lines of
+    /// any length
+    ///
+    /// Lines are measured after they have been read, so this protects what
+    /// is built from them rather than the reading itself; to read untrusted
+    /// input without ever holding a whole line, use
+    /// [`SectionReader`](crate::SectionReader).
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let minified = "x".repeat(100);
+    /// let lines = ["@@ multitext header", "@@ bundle.js", &minified];
+    /// let e = multitext::parse_document_with(lines.iter(), &ParseOptions::new().max_line_len(80)).unwrap_err();
+    /// assert!(e.to_string().contains("line is 100 bytes long, over the limit of 80 : (3)"));
+    /// ```
+    pub fn max_line_len(mut self, bytes: usize) -> ParseOptions {
+        self.max_line_len = Some(bytes);
+        self
+    }
+
+    /// Whether the text above the header line is kept as the document's
+    /// [`preamble`](crate::Document::preamble), to be written back out
+    /// above the header, rather than discarded with a warning
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["Copyright 2024", "", "@@ multitext header", "@@ a", "1"];
+    /// let doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().keep_preamble(true)).unwrap();
+    /// assert_eq!(doc.preamble(), Some("Copyright 2024\n\n"));
+    ///
+    /// let text = String::from_utf8(doc.write_with(Vec::new(), &Default::default()).unwrap()).unwrap();
+    /// assert_eq!(text, "Copyright 2024\n\n@@ multitext header\n@@ a\n1\n");
+    /// ```
+    pub fn keep_preamble(mut self, keep: bool) -> ParseOptions {
+        self.keep_preamble = keep;
+        self
+    }
+
+    /// Whether runs of continuation sections written by
+    /// [`WriteOptions::max_body_bytes`] are joined back into one section, as
+    /// they are by default
+    ///
+    /// Without joining each part is its own section under its numbered key,
+    /// so a document can be written back with the same layout.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    ///
+    /// let lines = ["@@ multitext header", "@@ log (1/2)", "a", "@@ log (2/2)", "b"];
+    /// let doc = multitext::parse_document_with(lines.iter(), &ParseOptions::new().join_continuations(false)).unwrap();
+    /// assert_eq!(&doc["log (2/2)"], "b\n");
+    /// assert!(doc.get("log").is_none());
+    /// ```
+    pub fn join_continuations(mut self, join: bool) -> ParseOptions {
+        self.join_continuations = join;
+        self
+    }
+}
+
+/// Options for [`Document::write_with`](crate::Document::write_with)
+///
+/// # Examples
+/// ```
+/// use multitext::options::WriteOptions;
+///
+/// let doc: multitext::Document = vec![("log", "aaaa\nbbbb\ncccc\n")].into_iter().collect();
+/// let options = WriteOptions::new().marker("##").max_body_bytes(10);
+/// let text = String::from_utf8(doc.write_with(Vec::new(), &options).unwrap()).unwrap();
+/// assert_eq!(text, "## multitext header\n## log (1/2)\naaaa\nbbbb\n## log (2/2)\ncccc\n");
+///
+/// let parsed = multitext::parse_document(text.lines()).unwrap();
+/// assert_eq!(&parsed["log"], "aaaa\nbbbb\ncccc\n");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    pub(crate) marker: Option<String>,
+    pub(crate) max_body_bytes: Option<usize>,
+    pub(crate) order: SectionOrder,
+    pub(crate) max_blank_lines: Option<usize>,
+}
+
+impl WriteOptions {
+    pub fn new() -> WriteOptions {
+        WriteOptions::default()
+    }
+
+    /// The marker to write with, instead of the document's own
+    pub fn marker<M: Into<String>>(mut self, marker: M) -> WriteOptions {
+        self.marker = Some(marker.into());
+        self
+    }
+
+    /// Splits bodies longer than `max` bytes into numbered continuation
+    /// sections, `key (1/3)` and so on, which parsing joins back together
+    ///
+    /// Bodies are only split between lines, so a single line longer than
+    /// `max` still gets a part of its own that exceeds it.
+    pub fn max_body_bytes(mut self, max: usize) -> WriteOptions {
+        self.max_body_bytes = Some(max);
+        self
+    }
+
+    /// The order to write sections in
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::{SectionOrder, WriteOptions};
+    ///
+    /// let doc: multitext::Document = vec![("section10", ""), ("Section2", ""), ("section1", "")]
+    ///     .into_iter().collect();
+    /// let options = WriteOptions::new().marker("@").order(SectionOrder::Natural);
+    /// let text = String::from_utf8(doc.write_with(Vec::new(), &options).unwrap()).unwrap();
+    /// assert_eq!(text, "@ multitext header\n@ section1\n@ Section2\n@ section10\n");
+    /// ```
+    pub fn order(mut self, order: SectionOrder) -> WriteOptions {
+        self.order = order;
+        self
+    }
+
+    /// Keeps at most `max` blank lines at the end of each body, which is to
+    /// say between one section's text and the next marker line
+    ///
+    /// Lines holding only whitespace count as blank, though the ones that are
+    /// kept are kept as they are. Blank lines inside a body are left alone.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::WriteOptions;
+    ///
+    /// let doc = multitext::parse_document(["@@ multitext header", "", "", "@@ a", "1", "", "2", " ", "", "@@ b"].iter())
+    ///     .unwrap();
+    /// let text = String::from_utf8(doc.write_with(Vec::new(), &WriteOptions::new().max_blank_lines(1)).unwrap())
+    ///     .unwrap();
+    /// assert_eq!(text, "@@ multitext header\n\n@@ a\n1\n\n2\n \n@@ b\n");
+    /// ```
+    pub fn max_blank_lines(mut self, max: usize) -> WriteOptions {
+        self.max_blank_lines = Some(max);
+        self
+    }
+}
+
+/// The order in which [`Document::write_with`](crate::Document::write_with)
+/// writes sections
+///
+/// The "multitext header" section is always written first.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum SectionOrder {
+    /// Document order
+    #[default]
+    Document,
+    /// By the bytes of the key, so `Z` comes before `a` and `10` before `2`
+    Alphabetical,
+    /// As people expect: runs of digits compare by value and letters ignore
+    /// case, so `section2` comes before `Section10`; see
+    /// [`natural_cmp`](crate::keys::natural_cmp)
+    Natural,
+    /// By a collation function, for locale-aware ordering
+    Collate(fn(&str, &str) -> std::cmp::Ordering),
+}
+
+impl SectionOrder {
+    pub(crate) fn comparison(self) -> Option<fn(&str, &str) -> std::cmp::Ordering> {
+        match self {
+            SectionOrder::Document => None,
+            SectionOrder::Alphabetical => Some(|a: &str, b: &str| a.cmp(b)),
+            SectionOrder::Natural => Some(crate::keys::natural_cmp),
+            SectionOrder::Collate(compare) => Some(compare),
+        }
+    }
+}
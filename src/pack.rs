@@ -0,0 +1,338 @@
+//! Converting between directories of text files and documents
+//!
+//! [`pack_dir`] turns every file under a directory into a section keyed by its
+//! `/`-separated path relative to that directory, in sorted order.
+//! [`unpack_dir`] writes each section back out as a file. Keys and paths are
+//! converted by a [`KeyCodec`], percent-encoding by default; a
+//! [`KeyTemplate`](crate::codec::KeyTemplate) maps them by pattern instead.
+//!
+//! Unpacking treats keys as untrusted: by default it refuses absolute paths,
+//! `..` components and writing through symbolic links, so a bundle can never
+//! write outside the target directory. [`UnpackOptions`] relaxes these rules
+//! for bundles that are known to be safe.
+
+use crate::codec::{KeyCodec, PercentEncoding};
+use crate::ignore::IgnoreRules;
+use crate::report::{plan_write, ChangeKind, ChangeReport};
+use crate::{Document, Error, ErrorKind, Section};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Options for [`pack_dir_with`]
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    ignore_files: Vec<String>,
+    codec: Arc<dyn KeyCodec>,
+}
+
+impl Default for PackOptions {
+    fn default() -> PackOptions {
+        PackOptions {
+            ignore_files: Vec::new(),
+            codec: Arc::new(PercentEncoding),
+        }
+    }
+}
+
+impl PackOptions {
+    pub fn new() -> PackOptions {
+        PackOptions::default()
+    }
+
+    /// Sets how file paths are turned into keys
+    pub fn codec<C: KeyCodec + 'static>(mut self, codec: C) -> PackOptions {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Skips files matched by `.gitignore` and `.mtignore` files found while
+    /// walking the directory, as well as any `.git` directory
+    pub fn respect_ignore_files(self, respect: bool) -> PackOptions {
+        if respect {
+            self.ignore_file(".gitignore").ignore_file(".mtignore")
+        } else {
+            PackOptions { ignore_files: Vec::new(), ..self }
+        }
+    }
+
+    /// Adds another file name to read gitignore-style patterns from
+    pub fn ignore_file<S: Into<String>>(mut self, name: S) -> PackOptions {
+        let name = name.into();
+        if !self.ignore_files.contains(&name) {
+            self.ignore_files.push(name);
+        }
+        self
+    }
+}
+
+/// Packs every file under `dir` into a document
+pub fn pack_dir<P: AsRef<Path>>(dir: P) -> Result<Document, Error> {
+    pack_dir_with(dir, &PackOptions::default())
+}
+
+/// Packs the files under `dir` into a document as configured by `options`
+///
+/// It is an error, naming both files, for two paths to decode to the same key.
+///
+/// # Examples
+/// ```no_run
+/// use multitext::pack::{pack_dir_with, PackOptions};
+///
+/// let options = PackOptions::new().respect_ignore_files(true);
+/// let doc = pack_dir_with("shaders", &options)?;
+/// for section in &doc {
+///     println!("{}", section.key());
+/// }
+/// # Ok::<(), multitext::Error>(())
+/// ```
+pub fn pack_dir_with<P: AsRef<Path>>(dir: P, options: &PackOptions) -> Result<Document, Error> {
+    let mut doc = Document::new();
+    let rules = IgnoreRules::default();
+    pack_into(dir.as_ref(), "", options, rules, &mut doc)?;
+    Ok(doc)
+}
+
+fn pack_into(dir: &Path, relative: &str, options: &PackOptions, mut rules: IgnoreRules, doc: &mut Document)
+    -> Result<(), Error>
+{
+    for name in &options.ignore_files {
+        let path = dir.join(name);
+        if path.is_file() {
+            let text = std::fs::read_to_string(&path).map_err(|e| Error::from(e).with_path(&path))?;
+            rules.add_file(relative, &text);
+        }
+    }
+
+    let mut entries = std::fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| Error::from(e).with_path(dir))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().into_string().map_err(|name| {
+            Error::new(ErrorKind::InvalidKey, format!("file name {:?} is not valid UTF-8", name)).with_path(&path)
+        })?;
+        let file = if relative.is_empty() { name.clone() } else { format!("{}/{}", relative, name) };
+        let is_dir = path.is_dir();
+
+        if !options.ignore_files.is_empty() && (name == ".git" || rules.is_ignored(&file, is_dir)) {
+            continue;
+        }
+        if is_dir {
+            pack_into(&path, &file, options, rules.clone(), doc)?;
+        } else {
+            let key = options.codec.decode(&file);
+            if let Some(earlier) = doc.section(&key) {
+                let earlier = earlier.origin().and_then(|origin| origin.file).unwrap_or_default();
+                let message = format!("\"{}\" and \"{}\" both pack to key \"{}\"", earlier, path.display(), key);
+                return Err(Error::new(ErrorKind::DuplicateKey, message).with_path(&path));
+            }
+            let mut section = Section::at_line(&key, 1);
+            section.body = std::fs::read_to_string(&path).map_err(|e| Error::from(e).with_path(&path))?;
+            section.set_file(path.to_string_lossy().into());
+            doc.insert_section(section);
+        }
+    }
+    Ok(())
+}
+
+/// Options for [`unpack_dir_with`]
+///
+/// Everything is rejected by default.
+#[derive(Debug, Clone)]
+pub struct UnpackOptions {
+    allow_absolute: bool,
+    allow_parent: bool,
+    follow_symlinks: bool,
+    dry_run: bool,
+    codec: Arc<dyn KeyCodec>,
+}
+
+impl Default for UnpackOptions {
+    fn default() -> UnpackOptions {
+        UnpackOptions {
+            allow_absolute: false,
+            allow_parent: false,
+            follow_symlinks: false,
+            dry_run: false,
+            codec: Arc::new(PercentEncoding),
+        }
+    }
+}
+
+impl UnpackOptions {
+    pub fn new() -> UnpackOptions {
+        UnpackOptions::default()
+    }
+
+    /// Sets how keys are turned into file paths
+    pub fn codec<C: KeyCodec + 'static>(mut self, codec: C) -> UnpackOptions {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Whether a key like `/etc/motd` is written to that absolute path
+    pub fn allow_absolute_paths(mut self, allow: bool) -> UnpackOptions {
+        self.allow_absolute = allow;
+        self
+    }
+
+    /// Whether keys may contain `..` components
+    pub fn allow_parent_components(mut self, allow: bool) -> UnpackOptions {
+        self.allow_parent = allow;
+        self
+    }
+
+    /// Whether files may be written through existing symbolic links, either
+    /// as the file itself or as one of its parent directories
+    pub fn follow_symlinks(mut self, follow: bool) -> UnpackOptions {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Whether to only report what would be written, without writing it
+    pub fn dry_run(mut self, dry_run: bool) -> UnpackOptions {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Works out where `key` would be written under `dir`, or why it can't be
+    pub fn resolve(&self, dir: &Path, key: &str) -> Result<PathBuf, String> {
+        if key.is_empty() {
+            return Err("an empty key has no file name".to_string());
+        }
+        let encoded = self.codec.encode(key);
+        let relative = Path::new(&encoded);
+        if relative.is_absolute() || relative.has_root() {
+            if !self.allow_absolute {
+                return Err(format!("key \"{}\" is an absolute path", key));
+            }
+        } else if relative.components().any(|c| c == Component::ParentDir) && !self.allow_parent {
+            return Err(format!("key \"{}\" contains a \"..\" component", key));
+        }
+
+        let path = dir.join(relative);
+        if !self.follow_symlinks {
+            let mut current = if relative.is_absolute() { PathBuf::new() } else { dir.to_path_buf() };
+            for component in relative.components() {
+                current.push(component);
+                let is_symlink = std::fs::symlink_metadata(&current)
+                    .map(|m| m.file_type().is_symlink())
+                    .unwrap_or(false);
+                if is_symlink {
+                    return Err(format!(
+                        "key \"{}\" would be written through the symbolic link {}", key, current.display()
+                    ));
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// Writes every section except the multitext header to a file under `dir`,
+/// rejecting unsafe keys
+pub fn unpack_dir<P: AsRef<Path>>(doc: &Document, dir: P) -> Result<ChangeReport, Error> {
+    unpack_dir_with(doc, dir, &UnpackOptions::default())
+}
+
+/// Writes every section except the multitext header to a file under `dir`,
+/// with the path rules in `options`
+///
+/// All keys are checked before anything is written, so an unsafe key leaves
+/// the directory untouched. Files whose contents already match are not
+/// rewritten.
+///
+/// # Examples
+/// ```no_run
+/// use multitext::pack::{unpack_dir_with, UnpackOptions};
+///
+/// let doc = multitext::open_and_parse_document("assets.mt")?;
+/// let report = unpack_dir_with(&doc, "assets", &UnpackOptions::new().dry_run(true))?;
+/// print!("{}", report);
+/// # Ok::<(), multitext::Error>(())
+/// ```
+pub fn unpack_dir_with<P: AsRef<Path>>(doc: &Document, dir: P, options: &UnpackOptions)
+    -> Result<ChangeReport, Error>
+{
+    let dir = dir.as_ref();
+    let mut targets = Vec::new();
+    for section in doc.iter().filter(|s| s.key() != "multitext header") {
+        let path = options.resolve(dir, section.key()).map_err(|message| section.error(ErrorKind::InvalidKey, message))?;
+        targets.push((path, section.body()));
+    }
+
+    let mut report = ChangeReport { dry_run: options.dry_run, changes: Vec::new() };
+    for (path, body) in targets {
+        let change = plan_write(&path, body.as_bytes());
+        if !options.dry_run && change.kind != ChangeKind::Unchanged {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| Error::from(e).with_path(parent))?;
+            }
+            std::fs::write(&path, body).map_err(|e| Error::from(e).with_path(&path))?;
+        }
+        report.changes.push(change);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unpack_rejects_unsafe_keys() {
+        let dir = crate::testing::TempDir::new("unpack").unwrap();
+        let options = UnpackOptions::new();
+        assert!(options.resolve(&dir, "a/b.txt").is_ok());
+        assert!(options.resolve(&dir, "../escape.txt").is_err());
+        assert!(options.resolve(&dir, "/etc/passwd").is_err());
+        assert!(options.allow_parent_components(true).resolve(&dir, "../ok.txt").is_ok());
+        assert_eq!(UnpackOptions::new().resolve(&dir, "a:b").unwrap(), dir.join("a%3Ab"));
+
+        let doc: Document = vec![("fine.txt", "1"), ("../bad.txt", "2")].into_iter().collect();
+        assert!(unpack_dir(&doc, &dir).is_err());
+        assert!(!dir.join("fine.txt").exists());
+    }
+
+    #[test]
+    fn test_pack_records_origins() {
+        let dir = crate::testing::TempDir::new("origin").unwrap();
+        std::fs::write(dir.join("a.txt"), "1\n").unwrap();
+
+        let packed = pack_dir(&dir).unwrap();
+        let merged = Document::concat_documents(vec![Document::new(), packed], crate::options::DuplicateKeys::Error)
+            .unwrap();
+        let origin = merged.section("a.txt").unwrap().origin().unwrap();
+        assert_eq!(origin.file, Some(dir.join("a.txt").to_string_lossy().into_owned()));
+        assert_eq!(origin.line_number, 1);
+    }
+
+    #[test]
+    fn test_pack_rejects_colliding_keys() {
+        let dir = crate::testing::TempDir::new("collision").unwrap();
+        std::fs::write(dir.join("a:b.txt"), "1\n").unwrap();
+        std::fs::write(dir.join("a%3Ab.txt"), "2\n").unwrap();
+
+        let e = pack_dir(&dir).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::DuplicateKey);
+        assert!(e.to_string().contains(&*dir.join("a%3Ab.txt").to_string_lossy()));
+        assert!(e.to_string().contains(&*dir.join("a:b.txt").to_string_lossy()));
+    }
+
+    #[test]
+    fn test_unpack_dry_run() {
+        let temp = crate::testing::TempDir::new("dry-run").unwrap();
+        let dir = temp.join("out");
+        let doc: Document = vec![("sub/a.txt", "1\n")].into_iter().collect();
+
+        let report = unpack_dir_with(&doc, &dir, &UnpackOptions::new().dry_run(true)).unwrap();
+        assert_eq!(report.changes[0].kind, ChangeKind::Create);
+        assert!(!dir.exists());
+
+        unpack_dir(&doc, &dir).unwrap();
+        let report = unpack_dir(&doc, &dir).unwrap();
+        assert!(report.is_noop());
+    }
+}
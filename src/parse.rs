@@ -0,0 +1,415 @@
+//! The line-splitting core shared by every parse entry point
+
+use crate::{Error, ErrorKind, Section};
+use crate::diagnostics::{DiagnosticsSink, Snippet, Warning, WarningKind};
+use crate::options::{DuplicateKeys, EmptyKeys, LineEndings, ParseOptions};
+use std::collections::HashSet;
+
+/// Splits lines into sections, handing each one to `emit` in file order, and
+/// returns the marker and, if [`ParseOptions::keep_preamble`] is set, the
+/// text above the header line
+///
+/// Duplicate keys are handled here for [`DuplicateKeys::Error`] and
+/// [`DuplicateKeys::KeepFirst`]; with the other policies every section is
+/// emitted and the consumer keeps the last one or appends to the first.
+///
+/// Runs of continuation sections written by
+/// [`WriteOptions::max_body_bytes`](crate::options::WriteOptions::max_body_bytes)
+/// are joined back into one section unless
+/// [`ParseOptions::join_continuations`] is turned off. The joined key is
+/// checked against the other sections' keys like any other.
+pub(crate) fn parse_sections<I, S, F>(it: I, options: &ParseOptions, sink: &mut S, emit: F)
+    -> Result<(String, Option<String>), Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>, S: DiagnosticsSink + ?Sized, F: FnMut(Section)
+{
+    let mut joiner = Continuations::new(options.duplicate_keys, emit);
+    let mut endings = EndingStats::default();
+    let normalize = options.line_endings == LineEndings::Normalize;
+    let lines = it.map(|line| endings.track(line, normalize));
+    let join = options.join_continuations;
+    let top = split_sections(lines, options, sink, |section| {
+        if join { joiner.push(section) } else { (joiner.emit)(section) }
+    })?;
+    joiner.flush();
+    for warning in &joiner.warnings {
+        sink.report(warning);
+    }
+    if let Some(error) = joiner.error {
+        return Err(error);
+    }
+    endings.report(sink);
+    Ok(top)
+}
+
+/// A line with its `\r` possibly cut off, without copying it
+pub(crate) struct Stripped<T> {
+    line: T,
+    len: usize,
+}
+
+impl<T: AsRef<str>> AsRef<str> for Stripped<T> {
+    fn as_ref(&self) -> &str {
+        &self.line.as_ref()[..self.len]
+    }
+}
+
+/// Counts which lines end in `\r\n` and which in `\n` alone
+///
+/// The last line is left out, since it may simply lack a line ending.
+#[derive(Debug, Default)]
+struct EndingStats {
+    line_number: usize,
+    crlf: usize,
+    lf: usize,
+    first_crlf: Option<usize>,
+    first_lf: Option<usize>,
+    pending_lf: Option<usize>,
+}
+
+impl EndingStats {
+    fn track<T: AsRef<str>>(&mut self, line: T, normalize: bool) -> Stripped<T> {
+        self.line_number += 1;
+        if let Some(lf_line) = self.pending_lf.take() {
+            self.lf += 1;
+            self.first_lf.get_or_insert(lf_line);
+        }
+        let len = line.as_ref().len();
+        if line.as_ref().ends_with('\r') {
+            self.crlf += 1;
+            self.first_crlf.get_or_insert(self.line_number);
+            Stripped { len: if normalize { len - 1 } else { len }, line }
+        } else {
+            self.pending_lf = Some(self.line_number);
+            Stripped { len, line }
+        }
+    }
+
+    fn report<S: DiagnosticsSink + ?Sized>(&self, sink: &mut S) {
+        let (minority, count, first) = match (self.first_crlf, self.first_lf) {
+            (Some(first_crlf), Some(_)) if self.crlf <= self.lf => ("\\r\\n", self.crlf, first_crlf),
+            (Some(_), Some(first_lf)) => ("\\n", self.lf, first_lf),
+            _ => return,
+        };
+        sink.report(&Warning {
+            kind: WarningKind::MixedLineEndings,
+            line_number: first,
+            message: format!(
+                "mixed line endings: {} of {} lines end in {}, starting here",
+                count, self.crlf + self.lf, minority
+            ),
+        });
+    }
+}
+
+/// How many lines, and how many characters of each, a missing-header error
+/// quotes
+const CONTEXT_LINES: usize = 3;
+const CONTEXT_WIDTH: usize = 60;
+
+fn missing_header_message(lines: usize, bytes: usize, first_lines: &[String], stopped_early: bool) -> String {
+    let mut message = if stopped_early {
+        format!("no multitext header in the first {} lines ({} bytes)", lines, bytes)
+    } else {
+        format!("missing multitext header after scanning {} lines ({} bytes)", lines, bytes)
+    };
+    if first_lines.is_empty() {
+        message.push_str("; the input is empty");
+    } else {
+        message.push_str("; the input starts with:");
+        for line in first_lines {
+            message.push_str("\n    | ");
+            message.push_str(line);
+        }
+    }
+    message
+}
+
+/// Formats the key of part `index` (1-based) of `count` continuation sections
+pub(crate) fn continuation_key(key: &str, index: usize, count: usize) -> String {
+    format!("{} ({}/{})", key, index, count)
+}
+
+/// Splits a key like `name (2/3)` into `("name", 2, 3)`
+pub(crate) fn parse_continuation_key(key: &str) -> Option<(&str, usize, usize)> {
+    let rest = key.strip_suffix(')')?;
+    let (base, counter) = rest.rsplit_once(" (")?;
+    let (index, count) = counter.split_once('/')?;
+    let (index, count) = (index.parse().ok()?, count.parse().ok()?);
+    if index == 0 || index > count || count < 2 {
+        return None;
+    }
+    Some((base, index, count))
+}
+
+/// Holds back a run of continuation parts until it is known to be complete
+///
+/// The splitter only sees the keys of the parts, so a joined key that repeats
+/// another section's is caught here and handled by `duplicate_keys`.
+struct Continuations<F> {
+    parts: Vec<Section>,
+    emit: F,
+    duplicate_keys: DuplicateKeys,
+    plain_keys: HashSet<String>,
+    joined_keys: HashSet<String>,
+    warnings: Vec<Warning>,
+    error: Option<Error>,
+}
+
+impl<F: FnMut(Section)> Continuations<F> {
+    fn new(duplicate_keys: DuplicateKeys, emit: F) -> Continuations<F> {
+        Continuations {
+            parts: Vec::new(),
+            emit,
+            duplicate_keys,
+            plain_keys: HashSet::new(),
+            joined_keys: HashSet::new(),
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn push(&mut self, section: Section) {
+        let continues = match (self.parts.first(), parse_continuation_key(section.key())) {
+            (Some(first), Some((base, index, count))) => {
+                let (first_base, _, first_count) = parse_continuation_key(first.key()).unwrap();
+                base == first_base && count == first_count && index == self.parts.len() + 1
+            }
+            _ => false,
+        };
+        if !continues {
+            self.flush();
+            match parse_continuation_key(section.key()) {
+                Some((_, 1, _)) => self.parts.push(section),
+                _ => self.pass_on(section, false),
+            }
+            return;
+        }
+        self.parts.push(section);
+
+        let (_, _, count) = parse_continuation_key(self.parts[0].key()).unwrap();
+        if self.parts.len() == count {
+            let mut parts = std::mem::take(&mut self.parts).into_iter();
+            let first = parts.next().unwrap();
+            let base = parse_continuation_key(first.key()).unwrap().0.to_string();
+            let mut joined = first.renamed(base);
+            for part in parts {
+                joined.body.push_str(part.body());
+                joined.end_line = part.end_line;
+            }
+            self.pass_on(joined, true);
+        }
+    }
+
+    /// Passes on an incomplete run unchanged
+    fn flush(&mut self) {
+        for part in std::mem::take(&mut self.parts) {
+            self.pass_on(part, false);
+        }
+    }
+
+    /// Emits `section` unless its key collides with a section on the other
+    /// side of a join; collisions between unjoined keys were already handled
+    fn pass_on(&mut self, section: Section, joined: bool) {
+        let duplicate = if joined {
+            self.joined_keys.insert(section.key().to_string());
+            self.plain_keys.contains(section.key())
+        } else {
+            self.plain_keys.insert(section.key().to_string());
+            self.joined_keys.contains(section.key())
+        };
+        if self.error.is_some() {
+            return;
+        }
+        let marker_line = section.line_number().map(|n| n - 1);
+        match self.duplicate_keys {
+            _ if !duplicate => {}
+            DuplicateKeys::Error => {
+                let message = format!("duplicate key \"{}\"", section.key());
+                self.error = Some(Error::new(ErrorKind::DuplicateKey, message).with_line(marker_line));
+                return;
+            }
+            DuplicateKeys::KeepFirst => return,
+            DuplicateKeys::KeepLast => self.warnings.push(Warning {
+                kind: WarningKind::DuplicateKey,
+                line_number: marker_line.unwrap_or(0),
+                message: format!("duplicate key \"{}\" replaces an earlier section", section.key()),
+            }),
+            DuplicateKeys::Concatenate => {}
+        }
+        (self.emit)(section);
+    }
+}
+
+fn split_sections<I, S, F>(mut it: I, options: &ParseOptions, sink: &mut S, mut emit: F)
+    -> Result<(String, Option<String>), Error>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>, S: DiagnosticsSink + ?Sized, F: FnMut(Section)
+{
+    let mut line_number = 0;
+    let mut preamble_line = None;
+    let mut scanned_bytes = 0;
+    let mut first_lines = Vec::new();
+    let mut preamble = if options.keep_preamble { Some(String::new()) } else { None };
+    let prefix = loop {
+        line_number += 1;
+        let limit_reached = options.header_scan_limit.is_some_and(|limit| line_number > limit);
+        let line = match it.next() {
+            Some(line) if !limit_reached => line,
+            next => return Err(Error::new(ErrorKind::MissingHeader, missing_header_message(
+                    line_number - 1, scanned_bytes, &first_lines, limit_reached && next.is_some()
+                )).with_line(line_number)),
+        };
+
+        check_line_len(line.as_ref(), line_number, options)?;
+        if let Some(index) = line.as_ref().find("multitext header") {
+            break line.as_ref().split_at(index).0.trim_end().to_string();
+        }
+        scanned_bytes += line.as_ref().len() + 1;
+        if first_lines.len() < CONTEXT_LINES {
+            first_lines.push(line.as_ref().chars().take(CONTEXT_WIDTH).collect::<String>());
+        }
+        if preamble_line.is_none() && !line.as_ref().trim().is_empty() {
+            preamble_line = Some(line_number);
+        }
+        if let Some(preamble) = &mut preamble {
+            preamble.push_str(line.as_ref());
+            preamble.push('\n');
+        }
+    };
+
+    if let (Some(preamble_line), None) = (preamble_line, &preamble) {
+        sink.report(&Warning {
+            kind: WarningKind::DiscardedPreamble,
+            line_number: preamble_line,
+            message: "text above the multitext header is discarded".to_string(),
+        });
+    }
+
+    let mut anonymous_count = 0;
+    let mut skipping = false;
+    let mut seen = HashSet::new();
+    seen.insert("multitext header".to_string());
+    let mut section = Section::at_line("multitext header", line_number + 1);
+    for line in it {
+        line_number += 1;
+        check_line_len(line.as_ref(), line_number, options)?;
+        if line.as_ref().starts_with(&prefix) {
+            let rest = line.as_ref().split_at(prefix.len()).1;
+            let rest = if options.trim_keys { rest.trim() } else { rest.strip_prefix(' ').unwrap_or(rest) };
+            let rest = options.trailing_decoration.strip(rest, &prefix);
+            let (rest, attrs) = if options.attributes { split_attributes(rest) } else { (rest, Vec::new()) };
+            let mut name = rest.to_string();
+            if name.is_empty() {
+                match options.empty_keys {
+                    EmptyKeys::Keep => sink.report(&Warning {
+                        kind: WarningKind::EmptyKey,
+                        line_number,
+                        message: "marker line has an empty key".to_string(),
+                    }),
+                    EmptyKeys::Error => return Err(Error::new(ErrorKind::EmptyKey, "marker line has an empty key")
+                        .with_line(line_number)
+                        .with_snippet(Snippet::of_part(line.as_ref(), line.as_ref()))),
+                    EmptyKeys::Anonymous => {
+                        anonymous_count += 1;
+                        name = format!("__anon_{}", anonymous_count);
+                    }
+                    EmptyKeys::Continue => continue,
+                }
+            }
+            let duplicate = !seen.insert(name.clone());
+            match options.duplicate_keys {
+                _ if !duplicate => {}
+                DuplicateKeys::Error => {
+                    let message = format!("duplicate key \"{}\"", name);
+                    return Err(Error::new(ErrorKind::DuplicateKey, message)
+                        .with_line(line_number)
+                        .with_snippet(Snippet::of_part(line.as_ref(), rest)));
+                }
+                DuplicateKeys::KeepLast => sink.report(&Warning {
+                    kind: WarningKind::DuplicateKey,
+                    line_number,
+                    message: format!("duplicate key \"{}\" replaces an earlier section", name),
+                }),
+                DuplicateKeys::KeepFirst | DuplicateKeys::Concatenate => {}
+            }
+            let mut next = Section::at_line(&name, line_number + 1);
+            next.attrs = attrs;
+            section.set_end_line(line_number);
+            let previous = std::mem::replace(&mut section, next);
+            let skip = duplicate && options.duplicate_keys == DuplicateKeys::KeepFirst
+                || options.skip_keys.as_ref().is_some_and(|pattern| pattern.is_match(&name));
+            if !std::mem::replace(&mut skipping, skip) {
+                emit(previous);
+            }
+        } else if !skipping {
+            section.body.push_str(unescape(line.as_ref(), &prefix));
+            section.body.push('\n');
+        }
+    }
+
+    section.set_end_line(line_number + 1);
+    if !skipping {
+        emit(section);
+    }
+
+    Ok((prefix, preamble.filter(|text| !text.is_empty())))
+}
+
+fn check_line_len(line: &str, line_number: usize, options: &ParseOptions) -> Result<(), Error> {
+    match options.max_line_len {
+        Some(max) if line.len() > max => {
+            let message = format!("line is {} bytes long, over the limit of {}", line.len(), max);
+            Err(Error::new(ErrorKind::LineTooLong, message)
+                .with_line(line_number)
+                .with_snippet(Snippet::of_part(line, &line[floor_char_boundary(line, max)..])))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The largest index no greater than `index` that starts a character
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0)
+}
+
+/// Removes the escape from a body line the writer escaped: one that starts
+/// with one or more backslashes and then the marker loses one backslash
+///
+/// Markers that themselves start with a backslash have no escape.
+pub(crate) fn unescape<'a>(line: &'a str, marker: &str) -> &'a str {
+    match line.strip_prefix('\\') {
+        Some(rest) if is_escapable(marker) && rest.trim_start_matches('\\').starts_with(marker) => rest,
+        _ => line,
+    }
+}
+
+pub(crate) fn is_escapable(marker: &str) -> bool {
+    !marker.is_empty() && !marker.starts_with('\\')
+}
+
+/// Splits trailing `name=value` words off the text after a marker
+pub(crate) fn split_attributes(text: &str) -> (&str, Vec<(String, String)>) {
+    let mut key = text;
+    let mut attrs = Vec::new();
+    loop {
+        let (rest, word) = match key.rsplit_once(char::is_whitespace) {
+            Some((rest, word)) => (rest.trim_end(), word),
+            None => ("", key),
+        };
+        match word.split_once('=') {
+            Some((name, value)) if is_attribute_name(name) && !value.is_empty() => {
+                attrs.push((name.to_string(), value.to_string()));
+                key = rest;
+            }
+            _ => break,
+        }
+        if key.is_empty() {
+            break;
+        }
+    }
+    attrs.reverse();
+    (key, attrs)
+}
+
+pub(crate) fn is_attribute_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
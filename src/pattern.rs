@@ -0,0 +1,253 @@
+//! A small regular expression matcher
+//!
+//! Supports literals, `.`, `^` and `$` anchors, the repetitions `*`, `+` and
+//! `?`, character classes such as `[a-z_]` and `[^0-9]`, the escapes `\d`,
+//! `\w`, `\s` (and their negations `\D`, `\W`, `\S`), and `|` between whole
+//! alternatives. Groups and counted repetition are not supported.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Char(char),
+    Any,
+    Class { negated: bool, items: Vec<ClassItem> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    One,
+    ZeroOrMore,
+    OneOrMore,
+    ZeroOrOne,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Branch {
+    anchored_start: bool,
+    anchored_end: bool,
+    pieces: Vec<(Atom, Repeat)>,
+}
+
+/// A compiled pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pattern {
+    branches: Vec<Branch>,
+}
+
+impl Pattern {
+    pub(crate) fn new(source: &str) -> Result<Pattern, String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut branches = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        let mut in_class = false;
+        while i <= chars.len() {
+            match chars.get(i) {
+                Some('\\') => i += 1,
+                Some('[') => in_class = true,
+                Some(']') => in_class = false,
+                Some('|') if !in_class => {
+                    branches.push(parse_branch(&chars[start..i])?);
+                    start = i + 1;
+                }
+                None => {
+                    branches.push(parse_branch(&chars[start..i])?);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(Pattern { branches })
+    }
+
+    /// Returns true if the pattern matches anywhere in `text`
+    pub(crate) fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.branches.iter().any(|branch| {
+            let last_start = if branch.anchored_start { 0 } else { text.len() };
+            (0..=last_start).any(|start| match_here(&branch.pieces, &text[start..], branch.anchored_end))
+        })
+    }
+}
+
+fn parse_branch(mut chars: &[char]) -> Result<Branch, String> {
+    let anchored_start = chars.first() == Some(&'^');
+    if anchored_start {
+        chars = &chars[1..];
+    }
+    let anchored_end = chars.last() == Some(&'$') && !ends_with_escape(chars);
+    if anchored_end {
+        chars = &chars[..chars.len() - 1];
+    }
+
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let atom = match chars[i] {
+            '.' => Atom::Any,
+            '\\' => {
+                i += 1;
+                let c = *chars.get(i).ok_or("pattern ends with a lone \\")?;
+                match escape_class(c) {
+                    Some(item) => Atom::Class { negated: false, items: vec![item] },
+                    None => Atom::Char(unescape(c)),
+                }
+            }
+            '[' => {
+                let (atom, end) = parse_class(chars, i)?;
+                i = end;
+                atom
+            }
+            '*' | '+' | '?' => return Err(format!("nothing to repeat before '{}'", chars[i])),
+            '(' | ')' | '{' | '}' => return Err(format!("'{}' is not supported; escape it to match it literally", chars[i])),
+            c => Atom::Char(c),
+        };
+        i += 1;
+        let repeat = match chars.get(i) {
+            Some('*') => Repeat::ZeroOrMore,
+            Some('+') => Repeat::OneOrMore,
+            Some('?') => Repeat::ZeroOrOne,
+            _ => Repeat::One,
+        };
+        if repeat != Repeat::One {
+            i += 1;
+        }
+        pieces.push((atom, repeat));
+    }
+    Ok(Branch { anchored_start, anchored_end, pieces })
+}
+
+fn ends_with_escape(chars: &[char]) -> bool {
+    let backslashes = chars[..chars.len() - 1].iter().rev().take_while(|&&c| c == '\\').count();
+    backslashes % 2 == 1
+}
+
+fn parse_class(chars: &[char], open: usize) -> Result<(Atom, usize), String> {
+    let mut i = open + 1;
+    let negated = chars.get(i) == Some(&'^');
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i).ok_or("unclosed character class")?;
+        if c == ']' && !first {
+            return Ok((Atom::Class { negated, items }, i));
+        }
+        first = false;
+        let low = if c == '\\' {
+            i += 1;
+            let e = *chars.get(i).ok_or("unclosed character class")?;
+            if let Some(item) = escape_class(e) {
+                items.push(item);
+                i += 1;
+                continue;
+            }
+            unescape(e)
+        } else {
+            c
+        };
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&c| c != ']') {
+            let high = chars[i + 2];
+            if high < low {
+                return Err(format!("invalid range {}-{}", low, high));
+            }
+            items.push(ClassItem::Range(low, high));
+            i += 3;
+        } else {
+            items.push(ClassItem::Range(low, low));
+            i += 1;
+        }
+    }
+}
+
+fn escape_class(c: char) -> Option<ClassItem> {
+    Some(match c {
+        'd' => ClassItem::Digit(true),
+        'D' => ClassItem::Digit(false),
+        'w' => ClassItem::Word(true),
+        'W' => ClassItem::Word(false),
+        's' => ClassItem::Space(true),
+        'S' => ClassItem::Space(false),
+        _ => return None,
+    })
+}
+
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => c == *expected,
+            Atom::Any => c != '\n',
+            Atom::Class { negated, items } => items.iter().any(|item| item.matches(c)) != *negated,
+        }
+    }
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(low, high) => low <= c && c <= high,
+            ClassItem::Digit(yes) => c.is_ascii_digit() == yes,
+            ClassItem::Word(yes) => (c.is_alphanumeric() || c == '_') == yes,
+            ClassItem::Space(yes) => c.is_whitespace() == yes,
+        }
+    }
+}
+
+fn match_here(pieces: &[(Atom, Repeat)], text: &[char], anchored_end: bool) -> bool {
+    let (atom, repeat) = match pieces.first() {
+        Some(piece) => piece,
+        None => return !anchored_end || text.is_empty(),
+    };
+    let rest = &pieces[1..];
+    let (min, max) = match repeat {
+        Repeat::One => (1, 1),
+        Repeat::ZeroOrOne => (0, 1),
+        Repeat::ZeroOrMore => (0, usize::MAX),
+        Repeat::OneOrMore => (1, usize::MAX),
+    };
+    let available = text.iter().take(max).take_while(|&&c| atom.matches(c)).count();
+    if available < min {
+        return false;
+    }
+    (min..=available).rev().any(|n| match_here(rest, &text[n..], anchored_end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let matches = |pattern: &str, text: &str| Pattern::new(pattern).unwrap().is_match(text);
+        assert!(matches("^test_", "test_parse"));
+        assert!(!matches("^test_", "a test_parse"));
+        assert!(matches("shader$", "vertex shader"));
+        assert!(matches("^v[a-z]+x\\s", "vertex shader"));
+        assert!(matches("^#version \\d+", "#version 330 core"));
+        assert!(matches("a.c|xyz", "xyz"));
+        assert!(matches("colou?r", "color"));
+        assert!(!matches("^[^.]*$", "file.txt"));
+        assert!(matches("\\.glsl$", "lit.glsl"));
+        assert!(Pattern::new("(a)").is_err());
+        assert!(Pattern::new("[a").is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! A small selector language for picking sections out of a document
+//!
+//! A query compares section properties with values and combines the results:
+//!
+//! ```text
+//! key == 'vertex shader'
+//! key =~ '^test_' && size > 10k
+//! attr.stage == 'vertex' || !(line < 100)
+//! ```
+//!
+//! The properties are `key`, `body`, `size` (the body length in bytes), `line`
+//! (the line the body starts on) and `attr.NAME` (see
+//! [`Section::attr`](crate::Section::attr)). Values are quoted strings or
+//! numbers with an optional `k`, `m` or `g` suffix for powers of 1024.
+//!
+//! `==`, `!=`, `<`, `<=`, `>` and `>=` compare numerically against a number
+//! and as text against a string. `=~` and `!~` match a regular expression
+//! (literals, `.`, `^`, `$`, `*`, `+`, `?`, `[...]`, `\d`, `\w`, `\s` and
+//! top-level `|`). A comparison with a missing attribute or line is false,
+//! except for `!=` and `!~`.
+
+use crate::pattern::Pattern;
+use crate::{Document, Error, ErrorKind, Section};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Key,
+    Body,
+    Size,
+    Line,
+    Attr(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NoMatch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Text(String),
+    Number(u64),
+    Pattern(Pattern),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Compare(Field, Op, Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// A parsed query
+///
+/// # Examples
+/// ```
+/// use multitext::query::Query;
+///
+/// let doc: multitext::Document = vec![("test_a", "1\n"), ("test_b", "22\n"), ("main", "333\n")]
+///     .into_iter().collect();
+/// let query = Query::parse("key =~ '^test_' && size >= 3").unwrap();
+/// let keys: Vec<_> = doc.iter().filter(|s| query.matches(s)).map(|s| s.key()).collect();
+/// assert_eq!(keys, ["test_b"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Query, Error> {
+        let error = |(column, message): (usize, String)| {
+            Error::new(ErrorKind::Malformed, format!("query column {}: {}", column, message))
+        };
+        let tokens = tokenize(source).map_err(error)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expr = parser.or().map_err(error)?;
+        if let Some((column, token)) = parser.tokens.get(parser.position) {
+            return Err(error((*column, format!("unexpected {}", token))));
+        }
+        Ok(Query { source: source.to_string(), expr })
+    }
+
+    pub fn matches(&self, section: &Section) -> bool {
+        evaluate(&self.expr, section)
+    }
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl std::str::FromStr for Query {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Query, Error> {
+        Query::parse(source)
+    }
+}
+
+impl Document {
+    /// Returns the sections matching a [query](crate::query), in document
+    /// order
+    ///
+    /// # Examples
+    /// ```
+    /// let lines = ["@@ multitext header", "@@ vertex shader", "...", "@@ fragment shader", "..."];
+    /// let doc = multitext::parse_document(lines.iter()).unwrap();
+    /// let found = doc.select("key == 'vertex shader'").unwrap();
+    /// assert_eq!(found.len(), 1);
+    /// ```
+    pub fn select(&self, query: &str) -> Result<Vec<&Section>, Error> {
+        let query = Query::parse(query)?;
+        Ok(self.iter().filter(|s| query.matches(s)).collect())
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(u64),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "\"{}\"", word),
+            Token::Text(text) => write!(f, "string {:?}", text),
+            Token::Number(n) => write!(f, "number {}", n),
+            Token::Op(_) => f.write_str("operator"),
+            Token::And => f.write_str("\"&&\""),
+            Token::Or => f.write_str("\"||\""),
+            Token::Not => f.write_str("\"!\""),
+            Token::Open => f.write_str("\"(\""),
+            Token::Close => f.write_str("\")\""),
+        }
+    }
+}
+
+type Spanned = (usize, Token);
+
+fn tokenize(source: &str) -> Result<Vec<Spanned>, (usize, String)> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let two = |token| (token, 2);
+        let (token, width) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('=', Some('=')) => two(Token::Op(Op::Eq)),
+            ('=', Some('~')) => two(Token::Op(Op::Match)),
+            ('!', Some('=')) => two(Token::Op(Op::Ne)),
+            ('!', Some('~')) => two(Token::Op(Op::NoMatch)),
+            ('<', Some('=')) => two(Token::Op(Op::Le)),
+            ('>', Some('=')) => two(Token::Op(Op::Ge)),
+            ('&', Some('&')) => two(Token::And),
+            ('|', Some('|')) => two(Token::Or),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('\'', _) | ('"', _) => {
+                let mut text = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err((column, "unterminated string".to_string())),
+                        Some('\\') if j + 1 < chars.len() => {
+                            text.push(chars[j + 1]);
+                            j += 2;
+                        }
+                        Some(&q) if q == c => break,
+                        Some(&other) => {
+                            text.push(other);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Text(text), j + 1 - i)
+            }
+            (c, _) if c.is_ascii_digit() => {
+                let digits: String = chars[i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                let mut width = digits.len();
+                let mut number: u64 = digits.parse().map_err(|_| (column, "number is too large".to_string()))?;
+                let scale = match chars.get(i + width).map(|c| c.to_ascii_lowercase()) {
+                    Some('k') => Some(1 << 10),
+                    Some('m') => Some(1 << 20),
+                    Some('g') => Some(1 << 30),
+                    _ => None,
+                };
+                if let Some(scale) = scale {
+                    number = number.checked_mul(scale).ok_or((column, "number is too large".to_string()))?;
+                    width += 1;
+                }
+                (Token::Number(number), width)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let word: String = chars[i..].iter()
+                    .take_while(|&&c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+                    .collect();
+                let width = word.chars().count();
+                (Token::Word(word), width)
+            }
+            (c, _) => return Err((column, format!("unexpected character '{}'", c))),
+        };
+        tokens.push((column, token));
+        i += width;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.position).map_or_else(
+            || self.tokens.last().map_or(1, |(column, _)| column + 1),
+            |(column, _)| *column,
+        )
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(_, token)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, (usize, String)> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, (usize, String)> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, (usize, String)> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                let column = self.column();
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err((column, "expected \")\"".to_string())),
+                }
+            }
+            Some(Token::Word(word)) => self.comparison(column, &word),
+            Some(token) => Err((column, format!("expected a property, found {}", token))),
+            None => Err((column, "expected a property".to_string())),
+        }
+    }
+
+    fn comparison(&mut self, column: usize, word: &str) -> Result<Expr, (usize, String)> {
+        let field = match word {
+            "key" => Field::Key,
+            "body" => Field::Body,
+            "size" => Field::Size,
+            "line" => Field::Line,
+            _ => match word.strip_prefix("attr.") {
+                Some(name) if crate::parse::is_attribute_name(name) => Field::Attr(name.to_string()),
+                _ => return Err((column, format!("unknown property \"{}\"", word))),
+            },
+        };
+        let column = self.column();
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err((column, "expected a comparison operator".to_string())),
+        };
+        let column = self.column();
+        let value = match (self.next(), op) {
+            (Some(Token::Text(text)), Op::Match) | (Some(Token::Text(text)), Op::NoMatch) => {
+                Value::Pattern(Pattern::new(&text).map_err(|message| (column, message))?)
+            }
+            (_, Op::Match) | (_, Op::NoMatch) => return Err((column, "expected a quoted pattern".to_string())),
+            (Some(Token::Text(text)), _) => Value::Text(text),
+            (Some(Token::Number(n)), _) => Value::Number(n),
+            _ => return Err((column, "expected a string or number".to_string())),
+        };
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+fn evaluate(expr: &Expr, section: &Section) -> bool {
+    match expr {
+        Expr::Not(inner) => !evaluate(inner, section),
+        Expr::And(a, b) => evaluate(a, section) && evaluate(b, section),
+        Expr::Or(a, b) => evaluate(a, section) || evaluate(b, section),
+        Expr::Compare(field, op, value) => compare(section, field, *op, value),
+    }
+}
+
+fn compare(section: &Section, field: &Field, op: Op, value: &Value) -> bool {
+    let missing = matches!(op, Op::Ne | Op::NoMatch);
+    let text = match field {
+        Field::Key => section.key().to_string(),
+        Field::Body => section.body().to_string(),
+        Field::Size => section.body().len().to_string(),
+        Field::Line => match section.line_number() {
+            Some(line) => line.to_string(),
+            None => return missing,
+        },
+        Field::Attr(name) => match section.attr(name) {
+            Some(value) => value.to_string(),
+            None => return missing,
+        },
+    };
+    let ordering = match value {
+        Value::Pattern(pattern) => return pattern.is_match(&text) == (op == Op::Match),
+        Value::Text(expected) => text.as_str().cmp(expected.as_str()),
+        Value::Number(expected) => match text.parse::<u64>() {
+            Ok(actual) => actual.cmp(expected),
+            Err(_) => return missing,
+        },
+    };
+    use std::cmp::Ordering::*;
+    match op {
+        Op::Eq => ordering == Equal,
+        Op::Ne => ordering != Equal,
+        Op::Lt => ordering == Less,
+        Op::Le => ordering != Greater,
+        Op::Gt => ordering == Greater,
+        Op::Ge => ordering != Less,
+        Op::Match | Op::NoMatch => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let lines = [
+            "@@ multitext header",
+            "@@ lit stage=vertex",
+            "void main() {}",
+            "@@ lit.frag stage=fragment",
+            "void main() {}",
+            "@@ notes",
+        ];
+        let options = crate::options::ParseOptions::new().attributes(true);
+        let doc = crate::parse_document_with(lines.iter(), &options).unwrap();
+        let keys = |query: &str| -> Vec<&str> { doc.select(query).unwrap().iter().map(|s| s.key()).collect() };
+
+        assert_eq!(keys("attr.stage == 'vertex'"), ["lit"]);
+        assert_eq!(keys("attr.stage != 'vertex'"), ["multitext header", "lit.frag", "notes"]);
+        assert_eq!(keys("size > 0 && !(key =~ '\\\\.frag$')"), ["lit"]);
+        assert_eq!(keys("line >= 4 || key == \"multitext header\""), ["multitext header", "lit.frag", "notes"]);
+        assert_eq!(keys("size < 1k && key !~ '^l'"), ["multitext header", "notes"]);
+
+        assert!(Query::parse("key = 'x'").is_err());
+        assert!(doc.select("colour == 'red'").is_err());
+        assert!(doc.select("(key == 'a'").is_err());
+        assert!(doc.select("key =~ 3").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Hiding sensitive bodies before a bundle is shared
+//!
+//! [`Document::redact`] replaces each chosen body with a one-line placeholder
+//! recording the body's size and hash, so a redacted bundle can still be
+//! compared with the original: two sections with the same placeholder had the
+//! same body.
+//!
+//! The hash is the crate's 64-bit FNV-1a, which is not a cryptographic hash.
+//! It hides a long random secret, but a short or guessable one such as a
+//! password can be recovered by hashing candidates.
+
+use crate::{Document, Error, ErrorKind};
+use crate::events::ChangeEvent;
+use crate::pattern::Pattern;
+
+/// The sections for [`Document::redact`] to hide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Selection<'a> {
+    /// Exactly these keys
+    Keys(&'a [&'a str]),
+    /// Every key matching a regular expression, using the same syntax as the
+    /// `=~` operator of [queries](crate::query)
+    Pattern(&'a str),
+}
+
+impl<'a> From<&'a [&'a str]> for Selection<'a> {
+    fn from(keys: &'a [&'a str]) -> Selection<'a> {
+        Selection::Keys(keys)
+    }
+}
+
+impl<'a, const N: usize> From<&'a [&'a str; N]> for Selection<'a> {
+    fn from(keys: &'a [&'a str; N]) -> Selection<'a> {
+        Selection::Keys(keys)
+    }
+}
+
+/// The placeholder that replaces `body`
+pub fn placeholder(body: &str) -> String {
+    format!("[redacted: {} bytes, fnv1a {:016x}]\n", body.len(), crate::hash::hash_str(body))
+}
+
+/// Reads the hash back out of a placeholder written by [`Document::redact`]
+///
+/// # Examples
+/// ```
+/// use multitext::redact::{placeholder, redacted_hash};
+///
+/// assert!(redacted_hash(&placeholder("hunter2\n")).is_some());
+/// assert_eq!(redacted_hash("hunter2\n"), None);
+/// ```
+pub fn redacted_hash(body: &str) -> Option<u64> {
+    let rest = body.strip_prefix("[redacted: ")?.strip_suffix("]\n")?;
+    let (_, hash) = rest.split_once(" bytes, fnv1a ")?;
+    if hash.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(hash, 16).ok()
+}
+
+impl Document {
+    /// Replaces the bodies of the selected sections with a
+    /// [placeholder](placeholder), returning the keys that were redacted
+    ///
+    /// Locked sections are redacted too, and sections that are already
+    /// redacted are left alone. Fails only on an invalid pattern.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::redact::Selection;
+    ///
+    /// let mut doc: multitext::Document = vec![
+    ///     ("config", "verbose = true\n"), ("api token", "abc123\n"), ("db password", "hunter2\n"),
+    /// ].into_iter().collect();
+    /// let redacted = doc.redact(Selection::Pattern("token$|password$")).unwrap();
+    /// assert_eq!(redacted, ["api token", "db password"]);
+    /// assert_eq!(&doc["config"], "verbose = true\n");
+    /// assert!(doc["api token"].starts_with("[redacted: 7 bytes, fnv1a "));
+    ///
+    /// doc.redact(&["config"]).unwrap();
+    /// assert!(multitext::redact::redacted_hash(&doc["config"]).is_some());
+    /// ```
+    pub fn redact<'a, S: Into<Selection<'a>>>(&mut self, selection: S) -> Result<Vec<String>, Error> {
+        let selected: Box<dyn Fn(&str) -> bool> = match selection.into() {
+            Selection::Keys(keys) => Box::new(move |key| keys.contains(&key)),
+            Selection::Pattern(source) => {
+                let pattern = Pattern::new(source).map_err(|message| {
+                    Error::new(ErrorKind::Malformed, format!("invalid pattern \"{}\": {}", source, message))
+                })?;
+                Box::new(move |key| pattern.is_match(key))
+            }
+        };
+
+        let mut redacted = Vec::new();
+        for index in 0..self.sections.len() {
+            let section = &mut self.sections[index];
+            if !selected(section.key()) || redacted_hash(&section.body).is_some() {
+                continue;
+            }
+            let new_body = placeholder(&section.body);
+            let old_body = std::mem::replace(&mut section.body, new_body);
+            section.touch();
+            let key = section.key().to_string();
+            if !self.observers.is_empty() {
+                let new_body = self.sections[index].body.clone();
+                self.emit(&ChangeEvent::Modified { key: &key, old_body: &old_body, new_body: &new_body });
+            }
+            redacted.push(key);
+        }
+        Ok(redacted)
+    }
+}
@@ -0,0 +1,315 @@
+//! A runtime hub of named documents
+//!
+//! Engines that load many bundles which link to each other keep them in a
+//! [`Registry`]. Sections are looked up by `bundle:key` paths, following
+//! [links](crate::link) into other registered bundles. The whole registry can
+//! be snapshotted cheaply in memory, to roll back a failed reload, or written
+//! out as a single multitext file and read back.
+
+use crate::link::{link_target, resolve_links, resolve_section, Resolver};
+use crate::options::ParseOptions;
+use crate::{Document, DocumentWriter, Error, ErrorKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Documents stored by name
+///
+/// # Examples
+/// ```
+/// use multitext::options::ParseOptions;
+/// use multitext::registry::Registry;
+///
+/// let options = ParseOptions::new().attributes(true);
+/// let mut registry = Registry::new();
+/// registry.insert("lib", multitext::parse_document_with(
+///     ["@@ multitext header", "@@ noise", "float noise(vec2 p);"].iter(), &options).unwrap());
+/// registry.insert("water", multitext::parse_document_with(
+///     ["@@ multitext header", "@@ common ref=lib#noise"].iter(), &options).unwrap());
+///
+/// assert_eq!(registry.lookup("water:common").unwrap(), "float noise(vec2 p);\n");
+/// assert!(registry.lookup("water:missing").is_err());
+///
+/// let snapshot = registry.snapshot();
+/// registry.remove("lib");
+/// assert!(registry.lookup("water:common").is_err());
+/// registry.restore(snapshot);
+/// assert!(registry.lookup("water:common").is_ok());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    bundles: BTreeMap<String, Arc<Document>>,
+}
+
+/// What [`Registry::gc`] found unreachable from its roots
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Garbage {
+    /// Bundles none of whose sections are reachable, in sorted order
+    pub bundles: Vec<String>,
+    /// Unreachable sections of the bundles that are otherwise in use, as
+    /// `(bundle, key)` pairs in bundle and then document order
+    pub sections: Vec<(String, String)>,
+}
+
+impl Garbage {
+    pub fn is_empty(&self) -> bool {
+        self.bundles.is_empty() && self.sections.is_empty()
+    }
+}
+
+/// The contents of a [`Registry`] at one moment; see [`Registry::snapshot`]
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    bundles: BTreeMap<String, Arc<Document>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Stores `doc` under `name`, returning the document it replaces
+    ///
+    /// Parse documents with
+    /// [`ParseOptions::attributes`](crate::options::ParseOptions::attributes)
+    /// for their links to be seen.
+    pub fn insert<N: Into<String>>(&mut self, name: N, doc: Document) -> Option<Arc<Document>> {
+        self.bundles.insert(name.into(), Arc::new(doc))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Arc<Document>> {
+        self.bundles.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Document> {
+        self.bundles.get(name).map(|doc| &**doc)
+    }
+
+    /// The names of the stored documents, in sorted order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.bundles.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bundles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bundles.is_empty()
+    }
+
+    /// The text of the section at `path`, written `bundle:key`, following
+    /// links into other stored bundles
+    ///
+    /// The bundle name ends at the first `:`, so keys may contain colons.
+    pub fn lookup(&self, path: &str) -> Result<String, Error> {
+        let (bundle, key) = path.split_once(':')
+            .ok_or_else(|| Error::new(ErrorKind::Malformed, format!("\"{}\" is not a bundle:key path", path)))?;
+        let doc = self.bundle(bundle)?;
+        resolve_section(doc, key, &mut &*self)?
+            .ok_or_else(|| {
+                Error::new(ErrorKind::MissingSection, format!("bundle \"{}\" has no section \"{}\"", bundle, key))
+            })
+    }
+
+    /// A copy of the document `name` with all its links resolved
+    pub fn resolved(&self, name: &str) -> Result<Document, Error> {
+        resolve_links(self.bundle(name)?, &mut &*self)
+    }
+
+    fn bundle(&self, name: &str) -> Result<&Document, Error> {
+        self.get(name).ok_or_else(|| Error::new(ErrorKind::Link, format!("no bundle named \"{}\"", name)))
+    }
+
+    /// Finds the bundles and sections that can't be reached by following links
+    /// from the bundles named in `roots`
+    ///
+    /// Every section of a root is in use, and so is every section a used
+    /// section links to, directly or through a chain of links. Links to
+    /// bundles or sections that don't exist are skipped, as they are weak.
+    /// Header sections are never reported on their own. Fails if a root isn't
+    /// stored, so a misspelt name can't make everything look unused.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::options::ParseOptions;
+    /// use multitext::registry::Registry;
+    ///
+    /// let options = ParseOptions::new().attributes(true);
+    /// let parse = |lines: &[&str]| multitext::parse_document_with(lines.iter(), &options).unwrap();
+    /// let mut registry = Registry::new();
+    /// registry.insert("main", parse(&["@@ multitext header", "@@ noise ref=lib"]));
+    /// registry.insert("lib", parse(&["@@ multitext header", "@@ noise", "@@ old_noise"]));
+    /// registry.insert("unused", parse(&["@@ multitext header", "@@ x"]));
+    ///
+    /// let garbage = registry.gc(&["main"]).unwrap();
+    /// assert_eq!(garbage.bundles, ["unused"]);
+    /// assert_eq!(garbage.sections, [("lib".to_string(), "old_noise".to_string())]);
+    ///
+    /// registry.prune(&["main"]).unwrap();
+    /// assert_eq!(registry.names().collect::<Vec<_>>(), ["lib", "main"]);
+    /// assert!(registry.get("lib").unwrap().section("old_noise").is_none());
+    /// ```
+    pub fn gc(&self, roots: &[&str]) -> Result<Garbage, Error> {
+        let mut live = BTreeSet::new();
+        let mut pending = Vec::new();
+        for &root in roots {
+            for section in self.bundle(root)?.iter() {
+                pending.push((root.to_string(), section.key().to_string()));
+            }
+        }
+        while let Some((bundle, key)) = pending.pop() {
+            let section = match self.bundles.get(&bundle).and_then(|doc| doc.section(&key)) {
+                Some(section) => section,
+                None => continue,
+            };
+            if let Some((next_bundle, next_key)) = link_target(section) {
+                let next = (next_bundle.to_string(), next_key.to_string());
+                if !live.contains(&next) {
+                    pending.push(next);
+                }
+            }
+            live.insert((bundle, key));
+        }
+
+        let mut garbage = Garbage::default();
+        for (name, doc) in &self.bundles {
+            let is_dead = |key: &str| !live.contains(&(name.clone(), key.to_string()));
+            let dead: Vec<_> = doc.iter()
+                .filter(|s| s.key() != "multitext header")
+                .map(|s| s.key())
+                .filter(|&key| is_dead(key))
+                .collect();
+            if doc.iter().all(|s| is_dead(s.key())) {
+                garbage.bundles.push(name.clone());
+            } else {
+                garbage.sections.extend(dead.into_iter().map(|key| (name.clone(), key.to_string())));
+            }
+        }
+        Ok(garbage)
+    }
+
+    /// Drops what [`Registry::gc`] finds unreachable from `roots`, and returns
+    /// it
+    ///
+    /// Locked sections are left in place. Documents shared with a snapshot
+    /// are copied before sections are removed, so the snapshot is unchanged.
+    pub fn prune(&mut self, roots: &[&str]) -> Result<Garbage, Error> {
+        let mut garbage = self.gc(roots)?;
+        let bundles = &self.bundles;
+        garbage.sections.retain(|(name, key)| bundles.get(name).is_none_or(|doc| doc.check_unlocked(key).is_ok()));
+        for name in &garbage.bundles {
+            self.bundles.remove(name);
+        }
+        for (name, key) in &garbage.sections {
+            if let Some(doc) = self.bundles.get_mut(name) {
+                Arc::make_mut(doc).remove(key)?;
+            }
+        }
+        Ok(garbage)
+    }
+
+    /// Captures the current contents, sharing the documents rather than
+    /// copying them
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { bundles: self.bundles.clone() }
+    }
+
+    /// Replaces the contents with those captured in `snapshot`
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.bundles = snapshot.bundles;
+    }
+
+    /// Writes every document as one multitext file, with one section per
+    /// document holding its text, and returns the underlying writer
+    ///
+    /// Lines of a document that start with the outer marker are escaped like
+    /// any other body text, so documents of any marker nest safely.
+    pub fn write_to<W: Write>(&self, inner: W, marker: &str) -> io::Result<W> {
+        let mut writer = DocumentWriter::new(inner, marker)?;
+        for (name, doc) in &self.bundles {
+            let text = doc.write_with(Vec::new(), &Default::default())?;
+            writer.begin_section(name)?.write_all(&text)?;
+        }
+        writer.finish()
+    }
+
+    /// Reads back a registry written by [`Registry::write_to`]
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::registry::Registry;
+    ///
+    /// let mut registry = Registry::new();
+    /// registry.insert("a", vec![("x", "1\n")].into_iter().collect());
+    /// let text = String::from_utf8(registry.write_to(Vec::new(), "@@@").unwrap()).unwrap();
+    /// let restored = Registry::read_from(text.lines()).unwrap();
+    /// assert_eq!(restored.lookup("a:x").unwrap(), "1\n");
+    /// ```
+    pub fn read_from<I>(lines: I) -> Result<Registry, Error>
+    where I: Iterator, <I as Iterator>::Item: AsRef<str>
+    {
+        let options = ParseOptions::new().attributes(true);
+        let outer = crate::parse_document(lines)?;
+        let mut registry = Registry::new();
+        for section in outer.iter().filter(|s| s.key() != "multitext header") {
+            let doc = crate::parse_document_with(section.body().lines(), &options).map_err(|e| {
+                Error::new(e.kind, format!("bundle \"{}\": {}", section.key(), e.error_message))
+            })?;
+            registry.insert(section.key(), doc);
+        }
+        Ok(registry)
+    }
+}
+
+impl Resolver for &Registry {
+    fn resolve(&mut self, bundle: &str) -> Result<Arc<Document>, Error> {
+        self.bundles.get(bundle).cloned()
+            .ok_or_else(|| Error::new(ErrorKind::Link, format!("no bundle named \"{}\"", bundle)))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let options = ParseOptions::new().attributes(true);
+        let mut registry = Registry::new();
+        registry.insert("lib", crate::parse_document_with(
+            ["## multitext header", "## noise", "@@@ looks like a marker"].iter(), &options).unwrap());
+        registry.insert("water", crate::parse_document_with(
+            ["@@@ multitext header", "@@@ common ref=lib#noise", "@@@ surface", "@@ also"].iter(), &options).unwrap());
+
+        let text = String::from_utf8(registry.write_to(Vec::new(), "@@@").unwrap()).unwrap();
+        let restored = Registry::read_from(text.lines()).unwrap();
+        assert_eq!(restored.lookup("water:common").unwrap(), "@@@ looks like a marker\n");
+        assert_eq!(&restored.resolved("water").unwrap()["common"], "@@@ looks like a marker\n");
+        assert_eq!(restored.names().collect::<Vec<_>>(), ["lib", "water"]);
+    }
+
+    #[test]
+    fn test_gc() {
+        let options = ParseOptions::new().attributes(true);
+        let parse = |lines: &[&str]| crate::parse_document_with(lines.iter(), &options).unwrap();
+        let mut registry = Registry::new();
+        registry.insert("empty_root", parse(&["@@ multitext header"]));
+        registry.insert("root", parse(&["@@ multitext header", "@@ a ref=x#loop", "@@ b ref=nowhere"]));
+        registry.insert("x", parse(&["@@ multitext header", "@@ loop ref=y", "@@ dead ref=z"]));
+        registry.insert("y", parse(&["@@ multitext header", "@@ loop ref=x"]));
+        registry.insert("z", parse(&["@@ multitext header", "@@ dead"]));
+        assert!(registry.gc(&["typo"]).is_err());
+
+        let snapshot = registry.snapshot();
+        let garbage = registry.prune(&["root", "empty_root"]).unwrap();
+        assert_eq!(garbage.bundles, ["z"]);
+        assert_eq!(garbage.sections, [("x".to_string(), "dead".to_string())]);
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["empty_root", "root", "x", "y"]);
+        assert!(registry.gc(&["root", "empty_root"]).unwrap().is_empty());
+
+        registry.restore(snapshot);
+        assert!(registry.get("x").unwrap().section("dead").is_some());
+    }
+}
@@ -0,0 +1,78 @@
+//! Descriptions of what a filesystem-writing operation did, or would do
+//!
+//! The multi-file writers, [`pack::unpack_dir_with`](crate::pack::unpack_dir_with)
+//! and [`Transaction::commit`](crate::transaction::Transaction::commit),
+//! return a [`ChangeReport`]. Run with the operation's dry-run flag set, the
+//! report is produced without touching the disk, so automation can preview an
+//! operation before committing to it. Single-file writers such as
+//! [`write_file`](crate::write_file) just write.
+
+use std::path::{Path, PathBuf};
+
+/// What happens to one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// The file doesn't exist yet
+    Create,
+    /// The file exists with different contents
+    Overwrite,
+    /// The file already has exactly these contents
+    Unchanged,
+}
+
+/// One file touched by an operation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    /// The size of the new contents
+    pub bytes: usize,
+}
+
+/// Every file an operation touched, in the order it touched them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeReport {
+    pub dry_run: bool,
+    pub changes: Vec<FileChange>,
+}
+
+impl ChangeReport {
+    /// The changes that actually alter the filesystem
+    pub fn modified(&self) -> impl Iterator<Item = &FileChange> {
+        self.changes.iter().filter(|c| c.kind != ChangeKind::Unchanged)
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.modified().next().is_none()
+    }
+}
+
+impl std::fmt::Display for ChangeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for change in &self.changes {
+            let verb = match (change.kind, self.dry_run) {
+                (ChangeKind::Create, false) => "created",
+                (ChangeKind::Create, true) => "would create",
+                (ChangeKind::Overwrite, false) => "overwrote",
+                (ChangeKind::Overwrite, true) => "would overwrite",
+                (ChangeKind::Unchanged, _) => "unchanged",
+            };
+            writeln!(f, "{} {} ({} bytes)", verb, change.path.display(), change.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares `contents` with what is currently at `path`
+pub(crate) fn plan_write(path: &Path, contents: &[u8]) -> FileChange {
+    let kind = match std::fs::read(path) {
+        Ok(existing) if existing == contents => ChangeKind::Unchanged,
+        Ok(_) => ChangeKind::Overwrite,
+        Err(_) => ChangeKind::Create,
+    };
+    FileChange {
+        path: path.to_path_buf(),
+        kind,
+        bytes: contents.len(),
+    }
+}
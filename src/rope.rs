@@ -0,0 +1,282 @@
+//! A chunked text buffer for editing large section bodies
+//!
+//! Editing a `String` in the middle moves everything after the edit, which
+//! is slow for an editor making keystroke-sized changes to a section of many
+//! megabytes. A [`Rope`] keeps the text in chunks of about a kilobyte, so an
+//! edit only moves the text of one chunk. Positions are counted in chars.
+//!
+//! Take a rope from a document with [`Document::rope`], edit it, and write it
+//! back with [`Document::insert`] once the edits are done.
+
+use crate::Document;
+use std::ops::Range;
+
+const CHUNK_BYTES: usize = 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Chunk {
+    text: String,
+    chars: usize,
+    newlines: usize,
+}
+
+impl Chunk {
+    fn new(text: String) -> Chunk {
+        let mut chunk = Chunk { text, chars: 0, newlines: 0 };
+        chunk.recount();
+        chunk
+    }
+
+    fn recount(&mut self) {
+        self.chars = self.text.chars().count();
+        self.newlines = self.text.bytes().filter(|&b| b == b'\n').count();
+    }
+
+    fn byte_offset(&self, char_index: usize) -> usize {
+        self.text.char_indices().nth(char_index).map_or(self.text.len(), |(i, _)| i)
+    }
+}
+
+/// Text stored in chunks, cheap to edit anywhere
+///
+/// # Examples
+/// ```
+/// let doc: multitext::Document = vec![("main", "fn main() {\n}\n")].into_iter().collect();
+/// let mut rope = doc.rope("main").unwrap();
+/// let at = rope.line_to_char(1);
+/// rope.insert(at, "    println!(\"hi\");\n");
+/// rope.remove(0..2);
+/// rope.insert(0, "pub fn");
+/// assert_eq!(rope.to_string(), "pub fn main() {\n    println!(\"hi\");\n}\n");
+/// assert_eq!(rope.len_lines(), 4);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rope {
+    chunks: Vec<Chunk>,
+}
+
+impl Rope {
+    pub fn new() -> Rope {
+        Rope::default()
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.chunks.iter().map(|c| c.text.len()).sum()
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.chunks.iter().map(|c| c.chars).sum()
+    }
+
+    /// The number of lines, counting the empty text after a final newline
+    /// as a line, the way editors do
+    pub fn len_lines(&self) -> usize {
+        self.chunks.iter().map(|c| c.newlines).sum::<usize>() + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Inserts `text` before the char at `char_index`
+    ///
+    /// # Panics
+    /// If `char_index` is past the end.
+    pub fn insert(&mut self, char_index: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let (chunk, byte) = self.locate(char_index);
+        if chunk == self.chunks.len() {
+            self.chunks.push(Chunk::default());
+        }
+        self.chunks[chunk].text.insert_str(byte, text);
+        self.rebalance(chunk);
+    }
+
+    /// Removes the chars in `range`
+    ///
+    /// # Panics
+    /// If the range is reversed or goes past the end.
+    pub fn remove(&mut self, range: Range<usize>) {
+        assert!(range.start <= range.end, "reversed range {:?}", range);
+        if range.start == range.end {
+            return;
+        }
+        let (first, start) = self.locate(range.start);
+        let (last, end) = self.locate(range.end);
+        if first == last {
+            self.chunks[first].text.replace_range(start..end, "");
+            self.chunks[first].recount();
+        } else {
+            self.chunks[first].text.truncate(start);
+            self.chunks[first].recount();
+            if last < self.chunks.len() {
+                self.chunks[last].text.replace_range(..end, "");
+                self.chunks[last].recount();
+            }
+            self.chunks.drain(first + 1..last);
+        }
+        self.chunks.retain(|c| !c.text.is_empty());
+    }
+
+    /// Copies out the chars in `range`
+    pub fn slice(&self, range: Range<usize>) -> String {
+        self.chunks().flat_map(str::chars).skip(range.start).take(range.end - range.start).collect()
+    }
+
+    /// The char index at which line `line` (0-based) starts
+    ///
+    /// # Panics
+    /// If there are fewer lines.
+    pub fn line_to_char(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+        let mut chars = 0;
+        let mut newlines = 0;
+        for chunk in &self.chunks {
+            if newlines + chunk.newlines >= line {
+                for (i, c) in chunk.text.chars().enumerate() {
+                    if c == '\n' {
+                        newlines += 1;
+                        if newlines == line {
+                            return chars + i + 1;
+                        }
+                    }
+                }
+            }
+            chars += chunk.chars;
+            newlines += chunk.newlines;
+        }
+        panic!("line {} is past the last line, {}", line, newlines);
+    }
+
+    /// The 0-based line containing the char at `char_index`
+    pub fn char_to_line(&self, char_index: usize) -> usize {
+        let mut remaining = char_index;
+        let mut line = 0;
+        for chunk in &self.chunks {
+            if remaining < chunk.chars {
+                return line + chunk.text.chars().take(remaining).filter(|&c| c == '\n').count();
+            }
+            remaining -= chunk.chars;
+            line += chunk.newlines;
+        }
+        line
+    }
+
+    /// Iterates over the stored chunks of text, in order
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.chunks.iter().map(|c| c.text.as_str())
+    }
+
+    /// Finds the chunk and byte offset for a char index; the end of the text
+    /// is the start of a chunk past the last
+    fn locate(&self, char_index: usize) -> (usize, usize) {
+        let mut remaining = char_index;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if remaining < chunk.chars {
+                return (i, chunk.byte_offset(remaining));
+            }
+            remaining -= chunk.chars;
+        }
+        assert!(remaining == 0, "char index {} is past the end, {}", char_index, self.len_chars());
+        match self.chunks.last() {
+            Some(last) if last.text.len() < CHUNK_BYTES => (self.chunks.len() - 1, last.text.len()),
+            _ => (self.chunks.len(), 0),
+        }
+    }
+
+    /// Splits an overgrown chunk back into chunks of about the usual size
+    fn rebalance(&mut self, index: usize) {
+        if self.chunks[index].text.len() <= 2 * CHUNK_BYTES {
+            self.chunks[index].recount();
+            return;
+        }
+        let text = std::mem::take(&mut self.chunks[index].text);
+        let pieces = split_chunks(&text);
+        self.chunks.splice(index..=index, pieces);
+    }
+}
+
+fn split_chunks(text: &str) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(CHUNK_BYTES);
+        while !rest.is_char_boundary(end) {
+            end += 1;
+        }
+        chunks.push(Chunk::new(rest[..end].to_string()));
+        rest = &rest[end..];
+    }
+    chunks
+}
+
+impl From<&str> for Rope {
+    fn from(text: &str) -> Rope {
+        Rope { chunks: split_chunks(text) }
+    }
+}
+
+impl From<String> for Rope {
+    fn from(text: String) -> Rope {
+        Rope::from(text.as_str())
+    }
+}
+
+impl From<Rope> for String {
+    fn from(rope: Rope) -> String {
+        rope.to_string()
+    }
+}
+
+impl std::fmt::Display for Rope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for chunk in &self.chunks {
+            f.write_str(&chunk.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Copies the body of `key` into a [`Rope`] for editing
+    pub fn rope(&self, key: &str) -> Option<Rope> {
+        self.get(key).map(Rope::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rope_edits_match_string_edits() {
+        let original: String = (0..500).map(|i| format!("line {} é\n", i)).collect();
+        let mut rope = Rope::from(original.as_str());
+        let mut expected: Vec<char> = original.chars().collect();
+        assert!(rope.chunks().count() > 1);
+
+        for step in 0..200 {
+            let at = (step * 7919) % (expected.len() + 1);
+            if step % 3 == 0 {
+                let end = (at + step % 2000).min(expected.len());
+                rope.remove(at..end);
+                expected.drain(at..end);
+            } else {
+                let text = "x\n".repeat(step % 700);
+                rope.insert(at, &text);
+                expected.splice(at..at, text.chars());
+            }
+        }
+        let expected: String = expected.into_iter().collect();
+        assert_eq!(rope.to_string(), expected);
+        assert_eq!(rope.len_chars(), expected.chars().count());
+        assert_eq!(rope.len_lines(), expected.matches('\n').count() + 1);
+        let line = rope.char_to_line(1234);
+        assert!(rope.line_to_char(line) <= 1234);
+        assert!(rope.chunks().all(|c| !c.is_empty() && c.len() <= 2 * CHUNK_BYTES + 4));
+    }
+}
@@ -0,0 +1,165 @@
+//! Many documents stored with their shared text held once
+//!
+//! Games often ship thousands of per-level bundles that repeat the same
+//! boilerplate sections. A [`DocumentSet`] interns every key and body it
+//! stores, so identical text is kept in memory only once no matter how many
+//! documents contain it.
+
+use crate::{Document, MemoryFootprint};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+struct Stored {
+    marker: Option<String>,
+    sections: Vec<(Arc<str>, Arc<str>)>,
+}
+
+/// A collection of named documents sharing identical keys and bodies
+///
+/// # Examples
+/// ```
+/// use multitext::set::DocumentSet;
+///
+/// let common = "// 200 lines of shared setup\n";
+/// let mut set = DocumentSet::new();
+/// for level in 1..=3 {
+///     let doc: multitext::Document = vec![
+///         ("prelude", common.to_string()),
+///         ("map", format!("level {}\n", level)),
+///     ].into_iter().collect();
+///     set.insert(format!("level{}", level), &doc);
+/// }
+///
+/// assert_eq!(set.len(), 3);
+/// assert_eq!(set.unique_bodies(), 4);
+/// assert_eq!(set.body("level2", "map"), Some("level 2\n"));
+/// assert_eq!(set.get("level3").unwrap()["prelude"], *common);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DocumentSet {
+    documents: BTreeMap<String, Stored>,
+    keys: HashSet<Arc<str>>,
+    bodies: HashSet<Arc<str>>,
+}
+
+impl DocumentSet {
+    pub fn new() -> DocumentSet {
+        DocumentSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Stores a copy of `doc` under `name`, returning true if it replaced a
+    /// document of the same name
+    pub fn insert<N: Into<String>>(&mut self, name: N, doc: &Document) -> bool {
+        let sections = doc.iter()
+            .map(|s| (intern(&mut self.keys, s.key()), intern(&mut self.bodies, s.body())))
+            .collect();
+        let stored = Stored { marker: doc.marker().map(str::to_string), sections };
+        let replaced = self.documents.insert(name.into(), stored).is_some();
+        if replaced {
+            self.release_unused();
+        }
+        replaced
+    }
+
+    /// Removes the document called `name`, releasing text no other document
+    /// shares
+    pub fn remove(&mut self, name: &str) -> Option<Document> {
+        let doc = self.get(name)?;
+        self.documents.remove(name);
+        self.release_unused();
+        Some(doc)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.documents.contains_key(name)
+    }
+
+    /// Rebuilds the document called `name`
+    pub fn get(&self, name: &str) -> Option<Document> {
+        let stored = self.documents.get(name)?;
+        let mut doc: Document = stored.sections.iter()
+            .map(|(key, body)| (key.to_string(), body.to_string()))
+            .collect();
+        if let Some(marker) = &stored.marker {
+            doc.set_marker(marker.clone());
+        }
+        Some(doc)
+    }
+
+    /// Returns one section body without rebuilding the document
+    pub fn body(&self, name: &str, key: &str) -> Option<&str> {
+        self.sections(name)?.find(|&(k, _)| k == key).map(|(_, body)| body)
+    }
+
+    /// Iterates over the `(key, body)` pairs of the document called `name`
+    pub fn sections(&self, name: &str) -> Option<impl Iterator<Item = (&str, &str)>> {
+        let stored = self.documents.get(name)?;
+        Some(stored.sections.iter().map(|(key, body)| (&**key, &**body)))
+    }
+
+    /// The document names, in sorted order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.documents.keys().map(String::as_str)
+    }
+
+    /// How many distinct bodies are stored
+    pub fn unique_bodies(&self) -> usize {
+        self.bodies.len()
+    }
+
+    /// Estimates the heap memory held, counting shared text once
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let entry = std::mem::size_of::<(Arc<str>, Arc<str>)>();
+        MemoryFootprint {
+            keys: self.keys.iter().map(|k| k.len()).sum(),
+            bodies: self.bodies.iter().map(|b| b.len()).sum(),
+            metadata: self.documents.iter()
+                .map(|(name, stored)| name.capacity() + stored.sections.capacity() * entry)
+                .sum(),
+        }
+    }
+
+    fn release_unused(&mut self) {
+        self.keys.retain(|k| Arc::strong_count(k) > 1);
+        self.bodies.retain(|b| Arc::strong_count(b) > 1);
+    }
+}
+
+fn intern(pool: &mut HashSet<Arc<str>>, text: &str) -> Arc<str> {
+    if let Some(existing) = pool.get(text) {
+        return existing.clone();
+    }
+    let text: Arc<str> = Arc::from(text);
+    pool.insert(text.clone());
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remove_releases_unshared_text() {
+        let a: Document = vec![("shared", "x\n"), ("only a", "a\n")].into_iter().collect();
+        let b: Document = vec![("shared", "x\n")].into_iter().collect();
+        let mut set = DocumentSet::new();
+        set.insert("a", &a);
+        set.insert("b", &b);
+        assert_eq!(set.unique_bodies(), 2);
+
+        assert_eq!(set.remove("a"), Some(a));
+        assert_eq!(set.unique_bodies(), 1);
+        assert_eq!(set.body("b", "shared"), Some("x\n"));
+        assert!(set.insert("b", &Document::new()));
+        assert_eq!(set.memory_footprint().bodies, 0);
+    }
+}
@@ -0,0 +1,98 @@
+//! Shader stage types shared by the [`glsl`](crate::glsl) and
+//! [`wgsl`](crate::wgsl) helpers
+
+use crate::Section;
+
+/// A shader pipeline stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Vertex,
+    TessControl,
+    TessEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+}
+
+impl Stage {
+    /// Infers the stage from a conventional section key such as
+    /// "vertex shader", "frag" or "compute"
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::shader::Stage;
+    /// assert_eq!(Stage::from_key("Vertex Shader"), Some(Stage::Vertex));
+    /// assert_eq!(Stage::from_key("frag"), Some(Stage::Fragment));
+    /// assert_eq!(Stage::from_key("multitext header"), None);
+    /// ```
+    pub fn from_key(key: &str) -> Option<Stage> {
+        let key = key.trim().to_lowercase();
+        let key = key.strip_suffix("shader").unwrap_or(&key).trim_end();
+        match key {
+            "vertex" | "vert" | "vs" => Some(Stage::Vertex),
+            "tess control" | "tessellation control" | "tesc" => Some(Stage::TessControl),
+            "tess evaluation" | "tessellation evaluation" | "tese" => Some(Stage::TessEvaluation),
+            "geometry" | "geom" | "gs" => Some(Stage::Geometry),
+            "fragment" | "frag" | "fs" | "pixel" => Some(Stage::Fragment),
+            "compute" | "comp" | "cs" => Some(Stage::Compute),
+            _ => None,
+        }
+    }
+}
+
+/// A message reported by a [`Compiler`], positioned within the source it was
+/// given
+///
+/// For GLSL, `source_string` and `line` are the values in effect after the
+/// `#line` directives inserted by include resolution, as compilers report them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerMessage {
+    pub source_string: Option<usize>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Compiles or validates a single shader stage, for example by handing it to
+/// shaderc or naga
+pub trait Compiler {
+    /// Returns the errors and warnings for `source`, or nothing if it compiled
+    /// cleanly
+    fn compile(&mut self, stage: Stage, source: &str) -> Vec<CompilerMessage>;
+}
+
+/// A compiler message mapped back onto the multitext file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileDiagnostic {
+    /// The section the offending text lives in
+    pub key: String,
+    /// The 1-based line within the multitext file, when it can be determined
+    pub line_number: Option<usize>,
+    pub message: String,
+}
+
+impl CompileDiagnostic {
+    /// Positions `message` within the file `section` was parsed from, treating
+    /// its line as relative to the start of the section body
+    ///
+    /// The line is left out when the body no longer matches the file, as for
+    /// an edited or inserted section.
+    pub(crate) fn remap(section: &Section, message: CompilerMessage) -> CompileDiagnostic {
+        let line_number = message.line
+            .and_then(|line| section.origin_at(line))
+            .map(|origin| origin.line_number);
+        CompileDiagnostic {
+            key: section.key().to_string(),
+            line_number,
+            message: message.message,
+        }
+    }
+}
+
+impl std::fmt::Display for CompileDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.line_number {
+            Some(line_number) => write!(f, "{}({}) : {}", self.key, line_number, self.message),
+            None => write!(f, "{} : {}", self.key, self.message),
+        }
+    }
+}
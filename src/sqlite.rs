@@ -0,0 +1,387 @@
+//! A [`Storage`] backend keeping bundles in one SQLite database
+//!
+//! A directory of `.mt` files gets unwieldy with thousands of bundles.
+//! [`SqliteStorage`] keeps them in a single database file instead, one row
+//! per section, so a single section can be read, or the bundles using a key
+//! found, without parsing whole documents. Saving a bundle replaces all its
+//! rows in one transaction, so readers see either the old bundle or the new
+//! one.
+//!
+//! The feature links against the system's `libsqlite3`. The schema is:
+//!
+//! ```sql
+//! CREATE TABLE bundles (name TEXT PRIMARY KEY, marker TEXT, preamble TEXT);
+//! CREATE TABLE sections (bundle TEXT NOT NULL, position INTEGER NOT NULL,
+//!                        key TEXT NOT NULL, attrs TEXT NOT NULL, body TEXT NOT NULL,
+//!                        PRIMARY KEY (bundle, position));
+//! ```
+//!
+//! where `attrs` holds a section's attributes as a JSON object.
+
+use crate::json::{self, Value};
+use crate::options::WriteOptions;
+use crate::storage::Storage;
+use crate::{Document, Error, ErrorKind, Section};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+
+#[allow(non_camel_case_types)]
+enum sqlite3 {}
+#[allow(non_camel_case_types)]
+enum sqlite3_stmt {}
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_OPEN_READWRITE: c_int = 0x2;
+const SQLITE_OPEN_CREATE: c_int = 0x4;
+const SQLITE_UTF8: u8 = 1;
+/// Tells SQLite to copy bound text before the call returns
+const SQLITE_TRANSIENT: isize = -1;
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(filename: *const c_char, db: *mut *mut sqlite3, flags: c_int, vfs: *const c_char) -> c_int;
+    fn sqlite3_close(db: *mut sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+    fn sqlite3_prepare_v2(
+        db: *mut sqlite3, sql: *const c_char, len: c_int, stmt: *mut *mut sqlite3_stmt, tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_bind_text64(
+        stmt: *mut sqlite3_stmt, index: c_int, text: *const c_char, len: u64, free: isize, encoding: u8,
+    ) -> c_int;
+    fn sqlite3_bind_null(stmt: *mut sqlite3_stmt, index: c_int) -> c_int;
+    fn sqlite3_step(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_column_count(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_column_text(stmt: *mut sqlite3_stmt, column: c_int) -> *const u8;
+    fn sqlite3_column_bytes(stmt: *mut sqlite3_stmt, column: c_int) -> c_int;
+    fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> c_int;
+}
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS bundles (name TEXT PRIMARY KEY, marker TEXT, preamble TEXT)",
+    "CREATE TABLE IF NOT EXISTS sections (bundle TEXT NOT NULL, position INTEGER NOT NULL, \
+     key TEXT NOT NULL, attrs TEXT NOT NULL, body TEXT NOT NULL, PRIMARY KEY (bundle, position))",
+    "CREATE INDEX IF NOT EXISTS sections_by_key ON sections (key)",
+];
+
+/// Bundles stored as rows of a SQLite database
+///
+/// Positions are bound as text and stored as integers by the column's type
+/// affinity.
+///
+/// # Examples
+/// ```
+/// use multitext::sqlite::SqliteStorage;
+/// use multitext::storage::Storage;
+///
+/// let mut storage = SqliteStorage::in_memory().unwrap();
+/// storage.write("water.mt", "@@ multitext header\n@@ vertex\nvoid main() {}\n").unwrap();
+/// storage.write("fire.mt", "@@ multitext header\n@@ fragment\n").unwrap();
+///
+/// assert_eq!(storage.section("water.mt", "vertex").unwrap().as_deref(), Some("void main() {}\n"));
+/// assert_eq!(storage.bundles_with_key("vertex").unwrap(), ["water.mt"]);
+/// assert_eq!(storage.read("water.mt").unwrap(), "@@ multitext header\n@@ vertex\nvoid main() {}\n");
+/// ```
+#[derive(Debug)]
+pub struct SqliteStorage {
+    db: *mut sqlite3,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it and its tables if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStorage, Error> {
+        let path = path.as_ref().to_string_lossy();
+        let filename = CString::new(path.as_bytes()).map_err(|_| {
+            Error::new(ErrorKind::Io, format!("sqlite: database path {:?} contains a NUL byte", path))
+        })?;
+        let mut db = std::ptr::null_mut();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE;
+        // SAFETY: the filename is NUL-terminated and `db` receives the handle,
+        // which is closed on drop even if opening failed
+        let code = unsafe { sqlite3_open_v2(filename.as_ptr(), &mut db, flags, std::ptr::null()) };
+        let storage = SqliteStorage { db };
+        if code != SQLITE_OK {
+            return Err(storage.last_error());
+        }
+        for sql in SCHEMA {
+            storage.execute(sql, &[])?;
+        }
+        Ok(storage)
+    }
+
+    /// A database that lives only as long as the returned value
+    pub fn in_memory() -> Result<SqliteStorage, Error> {
+        SqliteStorage::open(":memory:")
+    }
+
+    /// The body of section `key` of bundle `name`, read without loading the
+    /// rest of the bundle
+    pub fn section(&self, name: &str, key: &str) -> Result<Option<String>, Error> {
+        let mut rows = self.query(
+            "SELECT body FROM sections WHERE bundle = ?1 AND key = ?2 LIMIT 1",
+            &[Some(name), Some(key)],
+        )?;
+        Ok(rows.pop().and_then(|mut row| row.pop()).flatten())
+    }
+
+    /// The names of the bundles that have a section `key`, sorted
+    pub fn bundles_with_key(&self, key: &str) -> Result<Vec<String>, Error> {
+        let rows = self.query("SELECT DISTINCT bundle FROM sections WHERE key = ?1 ORDER BY bundle", &[Some(key)])?;
+        Ok(rows.into_iter().filter_map(|mut row| row.pop().flatten()).collect())
+    }
+
+    /// Every `(bundle, key)` whose key matches `glob`, in SQLite's `GLOB`
+    /// syntax, sorted
+    pub fn keys_matching(&self, glob: &str) -> Result<Vec<(String, String)>, Error> {
+        let rows = self.query(
+            "SELECT bundle, key FROM sections WHERE key GLOB ?1 ORDER BY bundle, position",
+            &[Some(glob)],
+        )?;
+        Ok(rows.into_iter().map(|row| {
+            let mut row = row.into_iter().map(Option::unwrap_or_default);
+            (row.next().unwrap_or_default(), row.next().unwrap_or_default())
+        }).collect())
+    }
+
+    fn last_error(&self) -> Error {
+        // SAFETY: sqlite3_errmsg accepts any handle returned by open, even a
+        // null one, and returns a NUL-terminated string it owns
+        let message = unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) };
+        Error::new(ErrorKind::Io, format!("sqlite: {}", message.to_string_lossy()))
+    }
+
+    fn prepare(&self, sql: &str, params: &[Option<&str>]) -> Result<Statement<'_>, Error> {
+        let sql = CString::new(sql).expect("queries are constants");
+        let mut raw = std::ptr::null_mut();
+        // SAFETY: the query is NUL-terminated and `raw` receives the statement
+        let code = unsafe { sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut raw, std::ptr::null_mut()) };
+        let statement = Statement { storage: self, raw };
+        if code != SQLITE_OK {
+            return Err(self.last_error());
+        }
+        for (index, param) in params.iter().enumerate() {
+            statement.bind(index as c_int + 1, *param)?;
+        }
+        Ok(statement)
+    }
+
+    /// Runs a statement that returns no rows
+    fn execute(&self, sql: &str, params: &[Option<&str>]) -> Result<(), Error> {
+        let statement = self.prepare(sql, params)?;
+        while statement.step()? {}
+        Ok(())
+    }
+
+    /// Runs a query, returning every row with each column as text
+    fn query(&self, sql: &str, params: &[Option<&str>]) -> Result<Vec<Vec<Option<String>>>, Error> {
+        let statement = self.prepare(sql, params)?;
+        // SAFETY: the statement is valid until drop
+        let columns = unsafe { sqlite3_column_count(statement.raw) };
+        let mut rows = Vec::new();
+        while statement.step()? {
+            rows.push((0..columns).map(|column| statement.text(column)).collect());
+        }
+        Ok(rows)
+    }
+
+    /// Runs `f` in a transaction, rolling it back if `f` or the commit fails
+    fn transaction<T, F: FnOnce() -> Result<T, Error>>(&self, f: F) -> Result<T, Error> {
+        self.execute("BEGIN IMMEDIATE", &[])?;
+        let result = f().and_then(|value| self.execute("COMMIT", &[]).map(|()| value));
+        if result.is_err() {
+            let _ = self.execute("ROLLBACK", &[]);
+        }
+        result
+    }
+}
+
+impl Drop for SqliteStorage {
+    fn drop(&mut self) {
+        // SAFETY: every statement borrows the storage, so none outlive it
+        unsafe { sqlite3_close(self.db) };
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn read(&self, name: &str) -> Result<String, Error> {
+        let bytes = self.load(name)?.write_with(Vec::new(), &WriteOptions::new())?;
+        Ok(String::from_utf8(bytes).expect("the writer only writes strings"))
+    }
+
+    fn write(&mut self, name: &str, text: &str) -> Result<(), Error> {
+        let options = crate::options::ParseOptions::new()
+            .attributes(true)
+            .keep_preamble(true)
+            .line_endings(crate::options::LineEndings::Normalize);
+        let doc = crate::parse_document_with(text.lines(), &options).map_err(|e| e.with_path(name))?;
+        self.save(name, &doc)
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Error> {
+        self.transaction(|| {
+            let existed = !self.query("SELECT name FROM bundles WHERE name = ?1", &[Some(name)])?.is_empty();
+            self.execute("DELETE FROM sections WHERE bundle = ?1", &[Some(name)])?;
+            self.execute("DELETE FROM bundles WHERE name = ?1", &[Some(name)])?;
+            Ok(existed)
+        })
+    }
+
+    fn names(&self) -> Result<Vec<String>, Error> {
+        let rows = self.query("SELECT name FROM bundles ORDER BY name", &[])?;
+        Ok(rows.into_iter().filter_map(|mut row| row.pop().flatten()).collect())
+    }
+
+    fn load(&self, name: &str) -> Result<Document, Error> {
+        let mut bundle = self.query("SELECT marker, preamble FROM bundles WHERE name = ?1", &[Some(name)])?;
+        let (marker, preamble) = match bundle.pop() {
+            Some(row) => {
+                let mut row = row.into_iter();
+                (row.next().flatten(), row.next().flatten())
+            }
+            None => {
+                return Err(Error::new(ErrorKind::Io, "no document stored").with_path(name));
+            }
+        };
+        let mut doc = Document::new();
+        if let Some(marker) = marker {
+            doc.set_marker(marker);
+        }
+        doc.set_preamble(preamble);
+        let rows = self.query("SELECT key, attrs, body FROM sections WHERE bundle = ?1 ORDER BY position", &[Some(name)])?;
+        for row in rows {
+            let mut row = row.into_iter().map(Option::unwrap_or_default);
+            let (key, attrs, body) = (row.next().unwrap_or_default(), row.next().unwrap_or_default(), row.next().unwrap_or_default());
+            let mut section = Section::new(key, body);
+            section.attrs = parse_attrs(&attrs)?;
+            doc.insert_section(section);
+        }
+        Ok(doc)
+    }
+
+    fn save(&mut self, name: &str, doc: &Document) -> Result<(), Error> {
+        self.transaction(|| {
+            self.execute("DELETE FROM sections WHERE bundle = ?1", &[Some(name)])?;
+            self.execute(
+                "INSERT OR REPLACE INTO bundles (name, marker, preamble) VALUES (?1, ?2, ?3)",
+                &[Some(name), doc.marker(), doc.preamble()],
+            )?;
+            for (position, section) in doc.iter().enumerate() {
+                let position = position.to_string();
+                let attrs = format_attrs(section);
+                self.execute(
+                    "INSERT INTO sections (bundle, position, key, attrs, body) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    &[Some(name), Some(&position), Some(section.key()), Some(&attrs), Some(section.body())],
+                )?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A prepared statement, finalized on drop
+struct Statement<'a> {
+    storage: &'a SqliteStorage,
+    raw: *mut sqlite3_stmt,
+}
+
+impl Statement<'_> {
+    fn bind(&self, index: c_int, value: Option<&str>) -> Result<(), Error> {
+        // SAFETY: SQLITE_TRANSIENT makes SQLite copy the text before returning
+        let code = unsafe {
+            match value {
+                Some(text) => sqlite3_bind_text64(
+                    self.raw, index, text.as_ptr() as *const c_char, text.len() as u64, SQLITE_TRANSIENT, SQLITE_UTF8,
+                ),
+                None => sqlite3_bind_null(self.raw, index),
+            }
+        };
+        if code != SQLITE_OK {
+            return Err(self.storage.last_error());
+        }
+        Ok(())
+    }
+
+    /// Advances to the next row, returning whether there is one
+    fn step(&self) -> Result<bool, Error> {
+        // SAFETY: the statement is valid until drop
+        match unsafe { sqlite3_step(self.raw) } {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => Ok(false),
+            _ => Err(self.storage.last_error()),
+        }
+    }
+
+    /// The text of `column` in the current row, or `None` if it is NULL
+    fn text(&self, column: c_int) -> Option<String> {
+        // SAFETY: the pointer is valid until the next step, and the length
+        // must be asked for after the text, as documented by SQLite
+        unsafe {
+            let text = sqlite3_column_text(self.raw, column);
+            if text.is_null() {
+                return None;
+            }
+            let len = sqlite3_column_bytes(self.raw, column) as usize;
+            Some(String::from_utf8_lossy(std::slice::from_raw_parts(text, len)).into_owned())
+        }
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        // SAFETY: finalizing a null statement is a no-op
+        unsafe { sqlite3_finalize(self.raw) };
+    }
+}
+
+fn format_attrs(section: &Section) -> String {
+    let members: Vec<String> = section.attrs()
+        .map(|(name, value)| format!("{}:{}", json::string(name), json::string(value)))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+fn parse_attrs(text: &str) -> Result<Vec<(String, String)>, Error> {
+    match json::parse(text) {
+        Ok(Value::Object(members)) => Ok(members.into_iter()
+            .filter_map(|(name, value)| value.as_str().map(|v| (name, v.to_string())))
+            .collect()),
+        _ => Err(Error::new(ErrorKind::Io, format!("sqlite: malformed attributes {:?}", text))),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_rollback() {
+        let mut storage = SqliteStorage::in_memory().unwrap();
+        let text = "notes\n## multitext header\n## a stage=1\n1\n## b\n2\n";
+        storage.write("one.mt", text).unwrap();
+        let doc = storage.load("one.mt").unwrap();
+        assert_eq!(doc.marker(), Some("##"));
+        assert_eq!(doc.preamble(), Some("notes\n"));
+        assert_eq!(doc.section("a").unwrap().attr("stage"), Some("1"));
+        assert_eq!(storage.read("one.mt").unwrap(), text);
+
+        assert!(storage.write("one.mt", "no header\n").is_err());
+        assert_eq!(storage.read("one.mt").unwrap(), text);
+        let failed = storage.transaction(|| {
+            storage.execute("DELETE FROM sections WHERE bundle = ?1", &[Some("one.mt")])?;
+            storage.execute("INSERT INTO sections (bundle) VALUES (?1)", &[Some("one.mt")])
+        });
+        assert!(failed.unwrap_err().to_string().contains("NOT NULL"));
+        assert_eq!(storage.read("one.mt").unwrap(), text);
+        assert_eq!(storage.keys_matching("[ab]").unwrap(), [
+            ("one.mt".to_string(), "a".to_string()), ("one.mt".to_string(), "b".to_string()),
+        ]);
+
+        assert!(storage.delete("one.mt").unwrap());
+        assert!(!storage.delete("one.mt").unwrap());
+        assert_eq!(storage.load("one.mt").unwrap_err().kind(), ErrorKind::Io);
+        assert!(storage.names().unwrap().is_empty());
+    }
+}
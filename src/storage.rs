@@ -0,0 +1,180 @@
+//! Where documents are kept
+//!
+//! Applications that persist bundles somewhere other than local files, such
+//! as a database or an object store, implement [`Storage`] for it. It only
+//! moves text in and out; [`Storage::load`] and [`Storage::save`] parse and
+//! write it the same way for every backend, keeping attributes and text above
+//! the header. [`FileStorage`] keeps documents as files under a directory and
+//! [`MemoryStorage`] keeps them in memory, which suits tests.
+
+use crate::options::{LineEndings, ParseOptions, WriteOptions};
+use crate::pack::UnpackOptions;
+use crate::{Document, Error, ErrorKind};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A place that stores the text of documents by name
+pub trait Storage {
+    /// The text stored under `name`
+    fn read(&self, name: &str) -> Result<String, Error>;
+
+    /// Stores `text` under `name`, replacing anything already there
+    fn write(&mut self, name: &str, text: &str) -> Result<(), Error>;
+
+    /// Deletes what is stored under `name`, returning whether there was
+    /// anything
+    fn delete(&mut self, name: &str) -> Result<bool, Error>;
+
+    /// The names of everything stored, sorted
+    fn names(&self) -> Result<Vec<String>, Error>;
+
+    /// Reads and parses the document stored under `name`
+    fn load(&self, name: &str) -> Result<Document, Error> {
+        let text = self.read(name)?;
+        let options = ParseOptions::new().attributes(true).keep_preamble(true).line_endings(LineEndings::Normalize);
+        crate::parse_document_with(text.lines(), &options).map_err(|e| e.with_path(name))
+    }
+
+    /// Writes `doc` and stores it under `name`
+    fn save(&mut self, name: &str, doc: &Document) -> Result<(), Error> {
+        let bytes = doc.write_with(Vec::new(), &WriteOptions::new())?;
+        let text = String::from_utf8(bytes).expect("the writer only writes strings");
+        self.write(name, &text)
+    }
+}
+
+/// Stores each document as a file under a base directory, named by its path
+/// relative to it
+///
+/// Names that would reach outside the directory are refused. Files are
+/// replaced by renaming a finished temporary file over them, so readers never
+/// see half a document.
+///
+/// # Examples
+/// ```
+/// use multitext::storage::{FileStorage, Storage};
+///
+/// let dir = multitext::testing::TempDir::new("storage-doc").unwrap();
+/// let mut storage = FileStorage::new(&dir);
+/// let doc: multitext::Document = vec![("a", "1\n")].into_iter().collect();
+/// storage.save("shaders/water.mt", &doc).unwrap();
+/// assert_eq!(&storage.load("shaders/water.mt").unwrap()["a"], "1\n");
+/// assert_eq!(storage.names().unwrap(), ["shaders/water.mt"]);
+/// assert!(storage.save("../escape.mt", &doc).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    base: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new<P: AsRef<Path>>(base: P) -> FileStorage {
+        FileStorage { base: base.as_ref().to_path_buf() }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        UnpackOptions::new().resolve(&self.base, name).map_err(|message| Error::new(ErrorKind::InvalidKey, message))
+    }
+}
+
+impl Storage for FileStorage {
+    fn read(&self, name: &str) -> Result<String, Error> {
+        let path = self.path(name)?;
+        std::fs::read_to_string(&path).map_err(|e| Error::from(e).with_path(&path))
+    }
+
+    fn write(&mut self, name: &str, text: &str) -> Result<(), Error> {
+        let path = self.path(name)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::from(e).with_path(parent))?;
+        }
+        let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+        std::fs::write(&temp, text).map_err(|e| Error::from(e).with_path(&temp))?;
+        std::fs::rename(&temp, &path).map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            Error::from(e).with_path(&path)
+        })
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Error> {
+        let path = self.path(name)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(Error::from(e).with_path(&path)),
+        }
+    }
+
+    fn names(&self) -> Result<Vec<String>, Error> {
+        if !self.base.exists() {
+            return Ok(Vec::new());
+        }
+        let files = crate::batch::find_files(&self.base, "**").map_err(|e| Error::from(e).with_path(&self.base))?;
+        Ok(files.iter()
+            .filter_map(|path| path.strip_prefix(&self.base).ok())
+            .map(|relative| relative.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/"))
+            .collect())
+    }
+}
+
+/// Stores documents in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    texts: BTreeMap<String, String>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, name: &str) -> Result<String, Error> {
+        self.texts.get(name).cloned().ok_or_else(|| Error::new(ErrorKind::Io, "no document stored").with_path(name))
+    }
+
+    fn write(&mut self, name: &str, text: &str) -> Result<(), Error> {
+        self.texts.insert(name.to_string(), text.to_string());
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<bool, Error> {
+        Ok(self.texts.remove(name).is_some())
+    }
+
+    fn names(&self) -> Result<Vec<String>, Error> {
+        Ok(self.texts.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_memory_storage_round_trip() {
+        let mut storage = MemoryStorage::new();
+        let options = ParseOptions::new().attributes(true).keep_preamble(true);
+        let doc = crate::parse_document_with(
+            ["notes", "@@ multitext header", "@@ a stage=1", "x"].iter(), &options).unwrap();
+        storage.save("b.mt", &doc).unwrap();
+        storage.write("a.mt", "not multitext\n").unwrap();
+        assert_eq!(storage.names().unwrap(), ["a.mt", "b.mt"]);
+
+        let loaded = storage.load("b.mt").unwrap();
+        assert_eq!(loaded.preamble(), Some("notes\n"));
+        assert_eq!(loaded.section("a").unwrap().attr("stage"), Some("1"));
+        let e = storage.load("a.mt").unwrap_err();
+        assert_eq!((e.kind(), e.path()), (ErrorKind::MissingHeader, Some(Path::new("a.mt"))));
+        assert_eq!(storage.read("c.mt").unwrap_err().kind(), ErrorKind::Io);
+
+        assert!(storage.delete("a.mt").unwrap());
+        assert!(!storage.delete("a.mt").unwrap());
+    }
+}
@@ -0,0 +1,453 @@
+//! Pulling sections out of a line iterator one at a time, or out of a reader
+//! a few bytes at a time, and parsing bytes pushed in as they arrive
+
+use crate::options::{LineEndings, ParseOptions};
+use crate::{Document, Error, ErrorKind};
+use std::io::{self, BufRead, Read};
+
+/// Yields `(key, body)` pairs from lines as each section ends, holding only
+/// the current section in memory
+///
+/// The first item is an error if there is no header line. Sections come out
+/// exactly as marked, so continuation parts are not joined and a repeated key
+/// is yielded again rather than replacing anything. Dropping the iterator
+/// early stops reading.
+///
+/// # Examples
+/// ```
+/// let lines = ["@@ multitext header", "@@ vertex", "...", "@@ fragment", "..."];
+/// let mut sections = multitext::SectionIter::new(lines.iter());
+/// assert_eq!(sections.next().unwrap().unwrap().0, "multitext header");
+///
+/// let vertex = sections
+///     .map(Result::unwrap)
+///     .find(|(key, _)| key == "vertex");
+/// assert_eq!(vertex, Some(("vertex".to_string(), "...\n".to_string())));
+/// ```
+#[derive(Debug)]
+pub struct SectionIter<I> {
+    lines: I,
+    marker: Option<String>,
+    current: Option<(String, String)>,
+    line_number: usize,
+    failed: bool,
+}
+
+impl<I> SectionIter<I>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    pub fn new<T: IntoIterator<IntoIter = I>>(lines: T) -> SectionIter<I> {
+        SectionIter { lines: lines.into_iter(), marker: None, current: None, line_number: 0, failed: false }
+    }
+
+    /// The marker, once the header line has been read
+    pub fn marker(&self) -> Option<&str> {
+        self.marker.as_deref()
+    }
+
+    /// Reads up to and including the header line
+    fn find_header(&mut self) -> Result<(), Error> {
+        loop {
+            self.line_number += 1;
+            let line = self.lines.next().ok_or_else(|| {
+                Error::new(ErrorKind::MissingHeader, "missing multitext header").with_line(self.line_number)
+            })?;
+            if let Some(index) = line.as_ref().find("multitext header") {
+                self.marker = Some(line.as_ref()[..index].trim_end().to_string());
+                self.current = Some(("multitext header".to_string(), String::new()));
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<I> Iterator for SectionIter<I>
+where I: Iterator, <I as Iterator>::Item: AsRef<str>
+{
+    type Item = Result<(String, String), Error>;
+
+    fn next(&mut self) -> Option<Result<(String, String), Error>> {
+        if self.failed {
+            return None;
+        }
+        if self.marker.is_none() {
+            if let Err(e) = self.find_header() {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        let marker = self.marker.as_deref().unwrap_or_default();
+        for line in &mut self.lines {
+            self.line_number += 1;
+            let line = line.as_ref();
+            match (line.strip_prefix(marker), &mut self.current) {
+                (Some(rest), current) => {
+                    let next = (rest.trim().to_string(), String::new());
+                    if let Some(finished) = current.replace(next) {
+                        return Some(Ok(finished));
+                    }
+                }
+                (None, Some((_, body))) => {
+                    body.push_str(crate::parse::unescape(line, marker));
+                    body.push('\n');
+                }
+                (None, None) => {}
+            }
+        }
+        self.current.take().map(Ok)
+    }
+}
+
+/// Reads sections from a [`BufRead`] without ever holding a whole body, or a
+/// whole body line, in memory
+///
+/// [`SectionReader::next_section`] moves to the next section and returns its
+/// key; the reader itself then implements [`Read`] over that section's body,
+/// which ends at the next marker line. Bodies come out as the raw bytes of the
+/// file, apart from escaped lines losing their escape, so they may hold `\r`
+/// and needn't be UTF-8. This suits input such as minified code on
+/// multi-megabyte lines, which would otherwise have to be read a line at a
+/// time. Only the lines up to the header, and marker lines, are read whole. As
+/// with [`SectionIter`], continuation parts are not joined.
+///
+/// # Examples
+/// ```
+/// use std::io::Read;
+///
+/// let text = "@@ multitext header\n@@ bundle.js\nvar a=1;var b=2;\n@@ notes\nsmall\n";
+/// let mut reader = multitext::SectionReader::new(text.as_bytes());
+/// assert_eq!(reader.next_section().unwrap().as_deref(), Some("multitext header"));
+/// assert_eq!(reader.next_section().unwrap().as_deref(), Some("bundle.js"));
+///
+/// let mut start = [0; 8];
+/// reader.read_exact(&mut start).unwrap();
+/// assert_eq!(&start, b"var a=1;");
+///
+/// assert_eq!(reader.next_section().unwrap().as_deref(), Some("notes"));
+/// let mut notes = String::new();
+/// reader.read_to_string(&mut notes).unwrap();
+/// assert_eq!(notes, "small\n");
+/// assert_eq!(reader.next_section().unwrap(), None);
+/// ```
+#[derive(Debug)]
+pub struct SectionReader<R> {
+    reader: R,
+    marker: Option<String>,
+    /// Bytes read while looking for a marker that turned out to be body text
+    pending: Vec<u8>,
+    position: LinePosition,
+    body_done: bool,
+    next_key: Option<String>,
+}
+
+/// Where in a body line a [`SectionReader`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinePosition {
+    Start,
+    /// In the backslashes a line starts with, one of which is held back in
+    /// case they escape the marker
+    Backslashes,
+    Middle,
+}
+
+impl<R: BufRead> SectionReader<R> {
+    pub fn new(reader: R) -> SectionReader<R> {
+        SectionReader {
+            reader,
+            marker: None,
+            pending: Vec::new(),
+            position: LinePosition::Start,
+            body_done: true,
+            next_key: None,
+        }
+    }
+
+    /// The marker, once the header line has been read
+    pub fn marker(&self) -> Option<&str> {
+        self.marker.as_deref()
+    }
+
+    /// Skips whatever is left of the current body and returns the key of the
+    /// next section, or `None` at the end of the input
+    ///
+    /// The first call reads up to the header line and returns
+    /// `"multitext header"`, or fails if there is none.
+    pub fn next_section(&mut self) -> Result<Option<String>, Error> {
+        if self.marker.is_none() {
+            return self.find_header().map(Some);
+        }
+        io::copy(self, &mut io::sink()).map_err(Error::from)?;
+        let key = self.next_key.take();
+        if key.is_some() {
+            self.body_done = false;
+            self.position = LinePosition::Start;
+        }
+        Ok(key)
+    }
+
+    fn find_header(&mut self) -> Result<String, Error> {
+        let mut line = Vec::new();
+        for line_number in 1.. {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line).map_err(Error::from)? == 0 {
+                return Err(Error::new(ErrorKind::MissingHeader, "missing multitext header").with_line(line_number));
+            }
+            let text = String::from_utf8_lossy(&line);
+            if let Some(index) = text.find("multitext header") {
+                self.marker = Some(text[..index].trim_end().to_string());
+                self.body_done = false;
+                return Ok("multitext header".to_string());
+            }
+        }
+        unreachable!()
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    /// Reads as much of the line as could still be the marker into `pending`
+    fn read_marker_prefix(&mut self) -> io::Result<()> {
+        let marker = self.marker.as_deref().unwrap_or_default().as_bytes();
+        while self.pending.len() < marker.len() {
+            let byte = match self.reader.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => break,
+            };
+            self.reader.consume(1);
+            self.pending.push(byte);
+            if byte != marker[self.pending.len() - 1] {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn pending_is_marker(&self) -> bool {
+        self.pending == self.marker.as_deref().unwrap_or_default().as_bytes()
+    }
+
+    /// Decides at the start of a line whether it is a marker line, ending the
+    /// body if it is, or an escaped line
+    fn check_line_start(&mut self) -> io::Result<()> {
+        let escapable = crate::parse::is_escapable(self.marker.as_deref().unwrap_or_default());
+        match self.peek()? {
+            None => {
+                self.body_done = true;
+                return Ok(());
+            }
+            Some(b'\\') if escapable => {
+                self.reader.consume(1);
+                self.position = LinePosition::Backslashes;
+                return self.check_after_backslashes();
+            }
+            Some(_) => {}
+        }
+        self.read_marker_prefix()?;
+        if self.pending_is_marker() {
+            let mut line = Vec::new();
+            self.reader.read_until(b'\n', &mut line)?;
+            self.pending.clear();
+            self.body_done = true;
+            let key = String::from_utf8(line)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "marker line is not valid UTF-8"))?;
+            self.next_key = Some(key.trim().to_string());
+        } else {
+            self.end_pending();
+        }
+        Ok(())
+    }
+
+    /// Once a line's leading backslashes have been read, drops the one held
+    /// back if the marker follows them and gives it back otherwise
+    fn check_after_backslashes(&mut self) -> io::Result<()> {
+        if self.peek()? == Some(b'\\') {
+            return Ok(());
+        }
+        self.read_marker_prefix()?;
+        if !self.pending_is_marker() {
+            self.pending.insert(0, b'\\');
+        }
+        self.end_pending();
+        Ok(())
+    }
+
+    fn end_pending(&mut self) {
+        self.position = match self.pending.last() {
+            Some(b'\n') => LinePosition::Start,
+            _ => LinePosition::Middle,
+        };
+    }
+}
+
+impl<R: BufRead> Read for SectionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.body_done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() {
+            match self.position {
+                LinePosition::Start => self.check_line_start()?,
+                LinePosition::Backslashes => self.check_after_backslashes()?,
+                LinePosition::Middle => {}
+            }
+            if self.body_done {
+                return Ok(0);
+            }
+        }
+        if !self.pending.is_empty() {
+            let n = self.pending.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            return Ok(n);
+        }
+        let available = self.reader.fill_buf()?;
+        if available.is_empty() {
+            self.body_done = true;
+            return Ok(0);
+        }
+        let n = if self.position == LinePosition::Backslashes {
+            available.iter().take_while(|&&b| b == b'\\').count()
+        } else {
+            available.iter().position(|&b| b == b'\n').map_or(available.len(), |i| i + 1)
+        };
+        let n = n.min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        if self.position == LinePosition::Middle && available[n - 1] == b'\n' {
+            self.position = LinePosition::Start;
+        }
+        self.reader.consume(n);
+        Ok(n)
+    }
+}
+
+/// Parses bytes pushed in as they arrive, for code that must not block on a
+/// reader, such as an async server reading an upload
+///
+/// Whatever runtime reads the chunks hands each one to [`PushParser::push`],
+/// which never waits, and [`PushParser::finish`] parses the result. Lines
+/// are checked as UTF-8 as soon as they are complete.
+///
+/// # Examples
+/// ```
+/// use multitext::PushParser;
+///
+/// let mut parser = PushParser::new();
+/// for chunk in [&b"@@ multitext header\r\n@@ gre"[..], b"eting\nhello", b"\n"] {
+///     parser.push(chunk).unwrap();
+/// }
+/// let doc = parser.finish().unwrap();
+/// assert_eq!(&doc["greeting"], "hello\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct PushParser {
+    options: ParseOptions,
+    lines: Vec<String>,
+    partial: Vec<u8>,
+}
+
+impl Default for PushParser {
+    fn default() -> PushParser {
+        PushParser::new()
+    }
+}
+
+impl PushParser {
+    /// A parser that normalizes line endings, as for files
+    pub fn new() -> PushParser {
+        PushParser::with_options(ParseOptions::new().line_endings(LineEndings::Normalize))
+    }
+
+    pub fn with_options(options: ParseOptions) -> PushParser {
+        PushParser { options, lines: Vec::new(), partial: Vec::new() }
+    }
+
+    /// Takes the next chunk of input, which may end anywhere, even inside a
+    /// character
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut rest = bytes;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&rest[..end]);
+            rest = &rest[end + 1..];
+            self.end_line()?;
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(())
+    }
+
+    /// Parses everything pushed, including a last line without a newline
+    pub fn finish(mut self) -> Result<Document, Error> {
+        if !self.partial.is_empty() {
+            self.end_line()?;
+        }
+        crate::parse_document_with(self.lines.iter(), &self.options)
+    }
+
+    fn end_line(&mut self) -> Result<(), Error> {
+        let line = String::from_utf8(std::mem::take(&mut self.partial)).map_err(|_| {
+            Error::new(ErrorKind::InvalidUtf8, "line is not valid UTF-8").with_line(self.lines.len() + 1)
+        })?;
+        self.lines.push(line);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_section_iter() {
+        let lines = ["ignored", "## multitext header", "h", "## a", "1", "## a", "2"];
+        let sections: Vec<_> = SectionIter::new(lines.iter()).map(Result::unwrap).collect();
+        let keys: Vec<_> = sections.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["multitext header", "a", "a"]);
+        assert_eq!(sections[2].1, "2\n");
+
+        let mut missing = SectionIter::new(["no", "header"].iter());
+        assert!(missing.next().unwrap().is_err());
+        assert!(missing.next().is_none());
+    }
+
+    #[test]
+    fn test_push_parser() {
+        let text = "@@ multitext header\n@@ s\u{e9}ction\n\u{e9}t\u{e9}\n@@ last\nno newline";
+        let mut parser = PushParser::new();
+        for byte in text.as_bytes() {
+            parser.push(std::slice::from_ref(byte)).unwrap();
+        }
+        let doc = parser.finish().unwrap();
+        assert_eq!(&doc["s\u{e9}ction"], "\u{e9}t\u{e9}\n");
+        assert_eq!(&doc["last"], "no newline\n");
+
+        let mut parser = PushParser::new();
+        let e = parser.push(b"@@ multitext header\n\xff\n").unwrap_err();
+        assert_eq!((e.kind(), e.line_number()), (ErrorKind::InvalidUtf8, Some(2)));
+    }
+
+    #[test]
+    fn test_section_reader() {
+        let long = "x".repeat(3_000_000);
+        let text = format!("junk\n## multitext header\n## big\n#x\n{}\n##c\n## ", long);
+        let mut reader = SectionReader::new(io::BufReader::with_capacity(16, text.as_bytes()));
+        assert_eq!(reader.next_section().unwrap().as_deref(), Some("multitext header"));
+        assert_eq!(reader.marker(), Some("##"));
+        assert_eq!(reader.next_section().unwrap().as_deref(), Some("big"));
+        let mut body = Vec::new();
+        let mut chunk = [0; 1000];
+        loop {
+            let n = reader.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(body, format!("#x\n{}\n", long).as_bytes());
+        assert_eq!(reader.next_section().unwrap().as_deref(), Some("c"));
+        assert_eq!(reader.next_section().unwrap().as_deref(), Some(""));
+        assert_eq!(reader.next_section().unwrap(), None);
+
+        assert!(SectionReader::new("no header".as_bytes()).next_section().is_err());
+    }
+}
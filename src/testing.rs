@@ -0,0 +1,164 @@
+//! Helpers for testing code that produces bundles
+//!
+//! Property tests and fuzzers can call [`roundtrip_check`] on generated text,
+//! or [`roundtrip_document`] on a generated document, to assert that it
+//! survives being written and parsed again unchanged. Tests that touch the
+//! file system can work inside a [`TempDir`], which is removed when dropped.
+
+use crate::options::{ParseOptions, WriteOptions};
+use crate::Document;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Checks that `input` parses, and that writing the result and parsing it
+/// again gives the same document, written the same way
+///
+/// Returns a description of the first difference. The written form may differ
+/// from `input` itself, for example by its whitespace around keys, but it must
+/// be stable from then on.
+///
+/// # Examples
+/// ```
+/// use multitext::testing::roundtrip_check;
+///
+/// assert!(roundtrip_check("@@ multitext header\n@@ a\n1\n@@   b  \n2").is_ok());
+/// assert!(roundtrip_check("no header").is_err());
+/// ```
+pub fn roundtrip_check(input: &str) -> Result<(), String> {
+    let doc = crate::parse_document(input.lines()).map_err(|e| format!("input does not parse: {}", e))?;
+    let written = write(&doc)?;
+    let reparsed = reparse(&written, &doc)?;
+    compare(&doc, &reparsed)?;
+    let rewritten = write(&reparsed)?;
+    if rewritten != written {
+        return Err(format!("writing is not stable:\n{}\nthen:\n{}", written, rewritten));
+    }
+    Ok(())
+}
+
+/// Checks that writing `doc` and parsing the result gives `doc` back
+///
+/// # Examples
+/// ```
+/// use multitext::testing::roundtrip_document;
+///
+/// let good: multitext::Document = vec![("a", "1\n")].into_iter().collect();
+/// assert!(roundtrip_document(&good).is_ok());
+///
+/// // a body without a final newline gains one
+/// let bad: multitext::Document = vec![("a", "1")].into_iter().collect();
+/// assert!(roundtrip_document(&bad).unwrap_err().contains("section \"a\""));
+/// ```
+pub fn roundtrip_document(doc: &Document) -> Result<(), String> {
+    let written = write(doc)?;
+    let reparsed = reparse(&written, doc)?;
+    compare(doc, &reparsed)
+}
+
+/// A new empty directory under the system temporary directory, removed with
+/// everything in it when dropped
+///
+/// The directory is named after `name`, the process and a counter, so tests
+/// running at the same time never share one.
+///
+/// # Examples
+/// ```
+/// use multitext::testing::TempDir;
+///
+/// let dir = TempDir::new("example").unwrap();
+/// std::fs::write(dir.join("a.mt"), "@@ multitext header\n").unwrap();
+/// let path = dir.to_path_buf();
+/// drop(dir);
+/// assert!(!path.exists());
+/// ```
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> io::Result<TempDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("multitext-{}-{}-{}", name, std::process::id(), count));
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+fn write(doc: &Document) -> Result<String, String> {
+    let bytes = doc.write_with(Vec::new(), &WriteOptions::new()).map_err(|e| format!("cannot be written: {}", e))?;
+    String::from_utf8(bytes).map_err(|e| format!("written text is not UTF-8: {}", e))
+}
+
+/// Parses written text, reading attributes back if the original had any
+fn reparse(written: &str, original: &Document) -> Result<Document, String> {
+    let has_attrs = original.iter().any(|s| s.attrs().next().is_some());
+    let options = ParseOptions::new().attributes(has_attrs);
+    crate::parse_document_with(written.lines(), &options)
+        .map_err(|e| format!("written text does not parse: {}\n{}", e, written))
+}
+
+fn compare(expected: &Document, actual: &Document) -> Result<(), String> {
+    let expected: Vec<_> = expected.iter().filter(|s| s.key() != "multitext header" || !s.body().is_empty()).collect();
+    let actual: Vec<_> = actual.iter().filter(|s| s.key() != "multitext header" || !s.body().is_empty()).collect();
+    for (e, a) in expected.iter().zip(&actual) {
+        if e != a {
+            return Err(format!(
+                "section \"{}\" came back as section \"{}\": {:?} became {:?}", e.key(), a.key(), e.body(), a.body()
+            ));
+        }
+    }
+    if expected.len() != actual.len() {
+        return Err(format!("{} sections came back as {}", expected.len(), actual.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generated_inputs_round_trip() {
+        let pieces = ["@@", " ", "multitext header", "\n", "\r\n", "a", "key=value", "\t", "@", "\u{e9}", "(1/2)"];
+        let mut state: u32 = 12345;
+        for _ in 0..2000 {
+            let mut input = String::from("@@ multitext header\n");
+            for _ in 0..12 {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                input.push_str(pieces[(state >> 16) as usize % pieces.len()]);
+            }
+            roundtrip_check(&input).unwrap_or_else(|e| panic!("{:?}: {}", input, e));
+        }
+    }
+}
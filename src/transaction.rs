@@ -0,0 +1,206 @@
+//! Updating several multitext files so that either all change or none do
+//!
+//! A [`Transaction`] stages documents for any number of paths. On commit,
+//! each is first written and synced to a temporary file next to its target.
+//! Only when every temporary file is written are they renamed over their
+//! targets, so a target is never missing or half-written. The files they
+//! replace are first hard-linked or copied to backups, which are kept until
+//! the last rename succeeds and are renamed back if any rename fails.
+//!
+//! This makes a commit all or none unless the process dies during the
+//! renames. Then some targets may be updated and others not, and the
+//! temporary and backup files (`.NAME.tmp-PID` and `.NAME.bak-PID`) are left
+//! next to them for the user to sort out.
+
+use crate::report::{plan_write, ChangeKind, ChangeReport};
+use crate::writer::{write_document, DEFAULT_MARKER};
+use crate::{Document, Error};
+use std::path::{Path, PathBuf};
+
+/// Staged document updates for a family of files
+///
+/// # Examples
+/// ```no_run
+/// let mut vertex = multitext::open_and_parse_document("a.mt")?;
+/// let mut fragment = multitext::open_and_parse_document("b.mt")?;
+/// vertex.insert("version", "2\n")?;
+/// fragment.insert("version", "2\n")?;
+///
+/// let mut transaction = multitext::transaction::Transaction::new();
+/// transaction.stage("a.mt", vertex);
+/// transaction.stage("b.mt", fragment);
+/// transaction.commit()?;
+/// # Ok::<(), multitext::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    staged: Vec<(PathBuf, Document)>,
+    dry_run: bool,
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction::default()
+    }
+
+    /// Whether [`Transaction::commit`] only reports what it would write
+    pub fn dry_run(mut self, dry_run: bool) -> Transaction {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Stages `doc` to be written to `path`, replacing anything staged for it
+    /// before
+    ///
+    /// The document is written with its own marker, or `@@@` if it has none.
+    pub fn stage<P: Into<PathBuf>>(&mut self, path: P, doc: Document) {
+        let path = path.into();
+        self.staged.retain(|(p, _)| *p != path);
+        self.staged.push((path, doc));
+    }
+
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Writes every staged document, or none of them
+    pub fn commit(self) -> Result<ChangeReport, Error> {
+        let mut planned = Vec::new();
+        for (path, doc) in &self.staged {
+            let marker = doc.marker().unwrap_or(DEFAULT_MARKER);
+            let contents = write_document(Vec::new(), doc, marker).map_err(|e| Error::from(e).with_path(path))?;
+            let change = plan_write(path, &contents);
+            planned.push((path.as_path(), contents, change));
+        }
+
+        let report = ChangeReport {
+            dry_run: self.dry_run,
+            changes: planned.iter().map(|(_, _, change)| change.clone()).collect(),
+        };
+        if self.dry_run {
+            return Ok(report);
+        }
+
+        let pending: Vec<_> = planned.iter()
+            .filter(|(_, _, change)| change.kind != ChangeKind::Unchanged)
+            .map(|(path, contents, _)| (*path, contents.as_slice()))
+            .collect();
+        write_atomically(&pending)?;
+        Ok(report)
+    }
+}
+
+fn write_atomically(files: &[(&Path, &[u8])]) -> Result<(), Error> {
+    let mut temps = Vec::new();
+    for (path, contents) in files {
+        let temp = sibling(path, "tmp");
+        if let Err(e) = write_synced(&temp, contents) {
+            remove_all(&temps);
+            let _ = std::fs::remove_file(&temp);
+            return Err(Error::from(e).with_path(path));
+        }
+        temps.push(temp);
+    }
+
+    // Each entry records a target that has been replaced, and its backup if
+    // the target existed before.
+    let mut replaced: Vec<(&Path, Option<PathBuf>)> = Vec::new();
+    for ((path, _), temp) in files.iter().zip(&temps) {
+        let backup = if path.exists() {
+            let backup = sibling(path, "bak");
+            if let Err(e) = back_up(path, &backup) {
+                roll_back(&replaced);
+                remove_all(&temps);
+                return Err(Error::from(e).with_path(path));
+            }
+            Some(backup)
+        } else {
+            None
+        };
+        if let Err(e) = std::fs::rename(temp, path) {
+            if let Some(backup) = &backup {
+                let _ = std::fs::remove_file(backup);
+            }
+            roll_back(&replaced);
+            remove_all(&temps);
+            return Err(Error::from(e).with_path(path));
+        }
+        replaced.push((path, backup));
+    }
+
+    for (_, backup) in replaced {
+        if let Some(backup) = backup {
+            let _ = std::fs::remove_file(backup);
+        }
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path` and waits for them to reach the disk
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Keeps the contents of `path` at `backup` while leaving `path` in place
+fn back_up(path: &Path, backup: &Path) -> std::io::Result<()> {
+    let _ = std::fs::remove_file(backup);
+    std::fs::hard_link(path, backup).or_else(|_| std::fs::copy(path, backup).map(|_| ()))
+}
+
+/// Puts back the original files for targets that were already replaced
+fn roll_back(replaced: &[(&Path, Option<PathBuf>)]) {
+    for (path, backup) in replaced.iter().rev() {
+        match backup {
+            Some(backup) => {
+                let _ = std::fs::rename(backup, path);
+            }
+            None => {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+fn remove_all(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// A hidden file next to `path` for staging or backing it up
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}-{}", name, suffix, std::process::id()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failed_commit_leaves_files_untouched() {
+        let dir = crate::testing::TempDir::new("transaction").unwrap();
+        let good = dir.join("good.mt");
+        std::fs::write(&good, "@@@ multitext header\n@@@ a\nold\n").unwrap();
+
+        let doc: Document = vec![("a", "new\n")].into_iter().collect();
+        let mut transaction = Transaction::new();
+        transaction.stage(&good, doc.clone());
+        transaction.stage(dir.join("missing-dir").join("bad.mt"), doc.clone());
+        assert!(transaction.commit().is_err());
+        assert_eq!(std::fs::read_to_string(&good).unwrap(), "@@@ multitext header\n@@@ a\nold\n");
+
+        let mut transaction = Transaction::new();
+        transaction.stage(&good, doc);
+        transaction.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&good).unwrap(), "@@@ multitext header\n@@@ a\nnew\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
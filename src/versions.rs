@@ -0,0 +1,230 @@
+//! Earlier versions of a section kept inside the bundle
+//!
+//! For files that don't live under version control, a document can keep the
+//! bodies it replaces. Once [`Document::keep_versions`] is on, each body
+//! replaced by [`Document::insert`] is kept in a section named
+//! `key@timestamp`, where the timestamp is in seconds since the Unix epoch
+//! (with `.1`, `.2`, ... added for several versions in the same second).
+//! Version sections are ordinary sections, so they are written, parsed and
+//! diffed like any other.
+//!
+//! The kept versions also record how the whole document changed over time:
+//! [`Document::at_revision`] and [`open_version`] rebuild it as it was a
+//! number of replacements ago or at a given time.
+
+use crate::options::{LineEndings, ParseOptions};
+use crate::{Document, Error};
+use crate::history::Edit;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An earlier body of a section, as returned by [`Document::history`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Version<'a> {
+    /// The key of the section holding this version
+    pub key: &'a str,
+    /// When the body was replaced, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub body: &'a str,
+}
+
+impl Version<'_> {
+    /// The key of the section this is a version of
+    pub fn base_key(&self) -> &str {
+        self.key.rsplit_once('@').map_or(self.key, |(base, _)| base)
+    }
+}
+
+/// An earlier state of a document, for [`Document::at_revision`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Revision {
+    /// Before the last `n` kept versions were made; `Back(0)` is the current
+    /// state
+    Back(usize),
+    /// As it was at this time, in seconds since the Unix epoch
+    At(u64),
+}
+
+/// Splits `key@timestamp` or `key@timestamp.n` into the timestamp and `n`
+fn parse_suffix(suffix: &str) -> Option<(u64, u64)> {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let (timestamp, n) = suffix.split_once('.').unwrap_or((suffix, "0"));
+    if !digits(timestamp) || !digits(n) {
+        return None;
+    }
+    Some((timestamp.parse().ok()?, n.parse().ok()?))
+}
+
+impl Document {
+    /// Keeps each body replaced by [`Document::insert`] in a `key@timestamp`
+    /// section
+    ///
+    /// Off by default. Keeping a version counts as a separate edit in the undo
+    /// history.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut doc: multitext::Document = vec![("config", "v1\n")].into_iter().collect();
+    /// doc.keep_versions(true);
+    /// doc.insert("config", "v2\n").unwrap();
+    /// doc.insert("config", "v3\n").unwrap();
+    ///
+    /// let bodies: Vec<_> = doc.history("config").iter().map(|v| v.body).collect();
+    /// assert_eq!(bodies, ["v1\n", "v2\n"]);
+    /// assert_eq!(&doc["config"], "v3\n");
+    /// assert!(doc.iter().nth(1).unwrap().key().starts_with("config@"));
+    /// ```
+    pub fn keep_versions(&mut self, yes: bool) {
+        self.keep_versions = yes;
+    }
+
+    /// The kept versions of the section named `key`, oldest first
+    pub fn history(&self, key: &str) -> Vec<Version<'_>> {
+        let mut versions: Vec<(u64, Version)> = self.sections.iter()
+            .filter_map(|section| {
+                let suffix = section.key().strip_prefix(key)?.strip_prefix('@')?;
+                let (timestamp, n) = parse_suffix(suffix)?;
+                Some((n, Version { key: section.key(), timestamp, body: &section.body }))
+            })
+            .collect();
+        versions.sort_by_key(|(n, version)| (version.timestamp, *n));
+        versions.into_iter().map(|(_, version)| version).collect()
+    }
+
+    /// Every kept version of every section, oldest first
+    pub fn versions(&self) -> Vec<Version<'_>> {
+        let mut versions: Vec<(u64, Version)> = self.sections.iter()
+            .filter_map(|section| {
+                let (_, suffix) = section.key().rsplit_once('@')?;
+                let (timestamp, n) = parse_suffix(suffix)?;
+                Some((n, Version { key: section.key(), timestamp, body: &section.body }))
+            })
+            .collect();
+        versions.sort_by_key(|(n, version)| (version.timestamp, *n));
+        versions.into_iter().map(|(_, version)| version).collect()
+    }
+
+    /// The document as it was at `revision`, rebuilt from its kept versions
+    ///
+    /// Each section gets back the body it had then, and the versions made
+    /// since are dropped. Sections added or removed since can't be told
+    /// apart, as only replaced bodies are kept, so they are left as they are.
+    ///
+    /// # Examples
+    /// ```
+    /// use multitext::versions::Revision;
+    ///
+    /// let mut doc: multitext::Document = vec![("a", "1\n"), ("b", "x\n")].into_iter().collect();
+    /// doc.keep_versions(true);
+    /// doc.insert("a", "2\n").unwrap();
+    /// doc.insert("b", "y\n").unwrap();
+    ///
+    /// let before = doc.at_revision(Revision::Back(1));
+    /// assert_eq!((&before["a"], &before["b"]), ("2\n", "x\n"));
+    /// let before = doc.at_revision(Revision::Back(2));
+    /// assert_eq!((&before["a"], &before["b"]), ("1\n", "x\n"));
+    /// assert_eq!(before.len(), 2);
+    /// ```
+    pub fn at_revision(&self, revision: Revision) -> Document {
+        let versions = self.versions();
+        let undone = match revision {
+            Revision::Back(n) => &versions[versions.len().saturating_sub(n)..],
+            Revision::At(time) => {
+                let kept = versions.iter().take_while(|v| v.timestamp <= time).count();
+                &versions[kept..]
+            }
+        };
+
+        let mut doc = self.clone();
+        for version in undone.iter().rev() {
+            if let Some(index) = doc.position(version.base_key()) {
+                let section = &mut doc.sections[index];
+                section.body = version.body.to_string();
+                section.touch();
+            }
+        }
+        doc.retain_sections(|s| !undone.iter().any(|v| v.key == s.key()));
+        doc
+    }
+
+    /// Stores `body` as a version of the section at `index`, right after the
+    /// section and its other versions
+    pub(crate) fn save_version(&mut self, index: usize, body: String) {
+        let key = self.sections[index].key().to_string();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut version_key = format!("{}@{}", key, timestamp);
+        let mut n = 0;
+        while self.contains_key(&version_key) {
+            n += 1;
+            version_key = format!("{}@{}.{}", key, timestamp, n);
+        }
+
+        let is_version = |k: &str| k.strip_prefix(key.as_str())
+            .and_then(|s| s.strip_prefix('@'))
+            .is_some_and(|s| parse_suffix(s).is_some());
+        let mut at = index + 1;
+        while at < self.sections.len() && is_version(self.sections[at].key()) {
+            at += 1;
+        }
+        if self.history.is_some() {
+            self.record(Edit::Insert { key: version_key.clone(), index: at, body: body.clone() });
+        }
+        self.insert_raw(at, version_key, body);
+    }
+}
+
+/// Opens the file at `path` and rebuilds it as it was at `revision`; see
+/// [`Document::at_revision`]
+///
+/// Text above the header is kept, so writing the result back restores the
+/// file to that state.
+pub fn open_version<P: AsRef<Path>>(path: P, revision: Revision) -> Result<Document, Error> {
+    let text = std::fs::read_to_string(path.as_ref()).map_err(|e| Error::from(e).with_path(path.as_ref()))?;
+    let options = ParseOptions::new().line_endings(LineEndings::Normalize).keep_preamble(true);
+    let mut doc = crate::parse_document_with(text.lines(), &options).map_err(|e| e.with_path(path.as_ref()))?;
+    doc.set_file(&path.as_ref().to_string_lossy());
+    Ok(doc.at_revision(revision))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history_order() {
+        let doc: Document = vec![
+            ("a", "now\n"), ("a@20.1", "third\n"), ("a@20", "second\n"), ("a@3", "first\n"),
+            ("a@x", "not a version\n"), ("ab@1", "other key\n"),
+        ].into_iter().collect();
+        let history = doc.history("a");
+        assert_eq!(history.iter().map(|v| v.body).collect::<Vec<_>>(), ["first\n", "second\n", "third\n"]);
+        assert_eq!(history[1].timestamp, 20);
+
+        let mut doc: Document = vec![("a", "1\n"), ("b", "\n")].into_iter().collect();
+        doc.keep_versions(true);
+        doc.insert("a", "2\n").unwrap();
+        doc.insert("a", "3\n").unwrap();
+        let keys: Vec<_> = doc.iter().map(|s| s.key()).collect();
+        assert_eq!(keys[3], "b");
+        assert!(keys[2].ends_with(".1"));
+    }
+
+    #[test]
+    fn test_at_revision() {
+        let doc: Document = vec![
+            ("a", "3\n"), ("a@10", "1\n"), ("a@20", "2\n"), ("b", "y\n"), ("b@15", "x\n"),
+        ].into_iter().collect();
+        let keys = |doc: &Document| doc.iter().map(|s| s.key().to_string()).collect::<Vec<_>>();
+        let contents = |doc: &Document| doc.iter().map(|s| (s.key().to_string(), s.body().to_string())).collect::<Vec<_>>();
+
+        let at = doc.at_revision(Revision::At(15));
+        assert_eq!((&at["a"], &at["b"]), ("2\n", "y\n"));
+        assert_eq!(keys(&at), ["a", "a@10", "b", "b@15"]);
+        let at = doc.at_revision(Revision::At(5));
+        assert_eq!((&at["a"], &at["b"]), ("1\n", "x\n"));
+        assert_eq!(keys(&at), ["a", "b"]);
+        assert_eq!(contents(&doc.at_revision(Revision::Back(2))), contents(&doc.at_revision(Revision::At(12))));
+        assert_eq!(keys(&doc.at_revision(Revision::Back(0))), keys(&doc));
+        assert_eq!(doc.versions().iter().map(|v| v.base_key()).collect::<Vec<_>>(), ["a", "b", "a"]);
+    }
+}
@@ -0,0 +1,136 @@
+//! Noticing when sections of a file change on disk
+//!
+//! A [`Watch`] rereads a file whenever its modification time or size changes
+//! and reports which sections were added or edited, for tools that rebuild or
+//! reload something per section, like live-reloading shaders. It polls rather
+//! than using operating system notifications, so it behaves the same
+//! everywhere.
+
+use crate::diff::{diff_documents, SectionChange};
+use crate::options::{LineEndings, ParseOptions};
+use crate::{Document, Error};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Watches one file; see [`watch_file`]
+#[derive(Debug)]
+pub struct Watch {
+    path: PathBuf,
+    doc: Document,
+    options: ParseOptions,
+    stamp: Option<(SystemTime, u64)>,
+    poll_interval: Duration,
+}
+
+/// Starts watching `path`, reading it now so that only later changes are
+/// reported
+///
+/// # Examples
+/// ```no_run
+/// let mut watch = multitext::watch::watch_file("shaders.mt")?;
+/// loop {
+///     for key in watch.wait()? {
+///         println!("recompiling {}", key);
+///     }
+/// }
+/// # Ok::<(), multitext::Error>(())
+/// ```
+pub fn watch_file<P: AsRef<Path>>(path: P) -> Result<Watch, Error> {
+    watch_file_with(path, &ParseOptions::new().line_endings(LineEndings::Normalize))
+}
+
+/// Like [`watch_file`], but parses the file with `options` every time
+pub fn watch_file_with<P: AsRef<Path>>(path: P, options: &ParseOptions) -> Result<Watch, Error> {
+    let path = path.as_ref().to_path_buf();
+    let stamp = stamp(&path);
+    let doc = read(&path, options)?;
+    Ok(Watch { path, doc, options: options.clone(), stamp, poll_interval: Duration::from_millis(250) })
+}
+
+fn read(path: &Path, options: &ParseOptions) -> Result<Document, Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| Error::from(e).with_path(path))?;
+    let mut doc = crate::parse_document_with(text.lines(), options).map_err(|e| e.with_path(path))?;
+    doc.set_file(&path.to_string_lossy());
+    Ok(doc)
+}
+
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl Watch {
+    /// How long [`Watch::wait`] sleeps between checks; 250ms by default
+    pub fn poll_interval(mut self, interval: Duration) -> Watch {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// The document as last read successfully
+    pub fn document(&self) -> &Document {
+        &self.doc
+    }
+
+    /// Checks the file without blocking, returning the keys of the sections
+    /// added or edited since it was last read, in file order
+    ///
+    /// A renamed section counts as added under its new key. Removed sections
+    /// aren't reported, though they are gone from [`Watch::document`]. If the
+    /// file can't be read or parsed, perhaps because an editor is halfway
+    /// through saving it, the error is returned and the next poll tries again.
+    pub fn poll(&mut self) -> Result<Vec<String>, Error> {
+        let stamp = stamp(&self.path);
+        if stamp.is_some() && stamp == self.stamp {
+            return Ok(Vec::new());
+        }
+        let doc = read(&self.path, &self.options)?;
+        self.stamp = stamp;
+        let mut changed: Vec<String> = diff_documents(&self.doc, &doc).into_iter()
+            .filter_map(|change| match change {
+                SectionChange::Added { key, .. } | SectionChange::Modified { key, .. } => Some(key.to_string()),
+                SectionChange::Renamed { to, .. } => Some(to.to_string()),
+                SectionChange::Removed { .. } => None,
+            })
+            .collect();
+        let position = |key: &String| doc.iter().position(|s| s.key() == key);
+        changed.sort_by_key(position);
+        self.doc = doc;
+        Ok(changed)
+    }
+
+    /// Blocks until at least one section has been added or edited, returning
+    /// their keys as [`Watch::poll`] does
+    pub fn wait(&mut self) -> Result<Vec<String>, Error> {
+        loop {
+            let changed = self.poll()?;
+            if !changed.is_empty() {
+                return Ok(changed);
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let dir = crate::testing::TempDir::new("watch").unwrap();
+        let path = dir.join("watch.mt");
+        std::fs::write(&path, "@@ multitext header\n@@ a\n1\n@@ b\n2\n").unwrap();
+        let mut watch = watch_file(&path).unwrap();
+        assert!(watch.poll().unwrap().is_empty());
+
+        // A different size is noticed even if the modification time is too
+        // coarse to change
+        std::fs::write(&path, "@@ multitext header\n@@ c\n3\n@@ b\n22\n@@ a\n1\n").unwrap();
+        assert_eq!(watch.poll().unwrap(), ["c", "b"]);
+        assert_eq!(&watch.document()["b"], "22\n");
+
+        std::fs::write(&path, "no header").unwrap();
+        assert!(watch.poll().is_err());
+        assert_eq!(&watch.document()["b"], "22\n");
+    }
+}
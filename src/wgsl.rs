@@ -0,0 +1,169 @@
+//! Helpers for documents holding WGSL shader modules
+//!
+//! Each section whose key names a vertex, fragment or compute stage becomes
+//! one [`ModuleSource`], shaped after wgpu's `ShaderModuleDescriptor`: the key
+//! is the label and the body is the WGSL source. Sections are checked with a
+//! user-supplied [`Compiler`] (typically a wrapper around naga's validator),
+//! and reported line numbers are mapped back onto the multitext file.
+
+use crate::{Document, Error, ErrorKind};
+use crate::shader::{Compiler, CompileDiagnostic, CompilerMessage, Stage};
+
+/// The label and source for one wgpu shader module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleSource<'a> {
+    pub label: &'a str,
+    pub stage: Stage,
+    pub source: &'a str,
+}
+
+impl<'a> ModuleSource<'a> {
+    /// The attribute WGSL requires on this stage's entry point
+    ///
+    /// Fails for the stages WGSL doesn't have, such as geometry.
+    pub fn entry_attribute(&self) -> Result<&'static str, Error> {
+        match self.stage {
+            Stage::Vertex => Ok("@vertex"),
+            Stage::Fragment => Ok("@fragment"),
+            Stage::Compute => Ok("@compute"),
+            stage => Err(Error::new(ErrorKind::Other, format!("WGSL has no {:?} stage", stage))),
+        }
+    }
+}
+
+/// Returns a module source for every vertex, fragment and compute section, in
+/// document order
+///
+/// # Examples
+/// ```
+/// use multitext::shader::Stage;
+///
+/// let lines = [
+///     "// multitext header",
+///     "// vertex shader",
+///     "@vertex fn main() -> @builtin(position) vec4<f32> { return vec4<f32>(); }",
+///     "// notes",
+///     "not a shader",
+/// ];
+/// let doc = multitext::parse_document(lines.iter()).unwrap();
+/// let modules = multitext::wgsl::stage_modules(&doc);
+/// assert_eq!(modules.len(), 1);
+/// assert_eq!(modules[0].label, "vertex shader");
+/// assert_eq!(modules[0].stage, Stage::Vertex);
+/// ```
+pub fn stage_modules(doc: &Document) -> Vec<ModuleSource<'_>> {
+    doc.iter().filter_map(|section| {
+        match Stage::from_key(section.key())? {
+            stage @ Stage::Vertex | stage @ Stage::Fragment | stage @ Stage::Compute => {
+                Some(ModuleSource { label: section.key(), stage, source: section.body() })
+            }
+            _ => None,
+        }
+    }).collect()
+}
+
+/// Checks every stage module, returning the messages from `compiler` plus one
+/// for any module that lacks an entry point for its stage
+///
+/// WGSL has no `#line` directive, so message lines are taken as relative to
+/// the start of the section being checked. Entry point attributes inside
+/// comments don't count.
+pub fn compile_check<C: Compiler>(doc: &Document, compiler: &mut C) -> Vec<CompileDiagnostic> {
+    let mut diagnostics = Vec::new();
+    for module in stage_modules(doc) {
+        let section = match doc.section(module.label) {
+            Some(section) => section,
+            None => continue,
+        };
+
+        let missing = match module.entry_attribute() {
+            Ok(attribute) if has_attribute(module.source, &attribute[1..]) => None,
+            Ok(attribute) => Some(format!("no {} entry point", attribute)),
+            Err(e) => Some(e.message().to_string()),
+        };
+        if let Some(message) = missing {
+            diagnostics.push(CompileDiagnostic::remap(section, CompilerMessage {
+                source_string: None,
+                line: None,
+                message,
+            }));
+        }
+
+        for message in compiler.compile(module.stage, module.source) {
+            diagnostics.push(CompileDiagnostic::remap(section, message));
+        }
+    }
+    diagnostics
+}
+
+/// Whether `@name` appears as an attribute outside comments
+fn has_attribute(source: &str, name: &str) -> bool {
+    let code = strip_comments(source);
+    let mut rest = code.as_str();
+    while let Some(at) = rest.find('@') {
+        rest = rest[at + 1..].trim_start();
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if &rest[..len] == name {
+            return true;
+        }
+    }
+    false
+}
+
+/// Replaces `//` and (nested) `/* */` comments with spaces; WGSL has no
+/// string literals for them to hide in
+fn strip_comments(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('*')) => {
+                chars.next();
+                depth += 1;
+                out.push(' ');
+            }
+            ('*', Some('/')) if depth > 0 => {
+                chars.next();
+                depth -= 1;
+                out.push(' ');
+            }
+            ('/', Some('/')) if depth == 0 => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                out.push(' ');
+            }
+            ('\n', _) => out.push('\n'),
+            _ if depth > 0 => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_points_in_comments_dont_count() {
+        let lines = [
+            "@@ multitext header",
+            "@@ vertex shader", "/* @vertex /* nested */ @vertex */", "// @vertex",
+            "@@ fragment shader", "@ fragment fn main() {}",
+        ];
+        let doc = crate::parse_document(lines.iter()).unwrap();
+
+        struct Accept;
+        impl Compiler for Accept {
+            fn compile(&mut self, _: Stage, _: &str) -> Vec<CompilerMessage> {
+                Vec::new()
+            }
+        }
+        let diagnostics = compile_check(&doc, &mut Accept);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((&*diagnostics[0].key, &*diagnostics[0].message), ("vertex shader", "no @vertex entry point"));
+
+        let geometry = ModuleSource { label: "geometry", stage: Stage::Geometry, source: "" };
+        assert!(geometry.entry_attribute().is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{Document, Map, Section};
+use crate::options::WriteOptions;
+use crate::parse::continuation_key;
+use std::io::{self, Write};
+
+/// The marker used when a document doesn't carry one of its own
+pub(crate) const DEFAULT_MARKER: &str = "@@@";
+
+/// Writes all of `doc` with `marker`, returning the underlying writer
+pub(crate) fn write_document<W: Write>(inner: W, doc: &Document, marker: &str) -> io::Result<W> {
+    let mut writer = DocumentWriter::new(inner, marker)?;
+    if let Some(preamble) = doc.preamble() {
+        writer.write_preamble(preamble)?;
+    }
+    for section in doc.iter() {
+        writer.begin_section_with_attrs(section.key(), section.attrs())?.write_all(section.body().as_bytes())?;
+    }
+    writer.finish()
+}
+
+/// Writes `map` as a multitext file with `marker`, the "multitext header"
+/// section first and the rest sorted by key
+///
+/// # Panics
+/// If the marker is unusable.
+///
+/// # Examples
+/// ```
+/// let mut map = multitext::Map::new();
+/// map.insert("b".to_string(), "2\n".to_string());
+/// map.insert("a".to_string(), "1".to_string());
+/// let text = multitext::write_string(&map, "@@");
+/// assert_eq!(text, "@@ multitext header\n@@ a\n1\n@@ b\n2\n");
+///
+/// let parsed = multitext::parse_lines(text.lines()).unwrap();
+/// assert_eq!(parsed["a"], "1\n");
+/// ```
+pub fn write_string(map: &Map, marker: &str) -> String {
+    let bytes = write_map(Vec::new(), map, marker).expect("invalid marker");
+    String::from_utf8(bytes).expect("sections are valid UTF-8")
+}
+
+/// Writes `map` to the file at `path`, as [`write_string`] would format it
+///
+/// The text goes to a temporary file next to `path` that is then renamed
+/// over it, so `path` never holds a partly written document.
+pub fn write_file<P: AsRef<std::path::Path>>(path: P, map: &Map, marker: &str) -> io::Result<()> {
+    let path = path.as_ref();
+    let temp = crate::transaction::sibling(path, "tmp");
+    let written = std::fs::File::create(&temp)
+        .and_then(|file| write_map(io::BufWriter::new(file), map, marker)?.into_inner().map_err(|e| e.into_error()))
+        .and_then(|file| file.sync_all())
+        .and_then(|_| std::fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_map<W: Write>(inner: W, map: &Map, marker: &str) -> io::Result<W> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort_by_key(|key| (*key != "multitext header", *key));
+    let mut writer = DocumentWriter::new(inner, marker)?;
+    for key in keys {
+        writer.begin_section(key)?.write_all(map[key].as_bytes())?;
+    }
+    writer.finish()
+}
+
+impl Document {
+    /// Writes the document to `inner` as configured by `options`, returning
+    /// the underlying writer
+    ///
+    /// Without a marker in `options`, the document's own marker is used, or
+    /// `@@@` if it has none.
+    pub fn write_with<W: Write>(&self, inner: W, options: &WriteOptions) -> io::Result<W> {
+        let marker = options.marker.as_deref().or_else(|| self.marker()).unwrap_or(DEFAULT_MARKER);
+        let mut sections: Vec<&Section> = self.iter().collect();
+        if let Some(compare) = options.order.comparison() {
+            sections.sort_by(|a, b| {
+                let header = |s: &Section| s.key() != "multitext header";
+                header(a).cmp(&header(b)).then_with(|| compare(a.key(), b.key()))
+            });
+        }
+
+        let mut writer = DocumentWriter::new(inner, marker)?;
+        if let Some(preamble) = self.preamble() {
+            writer.write_preamble(preamble)?;
+        }
+        for section in sections {
+            let body = match options.max_blank_lines {
+                Some(max) => trim_blank_lines(section.body(), max),
+                None => section.body(),
+            };
+            let parts = match options.max_body_bytes {
+                Some(max) if section.key() != "multitext header" => split_body(body, max),
+                _ => vec![body],
+            };
+            if parts.len() < 2 {
+                writer.begin_section_with_attrs(section.key(), section.attrs())?.write_all(body.as_bytes())?;
+                continue;
+            }
+            for (i, part) in parts.iter().enumerate() {
+                let key = continuation_key(section.key(), i + 1, parts.len());
+                let attrs = if i == 0 { section.attrs().collect() } else { Vec::new() };
+                writer.begin_section_with_attrs(&key, attrs)?.write_all(part.as_bytes())?;
+            }
+        }
+        writer.finish()
+    }
+}
+
+/// Cuts all but `max` of the blank lines off the end of `body`
+fn trim_blank_lines(body: &str, max: usize) -> &str {
+    let blank: usize = body.split_inclusive('\n').rev().take_while(|line| line.trim().is_empty()).map(str::len).sum();
+    let text_end = body.len() - blank;
+    let kept: usize = body[text_end..].split_inclusive('\n').take(max).map(str::len).sum();
+    &body[..text_end + kept]
+}
+
+/// Cuts `body` between lines into pieces of at most `max` bytes where possible
+fn split_body(body: &str, max: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for line in body.split_inclusive('\n') {
+        if end > start && end + line.len() - start > max {
+            parts.push(&body[start..end]);
+            start = end;
+        }
+        end += line.len();
+    }
+    if end > start || parts.is_empty() {
+        parts.push(&body[start..end]);
+    }
+    parts
+}
+
+/// Writes a multitext file to any `io::Write`, one section at a time
+///
+/// Bodies are streamed straight through to the underlying writer, so sections
+/// of any size can be written without holding them in memory.
+///
+/// # Examples
+/// ```
+/// use std::io::Write;
+///
+/// let mut out = Vec::new();
+/// let mut writer = multitext::DocumentWriter::new(&mut out, "@@@").unwrap();
+/// {
+///     let mut section = writer.begin_section("log").unwrap();
+///     std::io::copy(&mut "line 1\nline 2".as_bytes(), &mut section).unwrap();
+/// }
+/// writer.begin_section("notes").unwrap().write_all(b"none\n").unwrap();
+/// writer.finish().unwrap();
+///
+/// assert_eq!(String::from_utf8(out).unwrap(),
+///            "@@@ multitext header\n@@@ log\nline 1\nline 2\n@@@ notes\nnone\n");
+/// ```
+#[derive(Debug)]
+pub struct DocumentWriter<W: Write> {
+    inner: W,
+    marker: String,
+    header_written: bool,
+    needs_newline: bool,
+    /// The start of the current body line while it could still be a marker
+    /// line, after any backslashes, or `None` once it can't
+    line_start: Option<Vec<u8>>,
+}
+
+impl<W: Write> DocumentWriter<W> {
+    /// Prepares to write sections introduced by `marker`
+    ///
+    /// Fails if the marker could not be read back by the parser, or body lines
+    /// starting with it couldn't be escaped: if it is empty, spans lines, ends
+    /// in whitespace, starts with a backslash or contains "multitext header".
+    pub fn new<M: Into<String>>(inner: W, marker: M) -> io::Result<DocumentWriter<W>> {
+        let marker = marker.into();
+        check_marker(&marker)?;
+        Ok(DocumentWriter {
+            inner,
+            marker,
+            header_written: false,
+            needs_newline: false,
+            line_start: Some(Vec::new()),
+        })
+    }
+
+    /// Writes free text above the header line, such as a copyright notice
+    ///
+    /// Fails once a section has been started, or if a line of `text` contains
+    /// "multitext header" and would be read as the header. A final newline is
+    /// added if `text` lacks one.
+    pub fn write_preamble(&mut self, text: &str) -> io::Result<()> {
+        if self.header_written {
+            return Err(invalid_input("the preamble must be written before any section".to_string()));
+        }
+        if text.contains("multitext header") {
+            return Err(invalid_input("the preamble cannot contain \"multitext header\"".to_string()));
+        }
+        self.inner.write_all(text.as_bytes())?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            self.inner.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Starts a new section, returning a writer for its body
+    ///
+    /// The header line is written before the first section if that section
+    /// isn't the "multitext header" itself. A body that doesn't end in a
+    /// newline gets one before the next marker line.
+    pub fn begin_section(&mut self, key: &str) -> io::Result<SectionWriter<'_, W>> {
+        self.begin_section_with_attrs(key, std::iter::empty())
+    }
+
+    /// Starts a new section whose marker line carries `name=value` attributes
+    ///
+    /// Fails if an attribute couldn't be read back with
+    /// [`ParseOptions::attributes`](crate::options::ParseOptions::attributes).
+    pub fn begin_section_with_attrs<'k, A>(&mut self, key: &str, attrs: A) -> io::Result<SectionWriter<'_, W>>
+    where A: IntoIterator<Item = (&'k str, &'k str)>
+    {
+        if key != key.trim() || key.contains('\n') {
+            return Err(invalid_input(format!("key {:?} cannot be written on a marker line", key)));
+        }
+        let mut line = key.to_string();
+        for (name, value) in attrs {
+            if !crate::parse::is_attribute_name(name) || value.is_empty() || value.contains(char::is_whitespace) {
+                return Err(invalid_input(format!("attribute {}={:?} cannot be written on a marker line", name, value)));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&format!("{}={}", name, value));
+        }
+        self.end_body()?;
+        if key == "multitext header" {
+            if self.header_written {
+                return Err(invalid_input("the multitext header can only be written once".to_string()));
+            }
+        } else if !self.header_written {
+            writeln!(self.inner, "{} multitext header", self.marker)?;
+        }
+        self.header_written = true;
+        writeln!(self.inner, "{} {}", self.marker, line)?;
+        self.line_start = Some(Vec::new());
+        Ok(SectionWriter { document: self })
+    }
+
+    /// Terminates the last body and flushes, returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.header_written {
+            writeln!(self.inner, "{} multitext header", self.marker)?;
+        }
+        self.end_body()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn end_body(&mut self) -> io::Result<()> {
+        if let Some(start) = self.line_start.take() {
+            self.inner.write_all(&start)?;
+        }
+        if self.needs_newline {
+            self.inner.write_all(b"\n")?;
+            self.needs_newline = false;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the body of one section; see [`DocumentWriter::begin_section`]
+///
+/// A line that would be read back as a marker line, because it starts with
+/// the marker after any number of backslashes, is escaped with one more
+/// backslash, which the parser removes again.
+#[derive(Debug)]
+pub struct SectionWriter<'a, W: Write> {
+    document: &'a mut DocumentWriter<W>,
+}
+
+impl<'a, W: Write> Write for SectionWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let document = &mut *self.document;
+        let marker = document.marker.as_bytes();
+        let mut out = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            match document.line_start.as_mut() {
+                None => {
+                    out.push(byte);
+                    if byte == b'\n' {
+                        document.line_start = Some(Vec::new());
+                    }
+                }
+                Some(start) if start.is_empty() && (byte == b'\\' || byte == b'\n') => out.push(byte),
+                Some(start) => {
+                    start.push(byte);
+                    let is_marker = start[..] == *marker;
+                    if is_marker || !marker.starts_with(start) {
+                        if is_marker {
+                            out.push(b'\\');
+                        }
+                        out.append(start);
+                        if byte != b'\n' {
+                            document.line_start = None;
+                        }
+                    }
+                }
+            }
+        }
+        document.inner.write_all(&out)?;
+        if let Some(&last) = buf.last() {
+            document.needs_newline = last != b'\n';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.document.inner.flush()
+    }
+}
+
+pub(crate) fn check_marker(marker: &str) -> io::Result<()> {
+    if marker.is_empty() || marker != marker.trim_end() || marker.contains('\n') {
+        return Err(invalid_input(format!("{:?} is not a usable marker", marker)));
+    }
+    if marker.contains("multitext header") {
+        return Err(invalid_input("the marker cannot contain \"multitext header\"".to_string()));
+    }
+    if marker.starts_with('\\') {
+        return Err(invalid_input("the marker cannot start with a backslash, which escapes marker lines".to_string()));
+    }
+    Ok(())
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}